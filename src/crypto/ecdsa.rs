@@ -0,0 +1,194 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! ECDSA, a public-key signature algorithm over elliptic curves.
+
+use crate::crypto::sha256;
+
+#[cfg(doc)]
+use std::convert::Infallible;
+
+/// An elliptic curve that ECDSA keys may be defined over.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Curve {
+    /// The NIST P-256 curve, also known as `secp256r1`.
+    P256,
+    /// The NIST P-384 curve, also known as `secp384r1`.
+    P384,
+}
+
+impl Curve {
+    /// Returns the number of bytes necessary to represent a scalar (or a
+    /// single coordinate of a point) on this curve.
+    pub fn byte_len(self) -> usize {
+        self.bit_len() / 8
+    }
+
+    /// Returns the number of bits in this curve's field.
+    pub fn bit_len(self) -> usize {
+        match self {
+            Self::P256 => 256,
+            Self::P384 => 384,
+        }
+    }
+
+    /// Returns the number of bytes in a signature over this curve.
+    ///
+    /// Signatures are encoded as the big-endian scalars `r` and `s`, each
+    /// padded with zeroes out to [`Curve::byte_len()`], concatenated.
+    pub fn sig_len(self) -> usize {
+        self.byte_len() * 2
+    }
+}
+
+/// The ECDSA public key type for a particular [`Engine`] type.
+///
+/// Rather than prescribe specific types of ECDSA keys, a particular
+/// [`Engine`] implementation can provide its own key types, which implement
+/// this trait.
+pub trait PublicKey {
+    /// Returns the curve this key is defined over.
+    fn curve(&self) -> Curve;
+}
+
+/// The ECDSA public/private keypair type for a particular [`Signer`] type.
+///
+/// This type is the keypair analogue of [`PublicKey`].
+pub trait Keypair {
+    /// The corresponding [`PublicKey`] implementation for this `Keypair`.
+    type Pub: PublicKey;
+
+    /// Returns a copy of the public component of this `Keypair`.
+    fn public(&self) -> Self::Pub;
+
+    /// Returns the curve this keypair is defined over.
+    fn curve(&self) -> Curve;
+}
+
+/// An error returned by an ECDSA function.
+///
+/// This type serves as a combination of built-in error types known to
+/// Manticore, plus a "custom error" component for surfacing
+/// implementation-specific errors that Manticore can treat as a black box.
+///
+/// This type has the benefit that, unlike a pure associated type, `From`
+/// implementations for error-handling can be implemented on it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Error<E = ()> {
+    /// The "custom" error type, which is treated by Manticore as a black box.
+    Custom(E),
+}
+
+impl<E> Error<E> {
+    /// Erases the custom error type from this `Error`, replacing it with `()`.
+    pub fn erased(self) -> Error {
+        match self {
+            Self::Custom(_) => Error::Custom(()),
+        }
+    }
+}
+
+/// A builder for constructing primed ECDSA engines.
+///
+/// In particular, a value of a type implementing this trait already has
+/// everything it needs (such as OS handles) to start performing ECDSA
+/// operations.
+pub trait Builder {
+    /// The [`Engine`] type constructed by this builder. If there isn't a
+    /// meaningful custom error type, use [`Infallible`].
+    type Engine: Engine;
+
+    /// Checks whether [`Self::Engine`] supports public keys over `curve`.
+    /// This function is primarily for `manticore` to dynamically discover
+    /// all the capabilities of an engine.
+    fn supports_curve(&self, curve: Curve) -> bool;
+
+    /// Creates a new [`Engine`], primed with the given key, which may be used
+    /// repeatedly to perform operations.
+    fn new_engine(
+        &self,
+        key: <Self::Engine as Engine>::Key,
+    ) -> Result<Self::Engine, Error<<Self::Engine as Engine>::Error>>;
+}
+
+/// An enhanced [`Builder`] that can produce ECDSA signing engines.
+pub trait SignerBuilder: Builder {
+    /// The concrete `Signer` generated by this trait.
+    type Signer: Signer<Engine = Self::Engine>;
+
+    /// Creates a new [`Signer`], primed with the given keypair, which may be
+    /// used repeatedly to perform operations.
+    fn new_signer(
+        &self,
+        keypair: <Self::Signer as Signer>::Keypair,
+    ) -> Result<Self::Signer, Error<<Self::Engine as Engine>::Error>>;
+}
+
+/// An ECDSA engine, already primed with a key.
+///
+/// There is no way to extract the key back out of an `Engine` value.
+pub trait Engine {
+    /// The error returned when an ECDSA operation fails.
+    type Error;
+    /// The key type used by this engine.
+    type Key: PublicKey;
+
+    /// Returns the curve of the key this engine was primed with.
+    fn curve(&self) -> Curve;
+
+    /// Uses this engine to verify that `signature` is a valid signature of
+    /// `message`.
+    ///
+    /// `signature` is expected to be in the fixed-width format described in
+    /// [`Curve::sig_len()`]. The message is hashed with the digest that is
+    /// conventionally paired with the key's curve: SHA-256 for P-256, and
+    /// SHA-384 for P-384.
+    ///
+    /// If the underlying cryptographic operation succeeds, returns `Ok(())`.
+    /// Failures, including signature check failures, are included in the
+    /// `Err` variant.
+    fn verify_signature(
+        &mut self,
+        signature: &[u8],
+        message: &[u8],
+    ) -> Result<(), Error<Self::Error>>;
+
+    /// Returns the SHA-256 digest of the key this engine was primed with,
+    /// which identifies the key in a [key manifest].
+    ///
+    /// Which encoding of the key is hashed is up to the implementation, so
+    /// long as key manifests are minted with the same one.
+    ///
+    /// [key manifest]: crate::manifest::km
+    fn key_digest(&self) -> Result<sha256::Digest, Error<Self::Error>>;
+}
+
+/// An ECDSA signing engine, already primed with a keypair.
+///
+/// There is no way to extract the keypair back out of a `Signer` value.
+pub trait Signer {
+    /// The [`Engine`] type that this signer corresponds to.
+    type Engine: Engine;
+
+    /// The keypair type used by this signer.
+    type Keypair: Keypair<Pub = <Self::Engine as Engine>::Key>;
+
+    /// Returns the curve of the keypair (and, by extension, the length of a
+    /// signature value).
+    fn curve(&self) -> Curve;
+
+    /// Uses this signer to create a signature value for `message`.
+    ///
+    /// The resulting value is written to `signature`, in the format described
+    /// in [`Curve::sig_len()`]. As such, exactly `self.curve().sig_len()`
+    /// bytes will be written to by this function.
+    ///
+    /// If the underlying cryptographic operation succeeds, returns `Ok(())`.
+    /// Failures are included in the `Err` variant.
+    fn sign(
+        &mut self,
+        message: &[u8],
+        signature: &mut [u8],
+    ) -> Result<(), Error<<Self::Engine as Engine>::Error>>;
+}
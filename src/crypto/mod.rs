@@ -37,8 +37,10 @@
 //!
 //! [`ring` module]: ring/index.html
 
+pub mod ecdsa;
 pub mod rsa;
 pub mod sha256;
+pub mod sig;
 
 #[cfg(feature = "ring")]
 pub mod ring;
@@ -0,0 +1,249 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Implementations of [`crypto::ecdsa`] based on `ring`.
+//!
+//! Requires the `std` feature flag to be enabled.
+
+use ring::digest;
+use ring::error::Unspecified;
+use ring::signature::EcdsaSigningAlgorithm;
+use ring::signature::EcdsaVerificationAlgorithm;
+use ring::signature::KeyPair as _;
+
+use crate::crypto::ecdsa;
+use crate::crypto::sha256;
+
+#[cfg(doc)]
+use crate::crypto;
+
+fn signing_alg(curve: ecdsa::Curve) -> &'static EcdsaSigningAlgorithm {
+    match curve {
+        ecdsa::Curve::P256 => &ring::signature::ECDSA_P256_SHA256_FIXED_SIGNING,
+        ecdsa::Curve::P384 => &ring::signature::ECDSA_P384_SHA384_FIXED_SIGNING,
+    }
+}
+
+fn verification_alg(
+    curve: ecdsa::Curve,
+) -> &'static EcdsaVerificationAlgorithm {
+    match curve {
+        ecdsa::Curve::P256 => &ring::signature::ECDSA_P256_SHA256_FIXED,
+        ecdsa::Curve::P384 => &ring::signature::ECDSA_P384_SHA384_FIXED,
+    }
+}
+
+/// A `ring`-based [`ecdsa::PublicKey`].
+#[derive(Clone)]
+pub struct PublicKey {
+    curve: ecdsa::Curve,
+    point: Box<[u8]>,
+}
+
+impl PublicKey {
+    /// Creates a new `PublicKey` on `curve` out of the given point, which
+    /// should be in uncompressed SEC1 format: the byte `0x04`, followed by the
+    /// big-endian `x` and `y` coordinates, padded with zeroes out to the
+    /// curve's byte length.
+    ///
+    /// Returns `None` if the point is not of the expected length or format.
+    pub fn new(curve: ecdsa::Curve, point: Box<[u8]>) -> Option<Self> {
+        if point.len() != 1 + 2 * curve.byte_len() || point[0] != 0x04 {
+            return None;
+        }
+        Some(Self { curve, point })
+    }
+}
+
+impl ecdsa::PublicKey for PublicKey {
+    fn curve(&self) -> ecdsa::Curve {
+        self.curve
+    }
+}
+
+/// A `ring`-based [`ecdsa::Keypair`].
+pub struct Keypair {
+    curve: ecdsa::Curve,
+    keypair: ring::signature::EcdsaKeyPair,
+}
+
+impl Keypair {
+    /// Creates a new `Keypair` from the given PKCS#8-encoded private key.
+    ///
+    /// This function will return `None` if parsing fails or if the key is not
+    /// on one of the curves in [`ecdsa::Curve`].
+    pub fn from_pkcs8(pkcs8: &[u8]) -> Option<Self> {
+        [ecdsa::Curve::P256, ecdsa::Curve::P384]
+            .iter()
+            .find_map(|&curve| {
+                ring::signature::EcdsaKeyPair::from_pkcs8(
+                    signing_alg(curve),
+                    pkcs8,
+                )
+                .ok()
+                .map(|keypair| Self { curve, keypair })
+            })
+    }
+}
+
+impl ecdsa::Keypair for Keypair {
+    type Pub = PublicKey;
+    fn public(&self) -> Self::Pub {
+        let point = self.keypair.public_key().as_ref().to_vec();
+        PublicKey::new(self.curve, point.into_boxed_slice())
+            .expect("ring should produce uncompressed points!")
+    }
+
+    fn curve(&self) -> ecdsa::Curve {
+        self.curve
+    }
+}
+
+/// A `ring`-based [`ecdsa::Builder`] and [`ecdsa::SignerBuilder`].
+pub struct Builder {
+    _priv: (),
+}
+
+impl Builder {
+    /// Creates a new `Builder`.
+    pub fn new() -> Self {
+        Builder { _priv: () }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ecdsa::Builder for Builder {
+    type Engine = Engine;
+
+    fn supports_curve(&self, _: ecdsa::Curve) -> bool {
+        true
+    }
+
+    fn new_engine(
+        &self,
+        key: PublicKey,
+    ) -> Result<Engine, ecdsa::Error<Unspecified>> {
+        Ok(Engine { key })
+    }
+}
+
+impl ecdsa::SignerBuilder for Builder {
+    type Signer = Signer;
+
+    fn new_signer(
+        &self,
+        keypair: Keypair,
+    ) -> Result<Signer, ecdsa::Error<Unspecified>> {
+        Ok(Signer { keypair })
+    }
+}
+
+/// A `ring`-based [`ecdsa::Engine`].
+pub struct Engine {
+    key: PublicKey,
+}
+
+impl ecdsa::Engine for Engine {
+    type Error = Unspecified;
+    type Key = PublicKey;
+
+    fn curve(&self) -> ecdsa::Curve {
+        self.key.curve
+    }
+
+    fn verify_signature(
+        &mut self,
+        signature: &[u8],
+        message: &[u8],
+    ) -> Result<(), ecdsa::Error<Unspecified>> {
+        ring::signature::UnparsedPublicKey::new(
+            verification_alg(self.key.curve),
+            &self.key.point,
+        )
+        .verify(message, signature)
+        .map_err(ecdsa::Error::Custom)
+    }
+
+    /// Hashes the uncompressed SEC1 point, as given to [`PublicKey::new()`].
+    fn key_digest(&self) -> Result<sha256::Digest, ecdsa::Error<Unspecified>> {
+        let mut out = sha256::Digest::default();
+        out.copy_from_slice(
+            digest::digest(&digest::SHA256, &self.key.point).as_ref(),
+        );
+        Ok(out)
+    }
+}
+
+/// A `ring`-based [`ecdsa::Signer`].
+pub struct Signer {
+    keypair: Keypair,
+}
+
+impl ecdsa::Signer for Signer {
+    type Engine = Engine;
+    type Keypair = Keypair;
+
+    fn curve(&self) -> ecdsa::Curve {
+        self.keypair.curve
+    }
+
+    fn sign(
+        &mut self,
+        message: &[u8],
+        signature: &mut [u8],
+    ) -> Result<(), ecdsa::Error<Unspecified>> {
+        if signature.len() != self.keypair.curve.sig_len() {
+            return Err(ecdsa::Error::Custom(Unspecified));
+        }
+
+        let rng = ring::rand::SystemRandom::new();
+        let sig = self
+            .keypair
+            .keypair
+            .sign(&rng, message)
+            .map_err(ecdsa::Error::Custom)?;
+        signature.copy_from_slice(sig.as_ref());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ecdsa;
+    use crate::crypto::ecdsa::Builder as _;
+    use crate::crypto::ecdsa::Engine as _;
+    use crate::crypto::ecdsa::Keypair as _;
+    use crate::crypto::ecdsa::Signer as _;
+    use crate::crypto::ecdsa::SignerBuilder as _;
+    use crate::crypto::testdata;
+
+    #[test]
+    fn ecdsa() {
+        let keypair =
+            Keypair::from_pkcs8(testdata::ECDSA_P384_PRIV_PKCS8).unwrap();
+        assert_eq!(keypair.curve(), ecdsa::Curve::P384);
+
+        let ecdsa = Builder::new();
+        let mut engine = ecdsa.new_engine(keypair.public()).unwrap();
+        let mut signer = ecdsa.new_signer(keypair).unwrap();
+
+        let mut sig = vec![0; signer.curve().sig_len()];
+        signer.sign(testdata::PLAIN_TEXT, &mut sig).unwrap();
+        engine.verify_signature(&sig, testdata::PLAIN_TEXT).unwrap();
+
+        sig[7] ^= 0x01;
+        assert!(engine.verify_signature(&sig, testdata::PLAIN_TEXT).is_err());
+    }
+
+    #[test]
+    fn not_ecdsa() {
+        assert!(Keypair::from_pkcs8(testdata::RSA_2048_PRIV_PKCS8).is_none());
+    }
+}
@@ -19,6 +19,9 @@
 
 pub mod sha256;
 
+#[cfg(feature = "std")]
+pub mod ecdsa;
+
 #[cfg(feature = "std")]
 pub mod rsa;
 
@@ -6,11 +6,13 @@
 //!
 //! Requires the `std` feature flag to be enabled.
 
+use ring::digest;
 use ring::error::Unspecified;
 use ring::signature::KeyPair as _;
 use ring::signature::RsaPublicKeyComponents;
 
 use crate::crypto::rsa;
+use crate::crypto::sha256;
 
 #[cfg(doc)]
 use crate::crypto;
@@ -153,6 +155,18 @@ impl rsa::Engine for Engine {
             .verify(scheme, message, signature)
             .map_err(rsa::Error::Custom)
     }
+
+    /// Hashes the big-endian modulus, followed by the big-endian exponent,
+    /// as given to [`PublicKey::new()`].
+    fn key_digest(&self) -> Result<sha256::Digest, rsa::Error<Unspecified>> {
+        let mut ctx = digest::Context::new(&digest::SHA256);
+        ctx.update(&self.key.key.n);
+        ctx.update(&self.key.key.e);
+
+        let mut out = sha256::Digest::default();
+        out.copy_from_slice(ctx.finish().as_ref());
+        Ok(out)
+    }
 }
 
 /// A `ring`-based [`rsa::Signer`].
@@ -39,6 +39,20 @@ impl sha256::Builder for Builder {
             ctx: digest::Context::new(&digest::SHA256),
         })
     }
+
+    fn new_wide_hasher(
+        &self,
+        digest_len: usize,
+    ) -> Result<Hasher, sha256::Error<Infallible>> {
+        let algo = match digest_len {
+            48 => &digest::SHA384,
+            64 => &digest::SHA512,
+            _ => return Err(sha256::Error::Unsupported),
+        };
+        Ok(Hasher {
+            ctx: digest::Context::new(algo),
+        })
+    }
 }
 
 /// A `ring`-based [`sha256::Hasher`].
@@ -59,8 +73,18 @@ impl sha256::Hasher for Hasher {
     fn finish(
         self,
         out: &mut sha256::Digest,
+    ) -> Result<(), sha256::Error<Infallible>> {
+        self.finish_wide(out)
+    }
+
+    fn finish_wide(
+        self,
+        out: &mut [u8],
     ) -> Result<(), sha256::Error<Infallible>> {
         let digest = self.ctx.finish();
+        if out.len() != digest.as_ref().len() {
+            return Err(sha256::Error::Unsupported);
+        }
         out.copy_from_slice(digest.as_ref());
         Ok(())
     }
@@ -90,4 +114,29 @@ mod tests {
         hasher.finish(&mut digest).unwrap();
         assert_eq!(&digest, testdata::PLAIN_SHA256);
     }
+
+    #[test]
+    fn wide() {
+        let sha = Builder::new();
+        for &(len, algo) in &[(48, &digest::SHA384), (64, &digest::SHA512)] {
+            let expected = digest::digest(algo, testdata::PLAIN_TEXT);
+
+            let mut hasher = sha.new_wide_hasher(len).unwrap();
+            hasher.write(testdata::PLAIN_TEXT).unwrap();
+            let mut out = vec![0; len];
+            hasher.finish_wide(&mut out).unwrap();
+            assert_eq!(out, expected.as_ref());
+
+            // A wide hasher cannot produce a SHA-256-sized digest.
+            let hasher = sha.new_wide_hasher(len).unwrap();
+            assert_eq!(
+                hasher.finish(&mut sha256::Digest::default()),
+                Err(sha256::Error::Unsupported)
+            );
+        }
+        assert!(matches!(
+            sha.new_wide_hasher(32),
+            Err(sha256::Error::Unsupported)
+        ));
+    }
 }
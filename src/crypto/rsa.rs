@@ -4,6 +4,8 @@
 
 //! RSA, a public-key encryption algorithm.
 
+use crate::crypto::sha256;
+
 #[cfg(doc)]
 use std::convert::Infallible;
 
@@ -165,6 +167,15 @@ pub trait Engine {
         signature: &[u8],
         message: &[u8],
     ) -> Result<(), Error<Self::Error>>;
+
+    /// Returns the SHA-256 digest of the key this engine was primed with,
+    /// which identifies the key in a [key manifest].
+    ///
+    /// Which encoding of the key is hashed is up to the implementation, so
+    /// long as key manifests are minted with the same one.
+    ///
+    /// [key manifest]: crate::manifest::km
+    fn key_digest(&self) -> Result<sha256::Digest, Error<Self::Error>>;
 }
 
 /// An RSA signing engine, already primed with a keypair.
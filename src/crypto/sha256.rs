@@ -4,6 +4,8 @@
 
 //! SHA-256, a cryptographic hash algorithm.
 
+use core::convert::TryInto as _;
+
 #[cfg(doc)]
 use std::convert::Infallible;
 
@@ -22,6 +24,9 @@ pub type Digest = [u8; 32];
 /// implementations for error-handling can be implemented on it.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum Error<E = ()> {
+    /// Indicates that the requested algorithm is not supported by this
+    /// implementation.
+    Unsupported,
     /// The "custom" error type, which is treated by Manticore as a black box.
     Custom(E),
 }
@@ -30,6 +35,7 @@ impl<E> Error<E> {
     /// Erases the custom error type from this `Error`, replacing it with `()`.
     pub fn erased(self) -> Error {
         match self {
+            Self::Unsupported => Error::Unsupported,
             Self::Custom(_) => Error::Custom(()),
         }
     }
@@ -60,6 +66,20 @@ pub trait Builder {
         hasher.write(bytes)?;
         hasher.finish(out)
     }
+
+    /// Begins a new hashing operation using one of the wider SHA-2
+    /// algorithms: SHA-384 if `digest_len` is 48, or SHA-512 if it is 64.
+    ///
+    /// The returned [`Hasher`] must be finished with
+    /// [`Hasher::finish_wide()`]. Supporting these algorithms is optional;
+    /// by default, this function returns [`Error::Unsupported`].
+    fn new_wide_hasher(
+        &self,
+        digest_len: usize,
+    ) -> Result<Self::Hasher, Error<<Self::Hasher as Hasher>::Error>> {
+        let _ = digest_len;
+        Err(Error::Unsupported)
+    }
 }
 
 /// A particular hashing operation in progress.
@@ -77,4 +97,21 @@ pub trait Hasher {
     /// Finishes the current hashing operation, writing the result to the given
     /// buffer.
     fn finish(self, out: &mut Digest) -> Result<(), Error<Self::Error>>;
+
+    /// Finishes the current hashing operation, writing a digest of any
+    /// supported length to `out`.
+    ///
+    /// This must be used to finish hashers obtained from
+    /// [`Builder::new_wide_hasher()`]; `out` must be exactly as long as the
+    /// digest. By default, this function defers to [`Hasher::finish()`] if
+    /// `out` is 32 bytes long, and returns [`Error::Unsupported`] otherwise.
+    fn finish_wide(self, out: &mut [u8]) -> Result<(), Error<Self::Error>>
+    where
+        Self: Sized,
+    {
+        match out.try_into() {
+            Ok(out) => self.finish(out),
+            Err(_) => Err(Error::Unsupported),
+        }
+    }
 }
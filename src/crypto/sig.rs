@@ -0,0 +1,247 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Algorithm-agnostic signatures.
+//!
+//! Some signed objects, such as manifests, record which algorithm they were
+//! signed with. The traits in this module allow code that checks or creates
+//! such signatures to dispatch on that algorithm at runtime, rather than
+//! being hard-wired to a single algorithm.
+//!
+//! Algorithm-specific engines can be adapted to these traits with the
+//! [`Rsa`] and [`Ecdsa`] wrappers, and several [`Verify`] implementations
+//! can be combined into one by placing them in a tuple:
+//! ```ignore
+//! let mut verify = (sig::Rsa(rsa_engine), sig::Ecdsa(ecdsa_engine));
+//! ```
+
+use crate::crypto::ecdsa;
+use crate::crypto::rsa;
+use crate::crypto::sha256;
+
+/// A signature algorithm, along with its key size.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Algo {
+    /// RSA with PKCS v1.5 padding.
+    Rsa(rsa::ModulusLength),
+    /// ECDSA, with signatures in the fixed-width format described in
+    /// [`ecdsa::Curve::sig_len()`].
+    Ecdsa(ecdsa::Curve),
+}
+
+impl Algo {
+    /// Returns the number of bytes in a signature produced by this algorithm.
+    pub fn sig_len(self) -> usize {
+        match self {
+            Self::Rsa(len) => len.byte_len(),
+            Self::Ecdsa(curve) => curve.sig_len(),
+        }
+    }
+}
+
+/// An error returned by a signature operation.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// Indicates that the requested algorithm is not supported by this
+    /// engine.
+    Unsupported,
+    /// Indicates that an RSA operation failed.
+    Rsa(rsa::Error),
+    /// Indicates that an ECDSA operation failed.
+    Ecdsa(ecdsa::Error),
+}
+
+impl<E> From<rsa::Error<E>> for Error {
+    fn from(e: rsa::Error<E>) -> Self {
+        Self::Rsa(e.erased())
+    }
+}
+
+impl<E> From<ecdsa::Error<E>> for Error {
+    fn from(e: ecdsa::Error<E>) -> Self {
+        Self::Ecdsa(e.erased())
+    }
+}
+
+/// A signature verification engine for one or more [`Algo`]s.
+pub trait Verify {
+    /// Verifies that `signature` is a valid signature of `message`, made
+    /// using `algo`.
+    ///
+    /// If this engine cannot verify signatures made with `algo`, this
+    /// function returns [`Error::Unsupported`].
+    fn verify_signature(
+        &mut self,
+        algo: Algo,
+        signature: &[u8],
+        message: &[u8],
+    ) -> Result<(), Error>;
+
+    /// Returns the SHA-256 digest of the key this engine verifies `algo`
+    /// signatures with, which identifies it in a [key manifest].
+    ///
+    /// This function must return [`Error::Unsupported`] exactly when
+    /// [`Verify::verify_signature()`] would for `algo`, so that the digest
+    /// always describes the key that actually checks a signature.
+    ///
+    /// [key manifest]: crate::manifest::km
+    fn key_digest(&self, algo: Algo) -> Result<sha256::Digest, Error>;
+}
+
+impl<V: Verify + ?Sized> Verify for &mut V {
+    fn verify_signature(
+        &mut self,
+        algo: Algo,
+        signature: &[u8],
+        message: &[u8],
+    ) -> Result<(), Error> {
+        V::verify_signature(self, algo, signature, message)
+    }
+
+    fn key_digest(&self, algo: Algo) -> Result<sha256::Digest, Error> {
+        V::key_digest(self, algo)
+    }
+}
+
+/// A pair of engines, which verifies with whichever one supports the
+/// requested algorithm, preferring the first.
+impl<A: Verify, B: Verify> Verify for (A, B) {
+    fn verify_signature(
+        &mut self,
+        algo: Algo,
+        signature: &[u8],
+        message: &[u8],
+    ) -> Result<(), Error> {
+        match self.0.verify_signature(algo, signature, message) {
+            Err(Error::Unsupported) => {
+                self.1.verify_signature(algo, signature, message)
+            }
+            r => r,
+        }
+    }
+
+    fn key_digest(&self, algo: Algo) -> Result<sha256::Digest, Error> {
+        match self.0.key_digest(algo) {
+            Err(Error::Unsupported) => self.1.key_digest(algo),
+            r => r,
+        }
+    }
+}
+
+/// A signing engine for a specific [`Algo`].
+pub trait Sign {
+    /// Returns the algorithm this engine signs with.
+    fn algo(&self) -> Algo;
+
+    /// Creates a signature for `message`, writing it to `signature`.
+    ///
+    /// Exactly `self.algo().sig_len()` bytes will be written.
+    fn sign(
+        &mut self,
+        message: &[u8],
+        signature: &mut [u8],
+    ) -> Result<(), Error>;
+}
+
+impl<S: Sign + ?Sized> Sign for &mut S {
+    fn algo(&self) -> Algo {
+        S::algo(self)
+    }
+
+    fn sign(
+        &mut self,
+        message: &[u8],
+        signature: &mut [u8],
+    ) -> Result<(), Error> {
+        S::sign(self, message, signature)
+    }
+}
+
+/// An adapter for using an [`rsa::Engine`] as a [`Verify`], or an
+/// [`rsa::Signer`] as a [`Sign`].
+pub struct Rsa<E>(pub E);
+
+impl<E: rsa::Engine> Verify for Rsa<E> {
+    fn verify_signature(
+        &mut self,
+        algo: Algo,
+        signature: &[u8],
+        message: &[u8],
+    ) -> Result<(), Error> {
+        match algo {
+            Algo::Rsa(_) => {
+                self.0.verify_signature(signature, message)?;
+                Ok(())
+            }
+            _ => Err(Error::Unsupported),
+        }
+    }
+
+    fn key_digest(&self, algo: Algo) -> Result<sha256::Digest, Error> {
+        match algo {
+            Algo::Rsa(_) => Ok(self.0.key_digest()?),
+            _ => Err(Error::Unsupported),
+        }
+    }
+}
+
+impl<S: rsa::Signer> Sign for Rsa<S> {
+    fn algo(&self) -> Algo {
+        Algo::Rsa(self.0.pub_len())
+    }
+
+    fn sign(
+        &mut self,
+        message: &[u8],
+        signature: &mut [u8],
+    ) -> Result<(), Error> {
+        self.0.sign(message, signature)?;
+        Ok(())
+    }
+}
+
+/// An adapter for using an [`ecdsa::Engine`] as a [`Verify`], or an
+/// [`ecdsa::Signer`] as a [`Sign`].
+pub struct Ecdsa<E>(pub E);
+
+impl<E: ecdsa::Engine> Verify for Ecdsa<E> {
+    fn verify_signature(
+        &mut self,
+        algo: Algo,
+        signature: &[u8],
+        message: &[u8],
+    ) -> Result<(), Error> {
+        match algo {
+            Algo::Ecdsa(curve) if curve == self.0.curve() => {
+                self.0.verify_signature(signature, message)?;
+                Ok(())
+            }
+            _ => Err(Error::Unsupported),
+        }
+    }
+
+    fn key_digest(&self, algo: Algo) -> Result<sha256::Digest, Error> {
+        match algo {
+            Algo::Ecdsa(curve) if curve == self.0.curve() => {
+                Ok(self.0.key_digest()?)
+            }
+            _ => Err(Error::Unsupported),
+        }
+    }
+}
+
+impl<S: ecdsa::Signer> Sign for Ecdsa<S> {
+    fn algo(&self) -> Algo {
+        Algo::Ecdsa(self.0.curve())
+    }
+
+    fn sign(
+        &mut self,
+        message: &[u8],
+        signature: &mut [u8],
+    ) -> Result<(), Error> {
+        self.0.sign(message, signature)?;
+        Ok(())
+    }
+}
@@ -8,10 +8,14 @@
 //! only. In particular, we keep the private key around to make it possible to
 //! re-sign test data to reduce the brittleness of tests.
 
+use crate::crypto::ecdsa::Builder as _;
+use crate::crypto::ecdsa::Keypair as _;
+use crate::crypto::ecdsa::SignerBuilder as _;
 use crate::crypto::ring;
 use crate::crypto::rsa::Builder as _;
 use crate::crypto::rsa::Keypair as _;
 use crate::crypto::rsa::SignerBuilder as _;
+use crate::crypto::sig;
 
 /// A plaintext string.
 pub const PLAIN_TEXT: &[u8] = include_bytes!("plain.txt");
@@ -38,12 +42,34 @@ pub const RSA_2048_PRIV_PKCS8: &[u8] =
 pub const RSA_2048_SHA256_SIG_PKCS1: &[u8] =
     include_bytes!("rsa_2048_sha256_sig.pk1");
 
+/// A P-384 ECDSA private key, in PKCS#8 format.
+///
+/// This key was generated with `ring`'s `EcdsaKeyPair::generate_pkcs8()`,
+/// since `ring` rejects the encoding `openssl genpkey` produces for EC keys.
+/// The PEM version may be used with `openssl` as-is.
+pub const ECDSA_P384_PRIV_PKCS8: &[u8] =
+    include_bytes!("ecdsa_p384_private_key.pk8");
+
 /// Generates an RSA engine and signer out of test-only data.
-pub fn rsa() -> (ring::rsa::Engine, ring::rsa::Signer) {
+pub fn rsa() -> (sig::Rsa<ring::rsa::Engine>, sig::Rsa<ring::rsa::Signer>) {
     let keypair = ring::rsa::Keypair::from_pkcs8(RSA_2048_PRIV_PKCS8).unwrap();
     let pub_key = keypair.public();
     let rsa_builder = ring::rsa::Builder::new();
     let rsa = rsa_builder.new_engine(pub_key).unwrap();
     let signer = rsa_builder.new_signer(keypair).unwrap();
-    (rsa, signer)
+    (sig::Rsa(rsa), sig::Rsa(signer))
+}
+
+/// Generates an ECDSA engine and signer out of test-only data.
+pub fn ecdsa() -> (
+    sig::Ecdsa<ring::ecdsa::Engine>,
+    sig::Ecdsa<ring::ecdsa::Signer>,
+) {
+    let keypair =
+        ring::ecdsa::Keypair::from_pkcs8(ECDSA_P384_PRIV_PKCS8).unwrap();
+    let pub_key = keypair.public();
+    let ecdsa_builder = ring::ecdsa::Builder::new();
+    let ecdsa = ecdsa_builder.new_engine(pub_key).unwrap();
+    let signer = ecdsa_builder.new_signer(keypair).unwrap();
+    (sig::Ecdsa(ecdsa), sig::Ecdsa(signer))
 }
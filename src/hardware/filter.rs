@@ -0,0 +1,337 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Host SPI filtering.
+//!
+//! A PA-RoT sits between its host and the host's SPI flash, and enforces the
+//! policy in a PFM by filtering the host's traffic: the host may only read the
+//! regions the PFM describes, and may only write its read-write regions.
+//!
+//! This module provides the [`SpiFilter`] trait, which abstracts over the
+//! hardware that performs this filtering. To derive rules from a PFM, see
+//! [`ParsedPfm::apply_filter()`].
+//!
+//! [`ParsedPfm::apply_filter()`]: crate::manifest::pfm::ParsedPfm::apply_filter
+
+#![allow(unsafe_code)]
+
+use crate::hardware::flash::Region;
+
+#[cfg(feature = "std")]
+use crate::hardware::flash;
+#[cfg(feature = "std")]
+use crate::hardware::flash::Flash;
+#[cfg(feature = "std")]
+use crate::hardware::flash::Geometry;
+#[cfg(feature = "std")]
+use crate::mem::Arena;
+
+/// A [`SpiFilter`] error.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Error {
+    /// Indicates that the filter cannot hold any more rules.
+    TooManyRules,
+
+    /// Indicates that a rule's region was not aligned to the filter's
+    /// [`SpiFilter::granularity()`].
+    Unaligned,
+
+    /// Indicates that a writable region overlapped a read-only one, so that
+    /// no set of rules could enforce both.
+    Conflict,
+
+    /// Indicates that an unspecified error occured.
+    Unspecified,
+}
+
+/// The access a [`Rule`] grants the host.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Access {
+    /// The host may read, but not write, the region.
+    ReadOnly,
+    /// The host may both read and write the region.
+    ReadWrite,
+}
+
+impl Access {
+    /// Returns whether `self` grants at least the access `other` does.
+    pub fn allows(self, other: Access) -> bool {
+        self == Access::ReadWrite || other == Access::ReadOnly
+    }
+}
+
+/// A single filter rule, granting the host some access to a region of its
+/// flash.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Rule {
+    /// The region of host flash this rule covers.
+    pub region: Region,
+    /// The access granted to `region`.
+    pub access: Access,
+}
+
+/// A SPI filter, which mediates the host's access to its flash.
+///
+/// A filter holds a list of [`Rule`]s. Host accesses that are not covered by
+/// any rule are blocked; in particular, a filter with no rules blocks the host
+/// entirely.
+///
+/// Filters are expected to be reprogrammed while the host is held in reset;
+/// host accesses while rules are being added may be blocked spuriously.
+pub trait SpiFilter {
+    /// Returns the maximum number of rules this filter can hold.
+    fn capacity(&self) -> usize;
+
+    /// Returns the alignment, in bytes, that every rule's region must have.
+    fn granularity(&self) -> u32 {
+        1
+    }
+
+    /// Removes every rule from this filter.
+    fn clear(&mut self) -> Result<(), Error>;
+
+    /// Adds `rule` to this filter.
+    ///
+    /// Returns [`Error::TooManyRules`] if the filter is already full, and
+    /// [`Error::Unaligned`] if `rule.region` does not respect
+    /// [`SpiFilter::granularity()`].
+    fn add_rule(&mut self, rule: Rule) -> Result<(), Error>;
+}
+
+/// Replaces the rules in `filter` with `rules`.
+///
+/// If any rule cannot be added, `filter` is left with no rules, blocking the
+/// host entirely.
+pub fn apply(
+    filter: &mut dyn SpiFilter,
+    rules: impl IntoIterator<Item = Rule>,
+) -> Result<(), Error> {
+    filter.clear()?;
+    let result = rules.into_iter().try_for_each(|r| filter.add_rule(r));
+    if result.is_err() {
+        let _ = filter.clear();
+    }
+    result
+}
+
+/// A simulated [`SpiFilter`], which guards a [`Flash`] representing the host's
+/// view of its flash.
+///
+/// Reads and writes through a `Simulated` that are not covered by its rules
+/// fail with [`flash::Error::Locked`], and do not reach the underlying device.
+#[cfg(feature = "std")]
+pub struct Simulated<F> {
+    flash: F,
+    capacity: usize,
+    granularity: u32,
+    rules: Vec<Rule>,
+}
+
+#[cfg(feature = "std")]
+impl<F> Simulated<F> {
+    /// Creates a new `Simulated` filter over `flash`, which can hold up to
+    /// `capacity` rules aligned to `granularity`.
+    ///
+    /// The new filter has no rules, so it initially blocks all access.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `granularity` is not a power of two.
+    pub fn new(flash: F, capacity: usize, granularity: u32) -> Self {
+        assert!(granularity.is_power_of_two());
+        Self {
+            flash,
+            capacity,
+            granularity,
+            rules: Vec::new(),
+        }
+    }
+
+    /// Returns the rules currently in this filter.
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Returns a reference to the guarded device, bypassing the filter.
+    pub fn inner(&self) -> &F {
+        &self.flash
+    }
+
+    /// Consumes this filter, returning the guarded device.
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Checks that every byte of `region` is covered by a rule granting at
+    /// least `access`.
+    fn check(
+        &self,
+        region: Region,
+        access: Access,
+    ) -> Result<(), flash::Error> {
+        let mut cursor = region.offset;
+        while cursor < region.end() {
+            cursor = self
+                .rules
+                .iter()
+                .filter(|r| r.access.allows(access))
+                .map(|r| r.region)
+                .find(|r| r.offset <= cursor && cursor < r.end())
+                .ok_or(flash::Error::Locked)?
+                .end();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<F> SpiFilter for Simulated<F> {
+    fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn granularity(&self) -> u32 {
+        self.granularity
+    }
+
+    fn clear(&mut self) -> Result<(), Error> {
+        self.rules.clear();
+        Ok(())
+    }
+
+    fn add_rule(&mut self, rule: Rule) -> Result<(), Error> {
+        if self.rules.len() >= self.capacity {
+            return Err(Error::TooManyRules);
+        }
+        let mask = self.granularity - 1;
+        if rule.region.offset & mask != 0 || rule.region.len & mask != 0 {
+            return Err(Error::Unaligned);
+        }
+        self.rules.push(rule);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+unsafe impl<F: Flash> Flash for Simulated<F> {
+    fn size(&self) -> Result<u32, flash::Error> {
+        self.flash.size()
+    }
+
+    fn read(&self, offset: u32, out: &mut [u8]) -> Result<(), flash::Error> {
+        self.check(Region::new(offset, out.len() as u32), Access::ReadOnly)?;
+        self.flash.read(offset, out)
+    }
+
+    fn read_direct<'a: 'c, 'b: 'c, 'c>(
+        &'a self,
+        region: Region,
+        arena: &'b dyn Arena,
+        align: usize,
+    ) -> Result<&'c [u8], flash::Error> {
+        self.check(region, Access::ReadOnly)?;
+        self.flash.read_direct(region, arena, align)
+    }
+
+    fn program(&mut self, offset: u32, buf: &[u8]) -> Result<(), flash::Error> {
+        self.check(Region::new(offset, buf.len() as u32), Access::ReadWrite)?;
+        self.flash.program(offset, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), flash::Error> {
+        self.flash.flush()
+    }
+
+    fn geometry(&self) -> Result<Geometry, flash::Error> {
+        self.flash.geometry()
+    }
+
+    fn erase(&mut self, region: Region) -> Result<(), flash::Error> {
+        self.check(region, Access::ReadWrite)?;
+        self.flash.erase(region)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::hardware::flash::RamMut;
+
+    fn rule(offset: u32, len: u32, access: Access) -> Rule {
+        Rule {
+            region: Region::new(offset, len),
+            access,
+        }
+    }
+
+    #[test]
+    fn simulated() {
+        let mut filter = Simulated::new(RamMut(vec![0; 0x100]), 3, 0x10);
+        let mut buf = [0; 4];
+        assert!(matches!(
+            filter.read(0, &mut buf),
+            Err(flash::Error::Locked)
+        ));
+
+        apply(
+            &mut filter,
+            vec![
+                rule(0x00, 0x20, Access::ReadOnly),
+                rule(0x20, 0x10, Access::ReadWrite),
+                rule(0x80, 0x10, Access::ReadWrite),
+            ],
+        )
+        .unwrap();
+
+        // Accesses may span adjacent rules.
+        filter.read(0x1e, &mut buf).unwrap();
+        filter.program(0x24, b"rw!!").unwrap();
+        assert!(matches!(
+            filter.program(0x1e, &buf),
+            Err(flash::Error::Locked)
+        ));
+        assert!(matches!(
+            filter.program(0x2e, &buf),
+            Err(flash::Error::Locked)
+        ));
+        assert!(matches!(
+            filter.read(0x40, &mut buf),
+            Err(flash::Error::Locked)
+        ));
+        filter.erase(Region::new(0x80, 0x10)).unwrap();
+        assert!(matches!(
+            filter.erase(Region::new(0x00, 0x10)),
+            Err(flash::Error::Locked)
+        ));
+
+        let ram = filter.inner();
+        assert_eq!(&ram.0[0x20..0x28], b"\0\0\0\0rw!!");
+        assert_eq!(ram.0[0x80..0x90], [0xff; 0x10]);
+        assert_eq!(ram.0[0x00..0x20], [0; 0x20]);
+    }
+
+    #[test]
+    fn fails_closed() {
+        let mut filter = Simulated::new(RamMut(vec![0; 0x100]), 2, 0x10);
+        assert_eq!(
+            apply(&mut filter, vec![rule(0x08, 0x10, Access::ReadOnly)]),
+            Err(Error::Unaligned)
+        );
+        assert!(filter.rules().is_empty());
+
+        assert_eq!(
+            apply(
+                &mut filter,
+                vec![
+                    rule(0x00, 0x10, Access::ReadOnly),
+                    rule(0x10, 0x10, Access::ReadOnly),
+                    rule(0x20, 0x10, Access::ReadOnly),
+                ]
+            ),
+            Err(Error::TooManyRules)
+        );
+        assert!(filter.rules().is_empty());
+    }
+}
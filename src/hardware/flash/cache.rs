@@ -0,0 +1,431 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Read caching for slow flash devices.
+
+use core::cell::RefCell;
+
+use crate::hardware::flash::Error;
+use crate::hardware::flash::Flash;
+use crate::hardware::flash::Geometry;
+use crate::hardware::flash::Region;
+use crate::mem::Arena;
+use crate::mem::ArenaExt as _;
+
+/// A tag for a cache line that holds no data.
+///
+/// Lines are at least two bytes long and aligned, so no line can start here.
+const EMPTY: u32 = u32::MAX;
+
+/// A [`Flash`] that caches reads from another `Flash`.
+///
+/// Parsing structured data out of flash, such as a manifest, tends to issue
+/// many small, nearby reads; on a device behind a slow bus, such as SPI, each
+/// of these costs a whole transaction. `Cached` instead reads whole, aligned
+/// *lines* at a time into a direct-mapped cache, and serves later reads out of
+/// them.
+///
+/// On a miss, `Cached` can additionally read ahead a number of following
+/// lines in the same transaction; see [`Cached::read_ahead()`].
+///
+/// Writes go straight to the underlying device. Programming or erasing a
+/// region evicts any lines that overlap it, and flushing evicts every line.
+/// If the underlying device can change by other means, such as another bus
+/// master writing to it, [`Cached::invalidate()`] must be called before
+/// reading from it again.
+pub struct Cached<'buf, F> {
+    flash: F,
+    line_len: u32,
+    read_ahead: u32,
+    lines: RefCell<Lines<'buf>>,
+}
+
+/// The cache proper: line `i` holds the bytes starting at `tags[i]`.
+struct Lines<'buf> {
+    data: &'buf mut [u8],
+    tags: &'buf mut [u32],
+}
+
+impl<'buf, F: Flash> Cached<'buf, F> {
+    /// Creates a new `Cached` over `flash`, with `lines` lines of `line_len`
+    /// bytes each allocated from `arena`.
+    ///
+    /// Returns [`Error::Unaligned`] if `line_len` is not a power of two of
+    /// at least two, or if `lines` is zero; returns [`Error::Internal`] if
+    /// `arena` cannot fit the cache.
+    pub fn new(
+        flash: F,
+        line_len: u32,
+        lines: usize,
+        arena: &'buf dyn Arena,
+    ) -> Result<Self, Error> {
+        if line_len < 2 || !line_len.is_power_of_two() || lines == 0 {
+            return Err(Error::Unaligned);
+        }
+        let data_len = (line_len as usize)
+            .checked_mul(lines)
+            .ok_or(Error::Internal)?;
+
+        let data = arena.alloc_aligned(data_len, 1)?;
+        let tags = arena.alloc_slice::<u32>(lines)?;
+        for tag in tags.iter_mut() {
+            *tag = EMPTY;
+        }
+
+        Ok(Self {
+            flash,
+            line_len,
+            read_ahead: 0,
+            lines: RefCell::new(Lines { data, tags }),
+        })
+    }
+}
+
+impl<F> Cached<'_, F> {
+    /// Sets the number of lines to read ahead of a missed line.
+    ///
+    /// Read-ahead lines are fetched in the same read as the missed line, but
+    /// never wrap around the end of the cache or run past the end of the
+    /// device.
+    pub fn read_ahead(&mut self, lines: u32) -> &mut Self {
+        self.read_ahead = lines;
+        self
+    }
+
+    /// Evicts every line from the cache.
+    pub fn invalidate(&mut self) {
+        for tag in self.lines.get_mut().tags.iter_mut() {
+            *tag = EMPTY;
+        }
+    }
+
+    /// Returns a reference to the wrapped device.
+    pub fn inner(&self) -> &F {
+        &self.flash
+    }
+
+    /// Consumes this `Cached`, returning the wrapped device.
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Evicts every line that overlaps `region`.
+    fn evict(&mut self, region: Region) {
+        let line_len = self.line_len;
+        for tag in self.lines.get_mut().tags.iter_mut() {
+            if *tag != EMPTY
+                && *tag < region.end()
+                && region.offset < tag.saturating_add(line_len)
+            {
+                *tag = EMPTY;
+            }
+        }
+    }
+}
+
+impl<F: Flash> Cached<'_, F> {
+    /// Ensures that the line starting at `line` is cached, returning the index
+    /// of the cache line holding it.
+    ///
+    /// `size` is the size of the device; the final line of a device may be
+    /// short.
+    fn fill(
+        &self,
+        lines: &mut Lines<'_>,
+        line: u32,
+        size: u32,
+    ) -> Result<usize, Error> {
+        let slots = lines.tags.len();
+        let slot = ((line / self.line_len) as usize) % slots;
+        if lines.tags[slot] == line {
+            return Ok(slot);
+        }
+
+        let count = (self.read_ahead as usize)
+            .saturating_add(1)
+            .min(slots - slot);
+        let len = (size - line).min(self.line_len.saturating_mul(count as u32));
+        let count = ((len + self.line_len - 1) / self.line_len) as usize;
+
+        // Evict the lines we're about to overwrite first, in case the read
+        // fails part-way.
+        let tags = &mut lines.tags[slot..slot + count];
+        for tag in tags.iter_mut() {
+            *tag = EMPTY;
+        }
+        let start = slot * self.line_len as usize;
+        self.flash
+            .read(line, &mut lines.data[start..start + len as usize])?;
+        for (i, tag) in tags.iter_mut().enumerate() {
+            *tag = line + i as u32 * self.line_len;
+        }
+        Ok(slot)
+    }
+}
+
+unsafe impl<F: Flash> Flash for Cached<'_, F> {
+    fn size(&self) -> Result<u32, Error> {
+        self.flash.size()
+    }
+
+    fn read(&self, offset: u32, out: &mut [u8]) -> Result<(), Error> {
+        let end = offset
+            .checked_add(out.len() as u32)
+            .ok_or(Error::OutOfRange)?;
+        let size = self.flash.size()?;
+        if end > size {
+            return Err(Error::OutOfRange);
+        }
+
+        let mut lines = self.lines.borrow_mut();
+        // Reads at least as big as the whole cache would only thrash it.
+        if out.len() >= lines.data.len() {
+            return self.flash.read(offset, out);
+        }
+
+        let mut cursor = offset;
+        let mut out = out;
+        while !out.is_empty() {
+            let line = cursor & !(self.line_len - 1);
+            let slot = self.fill(&mut lines, line, size)?;
+
+            let skip = (cursor - line) as usize;
+            let len = out.len().min(self.line_len as usize - skip);
+            let start = slot * self.line_len as usize + skip;
+            let (dest, rest) = out.split_at_mut(len);
+            dest.copy_from_slice(&lines.data[start..start + len]);
+
+            out = rest;
+            cursor += len as u32;
+        }
+        Ok(())
+    }
+
+    fn read_direct<'a: 'c, 'b: 'c, 'c>(
+        &'a self,
+        region: Region,
+        arena: &'b dyn Arena,
+        align: usize,
+    ) -> Result<&'c [u8], Error> {
+        // Cache lines may be evicted at any time, so we can't hand out
+        // references into them.
+        let buf = arena.alloc_aligned(region.len as usize, align)?;
+        self.read(region.offset, buf)?;
+        Ok(buf)
+    }
+
+    fn program(&mut self, offset: u32, buf: &[u8]) -> Result<(), Error> {
+        self.evict(Region::new(offset, buf.len() as u32));
+        self.flash.program(offset, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.invalidate();
+        self.flash.flush()
+    }
+
+    fn geometry(&self) -> Result<Geometry, Error> {
+        self.flash.geometry()
+    }
+
+    fn erase(&mut self, region: Region) -> Result<(), Error> {
+        self.evict(region);
+        self.flash.erase(region)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use core::cell::Cell;
+
+    use crate::hardware::flash::Fault;
+    use crate::hardware::flash::Faulty;
+    use crate::hardware::flash::RamMut;
+    use crate::mem::BumpArena;
+    use crate::mem::OutOfMemory;
+
+    /// A `Flash` that counts the reads issued to it.
+    struct Counting<F> {
+        flash: F,
+        reads: Cell<usize>,
+    }
+
+    impl<F> Counting<F> {
+        fn new(flash: F) -> Self {
+            Self {
+                flash,
+                reads: Cell::new(0),
+            }
+        }
+
+        fn take_reads(&self) -> usize {
+            self.reads.replace(0)
+        }
+    }
+
+    unsafe impl<F: Flash> Flash for Counting<F> {
+        fn size(&self) -> Result<u32, Error> {
+            self.flash.size()
+        }
+
+        fn read(&self, offset: u32, out: &mut [u8]) -> Result<(), Error> {
+            self.reads.set(self.reads.get() + 1);
+            self.flash.read(offset, out)
+        }
+
+        fn read_direct<'a: 'c, 'b: 'c, 'c>(
+            &'a self,
+            region: Region,
+            arena: &'b dyn Arena,
+            align: usize,
+        ) -> Result<&'c [u8], Error> {
+            let buf = arena.alloc_aligned(region.len as usize, align)?;
+            self.read(region.offset, buf)?;
+            Ok(buf)
+        }
+
+        fn program(&mut self, offset: u32, buf: &[u8]) -> Result<(), Error> {
+            self.flash.program(offset, buf)
+        }
+    }
+
+    fn device() -> RamMut<Vec<u8>> {
+        RamMut((0..100).collect())
+    }
+
+    #[test]
+    fn bad_config() {
+        let flash = device();
+        assert!(matches!(
+            Cached::new(&flash, 3, 4, &OutOfMemory),
+            Err(Error::Unaligned)
+        ));
+        assert!(matches!(
+            Cached::new(&flash, 16, 0, &OutOfMemory),
+            Err(Error::Unaligned)
+        ));
+        assert!(matches!(
+            Cached::new(&flash, 16, 4, &OutOfMemory),
+            Err(Error::Internal)
+        ));
+    }
+
+    #[test]
+    fn hits_and_misses() {
+        let flash = Counting::new(device());
+        let mut arena = [0; 128];
+        let arena = BumpArena::new(&mut arena);
+        let cached = Cached::new(&flash, 16, 4, &arena).unwrap();
+
+        let mut buf = [0; 8];
+        cached.read(4, &mut buf).unwrap();
+        assert_eq!(buf, [4, 5, 6, 7, 8, 9, 10, 11]);
+        assert_eq!(flash.take_reads(), 1);
+
+        cached.read(8, &mut buf).unwrap();
+        assert_eq!(buf, [8, 9, 10, 11, 12, 13, 14, 15]);
+        assert_eq!(flash.take_reads(), 0);
+
+        // Straddles into the next line.
+        cached.read(12, &mut buf).unwrap();
+        assert_eq!(buf, [12, 13, 14, 15, 16, 17, 18, 19]);
+        assert_eq!(flash.take_reads(), 1);
+
+        // Maps to the same line as offset 0, evicting it.
+        cached.read(64, &mut buf).unwrap();
+        assert_eq!(flash.take_reads(), 1);
+        cached.read(0, &mut buf).unwrap();
+        assert_eq!(flash.take_reads(), 1);
+
+        // The final line is short.
+        let mut tail = [0; 4];
+        cached.read(96, &mut tail).unwrap();
+        assert_eq!(tail, [96, 97, 98, 99]);
+        assert_eq!(flash.take_reads(), 1);
+        assert!(matches!(cached.read(97, &mut tail), Err(Error::OutOfRange)));
+
+        // Big reads go straight to the device.
+        let mut big = [0; 64];
+        cached.read(8, &mut big).unwrap();
+        assert_eq!(big[0], 8);
+        assert_eq!(flash.take_reads(), 1);
+
+        let direct = cached.read_direct(Region::new(90, 4), &arena, 1).unwrap();
+        assert_eq!(direct, &[90, 91, 92, 93]);
+    }
+
+    #[test]
+    fn read_ahead() {
+        let flash = Counting::new(device());
+        let mut arena = [0; 128];
+        let arena = BumpArena::new(&mut arena);
+        let mut cached = Cached::new(&flash, 8, 8, &arena).unwrap();
+        cached.read_ahead(2);
+
+        let mut buf = [0; 4];
+        cached.read(0, &mut buf).unwrap();
+        assert_eq!(flash.take_reads(), 1);
+        for &offset in &[4, 8, 16, 20] {
+            cached.read(offset, &mut buf).unwrap();
+            assert_eq!(buf[0], offset as u8);
+        }
+        assert_eq!(flash.take_reads(), 0);
+
+        // Read-ahead does not wrap around the end of the cache.
+        cached.read(56, &mut buf).unwrap();
+        assert_eq!(flash.take_reads(), 1);
+        cached.read(64, &mut buf).unwrap();
+        assert_eq!(buf, [64, 65, 66, 67]);
+        assert_eq!(flash.take_reads(), 1);
+
+        // Nor past the end of the device.
+        cached.read(96, &mut buf).unwrap();
+        assert_eq!(buf, [96, 97, 98, 99]);
+    }
+
+    #[test]
+    fn failed_fill() {
+        let mut flash = Faulty::new(device());
+        flash.inject(Fault::Read {
+            region: Region::new(64, 1),
+            error: Error::Unspecified,
+        });
+        let flash = Counting::new(flash);
+        let mut arena = [0; 128];
+        let arena = BumpArena::new(&mut arena);
+        let cached = Cached::new(&flash, 16, 4, &arena).unwrap();
+
+        let mut buf = [0; 4];
+        cached.read(4, &mut buf).unwrap();
+        assert!(matches!(cached.read(68, &mut buf), Err(Error::Unspecified)));
+        cached.read(4, &mut buf).unwrap();
+        assert_eq!(buf, [4, 5, 6, 7]);
+        assert_eq!(flash.take_reads(), 3);
+    }
+
+    #[test]
+    fn invalidation() {
+        let mut flash = device();
+        let mut arena = [0; 128];
+        let arena = BumpArena::new(&mut arena);
+        let mut cached = Cached::new(&mut flash, 16, 4, &arena).unwrap();
+
+        let mut buf = [0; 4];
+        cached.read(16, &mut buf).unwrap();
+        cached.read(32, &mut buf).unwrap();
+        cached.program(20, b"wxyz").unwrap();
+        cached.read(20, &mut buf).unwrap();
+        assert_eq!(&buf, b"wxyz");
+
+        cached.erase(Region::new(34, 2)).unwrap();
+        cached.read(32, &mut buf).unwrap();
+        assert_eq!(buf, [32, 33, 0xff, 0xff]);
+
+        cached.flush().unwrap();
+        cached.read(20, &mut buf).unwrap();
+        assert_eq!(&buf, b"wxyz");
+    }
+}
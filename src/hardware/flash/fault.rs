@@ -0,0 +1,406 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A fault-injecting flash wrapper, for robustness testing.
+
+use core::cell::Cell;
+
+use crate::hardware::flash::Error;
+use crate::hardware::flash::Flash;
+use crate::hardware::flash::Geometry;
+use crate::hardware::flash::Region;
+use crate::mem::Arena;
+
+/// A fault that a [`Faulty`] may be scripted to inject.
+///
+/// Faults remain active until [`Faulty::clear()`] is called.
+#[derive(Copy, Clone, Debug)]
+pub enum Fault {
+    /// Causes any read that overlaps `region` to fail with `error`.
+    Read {
+        /// The region reads of which should fail.
+        region: Region,
+        /// The error to return.
+        error: Error,
+    },
+
+    /// Causes any `program()` or `erase()` that overlaps `region` to fail
+    /// with `error`, without modifying the underlying device.
+    Write {
+        /// The region writes to which should fail.
+        region: Region,
+        /// The error to return.
+        error: Error,
+    },
+
+    /// XORs the byte at `offset` with `mask` whenever it is read.
+    ///
+    /// The underlying device is not modified.
+    BitFlip {
+        /// The address of the byte to corrupt.
+        offset: u32,
+        /// The bits to flip.
+        mask: u8,
+    },
+
+    /// XORs the byte at `offset` with `mask` whenever it is read, but only
+    /// once it has already been read `after` times.
+    ///
+    /// This models a device whose contents change between two reads of the
+    /// same data, such as one that another bus master can write to. The
+    /// underlying device is not modified.
+    LateBitFlip {
+        /// The address of the byte to corrupt.
+        offset: u32,
+        /// The bits to flip.
+        mask: u8,
+        /// The number of reads of the byte to leave untouched.
+        after: u32,
+    },
+}
+
+/// A [`Flash`] wrapper that injects faults into operations on another
+/// `Flash`.
+///
+/// Besides the [`Fault`]s that may be injected at particular addresses, a
+/// `Faulty` can also pretend that the device is smaller than it really is,
+/// and can simulate power being lost partway through a write: once a budget
+/// of written bytes is exhausted, the write in progress is cut short and
+/// every subsequent operation fails with [`Error::Unspecified`] until
+/// [`Faulty::restore_power()`] is called.
+///
+/// This is intended for testing that code operating on flash, such as manifest
+/// parsing, reports corruption as an error rather than panicking or silently
+/// accepting bad data.
+pub struct Faulty<F> {
+    inner: F,
+    // Each fault is paired with the number of reads it has seen.
+    faults: Vec<(Fault, Cell<u32>)>,
+    size: Option<u32>,
+    write_budget: Option<u32>,
+    powered: bool,
+}
+
+impl<F: Flash> Faulty<F> {
+    /// Wraps `inner`, initially without injecting any faults.
+    pub fn new(inner: F) -> Self {
+        Self {
+            inner,
+            faults: Vec::new(),
+            size: None,
+            write_budget: None,
+            powered: true,
+        }
+    }
+
+    /// Adds `fault` to the faults this `Faulty` will inject.
+    pub fn inject(&mut self, fault: Fault) -> &mut Self {
+        self.faults.push((fault, Cell::new(0)));
+        self
+    }
+
+    /// Makes the device report that it is only `size` bytes long, failing any
+    /// operation beyond that with [`Error::OutOfRange`].
+    ///
+    /// This has no effect if `size` is larger than the underlying device.
+    pub fn truncate(&mut self, size: u32) -> &mut Self {
+        self.size = Some(size);
+        self
+    }
+
+    /// Arranges for power to be lost after a further `bytes` bytes have been
+    /// programmed or erased.
+    ///
+    /// The write that exhausts the budget is only partially applied: a
+    /// `program()` writes out as many bytes as the budget allows, while an
+    /// `erase()` is not applied at all.
+    pub fn power_loss_after(&mut self, bytes: u32) -> &mut Self {
+        self.write_budget = Some(bytes);
+        self
+    }
+
+    /// Returns whether the simulated device currently has power.
+    pub fn is_powered(&self) -> bool {
+        self.powered
+    }
+
+    /// Restores power after a simulated power loss, and cancels any pending
+    /// one.
+    pub fn restore_power(&mut self) -> &mut Self {
+        self.powered = true;
+        self.write_budget = None;
+        self
+    }
+
+    /// Removes every injected fault, and restores power and the device's
+    /// true size.
+    pub fn clear(&mut self) -> &mut Self {
+        self.faults.clear();
+        self.size = None;
+        self.restore_power()
+    }
+
+    /// Returns a reference to the wrapped device.
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Consumes this `Faulty`, returning the wrapped device.
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    /// Checks that an operation on `region` may proceed at all.
+    fn check(&self, region: Region) -> Result<(), Error> {
+        if !self.powered {
+            return Err(Error::Unspecified);
+        }
+        if let Some(size) = self.size {
+            match region.offset.checked_add(region.len) {
+                Some(end) if end <= size => {}
+                _ => return Err(Error::OutOfRange),
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether a read of `region` should fail.
+    fn check_read(&self, region: Region) -> Result<(), Error> {
+        self.check(region)?;
+        for (fault, _) in &self.faults {
+            if let Fault::Read { region: r, error } = *fault {
+                if r.overlaps(region) {
+                    return Err(error);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks whether a write to `region` should fail.
+    fn check_write(&self, region: Region) -> Result<(), Error> {
+        self.check(region)?;
+        for (fault, _) in &self.faults {
+            if let Fault::Write { region: r, error } = *fault {
+                if r.overlaps(region) {
+                    return Err(error);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether any bit flips apply to `region`.
+    fn flips(&self, region: Region) -> bool {
+        self.faults.iter().any(|(f, _)| match *f {
+            Fault::BitFlip { offset, .. }
+            | Fault::LateBitFlip { offset, .. } => {
+                Region::new(offset, 1).overlaps(region)
+            }
+            _ => false,
+        })
+    }
+
+    /// Applies any bit flips to `out`, which was read from `offset`.
+    fn apply_flips(&self, offset: u32, out: &mut [u8]) {
+        for (fault, reads) in &self.faults {
+            let (o, mask, after) = match *fault {
+                Fault::BitFlip { offset, mask } => (offset, mask, 0),
+                Fault::LateBitFlip {
+                    offset,
+                    mask,
+                    after,
+                } => (offset, mask, after),
+                _ => continue,
+            };
+            if o >= offset && o - offset < out.len() as u32 {
+                let seen = reads.get();
+                reads.set(seen.saturating_add(1));
+                if seen >= after {
+                    out[(o - offset) as usize] ^= mask;
+                }
+            }
+        }
+    }
+}
+
+unsafe impl<F: Flash> Flash for Faulty<F> {
+    fn size(&self) -> Result<u32, Error> {
+        let size = self.inner.size()?;
+        Ok(self.size.map(|s| s.min(size)).unwrap_or(size))
+    }
+
+    fn read(&self, offset: u32, out: &mut [u8]) -> Result<(), Error> {
+        self.check_read(Region::new(offset, out.len() as u32))?;
+        self.inner.read(offset, out)?;
+        self.apply_flips(offset, out);
+        Ok(())
+    }
+
+    fn read_direct<'a: 'c, 'b: 'c, 'c>(
+        &'a self,
+        region: Region,
+        arena: &'b dyn Arena,
+        align: usize,
+    ) -> Result<&'c [u8], Error> {
+        if !self.flips(region) {
+            self.check_read(region)?;
+            return self.inner.read_direct(region, arena, align);
+        }
+
+        let buf = arena.alloc_aligned(region.len as usize, align)?;
+        self.read(region.offset, buf)?;
+        Ok(buf)
+    }
+
+    fn program(&mut self, offset: u32, buf: &[u8]) -> Result<(), Error> {
+        self.check_write(Region::new(offset, buf.len() as u32))?;
+        match self.write_budget {
+            Some(budget) if (budget as usize) < buf.len() => {
+                self.powered = false;
+                self.write_budget = Some(0);
+                if budget > 0 {
+                    self.inner.program(offset, &buf[..budget as usize])?;
+                }
+                Err(Error::Unspecified)
+            }
+            Some(budget) => {
+                self.write_budget = Some(budget - buf.len() as u32);
+                self.inner.program(offset, buf)
+            }
+            None => self.inner.program(offset, buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        if !self.powered {
+            return Err(Error::Unspecified);
+        }
+        self.inner.flush()
+    }
+
+    fn geometry(&self) -> Result<Geometry, Error> {
+        self.inner.geometry()
+    }
+
+    fn erase(&mut self, region: Region) -> Result<(), Error> {
+        self.check_write(region)?;
+        match self.write_budget {
+            Some(budget) if budget < region.len => {
+                self.powered = false;
+                self.write_budget = Some(0);
+                Err(Error::Unspecified)
+            }
+            Some(budget) => {
+                self.write_budget = Some(budget - region.len);
+                self.inner.erase(region)
+            }
+            None => self.inner.erase(region),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::hardware::flash::Ram;
+    use crate::hardware::flash::RamMut;
+    use crate::mem::BumpArena;
+    use crate::mem::OutOfMemory;
+
+    #[test]
+    fn reads() {
+        let bytes = (0..64).collect::<Vec<u8>>();
+        let mut flash = Faulty::new(Ram(&bytes[..]));
+        flash
+            .inject(Fault::Read {
+                region: Region::new(16, 4),
+                error: Error::Locked,
+            })
+            .inject(Fault::BitFlip {
+                offset: 40,
+                mask: 0x81,
+            });
+
+        let mut buf = [0; 4];
+        flash.read(12, &mut buf).unwrap();
+        assert!(matches!(flash.read(14, &mut buf), Err(Error::Locked)));
+        assert!(matches!(flash.read(19, &mut buf), Err(Error::Locked)));
+        flash.read(20, &mut buf).unwrap();
+
+        flash.read(38, &mut buf).unwrap();
+        assert_eq!(buf, [38, 39, 40 ^ 0x81, 41]);
+
+        // Direct reads are only copied if they need to be corrupted.
+        let direct = flash
+            .read_direct(Region::new(0, 8), &OutOfMemory, 1)
+            .unwrap();
+        assert_eq!(direct, &bytes[..8]);
+        let mut arena = [0; 8];
+        let arena = BumpArena::new(&mut arena);
+        let direct = flash.read_direct(Region::new(40, 2), &arena, 1).unwrap();
+        assert_eq!(direct, &[40 ^ 0x81, 41]);
+
+        flash.truncate(32);
+        assert_eq!(flash.size().unwrap(), 32);
+        assert!(matches!(flash.read(30, &mut buf), Err(Error::OutOfRange)));
+
+        flash.clear();
+        assert_eq!(flash.size().unwrap(), 64);
+        flash.read(38, &mut buf).unwrap();
+        assert_eq!(buf, [38, 39, 40, 41]);
+
+        flash.inject(Fault::LateBitFlip {
+            offset: 8,
+            mask: 0xff,
+            after: 2,
+        });
+        flash.read(6, &mut buf).unwrap();
+        assert_eq!(buf, [6, 7, 8, 9]);
+        // Reads that miss the byte do not count.
+        flash.read(0, &mut buf).unwrap();
+        let direct = flash.read_direct(Region::new(8, 2), &arena, 1).unwrap();
+        assert_eq!(direct, &[8, 9]);
+        flash.read(8, &mut buf).unwrap();
+        assert_eq!(buf, [8 ^ 0xff, 9, 10, 11]);
+    }
+
+    #[test]
+    fn writes() {
+        let mut flash = Faulty::new(RamMut(vec![0; 64]));
+        flash.inject(Fault::Write {
+            region: Region::new(32, 32),
+            error: Error::Locked,
+        });
+        assert!(matches!(flash.program(30, b"abcd"), Err(Error::Locked)));
+        assert!(matches!(
+            flash.erase(Region::new(32, 1)),
+            Err(Error::Locked)
+        ));
+        assert_eq!(flash.inner().0[30..34], [0; 4]);
+        flash.program(28, b"abcd").unwrap();
+        assert_eq!(&flash.inner().0[28..32], b"abcd");
+    }
+
+    #[test]
+    fn power_loss() {
+        let mut flash = Faulty::new(RamMut(vec![0; 64]));
+        flash.power_loss_after(6);
+        flash.program(0, b"abcd").unwrap();
+        assert!(matches!(flash.program(4, b"efgh"), Err(Error::Unspecified)));
+        assert!(!flash.is_powered());
+        assert_eq!(&flash.inner().0[..8], b"abcdef\0\0");
+
+        let mut buf = [0; 1];
+        assert!(matches!(flash.read(0, &mut buf), Err(Error::Unspecified)));
+        assert!(matches!(flash.flush(), Err(Error::Unspecified)));
+
+        flash.restore_power();
+        flash.read(5, &mut buf).unwrap();
+        assert_eq!(&buf, b"f");
+        flash.program(6, b"gh").unwrap();
+        assert_eq!(&flash.into_inner().0[..8], b"abcdefgh");
+    }
+}
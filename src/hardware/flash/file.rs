@@ -0,0 +1,232 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A file-backed flash device.
+
+use std::convert::TryInto;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::hardware::flash::Error;
+use crate::hardware::flash::Flash;
+use crate::hardware::flash::Geometry;
+use crate::hardware::flash::Region;
+use crate::mem::Arena;
+
+/// A [`Flash`] backed by a file on disk.
+///
+/// Reads go straight to the file, using positioned I/O, so that the image
+/// never needs to be loaded into memory in its entirety. Writes are buffered
+/// in memory until [`Flash::flush()`] is called, at which point they are
+/// written out and synced to disk; pending writes are nonetheless visible to
+/// reads.
+///
+/// The size of the device is fixed to the size of the file when it is opened.
+pub struct File {
+    file: fs::File,
+    size: u32,
+    pending: Vec<(u32, Vec<u8>)>,
+}
+
+impl File {
+    /// Wraps an already-open file.
+    ///
+    /// If `file` was not opened for writing, [`Flash::flush()`] will fail.
+    pub fn new(file: fs::File) -> io::Result<Self> {
+        let size = file.metadata()?.len().try_into().map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidData, "file too large")
+        })?;
+        Ok(Self {
+            file,
+            size,
+            pending: Vec::new(),
+        })
+    }
+
+    /// Opens the file at `path` for reading and writing.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(fs::OpenOptions::new().read(true).write(true).open(path)?)
+    }
+
+    /// Creates a new file at `path`, replacing any existing one, of `size`
+    /// erased bytes.
+    pub fn create(path: impl AsRef<Path>, size: u32) -> io::Result<Self> {
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+
+        let chunk = vec![Geometry::ERASED; 4096];
+        let mut offset = 0;
+        while offset < size {
+            let len = (size - offset).min(chunk.len() as u32);
+            write_at(&file, offset, &chunk[..len as usize])?;
+            offset += len;
+        }
+        file.sync_data()?;
+        Self::new(file)
+    }
+
+    /// Checks that `region` is in-bounds.
+    fn check(&self, region: Region) -> Result<(), Error> {
+        match region.offset.checked_add(region.len) {
+            Some(end) if end <= self.size => Ok(()),
+            _ => Err(Error::OutOfRange),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn read_at(file: &fs::File, offset: u32, out: &mut [u8]) -> io::Result<()> {
+    use std::os::unix::fs::FileExt as _;
+    file.read_exact_at(out, offset as u64)
+}
+
+#[cfg(unix)]
+fn write_at(file: &fs::File, offset: u32, buf: &[u8]) -> io::Result<()> {
+    use std::os::unix::fs::FileExt as _;
+    file.write_all_at(buf, offset as u64)
+}
+
+// NOTE: `&fs::File` implements `Read`, `Write` and `Seek`, so on other
+// platforms we can fall back to seeking, at the cost of moving the shared
+// file cursor.
+#[cfg(not(unix))]
+fn read_at(mut file: &fs::File, offset: u32, out: &mut [u8]) -> io::Result<()> {
+    use std::io::Read as _;
+    use std::io::Seek as _;
+    file.seek(io::SeekFrom::Start(offset as u64))?;
+    file.read_exact(out)
+}
+
+#[cfg(not(unix))]
+fn write_at(mut file: &fs::File, offset: u32, buf: &[u8]) -> io::Result<()> {
+    use std::io::Seek as _;
+    use std::io::Write as _;
+    file.seek(io::SeekFrom::Start(offset as u64))?;
+    file.write_all(buf)
+}
+
+unsafe impl Flash for File {
+    fn size(&self) -> Result<u32, Error> {
+        Ok(self.size)
+    }
+
+    fn read(&self, offset: u32, out: &mut [u8]) -> Result<(), Error> {
+        let region = Region::new(offset, out.len() as u32);
+        self.check(region)?;
+        read_at(&self.file, offset, out).map_err(|_| Error::Unspecified)?;
+
+        // Overlay any writes that have not been flushed yet, oldest first.
+        for (start, data) in &self.pending {
+            let lo = offset.max(*start);
+            let hi = region.end().min(start + data.len() as u32);
+            if lo < hi {
+                out[(lo - offset) as usize..(hi - offset) as usize]
+                    .copy_from_slice(
+                        &data[(lo - start) as usize..(hi - start) as usize],
+                    );
+            }
+        }
+        Ok(())
+    }
+
+    fn read_direct<'a: 'c, 'b: 'c, 'c>(
+        &'a self,
+        region: Region,
+        arena: &'b dyn Arena,
+        align: usize,
+    ) -> Result<&'c [u8], Error> {
+        let buf = arena.alloc_aligned(region.len as usize, align)?;
+        self.read(region.offset, buf)?;
+        Ok(buf)
+    }
+
+    fn program(&mut self, offset: u32, buf: &[u8]) -> Result<(), Error> {
+        self.check(Region::new(offset, buf.len() as u32))?;
+        self.pending.push((offset, buf.to_vec()));
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        for (offset, data) in &self.pending {
+            write_at(&self.file, *offset, data)
+                .map_err(|_| Error::Unspecified)?;
+        }
+        self.file.sync_data().map_err(|_| Error::Unspecified)?;
+        self.pending.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::mem::BumpArena;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "manticore-{}-{}",
+            std::process::id(),
+            name
+        ))
+    }
+
+    #[test]
+    fn persist() {
+        let path = temp_path("persist");
+        let mut flash = File::create(&path, 0x2345).unwrap();
+        assert_eq!(flash.size().unwrap(), 0x2345);
+
+        let mut buf = [0; 4];
+        flash.read(0x2341, &mut buf).unwrap();
+        assert_eq!(buf, [0xff; 4]);
+        assert!(matches!(
+            flash.read(0x2342, &mut buf),
+            Err(Error::OutOfRange)
+        ));
+        assert!(matches!(
+            flash.program(0x2344, b"ab"),
+            Err(Error::OutOfRange)
+        ));
+
+        flash.program(0x10, b"hello").unwrap();
+        flash.program(0x12, b"y!").unwrap();
+        flash.program(0x2000, b"world").unwrap();
+
+        // Pending writes are visible before a flush, but not persisted.
+        let mut buf = [0; 8];
+        flash.read(0xe, &mut buf).unwrap();
+        assert_eq!(&buf, b"\xff\xffhey!o\xff");
+        assert_eq!(fs::read(&path).unwrap()[0x10], 0xff);
+
+        let mut arena = [0; 16];
+        let arena = BumpArena::new(&mut arena);
+        let direct = flash
+            .read_direct(Region::new(0x2000, 5), &arena, 4)
+            .unwrap();
+        assert_eq!(direct, b"world");
+
+        flash.flush().unwrap();
+        drop(flash);
+
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 0x2345);
+        assert_eq!(&bytes[0x10..0x15], b"hey!o");
+        assert_eq!(&bytes[0x2000..0x2005], b"world");
+
+        let flash = File::open(&path).unwrap();
+        let mut buf = [0; 5];
+        flash.read(0x10, &mut buf).unwrap();
+        assert_eq!(&buf, b"hey!o");
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Hashing of data stored in flash.
+
+use core::cell::Cell;
+
+use crate::crypto::sha256;
+use crate::hardware::flash::Error;
+use crate::hardware::flash::Flash;
+use crate::hardware::flash::Region;
+use crate::mem::Arena;
+use crate::mem::OutOfMemory;
+
+/// An error returned by [`hash_regions()`].
+#[derive(Copy, Clone, Debug)]
+pub enum HashError {
+    /// Indicates that reading from flash failed.
+    Flash(Error),
+
+    /// Indicates that an error occured inside of a hashing engine.
+    HashingError(sha256::Error),
+}
+
+impl From<Error> for HashError {
+    fn from(e: Error) -> Self {
+        Self::Flash(e)
+    }
+}
+
+impl<E> From<sha256::Error<E>> for HashError {
+    fn from(e: sha256::Error<E>) -> Self {
+        Self::HashingError(e.erased())
+    }
+}
+
+/// An arena that never allocates, but records whether it was asked to.
+///
+/// This tells apart a device that would have had to copy a region in
+/// [`Flash::read_direct()`] from one that failed for some other reason.
+struct Probe {
+    asked: Cell<bool>,
+    oom: OutOfMemory,
+}
+
+// SAFE: every allocation is delegated to `OutOfMemory`.
+unsafe impl Arena for Probe {
+    fn alloc_aligned(
+        &self,
+        len: usize,
+        align: usize,
+    ) -> Result<&mut [u8], OutOfMemory> {
+        if len > 0 {
+            self.asked.set(true);
+        }
+        self.oom.alloc_aligned(len, align)
+    }
+
+    fn reset(&mut self) {}
+}
+
+/// Feeds the contents of each of `regions` of `flash`, in order, into
+/// `hasher`.
+///
+/// Data is streamed through `buf`, a caller-supplied bounce buffer, so this
+/// function never allocates; a bigger buffer means fewer, larger reads.
+///
+/// If `direct` is set, each region is first requested with
+/// [`Flash::read_direct()`] and an arena that cannot allocate. Memory-mapped
+/// devices, such as [`Ram`](super::Ram), can satisfy this without copying,
+/// in which case the region is hashed in place. A device that tries to
+/// allocate instead falls back to reading through `buf`; any other error from
+/// `read_direct()` is returned as-is.
+///
+/// `hasher` is not finished, so callers may mix in other data before or
+/// after the regions.
+///
+/// # Panics
+///
+/// This function panics if `buf` is empty.
+pub fn hash_regions<H: sha256::Hasher>(
+    flash: &dyn Flash,
+    regions: impl IntoIterator<Item = Region>,
+    hasher: &mut H,
+    buf: &mut [u8],
+    direct: bool,
+) -> Result<(), HashError> {
+    assert!(!buf.is_empty(), "bounce buffer must not be empty");
+    for region in regions {
+        if direct {
+            let probe = Probe {
+                asked: Cell::new(false),
+                oom: OutOfMemory,
+            };
+            match flash.read_direct(region, &probe, 1) {
+                Ok(data) => {
+                    hasher.write(data)?;
+                    continue;
+                }
+                // The device would have needed to allocate, so it is not
+                // memory-mapped; use the bounce buffer instead.
+                Err(_) if probe.asked.get() => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        let mut offset = 0;
+        while offset < region.len {
+            let len = (region.len - offset).min(buf.len() as u32);
+            let buf = &mut buf[..len as usize];
+            flash.read(region.offset + offset, buf)?;
+            hasher.write(buf)?;
+            offset += len;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::crypto::ring;
+    use crate::crypto::sha256::Builder as _;
+    use crate::crypto::sha256::Hasher as _;
+    use crate::hardware::flash::Fault;
+    use crate::hardware::flash::Faulty;
+    use crate::hardware::flash::Ram;
+
+    fn digest(
+        flash: &dyn Flash,
+        regions: &[Region],
+        buf_len: usize,
+        direct: bool,
+    ) -> Result<sha256::Digest, HashError> {
+        let sha = ring::sha256::Builder::new();
+        let mut hasher = sha.new_hasher()?;
+        let mut buf = vec![0; buf_len];
+        hash_regions(
+            flash,
+            regions.iter().copied(),
+            &mut hasher,
+            &mut buf,
+            direct,
+        )?;
+
+        let mut out = sha256::Digest::default();
+        hasher.finish(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn hash() {
+        let bytes = (0..=255).collect::<Vec<u8>>();
+        let regions = [
+            Region::new(0x10, 0x40),
+            Region::new(0x00, 0x00),
+            Region::new(0xa0, 0x33),
+        ];
+
+        let mut expected = sha256::Digest::default();
+        let mut concat = bytes[0x10..0x50].to_vec();
+        concat.extend_from_slice(&bytes[0xa0..0xd3]);
+        ring::sha256::Builder::new()
+            .hash_contiguous(&concat, &mut expected)
+            .unwrap();
+
+        let ram = Ram(&bytes[..]);
+        for &buf_len in &[1, 7, 64, 512] {
+            for &direct in &[false, true] {
+                assert_eq!(
+                    digest(&ram, &regions, buf_len, direct).unwrap(),
+                    expected
+                );
+            }
+        }
+
+        // A flipped bit forces `Faulty` to copy on direct reads, which
+        // exercises the fallback path even when `direct` is set.
+        let mut faulty = Faulty::new(Ram(&bytes[..]));
+        faulty.inject(Fault::BitFlip {
+            offset: 0xb0,
+            mask: 0x01,
+        });
+        let flipped = digest(&faulty, &regions, 16, false).unwrap();
+        assert_ne!(flipped, expected);
+        assert_eq!(digest(&faulty, &regions, 16, true).unwrap(), flipped);
+
+        for &direct in &[false, true] {
+            assert!(matches!(
+                digest(&ram, &[Region::new(0xff, 2)], 16, direct),
+                Err(HashError::Flash(Error::OutOfRange))
+            ));
+        }
+    }
+
+    /// A device whose direct reads fail without ever trying to allocate.
+    struct NoDirect<'a>(Ram<&'a [u8]>);
+
+    unsafe impl Flash for NoDirect<'_> {
+        fn size(&self) -> Result<u32, Error> {
+            self.0.size()
+        }
+
+        fn read(&self, offset: u32, out: &mut [u8]) -> Result<(), Error> {
+            self.0.read(offset, out)
+        }
+
+        fn read_direct<'a: 'c, 'b: 'c, 'c>(
+            &'a self,
+            _: Region,
+            _: &'b dyn Arena,
+            _: usize,
+        ) -> Result<&'c [u8], Error> {
+            Err(Error::Internal)
+        }
+
+        fn program(&mut self, _: u32, _: &[u8]) -> Result<(), Error> {
+            Err(Error::Locked)
+        }
+    }
+
+    #[test]
+    fn direct_errors() {
+        let bytes = (0..=255).collect::<Vec<u8>>();
+        let flash = NoDirect(Ram(&bytes[..]));
+        let regions = [Region::new(0x10, 0x40)];
+
+        // Only a failed allocation means the bounce buffer should be used;
+        // any other error is the device's, and is reported as-is.
+        assert!(digest(&flash, &regions, 16, false).is_ok());
+        assert!(matches!(
+            digest(&flash, &regions, 16, true),
+            Err(HashError::Flash(Error::Internal))
+        ));
+    }
+}
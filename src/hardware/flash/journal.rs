@@ -0,0 +1,435 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Power-loss-safe region replacement.
+
+use core::mem;
+
+use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+
+use crate::hardware::flash::program_paged;
+use crate::hardware::flash::Error;
+use crate::hardware::flash::Flash;
+use crate::hardware::flash::Geometry;
+use crate::hardware::flash::Region;
+
+/// The header of the single record stored in a [`Journal`]'s journal region.
+///
+/// The record's payload follows it immediately. The `commit` and `done`
+/// words are programmed separately, after everything before them has been
+/// flushed; both transitions only clear bits, so they are safe on NOR flash
+/// without an intervening erase. The same goes for clearing `magic`, which
+/// invalidates the record before the journal is erased.
+///
+/// `checksum` covers the fields before it and the payload, so that a record
+/// that was damaged after it committed is never copied into place.
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+struct Header {
+    magic: u32,
+    target: u32,
+    len: u32,
+    checksum: u32,
+    commit: u32,
+    done: u32,
+}
+
+const MAGIC: u32 = u32::from_le_bytes(*b"MJNL");
+const COMMITTED: u32 = u32::from_le_bytes(*b"COMM");
+const DONE: u32 = u32::from_le_bytes(*b"DONE");
+const ERASED: u32 = u32::from_le_bytes([Geometry::ERASED; 4]);
+
+const HEADER_LEN: u32 = mem::size_of::<Header>() as u32;
+const MAGIC_OFFSET: u32 = 0;
+const CHECKSUM_OFFSET: u32 = 12;
+const COMMIT_OFFSET: u32 = 16;
+const DONE_OFFSET: u32 = 20;
+
+/// Folds `bytes` into a running CRC-32 (IEEE 802.3), which starts out as
+/// `!0` and is complemented once all the bytes have been folded in.
+fn crc32(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    crc
+}
+
+/// What [`Journal::new()`] found when it inspected the journal.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Recovery {
+    /// No replacement was in progress.
+    Clean,
+    /// A replacement was interrupted before it committed; its target was left
+    /// untouched, and the journal was discarded.
+    RolledBack,
+    /// A replacement was interrupted after it committed; it was completed
+    /// from the journal.
+    RolledForward,
+}
+
+/// A write-ahead journal that allows regions of a [`Flash`] to be replaced
+/// atomically with respect to power loss.
+///
+/// A `Journal` reserves a region of the device, which must consist of whole
+/// sectors, to hold a copy of the data being written. [`Journal::replace()`]
+/// first writes the new data to the journal and commits it; only then is the
+/// target region erased and rewritten. If power is lost partway through,
+/// [`Journal::new()`] will find the journal on the next startup and either
+/// discard it, if it never committed, or finish copying it into place. Either
+/// way, the target region ends up containing entirely old or entirely new
+/// data.
+///
+/// This relies on [`Flash::flush()`] making every preceding write durable.
+pub struct Journal<F> {
+    flash: F,
+    journal: Region,
+    recovery: Recovery,
+}
+
+impl<F: Flash> Journal<F> {
+    /// Creates a new `Journal` that uses `journal` as its scratch area,
+    /// recovering from any replacement that was interrupted by power loss.
+    ///
+    /// This function must be called on every startup before the contents of
+    /// `flash` are trusted; the result of recovery is available from
+    /// [`Journal::recovery()`].
+    pub fn new(flash: F, journal: Region) -> Result<Self, Error> {
+        if journal.end() > flash.size()?
+            || journal.len <= HEADER_LEN
+            || !flash.geometry()?.is_erasable(journal)
+        {
+            return Err(Error::OutOfRange);
+        }
+
+        let mut this = Self {
+            flash,
+            journal,
+            recovery: Recovery::Clean,
+        };
+        this.recovery = this.recover()?;
+        Ok(this)
+    }
+
+    /// Returns what was found in the journal when this `Journal` was
+    /// created.
+    pub fn recovery(&self) -> Recovery {
+        self.recovery
+    }
+
+    /// Returns the largest region that may be replaced in one go.
+    pub fn capacity(&self) -> u32 {
+        self.journal.len - HEADER_LEN
+    }
+
+    /// Returns a reference to the underlying device, for reading.
+    pub fn flash(&self) -> &F {
+        &self.flash
+    }
+
+    /// Consumes this `Journal`, returning the underlying device.
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Atomically replaces the contents of `region` with `data`.
+    ///
+    /// `region` must be exactly as long as `data`, must consist of whole
+    /// sectors, must not overlap the journal, and must fit within
+    /// [`Journal::capacity()`].
+    ///
+    /// If this function returns successfully, `region` contains `data`. If it
+    /// fails, or power is lost while it runs, `region` will contain either its
+    /// old contents or `data` once a new `Journal` has been created.
+    pub fn replace(
+        &mut self,
+        region: Region,
+        data: &[u8],
+    ) -> Result<(), Error> {
+        if region.len as usize != data.len()
+            || region.len > self.capacity()
+            || region.end() > self.flash.size()?
+            || (region.offset < self.journal.end()
+                && self.journal.offset < region.end())
+        {
+            return Err(Error::OutOfRange);
+        }
+        if !self.flash.geometry()?.is_erasable(region) {
+            return Err(Error::Unaligned);
+        }
+
+        let mut header = Header {
+            magic: MAGIC,
+            target: region.offset,
+            len: region.len,
+            checksum: ERASED,
+            commit: ERASED,
+            done: ERASED,
+        };
+        let crc = crc32(!0, &header.as_bytes()[..CHECKSUM_OFFSET as usize]);
+        header.checksum = !crc32(crc, data);
+
+        // An erase may be cut short by power loss, leaving any mix of old and
+        // erased bits behind; the previous record must not look valid if that
+        // happens.
+        self.mark(MAGIC_OFFSET, 0)?;
+        self.flash.erase(self.journal)?;
+        self.flash.flush()?;
+        program_paged(&mut self.flash, self.journal.offset, header.as_bytes())?;
+        program_paged(&mut self.flash, self.journal.offset + HEADER_LEN, data)?;
+        self.flash.flush()?;
+
+        self.mark(COMMIT_OFFSET, COMMITTED)?;
+        self.apply(region)
+    }
+
+    /// Copies a committed record's payload into `target`, and marks the
+    /// record as done.
+    fn apply(&mut self, target: Region) -> Result<(), Error> {
+        self.flash.erase(target)?;
+        let mut buf = [0; 64];
+        let mut offset = 0;
+        while offset < target.len {
+            let len = (target.len - offset).min(buf.len() as u32);
+            let buf = &mut buf[..len as usize];
+            self.flash
+                .read(self.journal.offset + HEADER_LEN + offset, buf)?;
+            program_paged(&mut self.flash, target.offset + offset, buf)?;
+            offset += len;
+        }
+        self.flash.flush()?;
+
+        self.mark(DONE_OFFSET, DONE)
+    }
+
+    /// Programs a marker word into the journal header, and flushes it.
+    fn mark(&mut self, offset: u32, value: u32) -> Result<(), Error> {
+        program_paged(
+            &mut self.flash,
+            self.journal.offset + offset,
+            &value.to_le_bytes(),
+        )?;
+        self.flash.flush()
+    }
+
+    /// Inspects the journal, completing or discarding any replacement in
+    /// progress.
+    fn recover(&mut self) -> Result<Recovery, Error> {
+        let mut header = Header::default();
+        self.flash
+            .read(self.journal.offset, header.as_bytes_mut())?;
+
+        let erased = Header {
+            magic: ERASED,
+            target: ERASED,
+            len: ERASED,
+            checksum: ERASED,
+            commit: ERASED,
+            done: ERASED,
+        };
+        if header.as_bytes() == erased.as_bytes()
+            || (header.magic == MAGIC
+                && header.commit == COMMITTED
+                && header.done == DONE)
+        {
+            return Ok(Recovery::Clean);
+        }
+
+        if header.magic != MAGIC || header.commit != COMMITTED {
+            // Whatever is here never committed, so the target was never
+            // touched.
+            self.flash.erase(self.journal)?;
+            self.flash.flush()?;
+            return Ok(Recovery::RolledBack);
+        }
+
+        // The record was flushed before the commit marker was written, but it
+        // may have been damaged since; check both where it says to write and
+        // what it says to write there.
+        let target = Region::new(header.target, header.len);
+        if target.len > self.capacity() || target.end() > self.flash.size()? {
+            return Err(Error::OutOfRange);
+        }
+        let mut crc = crc32(!0, &header.as_bytes()[..CHECKSUM_OFFSET as usize]);
+        let mut buf = [0; 64];
+        let mut offset = 0;
+        while offset < target.len {
+            let len = (target.len - offset).min(buf.len() as u32);
+            let buf = &mut buf[..len as usize];
+            self.flash
+                .read(self.journal.offset + HEADER_LEN + offset, buf)?;
+            crc = crc32(crc, buf);
+            offset += len;
+        }
+        if !crc != header.checksum {
+            return Err(Error::Corrupt);
+        }
+
+        self.apply(target)?;
+        Ok(Recovery::RolledForward)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::hardware::flash::Faulty;
+    use crate::hardware::flash::Nor;
+
+    const GEOMETRY: Geometry = Geometry {
+        page_size: 16,
+        sector_size: 64,
+        block_size: 64,
+    };
+    const JOURNAL: Region = Region::new(0x100, 0x80);
+    const TARGET: Region = Region::new(0x40, 0x40);
+
+    fn device() -> Nor<Vec<u8>> {
+        let mut nor = Nor::new(vec![0xff; 0x200], GEOMETRY).unwrap();
+        program_paged(&mut nor, TARGET.offset, &[0xaa; 0x40]).unwrap();
+        nor
+    }
+
+    #[test]
+    fn replace() {
+        let mut journal = Journal::new(device(), JOURNAL).unwrap();
+        assert_eq!(journal.recovery(), Recovery::Clean);
+        assert_eq!(journal.capacity(), 0x80 - HEADER_LEN);
+
+        journal.replace(TARGET, &[0x55; 0x40]).unwrap();
+        let bytes = journal.flash().as_bytes();
+        assert!(bytes[0x40..0x80].iter().all(|&b| b == 0x55));
+        assert!(bytes[..0x40].iter().all(|&b| b == 0xff));
+
+        journal.replace(TARGET, &[0x33; 0x40]).unwrap();
+        let journal = Journal::new(journal.into_inner(), JOURNAL).unwrap();
+        assert_eq!(journal.recovery(), Recovery::Clean);
+        assert!(journal.flash().as_bytes()[0x40..0x80]
+            .iter()
+            .all(|&b| b == 0x33));
+    }
+
+    #[test]
+    fn bad_requests() {
+        assert!(Journal::new(device(), Region::new(0x120, 0x40)).is_err());
+        assert!(Journal::new(device(), Region::new(0x1c0, 0x80)).is_err());
+
+        let mut journal = Journal::new(device(), JOURNAL).unwrap();
+        assert!(matches!(
+            journal.replace(TARGET, &[0; 4]),
+            Err(Error::OutOfRange)
+        ));
+        assert!(matches!(
+            journal.replace(Region::new(0xc0, 0x80), &[0; 0x80]),
+            Err(Error::OutOfRange)
+        ));
+        assert!(matches!(
+            journal.replace(Region::new(0x50, 0x10), &[0; 0x10]),
+            Err(Error::Unaligned)
+        ));
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(!crc32(!0, b"123456789"), 0xcbf4_3926);
+    }
+
+    /// Cuts power after every possible number of bytes written by a
+    /// replacement of `TARGET`, made after replacing it with each byte of
+    /// `history` in turn, and checks that recovery always yields entirely old
+    /// or entirely new data.
+    fn check_power_loss(history: &[u8]) {
+        let old = history.last().copied().unwrap_or(0xaa);
+        let mut budget = 0;
+        loop {
+            let mut journal =
+                Journal::new(Faulty::new(device()), JOURNAL).unwrap();
+            for &byte in history {
+                journal.replace(TARGET, &[byte; 0x40]).unwrap();
+            }
+            journal.flash.power_loss_after(budget);
+            let result = journal.replace(TARGET, &[0x55; 0x40]);
+
+            let mut flash = journal.into_inner();
+            let finished = flash.is_powered();
+            flash.restore_power();
+            let journal = Journal::new(flash, JOURNAL).unwrap();
+
+            let target = &journal.flash().inner().as_bytes()[0x40..0x80];
+            let old = target.iter().all(|&b| b == old);
+            let new = target.iter().all(|&b| b == 0x55);
+            assert!(old || new, "torn write with budget {}", budget);
+            if result.is_ok() {
+                assert!(new);
+            }
+            match journal.recovery() {
+                Recovery::RolledForward => assert!(new),
+                Recovery::RolledBack => assert!(old),
+                Recovery::Clean => {}
+            }
+
+            if finished {
+                assert!(result.is_ok());
+                break;
+            }
+            budget += 1;
+        }
+        assert!(budget > 0x40);
+    }
+
+    #[test]
+    fn power_loss() {
+        check_power_loss(&[]);
+    }
+
+    #[test]
+    fn power_loss_over_record() {
+        check_power_loss(&[0x11]);
+    }
+
+    #[test]
+    fn invalidate_before_erase() {
+        let mut journal = Journal::new(Faulty::new(device()), JOURNAL).unwrap();
+        journal.replace(TARGET, &[0x11; 0x40]).unwrap();
+
+        // Lose power just after invalidating the finished record, before the
+        // journal is erased.
+        journal.flash.power_loss_after(4);
+        assert!(journal.replace(TARGET, &[0x55; 0x40]).is_err());
+        let mut flash = journal.into_inner();
+        assert_eq!(&flash.inner().as_bytes()[0x100..0x104], &[0; 4]);
+
+        flash.restore_power();
+        let journal = Journal::new(flash, JOURNAL).unwrap();
+        assert_eq!(journal.recovery(), Recovery::RolledBack);
+        let bytes = journal.flash().inner().as_bytes();
+        assert!(bytes[0x40..0x80].iter().all(|&b| b == 0x11));
+        assert!(bytes[0x100..0x180].iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn corrupt_record() {
+        // Lose power right after the record commits, before the target is
+        // erased: invalidating the empty journal, erasing it, and writing the
+        // record and its commit marker.
+        let budget = 4 + JOURNAL.len + HEADER_LEN + TARGET.len + 4;
+        let mut journal = Journal::new(Faulty::new(device()), JOURNAL).unwrap();
+        journal.flash.power_loss_after(budget);
+        assert!(journal.replace(TARGET, &[0x55; 0x40]).is_err());
+
+        // Damage the committed payload, as a torn erase of it might.
+        let mut bytes = journal.into_inner().into_inner().into_inner();
+        let offset = (JOURNAL.offset + COMMIT_OFFSET) as usize;
+        assert_eq!(&bytes[offset..offset + 4], &COMMITTED.to_le_bytes());
+        let payload = (JOURNAL.offset + HEADER_LEN) as usize;
+        bytes[payload + 0x20] = 0xff;
+
+        let nor = Nor::new(bytes, GEOMETRY).unwrap();
+        assert!(matches!(Journal::new(nor, JOURNAL), Err(Error::Corrupt)));
+    }
+}
@@ -29,6 +29,37 @@ use crate::io::Read as _;
 use crate::mem::Arena;
 use crate::mem::OutOfMemory;
 
+mod cache;
+pub use cache::Cached;
+
+#[cfg(feature = "std")]
+mod fault;
+#[cfg(feature = "std")]
+pub use fault::Fault;
+#[cfg(feature = "std")]
+pub use fault::Faulty;
+
+#[cfg(feature = "std")]
+mod file;
+#[cfg(feature = "std")]
+pub use file::File;
+
+mod hash;
+pub use hash::hash_regions;
+pub use hash::HashError;
+
+mod journal;
+pub use journal::Journal;
+pub use journal::Recovery;
+
+mod nor;
+pub use nor::Nor;
+
+pub mod partition;
+
+mod sub;
+pub use sub::SubFlash;
+
 /// A [`Flash`] error.
 ///
 /// All of these errors are non-retryable; a [`Flash`] implementation should
@@ -47,6 +78,19 @@ pub enum Error {
     /// of memory.
     Internal,
 
+    /// Indicates that an operation did not respect the device's
+    /// [`Geometry`], such as an erase that does not cover whole sectors, or a
+    /// program that crosses a page boundary.
+    Unaligned,
+
+    /// Indicates that a program operation attempted to set a bit that was not
+    /// erased; NOR flash can only clear bits when programming.
+    NotErased,
+
+    /// Indicates that data read back from the device failed an integrity
+    /// check, such as a checksum.
+    Corrupt,
+
     /// Indicates that an unspecified error occured.
     Unspecified,
 }
@@ -57,6 +101,91 @@ impl From<OutOfMemory> for Error {
     }
 }
 
+/// The erase and programming geometry of a [`Flash`] device.
+///
+/// NOR flash is programmed a page at a time, but can only be erased in much
+/// larger sectors; a device may additionally support erasing entire blocks of
+/// sectors at once, which is usually faster. Erasing sets every bit to one,
+/// while programming may only clear bits.
+///
+/// All three sizes are powers of two, and each divides the next.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Geometry {
+    /// The size of a program page, in bytes; a single `program()` may not
+    /// cross a page boundary.
+    pub page_size: u32,
+    /// The size of the smallest erasable unit, in bytes.
+    pub sector_size: u32,
+    /// The size of the largest erasable unit, in bytes.
+    pub block_size: u32,
+}
+
+impl Geometry {
+    /// The geometry of a device with no erase semantics at all, such as RAM.
+    pub const BYTE: Self = Self {
+        page_size: 1,
+        sector_size: 1,
+        block_size: 1,
+    };
+
+    /// The value every byte takes on after being erased.
+    pub const ERASED: u8 = 0xff;
+
+    /// Checks that this geometry is well-formed: every size is a nonzero
+    /// power of two, and each divides the next.
+    pub fn is_valid(self) -> bool {
+        self.page_size.is_power_of_two()
+            && self.sector_size.is_power_of_two()
+            && self.block_size.is_power_of_two()
+            && self.page_size <= self.sector_size
+            && self.sector_size <= self.block_size
+    }
+
+    /// Returns whether `region` consists of whole sectors, and can thus be
+    /// passed to [`Flash::erase()`].
+    ///
+    /// Returns `false` if this geometry is not [valid](Self::is_valid).
+    pub fn is_erasable(self, region: Region) -> bool {
+        self.is_valid()
+            && region.offset % self.sector_size == 0
+            && region.len % self.sector_size == 0
+    }
+
+    /// Returns whether `region` fits within a single program page.
+    ///
+    /// Returns `false` if this geometry is not [valid](Self::is_valid).
+    pub fn fits_in_page(self, region: Region) -> bool {
+        if !self.is_valid() {
+            return false;
+        }
+        if region.len == 0 {
+            return true;
+        }
+        let last = match region.offset.checked_add(region.len - 1) {
+            Some(last) => last,
+            None => return false,
+        };
+        region.offset / self.page_size == last / self.page_size
+    }
+
+    /// Returns the smallest erasable region that contains all of `region`.
+    ///
+    /// Returns `None` on overflow, or if this geometry is not
+    /// [valid](Self::is_valid).
+    pub fn erase_cover(self, region: Region) -> Option<Region> {
+        if !self.is_valid() {
+            return None;
+        }
+        let start = region.offset - region.offset % self.sector_size;
+        let end = region.offset.checked_add(region.len)?;
+        let end = match end % self.sector_size {
+            0 => end,
+            r => end.checked_add(self.sector_size - r)?,
+        };
+        Some(Region::new(start, end - start))
+    }
+}
+
 /// Provides access to a flash-like storage device.
 ///
 /// This trait provides abstract operations on a device, as if it were a
@@ -136,6 +265,38 @@ pub unsafe trait Flash {
     fn flush(&mut self) -> Result<(), Error> {
         Ok(())
     }
+
+    /// Returns the erase and programming geometry of this device.
+    ///
+    /// The default implementation returns [`Geometry::BYTE`], which is
+    /// appropriate for devices that may be freely overwritten.
+    fn geometry(&self) -> Result<Geometry, Error> {
+        Ok(Geometry::BYTE)
+    }
+
+    /// Erases `region`, setting every byte in it to [`Geometry::ERASED`].
+    ///
+    /// `region` must consist of whole sectors, as described by
+    /// [`Flash::geometry()`]; otherwise, [`Error::Unaligned`] is returned.
+    ///
+    /// The default implementation simply programs `region` with
+    /// [`Geometry::ERASED`], which is only correct for devices that may be
+    /// freely overwritten; devices with real erase semantics must override
+    /// it.
+    fn erase(&mut self, region: Region) -> Result<(), Error> {
+        if !self.geometry()?.is_erasable(region) {
+            return Err(Error::Unaligned);
+        }
+
+        let buf = [Geometry::ERASED; 32];
+        let mut offset = 0;
+        while offset < region.len {
+            let len = (region.len - offset).min(buf.len() as u32);
+            self.program(region.offset + offset, &buf[..len as usize])?;
+            offset += len;
+        }
+        Ok(())
+    }
 }
 assert_obj_safe!(Flash);
 
@@ -169,6 +330,16 @@ unsafe impl<F: Flash> Flash for &F {
     fn flush(&mut self) -> Result<(), Error> {
         Err(Error::Locked)
     }
+
+    #[inline]
+    fn geometry(&self) -> Result<Geometry, Error> {
+        F::geometry(self)
+    }
+
+    #[inline]
+    fn erase(&mut self, _: Region) -> Result<(), Error> {
+        Err(Error::Locked)
+    }
 }
 
 unsafe impl<F: Flash> Flash for &mut F {
@@ -201,6 +372,16 @@ unsafe impl<F: Flash> Flash for &mut F {
     fn flush(&mut self) -> Result<(), Error> {
         F::flush(self)
     }
+
+    #[inline]
+    fn geometry(&self) -> Result<Geometry, Error> {
+        F::geometry(self)
+    }
+
+    #[inline]
+    fn erase(&mut self, region: Region) -> Result<(), Error> {
+        F::erase(self, region)
+    }
 }
 
 /// Convenience functions for direct flash reads, exposed as a trait.
@@ -311,23 +492,7 @@ unsafe impl<Bytes: AsRef<[u8]>> Flash for Ram<Bytes> {
         arena: &'b dyn Arena,
         align: usize,
     ) -> Result<&'c [u8], Error> {
-        let start = region.offset as usize;
-        let end = start
-            .checked_add(region.len as usize)
-            .ok_or(Error::OutOfRange)?;
-        if end > self.0.as_ref().len() {
-            return Err(Error::OutOfRange);
-        }
-
-        let slice = &self.0.as_ref()[start..end];
-        assert!(align.is_power_of_two());
-        if slice.as_ptr() as usize & (align - 1) == 0 {
-            return Ok(slice);
-        }
-
-        let buf = arena.alloc_aligned(slice.len(), align)?;
-        buf.copy_from_slice(slice);
-        Ok(buf)
+        read_direct_from(self.0.as_ref(), region, arena, align)
     }
 
     fn program(&mut self, _: u32, _: &[u8]) -> Result<(), Error> {
@@ -335,6 +500,51 @@ unsafe impl<Bytes: AsRef<[u8]>> Flash for Ram<Bytes> {
     }
 }
 
+/// Implements [`Flash::read_direct()`] for storage that is already in memory,
+/// only copying into `arena` if `bytes` is insufficiently aligned.
+fn read_direct_from<'a: 'c, 'b: 'c, 'c>(
+    bytes: &'a [u8],
+    region: Region,
+    arena: &'b dyn Arena,
+    align: usize,
+) -> Result<&'c [u8], Error> {
+    let start = region.offset as usize;
+    let end = start
+        .checked_add(region.len as usize)
+        .ok_or(Error::OutOfRange)?;
+    if end > bytes.len() {
+        return Err(Error::OutOfRange);
+    }
+
+    let slice = &bytes[start..end];
+    assert!(align.is_power_of_two());
+    if slice.as_ptr() as usize & (align - 1) == 0 {
+        return Ok(slice);
+    }
+
+    let buf = arena.alloc_aligned(slice.len(), align)?;
+    buf.copy_from_slice(slice);
+    Ok(buf)
+}
+
+/// Programs `data` at `offset`, splitting it so that no single `program()`
+/// crosses a page boundary.
+pub(crate) fn program_paged(
+    flash: &mut (impl Flash + ?Sized),
+    mut offset: u32,
+    mut data: &[u8],
+) -> Result<(), Error> {
+    let page = flash.geometry()?.page_size;
+    while !data.is_empty() {
+        let room = page - offset % page;
+        let (chunk, rest) = data.split_at(data.len().min(room as usize));
+        flash.program(offset, chunk)?;
+        offset += chunk.len() as u32;
+        data = rest;
+    }
+    Ok(())
+}
+
 /// Adapter for converting mutable, RAM-backed storage into a [`Flash`].
 ///
 /// For the purposes of this type, "RAM-backed" means that `AsRef<[u8]>`
@@ -367,23 +577,7 @@ unsafe impl<Bytes: AsRef<[u8]> + AsMut<[u8]>> Flash for RamMut<Bytes> {
         arena: &'b dyn Arena,
         align: usize,
     ) -> Result<&'c [u8], Error> {
-        let start = region.offset as usize;
-        let end = start
-            .checked_add(region.len as usize)
-            .ok_or(Error::OutOfRange)?;
-        if end > self.0.as_ref().len() {
-            return Err(Error::OutOfRange);
-        }
-
-        let slice = &self.0.as_ref()[start..end];
-        assert!(align.is_power_of_two());
-        if slice.as_ptr() as usize & (align - 1) == 0 {
-            return Ok(slice);
-        }
-
-        let buf = arena.alloc_aligned(slice.len(), align)?;
-        buf.copy_from_slice(slice);
-        Ok(buf)
+        read_direct_from(self.0.as_ref(), region, arena, align)
     }
 
     fn program(&mut self, offset: u32, buf: &[u8]) -> Result<(), Error> {
@@ -579,6 +773,14 @@ impl Region {
         ))
     }
 
+    /// Returns whether `self` and `other` share at least one byte.
+    pub fn overlaps(self, other: Region) -> bool {
+        self.len > 0
+            && other.len > 0
+            && self.offset < other.end()
+            && other.offset < self.end()
+    }
+
     /// Returns a new `Region` that comes immediately after `self`, with the
     /// given length.
     pub fn and_then(self, len: u32) -> Self {
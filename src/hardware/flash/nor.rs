@@ -0,0 +1,253 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! A simulated NOR flash device.
+
+use core::convert::TryInto;
+
+use crate::hardware::flash::read_direct_from;
+use crate::hardware::flash::Error;
+use crate::hardware::flash::Flash;
+use crate::hardware::flash::Geometry;
+use crate::hardware::flash::Region;
+use crate::mem::Arena;
+use crate::mem::OutOfMemory;
+
+/// A RAM-backed [`Flash`] that behaves like a NOR flash part.
+///
+/// Unlike [`RamMut`], a `Nor` enforces the restrictions of real NOR flash:
+/// - A `program()` may not cross a page boundary.
+/// - A `program()` may only clear bits; setting a bit requires erasing the
+///   sector containing it first.
+/// - An `erase()` must cover whole sectors.
+///
+/// Operations that violate these rules fail without modifying the device.
+/// This makes `Nor` useful for testing code that writes to flash, such as
+/// firmware update logic, before running it against real hardware.
+///
+/// [`RamMut`]: super::RamMut
+#[derive(Copy, Clone)]
+pub struct Nor<Bytes> {
+    bytes: Bytes,
+    geometry: Geometry,
+}
+
+impl<Bytes: AsRef<[u8]> + AsMut<[u8]>> Nor<Bytes> {
+    /// Creates a new `Nor` over `bytes`, with the given geometry.
+    ///
+    /// The existing contents of `bytes` become the initial contents of the
+    /// device; they are not erased.
+    ///
+    /// Returns [`Error::Unaligned`] if `geometry` is not valid, or if `bytes`
+    /// is not a whole number of blocks.
+    pub fn new(bytes: Bytes, geometry: Geometry) -> Result<Self, Error> {
+        if !geometry.is_valid()
+            || bytes.as_ref().len() % geometry.block_size as usize != 0
+        {
+            return Err(Error::Unaligned);
+        }
+        Ok(Self { bytes, geometry })
+    }
+
+    /// Returns the current contents of the device.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.bytes.as_ref()
+    }
+
+    /// Consumes this `Nor`, returning the underlying storage.
+    pub fn into_inner(self) -> Bytes {
+        self.bytes
+    }
+
+    /// Checks that `region` is in-bounds, returning it as a range of
+    /// indices.
+    fn range(&self, region: Region) -> Result<(usize, usize), Error> {
+        let start = region.offset as usize;
+        let end = start
+            .checked_add(region.len as usize)
+            .ok_or(Error::OutOfRange)?;
+        if end > self.bytes.as_ref().len() {
+            return Err(Error::OutOfRange);
+        }
+        Ok((start, end))
+    }
+}
+
+unsafe impl<Bytes: AsRef<[u8]> + AsMut<[u8]>> Flash for Nor<Bytes> {
+    fn size(&self) -> Result<u32, Error> {
+        self.bytes
+            .as_ref()
+            .len()
+            .try_into()
+            .map_err(|_| Error::Unspecified)
+    }
+
+    #[inline]
+    fn read(&self, offset: u32, out: &mut [u8]) -> Result<(), Error> {
+        out.copy_from_slice(self.read_direct(
+            Region::new(offset, out.len() as u32),
+            &OutOfMemory,
+            1,
+        )?);
+        Ok(())
+    }
+
+    fn read_direct<'a: 'c, 'b: 'c, 'c>(
+        &'a self,
+        region: Region,
+        arena: &'b dyn Arena,
+        align: usize,
+    ) -> Result<&'c [u8], Error> {
+        read_direct_from(self.bytes.as_ref(), region, arena, align)
+    }
+
+    fn program(&mut self, offset: u32, buf: &[u8]) -> Result<(), Error> {
+        let region = Region::new(offset, buf.len() as u32);
+        let (start, end) = self.range(region)?;
+        if !self.geometry.fits_in_page(region) {
+            return Err(Error::Unaligned);
+        }
+
+        let dest = &mut self.bytes.as_mut()[start..end];
+        if dest.iter().zip(buf).any(|(&old, &new)| old & new != new) {
+            return Err(Error::NotErased);
+        }
+        dest.copy_from_slice(buf);
+        Ok(())
+    }
+
+    fn geometry(&self) -> Result<Geometry, Error> {
+        Ok(self.geometry)
+    }
+
+    fn erase(&mut self, region: Region) -> Result<(), Error> {
+        let (start, end) = self.range(region)?;
+        if !self.geometry.is_erasable(region) {
+            return Err(Error::Unaligned);
+        }
+
+        for b in &mut self.bytes.as_mut()[start..end] {
+            *b = Geometry::ERASED;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::hardware::flash::RamMut;
+
+    const GEOMETRY: Geometry = Geometry {
+        page_size: 16,
+        sector_size: 64,
+        block_size: 128,
+    };
+
+    #[test]
+    fn geometry() {
+        assert!(GEOMETRY.is_valid());
+        assert!(Geometry::BYTE.is_valid());
+        assert!(!Geometry {
+            page_size: 16,
+            sector_size: 48,
+            block_size: 96,
+        }
+        .is_valid());
+        assert!(!Geometry {
+            page_size: 64,
+            sector_size: 16,
+            block_size: 128,
+        }
+        .is_valid());
+
+        assert!(GEOMETRY.is_erasable(Region::new(64, 128)));
+        assert!(!GEOMETRY.is_erasable(Region::new(32, 64)));
+        assert!(!GEOMETRY.is_erasable(Region::new(64, 32)));
+
+        assert!(GEOMETRY.fits_in_page(Region::new(16, 16)));
+        assert!(GEOMETRY.fits_in_page(Region::new(20, 0)));
+        assert!(!GEOMETRY.fits_in_page(Region::new(20, 16)));
+
+        assert_eq!(
+            GEOMETRY.erase_cover(Region::new(70, 100)),
+            Some(Region::new(64, 128))
+        );
+        assert_eq!(
+            GEOMETRY.erase_cover(Region::new(64, 64)),
+            Some(Region::new(64, 64))
+        );
+        assert_eq!(GEOMETRY.erase_cover(Region::new(u32::MAX - 4, 4)), None);
+
+        // A malformed geometry must not cause a division by zero.
+        let zero = Geometry {
+            page_size: 0,
+            sector_size: 0,
+            block_size: 0,
+        };
+        assert!(!zero.is_valid());
+        assert!(!zero.is_erasable(Region::new(0, 64)));
+        assert!(!zero.fits_in_page(Region::new(0, 16)));
+        assert_eq!(zero.erase_cover(Region::new(0, 64)), None);
+    }
+
+    #[test]
+    fn program_and_erase() {
+        assert!(matches!(
+            Nor::new(vec![0xff; 100], GEOMETRY),
+            Err(Error::Unaligned)
+        ));
+
+        let mut nor = Nor::new(vec![0xff; 256], GEOMETRY).unwrap();
+        assert_eq!(nor.geometry().unwrap(), GEOMETRY);
+
+        nor.program(0x10, b"hello").unwrap();
+        let mut buf = [0; 5];
+        nor.read(0x10, &mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+
+        // Clearing further bits is fine; setting them is not.
+        nor.program(0x10, &[b'h' & 0xf0]).unwrap();
+        assert!(matches!(nor.program(0x10, b"j"), Err(Error::NotErased)));
+        assert_eq!(nor.as_bytes()[0x10], b'h' & 0xf0);
+
+        // Writes may not straddle pages, and fail atomically.
+        assert!(matches!(
+            nor.program(0x1c, b"12345678"),
+            Err(Error::Unaligned)
+        ));
+        assert!(nor.as_bytes()[0x1c..0x24].iter().all(|&b| b == 0xff));
+        assert!(matches!(
+            nor.program(0xfc, b"12345678"),
+            Err(Error::OutOfRange)
+        ));
+
+        assert!(matches!(
+            nor.erase(Region::new(0, 32)),
+            Err(Error::Unaligned)
+        ));
+        assert!(matches!(
+            nor.erase(Region::new(192, 128)),
+            Err(Error::OutOfRange)
+        ));
+        nor.program(0x40, b"next sector").unwrap();
+        nor.erase(Region::new(0, 64)).unwrap();
+        assert!(nor.as_bytes()[..0x40].iter().all(|&b| b == 0xff));
+        assert_eq!(&nor.as_bytes()[0x40..0x4b], b"next sector");
+
+        nor.program(0x10, b"j").unwrap();
+        assert_eq!(nor.into_inner()[0x10], b'j');
+    }
+
+    #[test]
+    fn default_erase() {
+        let mut ram = RamMut(vec![0; 100]);
+        assert_eq!(ram.geometry().unwrap(), Geometry::BYTE);
+        ram.erase(Region::new(10, 50)).unwrap();
+        assert!(ram.0[..10].iter().all(|&b| b == 0));
+        assert!(ram.0[10..60].iter().all(|&b| b == 0xff));
+        assert!(ram.0[60..].iter().all(|&b| b == 0));
+    }
+}
@@ -0,0 +1,592 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Flash partition tables.
+//!
+//! A partition table divides a single flash device into named,
+//! non-overlapping regions, each tagged with a [`Purpose`], so that code
+//! dealing with (for example) manifest slots or logs need not hard-code their
+//! locations.
+//!
+//! A table is stored at a known offset in the device it describes, and is
+//! encoded as follows, with all integers little-endian:
+//! ```text
+//! struct Table {
+//!   magic: [u8; 4],  // "MPRT"
+//!   version: u8,     // Always 0.
+//!   count: u8,
+//!   reserved: u16,   // Must be zero.
+//!   entries: [Entry; self.count],
+//!   digest: [u8; 32],
+//! }
+//!
+//! struct Entry {
+//!   name: [u8; 16],  // ASCII, padded with NULs.
+//!   purpose: u8,
+//!   reserved: [u8; 3],  // Must be zero.
+//!   offset: u32,
+//!   len: u32,
+//! }
+//! ```
+//!
+//! `digest` is the SHA-256 hash of every byte that precedes it. No partition
+//! may overlap another, or the table itself.
+//!
+//! To confine code to a single partition, wrap the device in a
+//! [`SubFlash`](super::SubFlash) over that partition's region.
+
+use core::mem;
+
+use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::sha256;
+use crate::crypto::sha256::Hasher as _;
+use crate::hardware::flash;
+use crate::hardware::flash::Flash;
+use crate::hardware::flash::FlashExt as _;
+use crate::hardware::flash::Region;
+use crate::mem::Arena;
+use crate::mem::OutOfMemory;
+use crate::protocol::wire::WireEnum as _;
+
+wire_enum! {
+    /// What a partition is used for.
+    #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+    pub enum Purpose: u8 {
+        /// A slot holding a manifest.
+        Manifest = 0x01,
+        /// Firmware that the RoT runs or protects.
+        Firmware = 0x02,
+        /// A log of events.
+        Log = 0x03,
+        /// A staging area for updates before they are applied.
+        Staging = 0x04,
+        /// A known-good image to recover from.
+        Recovery = 0x05,
+        /// A scratch area for a [`Journal`](super::Journal).
+        Journal = 0x06,
+        /// Anything else.
+        Data = 0xff,
+    }
+}
+
+/// A partition table error.
+#[derive(Copy, Clone, Debug)]
+pub enum Error {
+    /// Indicates an error in the underlying flash device.
+    Flash(flash::Error),
+
+    /// Indicates that an error occured inside of a hashing engine.
+    HashingError(sha256::Error),
+
+    /// Indicates that no partition table was found at the given offset.
+    BadMagic,
+
+    /// Indicates that the table's format version is not supported.
+    UnsupportedVersion(u8),
+
+    /// Indicates that the table header's reserved field was not zero.
+    BadReserved,
+
+    /// Indicates that the table's digest did not match its contents.
+    BadDigest,
+
+    /// Indicates that there were too many partitions to encode.
+    TooMany,
+
+    /// Indicates that a partition's name was empty, too long, or not
+    /// printable ASCII.
+    BadName {
+        /// The index of the bad partition.
+        index: usize,
+    },
+
+    /// Indicates that two partitions had the same name.
+    DuplicateName {
+        /// The index of the second partition with that name.
+        index: usize,
+    },
+
+    /// Indicates that a partition had an unknown purpose.
+    UnknownPurpose {
+        /// The index of the bad partition.
+        index: usize,
+    },
+
+    /// Indicates that a partition's reserved field was not zero.
+    BadEntryReserved {
+        /// The index of the bad partition.
+        index: usize,
+    },
+
+    /// Indicates that a partition did not fit within the device.
+    OutOfBounds {
+        /// The index of the bad partition.
+        index: usize,
+    },
+
+    /// Indicates that a partition overlapped another partition, or the table
+    /// itself.
+    Overlap {
+        /// The index of the bad partition.
+        index: usize,
+    },
+}
+
+impl From<flash::Error> for Error {
+    fn from(e: flash::Error) -> Self {
+        Self::Flash(e)
+    }
+}
+
+impl From<OutOfMemory> for Error {
+    fn from(e: OutOfMemory) -> Self {
+        Self::Flash(e.into())
+    }
+}
+
+impl<E> From<sha256::Error<E>> for Error {
+    fn from(e: sha256::Error<E>) -> Self {
+        Self::HashingError(e.erased())
+    }
+}
+
+/// The maximum length of a partition name.
+pub const NAME_LEN: usize = 16;
+
+const MAGIC: [u8; 4] = *b"MPRT";
+const VERSION: u8 = 0;
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+struct RawHeader {
+    magic: [u8; 4],
+    version: u8,
+    count: u8,
+    reserved: u16,
+}
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+struct RawEntry {
+    name: [u8; NAME_LEN],
+    purpose: u8,
+    reserved: [u8; 3],
+    offset: u32,
+    len: u32,
+}
+
+impl RawEntry {
+    fn name(&self) -> &[u8] {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(NAME_LEN);
+        &self.name[..len]
+    }
+
+    fn region(&self) -> Region {
+        Region::new(self.offset, self.len)
+    }
+}
+
+/// Checks every invariant of a table located at `table`, other than its
+/// digest.
+fn check_entries(
+    table: Region,
+    entries: &[RawEntry],
+    flash_size: Option<u32>,
+) -> Result<(), Error> {
+    for (index, entry) in entries.iter().enumerate() {
+        let name = entry.name();
+        if name.is_empty()
+            || !name.iter().all(u8::is_ascii_graphic)
+            || entry.name[name.len()..].iter().any(|&b| b != 0)
+        {
+            return Err(Error::BadName { index });
+        }
+        if entries[..index].iter().any(|e| e.name() == name) {
+            return Err(Error::DuplicateName { index });
+        }
+        if Purpose::from_wire_value(entry.purpose).is_none() {
+            return Err(Error::UnknownPurpose { index });
+        }
+        if entry.reserved != [0; 3] {
+            return Err(Error::BadEntryReserved { index });
+        }
+
+        let region = entry.region();
+        let end = region.offset.checked_add(region.len);
+        match (end, flash_size) {
+            (None, _) => return Err(Error::OutOfBounds { index }),
+            (Some(end), Some(size)) if end > size => {
+                return Err(Error::OutOfBounds { index })
+            }
+            _ => {}
+        }
+        if region.overlaps(table)
+            || entries[..index].iter().any(|e| e.region().overlaps(region))
+        {
+            return Err(Error::Overlap { index });
+        }
+    }
+    Ok(())
+}
+
+/// A partition described by a [`Table`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Partition<'a> {
+    name: &'a [u8],
+    purpose: Purpose,
+    region: Region,
+}
+
+impl<'a> Partition<'a> {
+    /// Returns this partition's name.
+    pub fn name(&self) -> &'a [u8] {
+        self.name
+    }
+
+    /// Returns what this partition is used for.
+    pub fn purpose(&self) -> Purpose {
+        self.purpose
+    }
+
+    /// Returns the region of flash this partition occupies.
+    pub fn region(&self) -> Region {
+        self.region
+    }
+}
+
+/// A parsed, validated partition table.
+pub struct Table<'a> {
+    region: Region,
+    entries: &'a [RawEntry],
+}
+
+impl<'a> Table<'a> {
+    /// Parses the partition table at `offset` in `flash`, checking its digest
+    /// and that it describes a consistent set of partitions.
+    ///
+    /// `arena` may be used to allocate the table's entries.
+    pub fn parse<'f: 'a, 'b: 'a>(
+        flash: &'f impl Flash,
+        offset: u32,
+        sha: &impl sha256::Builder,
+        arena: &'b dyn Arena,
+    ) -> Result<Self, Error> {
+        let mut header = RawHeader::default();
+        flash.read(offset, header.as_bytes_mut())?;
+        if header.magic != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        if header.version != VERSION {
+            return Err(Error::UnsupportedVersion(header.version));
+        }
+        if header.reserved != 0 {
+            return Err(Error::BadReserved);
+        }
+
+        // The table must not run off the end of the address space; checking
+        // its total length up front covers every offset computed below.
+        let len = mem::size_of::<RawHeader>()
+            + header.count as usize * mem::size_of::<RawEntry>()
+            + mem::size_of::<sha256::Digest>();
+        let region = Region::new(offset, len as u32);
+        if offset.checked_add(region.len).is_none() {
+            return Err(flash::Error::OutOfRange.into());
+        }
+
+        let entries_offset = offset + mem::size_of::<RawHeader>() as u32;
+        let entries = flash.read_slice::<RawEntry>(
+            entries_offset,
+            header.count as usize,
+            arena,
+        )?;
+
+        let mut digest = sha256::Digest::default();
+        let mut hasher = sha.new_hasher()?;
+        hasher.write(header.as_bytes())?;
+        hasher.write(entries.as_bytes())?;
+        hasher.finish(&mut digest)?;
+
+        let digest_offset = entries_offset + mem::size_of_val(entries) as u32;
+        let mut expected = sha256::Digest::default();
+        flash.read(digest_offset, &mut expected)?;
+        if digest != expected {
+            return Err(Error::BadDigest);
+        }
+
+        check_entries(region, entries, Some(flash.size()?))?;
+        Ok(Self { region, entries })
+    }
+
+    /// Returns the region of flash occupied by the table itself.
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Returns the number of partitions in this table.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns whether this table has no partitions.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the `i`th partition, if there is one.
+    pub fn get(&self, i: usize) -> Option<Partition<'a>> {
+        let entry = self.entries.get(i)?;
+        Some(Partition {
+            name: entry.name(),
+            purpose: Purpose::from_wire_value(entry.purpose)
+                .expect("checked in parse()"),
+            region: entry.region(),
+        })
+    }
+
+    /// Returns an iterator over every partition, in table order.
+    pub fn iter(&self) -> impl Iterator<Item = Partition<'a>> + '_ {
+        (0..self.len()).filter_map(move |i| self.get(i))
+    }
+
+    /// Finds the partition with the given name.
+    pub fn find(&self, name: &[u8]) -> Option<Partition<'a>> {
+        self.iter().find(|p| p.name() == name)
+    }
+
+    /// Returns an iterator over every partition with the given purpose.
+    pub fn with_purpose(
+        &self,
+        purpose: Purpose,
+    ) -> impl Iterator<Item = Partition<'a>> + '_ {
+        self.iter().filter(move |p| p.purpose() == purpose)
+    }
+}
+
+/// A partition to be encoded with [`encode()`].
+#[cfg(feature = "std")]
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Spec {
+    /// The partition's name.
+    pub name: String,
+    /// What the partition is used for.
+    pub purpose: Purpose,
+    /// Where the partition starts.
+    #[serde(
+        deserialize_with = "crate::serde::de_radix",
+        serialize_with = "crate::serde::se_hex"
+    )]
+    pub offset: u32,
+    /// How long the partition is.
+    #[serde(
+        deserialize_with = "crate::serde::de_radix",
+        serialize_with = "crate::serde::se_hex"
+    )]
+    pub len: u32,
+}
+
+#[cfg(feature = "std")]
+impl From<Partition<'_>> for Spec {
+    fn from(p: Partition<'_>) -> Self {
+        Self {
+            name: String::from_utf8_lossy(p.name()).into_owned(),
+            purpose: p.purpose(),
+            offset: p.region().offset,
+            len: p.region().len,
+        }
+    }
+}
+
+/// Encodes a partition table describing `partitions`, to be stored at
+/// `offset`.
+///
+/// The same checks that [`Table::parse()`] performs are applied, except that
+/// partitions are not checked against the size of any particular device.
+#[cfg(feature = "std")]
+pub fn encode(
+    offset: u32,
+    partitions: &[Spec],
+    sha: &impl sha256::Builder,
+) -> Result<Vec<u8>, Error> {
+    if partitions.len() > u8::MAX as usize {
+        return Err(Error::TooMany);
+    }
+
+    let mut entries = Vec::with_capacity(partitions.len());
+    for (index, spec) in partitions.iter().enumerate() {
+        let mut entry = RawEntry::default();
+        if spec.name.len() > NAME_LEN {
+            return Err(Error::BadName { index });
+        }
+        entry.name[..spec.name.len()].copy_from_slice(spec.name.as_bytes());
+        entry.purpose = spec.purpose.to_wire_value();
+        entry.offset = spec.offset;
+        entry.len = spec.len;
+        entries.push(entry);
+    }
+
+    let header = RawHeader {
+        magic: MAGIC,
+        version: VERSION,
+        count: entries.len() as u8,
+        reserved: 0,
+    };
+    let mut bytes = header.as_bytes().to_vec();
+    bytes.extend_from_slice(entries.as_bytes());
+
+    let len = bytes.len() + mem::size_of::<sha256::Digest>();
+    let region = Region::new(offset, len as u32);
+    if offset.checked_add(region.len).is_none() {
+        return Err(flash::Error::OutOfRange.into());
+    }
+    check_entries(region, &entries, None)?;
+
+    let mut digest = sha256::Digest::default();
+    sha.hash_contiguous(&bytes, &mut digest)?;
+    bytes.extend_from_slice(&digest);
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::crypto::ring;
+    use crate::crypto::sha256::Builder as _;
+    use crate::hardware::flash::RamMut;
+    use crate::hardware::flash::SubFlash;
+    use crate::mem::BumpArena;
+
+    fn spec(name: &str, purpose: Purpose, offset: u32, len: u32) -> Spec {
+        Spec {
+            name: name.to_string(),
+            purpose,
+            offset,
+            len,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let sha = ring::sha256::Builder::new();
+        let specs = vec![
+            spec("pfm_a", Purpose::Manifest, 0x1000, 0x1000),
+            spec("pfm_b", Purpose::Manifest, 0x2000, 0x1000),
+            spec("log", Purpose::Log, 0x3000, 0x800),
+        ];
+        let table = encode(0x100, &specs, &sha).unwrap();
+
+        let mut flash = RamMut(vec![0xff; 0x4000]);
+        flash.program(0x100, &table).unwrap();
+
+        let mut arena = [0; 128];
+        let arena = BumpArena::new(&mut arena);
+        let parsed = Table::parse(&flash, 0x100, &sha, &arena).unwrap();
+        assert_eq!(parsed.len(), 3);
+        assert_eq!(parsed.region(), Region::new(0x100, table.len() as u32));
+        assert_eq!(parsed.iter().map(Spec::from).collect::<Vec<_>>(), specs);
+        assert_eq!(parsed.with_purpose(Purpose::Manifest).count(), 2);
+        assert!(parsed.find(b"staging").is_none());
+
+        let log = parsed.find(b"log").unwrap().region();
+        assert_eq!(log, Region::new(0x3000, 0x800));
+        let mut view = SubFlash::new(&mut flash, log).unwrap();
+        assert_eq!(view.size().unwrap(), 0x800);
+        view.program(0x7fc, b"last").unwrap();
+        assert!(matches!(
+            view.program(0x7fd, b"last"),
+            Err(flash::Error::OutOfRange)
+        ));
+        let mut buf = [0; 4];
+        view.read(0x7fc, &mut buf).unwrap();
+        assert_eq!(&buf, b"last");
+        assert_eq!(&flash.0[0x37fc..0x3800], b"last");
+    }
+
+    #[test]
+    fn bad_tables() {
+        let sha = ring::sha256::Builder::new();
+        let encode = |specs: &[Spec]| encode(0x0, specs, &sha);
+
+        assert!(matches!(
+            encode(&[spec("", Purpose::Data, 0x100, 0x10)]),
+            Err(Error::BadName { index: 0 })
+        ));
+        assert!(matches!(
+            encode(&[spec("a name that is too long", Purpose::Data, 0, 0)]),
+            Err(Error::BadName { index: 0 })
+        ));
+        assert!(matches!(
+            encode(&[
+                spec("a", Purpose::Data, 0x100, 0x10),
+                spec("a", Purpose::Data, 0x200, 0x10),
+            ]),
+            Err(Error::DuplicateName { index: 1 })
+        ));
+        assert!(matches!(
+            encode(&[
+                spec("a", Purpose::Data, 0x100, 0x10),
+                spec("b", Purpose::Data, 0x108, 0x10),
+            ]),
+            Err(Error::Overlap { index: 1 })
+        ));
+        assert!(matches!(
+            encode(&[spec("a", Purpose::Data, 0x10, 0x10)]),
+            Err(Error::Overlap { index: 0 })
+        ));
+        assert!(matches!(
+            encode(&[spec("a", Purpose::Data, 0xffff_fff0, 0x20)]),
+            Err(Error::OutOfBounds { index: 0 })
+        ));
+
+        let table = encode(&[spec("a", Purpose::Data, 0x100, 0x100)]).unwrap();
+        let parse = |bytes: &[u8]| {
+            let mut flash = RamMut(vec![0xff; 0x180]);
+            flash.program(0, bytes).unwrap();
+            Table::parse(&flash, 0, &sha, &OutOfMemory).map(|t| t.len())
+        };
+        assert!(matches!(
+            parse(&table),
+            Err(Error::OutOfBounds { index: 0 })
+        ));
+
+        let mut corrupt = table.clone();
+        corrupt[10] ^= 1;
+        assert!(matches!(parse(&corrupt), Err(Error::BadDigest)));
+        let mut corrupt = table.clone();
+        corrupt[0] = b'X';
+        assert!(matches!(parse(&corrupt), Err(Error::BadMagic)));
+        let mut corrupt = table.clone();
+        corrupt[4] = 1;
+        assert!(matches!(parse(&corrupt), Err(Error::UnsupportedVersion(1))));
+        let mut corrupt = table;
+        corrupt[6] = 1;
+        assert!(matches!(parse(&corrupt), Err(Error::BadReserved)));
+
+        // A nonzero entry reserved field is rejected even if the digest is
+        // correct.
+        let mut table =
+            encode(&[spec("a", Purpose::Data, 0x100, 0x80)]).unwrap();
+        let digest_offset = table.len() - mem::size_of::<sha256::Digest>();
+        table[8 + NAME_LEN + 1] = 1;
+        let mut digest = sha256::Digest::default();
+        sha.hash_contiguous(&table[..digest_offset], &mut digest)
+            .unwrap();
+        table[digest_offset..].copy_from_slice(&digest);
+        assert!(matches!(
+            parse(&table),
+            Err(Error::BadEntryReserved { index: 0 })
+        ));
+
+        // A table at the very end of the address space must not wrap around.
+        assert!(matches!(
+            super::encode(u32::MAX - 4, &[], &sha),
+            Err(Error::Flash(flash::Error::OutOfRange))
+        ));
+    }
+}
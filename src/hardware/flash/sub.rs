@@ -0,0 +1,167 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Bounded views into a flash device.
+
+use crate::hardware::flash::Error;
+use crate::hardware::flash::Flash;
+use crate::hardware::flash::Geometry;
+use crate::hardware::flash::Region;
+use crate::mem::Arena;
+
+/// A [`Flash`] confined to a [`Region`] of another `Flash`.
+///
+/// Offsets into a `SubFlash` are relative to the start of its region, and
+/// are translated with [`Region::subregion()`]; any access that would fall
+/// outside of the region fails with [`Error::OutOfRange`]. This makes it
+/// possible to hand out capability-limited slices of a single physical part,
+/// such as one per partition, to code that should not be able to touch the
+/// rest of it.
+///
+/// [`Flash::read_direct()`] is passed straight through, so a `SubFlash` over
+/// an in-memory device still avoids copies.
+///
+/// Erasing through a `SubFlash` is only meaningful if its region is aligned
+/// to the underlying device's sectors.
+#[derive(Copy, Clone)]
+pub struct SubFlash<F> {
+    flash: F,
+    region: Region,
+}
+
+impl<F: Flash> SubFlash<F> {
+    /// Creates a view of `region` within `flash`.
+    ///
+    /// Returns [`Error::OutOfRange`] if `region` does not fit within `flash`.
+    pub fn new(flash: F, region: Region) -> Result<Self, Error> {
+        match region.offset.checked_add(region.len) {
+            Some(end) if end <= flash.size()? => Ok(Self { flash, region }),
+            _ => Err(Error::OutOfRange),
+        }
+    }
+}
+
+impl<F> SubFlash<F> {
+    /// Returns the region of the underlying device this view covers.
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// Returns a reference to the underlying device.
+    pub fn inner(&self) -> &F {
+        &self.flash
+    }
+
+    /// Consumes this view, returning the underlying device.
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Translates `sub`, relative to this view, into a region of the
+    /// underlying device.
+    fn translate(&self, sub: Region) -> Result<Region, Error> {
+        self.region.subregion(sub).ok_or(Error::OutOfRange)
+    }
+}
+
+unsafe impl<F: Flash> Flash for SubFlash<F> {
+    fn size(&self) -> Result<u32, Error> {
+        Ok(self.region.len)
+    }
+
+    fn read(&self, offset: u32, out: &mut [u8]) -> Result<(), Error> {
+        let region = self.translate(Region::new(offset, out.len() as u32))?;
+        self.flash.read(region.offset, out)
+    }
+
+    fn read_direct<'a: 'c, 'b: 'c, 'c>(
+        &'a self,
+        region: Region,
+        arena: &'b dyn Arena,
+        align: usize,
+    ) -> Result<&'c [u8], Error> {
+        let region = self.translate(region)?;
+        self.flash.read_direct(region, arena, align)
+    }
+
+    fn program(&mut self, offset: u32, buf: &[u8]) -> Result<(), Error> {
+        let region = self.translate(Region::new(offset, buf.len() as u32))?;
+        self.flash.program(region.offset, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.flash.flush()
+    }
+
+    fn geometry(&self) -> Result<Geometry, Error> {
+        self.flash.geometry()
+    }
+
+    fn erase(&mut self, region: Region) -> Result<(), Error> {
+        let region = self.translate(region)?;
+        self.flash.erase(region)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::hardware::flash::Ram;
+    use crate::hardware::flash::RamMut;
+    use crate::mem::OutOfMemory;
+
+    #[test]
+    fn bounds() {
+        let mut ram = RamMut(vec![0; 64]);
+        assert!(matches!(
+            SubFlash::new(&mut ram, Region::new(32, 33)),
+            Err(Error::OutOfRange)
+        ));
+        assert!(matches!(
+            SubFlash::new(&mut ram, Region::new(u32::MAX, 2)),
+            Err(Error::OutOfRange)
+        ));
+
+        let mut sub = SubFlash::new(&mut ram, Region::new(16, 32)).unwrap();
+        assert_eq!(sub.size().unwrap(), 32);
+        sub.program(0, b"start").unwrap();
+        sub.program(28, b"end!").unwrap();
+        assert!(matches!(sub.program(29, b"end!"), Err(Error::OutOfRange)));
+        assert!(matches!(
+            sub.erase(Region::new(u32::MAX, 1)),
+            Err(Error::OutOfRange)
+        ));
+
+        let mut buf = [0; 4];
+        assert!(matches!(sub.read(30, &mut buf), Err(Error::OutOfRange)));
+        sub.read(28, &mut buf).unwrap();
+        assert_eq!(&buf, b"end!");
+
+        sub.erase(Region::new(5, 4)).unwrap();
+        assert_eq!(&ram.0[16..28], b"start\xff\xff\xff\xff\0\0\0");
+        assert_eq!(&ram.0[44..48], b"end!");
+        assert_eq!(&ram.0[48..52], &[0; 4]);
+    }
+
+    #[test]
+    fn read_direct() {
+        let bytes = (0..64).collect::<Vec<u8>>();
+        let ram = Ram(&bytes[..]);
+        let sub = SubFlash::new(&ram, Region::new(8, 48)).unwrap();
+
+        // Views nest, and direct reads never copy out of `Ram`.
+        let nested = SubFlash::new(&sub, Region::new(8, 16)).unwrap();
+        assert_eq!(nested.region(), Region::new(8, 16));
+        let direct = nested
+            .read_direct(Region::new(4, 8), &OutOfMemory, 1)
+            .unwrap();
+        assert_eq!(direct, &bytes[20..28]);
+        assert_eq!(direct.as_ptr(), bytes[20..].as_ptr());
+        assert!(matches!(
+            nested.read_direct(Region::new(12, 8), &OutOfMemory, 1),
+            Err(Error::OutOfRange)
+        ));
+    }
+}
@@ -11,6 +11,7 @@
 
 use core::time::Duration;
 
+pub mod filter;
 pub mod flash;
 
 /// Provides access to "chip identity" information of various types.
@@ -46,6 +47,59 @@ pub trait Reset {
     fn uptime(&self) -> Duration;
 }
 
+/// Provides access to persistent anti-rollback state, such as a bank of
+/// fuses or a monotonic counter.
+///
+/// Manticore uses this to refuse to load manifests (or other versioned data)
+/// older than the newest one it has previously committed to.
+pub trait AntiRollback {
+    /// Returns the smallest version number that may currently be loaded.
+    fn min_version(&self) -> u32;
+
+    /// Raises the smallest version number that may be loaded to `version`.
+    ///
+    /// Implementations must never lower the stored value; if `version` is
+    /// smaller than [`AntiRollback::min_version()`], this function should do
+    /// nothing.
+    fn raise_min_version(&mut self, version: u32) -> Result<(), flash::Error>;
+}
+
+/// One of the two flash parts of a host with dual flash.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FlashPart {
+    /// The first part.
+    A,
+    /// The second part.
+    B,
+}
+
+impl FlashPart {
+    /// Returns the part that is not `self`.
+    pub fn other(self) -> Self {
+        match self {
+            Self::A => Self::B,
+            Self::B => Self::A,
+        }
+    }
+}
+
+/// Provides control over the mux that connects a host to one of its two
+/// flash parts.
+///
+/// The part the mux selects is the *active* part, which the host boots from;
+/// the RoT has exclusive access to the other, *inactive* part.
+pub trait FlashMux {
+    /// Returns the part the host is currently connected to.
+    fn active(&self) -> FlashPart;
+
+    /// Connects the host to `part`.
+    ///
+    /// Implementations must record `part` persistently, so that the same part
+    /// is active after the RoT resets. This function should only be called
+    /// while the host is held in reset.
+    fn set_active(&mut self, part: FlashPart) -> Result<(), flash::Error>;
+}
+
 #[allow(missing_docs)]
 pub mod fake {
     use core::convert::TryInto;
@@ -123,4 +177,68 @@ pub mod fake {
             self.uptime
         }
     }
+
+    /// A fake `FlashMux` that stores its state in memory.
+    pub struct FlashMux {
+        active: super::FlashPart,
+        switches: usize,
+    }
+
+    impl FlashMux {
+        /// Creates a new `fake::FlashMux`.
+        pub fn new(active: super::FlashPart) -> Self {
+            Self {
+                active,
+                switches: 0,
+            }
+        }
+
+        /// Returns the number of times the active part has been changed.
+        pub fn switches(&self) -> usize {
+            self.switches
+        }
+    }
+
+    impl super::FlashMux for FlashMux {
+        fn active(&self) -> super::FlashPart {
+            self.active
+        }
+
+        fn set_active(
+            &mut self,
+            part: super::FlashPart,
+        ) -> Result<(), super::flash::Error> {
+            if part != self.active {
+                self.active = part;
+                self.switches += 1;
+            }
+            Ok(())
+        }
+    }
+
+    /// A fake `AntiRollback` that stores its state in memory.
+    pub struct AntiRollback {
+        min_version: u32,
+    }
+
+    impl AntiRollback {
+        /// Creates a new `fake::AntiRollback`.
+        pub fn new(min_version: u32) -> Self {
+            Self { min_version }
+        }
+    }
+
+    impl super::AntiRollback for AntiRollback {
+        fn min_version(&self) -> u32 {
+            self.min_version
+        }
+
+        fn raise_min_version(
+            &mut self,
+            version: u32,
+        ) -> Result<(), super::flash::Error> {
+            self.min_version = self.min_version.max(version);
+            Ok(())
+        }
+    }
 }
@@ -6,20 +6,23 @@
 //!
 //! See the `manticore::manifest` documentation for more information.
 
+use core::cell::Cell;
 use core::marker::PhantomData;
 use core::mem;
 
 use zerocopy::AsBytes;
 use zerocopy::FromBytes;
 
+use crate::crypto::ecdsa;
 use crate::crypto::rsa;
 use crate::crypto::sha256;
 use crate::crypto::sha256::Hasher as _;
+use crate::crypto::sig;
+use crate::hardware::flash;
 use crate::hardware::flash::Flash;
 use crate::hardware::flash::FlashExt as _;
-use crate::hardware::flash::FlashIo;
 use crate::hardware::flash::Region;
-use crate::io::Read as _;
+use crate::manifest::km;
 use crate::manifest::provenance;
 use crate::manifest::Error;
 use crate::manifest::Manifest;
@@ -60,6 +63,61 @@ wire_enum! {
     }
 }
 
+impl HashType {
+    /// Returns the length, in bytes, of a digest of this type.
+    pub fn digest_len(self) -> usize {
+        match self {
+            Self::Sha256 => 32,
+            Self::Sha384 => 48,
+            Self::Sha512 => 64,
+        }
+    }
+}
+
+/// Decodes the `sig_type` byte of a manifest header into the signature
+/// algorithm and the hash of the signed digest.
+///
+/// The byte is laid out as follows:
+/// - Bits `[2:0]` are the [`HashType`].
+/// - Bits `[5:3]` are the key strength: for RSA, `0`, `1` and `2` are 2048-,
+///   3072- and 4096-bit moduli, respectively; for ECC, `0` and `1` are the
+///   P-256 and P-384 curves, respectively.
+/// - Bits `[7:6]` are the key type: `0` for RSA, and `1` for ECC.
+///
+/// Returns `None` if any of the fields are unknown to Manticore.
+pub(crate) fn decode_sig_type(byte: u8) -> Option<(sig::Algo, HashType)> {
+    let hash_type = HashType::from_wire_value(byte & 0b111)?;
+    let strength = (byte >> 3) & 0b111;
+    let algo = match byte >> 6 {
+        0 => sig::Algo::Rsa(match strength {
+            0 => rsa::ModulusLength::Bits2048,
+            1 => rsa::ModulusLength::Bits3072,
+            2 => rsa::ModulusLength::Bits4096,
+            _ => return None,
+        }),
+        1 => sig::Algo::Ecdsa(match strength {
+            0 => ecdsa::Curve::P256,
+            1 => ecdsa::Curve::P384,
+            _ => return None,
+        }),
+        _ => return None,
+    };
+    Some((algo, hash_type))
+}
+
+/// Encodes a `sig_type` byte; the inverse of [`decode_sig_type()`].
+#[cfg(feature = "std")]
+pub(crate) fn encode_sig_type(algo: sig::Algo, hash_type: HashType) -> u8 {
+    let (key_type, strength) = match algo {
+        sig::Algo::Rsa(rsa::ModulusLength::Bits2048) => (0, 0),
+        sig::Algo::Rsa(rsa::ModulusLength::Bits3072) => (0, 1),
+        sig::Algo::Rsa(rsa::ModulusLength::Bits4096) => (0, 2),
+        sig::Algo::Ecdsa(ecdsa::Curve::P256) => (1, 0),
+        sig::Algo::Ecdsa(ecdsa::Curve::P384) => (1, 1),
+    };
+    key_type << 6 | strength << 3 | hash_type.to_wire_value()
+}
+
 /// A TOC entry's raw bits.
 #[derive(Copy, Clone, PartialEq, Eq, Default, Debug, AsBytes, FromBytes)]
 #[repr(C)]
@@ -89,7 +147,7 @@ pub struct TocEntry<'entry, 'toc, M> {
 
 impl<'entry, 'toc, M: Manifest> TocEntry<'entry, 'toc, M> {
     #[inline]
-    fn raw(self) -> &'toc RawTocEntry {
+    pub(crate) fn raw(self) -> &'toc RawTocEntry {
         &self.toc.entries[self.index]
     }
 
@@ -141,6 +199,22 @@ impl<'entry, 'toc, M: Manifest> TocEntry<'entry, 'toc, M> {
         }
     }
 
+    /// Returns whether this entry's element has already had its hash checked
+    /// against the one recorded in the TOC.
+    ///
+    /// Entries without a hash are never considered verified.
+    pub fn is_verified(self) -> bool {
+        let bits = self.toc.verified.get();
+        bits[self.index / 32] & (1 << (self.index % 32)) != 0
+    }
+
+    /// Records that this entry's element hash has been checked.
+    fn mark_verified(self) {
+        let mut bits = self.toc.verified.get();
+        bits[self.index / 32] |= 1 << (self.index % 32);
+        self.toc.verified.set(bits);
+    }
+
     /// Returns an iterator over all of this entry's children.
     pub fn children(self) -> impl Iterator<Item = TocEntry<'entry, 'toc, M>> {
         let mut index = self.index() + 1;
@@ -207,6 +281,8 @@ impl<M> Copy for TocEntry<'_, '_, M> {}
 pub struct Toc<'toc, M> {
     entries: &'toc [RawTocEntry],
     hashes: &'toc [sha256::Digest],
+    /// One bit per entry, set once that entry's hash has been checked.
+    verified: Cell<[u32; 8]>,
     _ph: PhantomData<fn() -> M>,
 }
 
@@ -219,6 +295,14 @@ impl<'toc, M: Manifest> Toc<'toc, M> {
     /// Returns true if the invariants are upheld.
     fn check_invariants(&self) -> Result<(), Error> {
         for (i, entry) in self.entries.iter().enumerate() {
+            if let Some(ty) =
+                M::ElementType::from_wire_value(entry.element_type)
+            {
+                if entry.format_version < M::min_version(ty) {
+                    return Err(Error::OldFormatVersion { toc_index: i });
+                }
+            }
+
             if entry.hash_idx != 0xff
                 && self.hashes.len() <= entry.hash_idx as usize
             {
@@ -332,18 +416,49 @@ impl<'f, M: Manifest, F: Flash> Container<'f, M, F, provenance::Signed> {
     /// This is the only function capable of prodiucing a container with the
     /// `Signed` provenance.
     ///
+    /// The signature is checked with the algorithm recorded in the
+    /// container's header; `sig` must support that algorithm.
+    ///
     /// `buf` must be aligned to a four-byte boundary.
     pub fn parse_and_verify(
         flash: &'f F,
         sha: &impl sha256::Builder,
-        rsa: &mut impl rsa::Engine,
+        sig: &mut impl sig::Verify,
+        toc_arena: &'f impl Arena,
+        verify_arena: &impl Arena,
+    ) -> Result<Self, Error> {
+        let c = Self::parse_inner(flash, toc_arena)?;
+
+        c.verify_toc_hash(sha)?;
+        c.verify_signature(sha, sig, verify_arena)?;
+
+        Ok(c)
+    }
+}
+
+impl<'f, M: Manifest, F: Flash> Container<'f, M, F, provenance::Signed> {
+    /// Parses and verifies a `Container`, like
+    /// [`Container::parse_and_verify()`], additionally checking that the key
+    /// that signed it is authorized by `km` to sign manifests.
+    ///
+    /// The key is identified by [`sig::Verify::key_digest()`], for the same
+    /// algorithm its signature is checked with; see
+    /// [`km::ParsedKm::authorize()`].
+    pub fn parse_and_verify_with_km(
+        flash: &'f F,
+        sha: &impl sha256::Builder,
+        sig: &mut impl sig::Verify,
+        km: &km::ParsedKm<'_, impl Flash>,
         toc_arena: &'f impl Arena,
         verify_arena: &impl Arena,
     ) -> Result<Self, Error> {
         let c = Self::parse_inner(flash, toc_arena)?;
 
+        let signer = sig.key_digest(c.sig_algo())?;
+        km.authorize(&signer, km::KeyUsage::MANIFEST, sha)?;
+
         c.verify_toc_hash(sha)?;
-        c.verify_signature(sha, rsa, verify_arena)?;
+        c.verify_signature(sha, sig, verify_arena)?;
 
         Ok(c)
     }
@@ -405,28 +520,30 @@ impl<'f, M: Manifest, F: Flash, Provenance> Container<'f, M, F, Provenance> {
     pub(crate) fn verify_signature(
         &self,
         sha: &impl sha256::Builder,
-        rsa: &mut impl rsa::Engine,
+        sig: &mut impl sig::Verify,
         verify_arena: &impl Arena,
     ) -> Result<(), Error> {
-        let mut bytes = [0u8; 16];
-        let signed_region = self.signed_region();
-        let mut r = FlashIo::new(&self.flash)?;
-        r.reslice(signed_region);
+        let algo = self.sig_algo();
+        if self.header.sig_len as usize != algo.sig_len() {
+            return Err(Error::BadSignatureLen);
+        }
 
         let mut hasher = sha.new_hasher()?;
-        while r.remaining_data() > 0 {
-            let to_read = r.remaining_data().min(16);
-            r.read_bytes(&mut bytes[..to_read])?;
-            hasher.write(&bytes[..to_read])?;
-        }
+        flash::hash_regions(
+            &self.flash,
+            Some(self.signed_region()),
+            &mut hasher,
+            &mut [0; 16],
+            true,
+        )?;
 
         let mut digest = [0; 32];
         hasher.finish(&mut digest)?;
 
-        let sig =
+        let signature =
             self.flash
                 .read_direct(self.signature_region(), verify_arena, 1)?;
-        rsa.verify_signature(sig, &digest)?;
+        sig.verify_signature(algo, signature, &digest)?;
         Ok(())
     }
 
@@ -437,7 +554,6 @@ impl<'f, M: Manifest, F: Flash, Provenance> Container<'f, M, F, Provenance> {
         flash: &'f F,
         toc_arena: &'f impl Arena,
     ) -> Result<Self, Error> {
-        // TODO(#58): Manticore currently ignores header.sig_type.
         let header = flash.read_object::<RawHeader>(0, toc_arena)?;
 
         if ManifestType::from_wire_value(header.manifest_type) != Some(M::TYPE)
@@ -449,6 +565,14 @@ impl<'f, M: Manifest, F: Flash, Provenance> Container<'f, M, F, Provenance> {
             return Err(Error::OutOfRange);
         }
 
+        // The signed digest is always computed with SHA-256, regardless of
+        // the signature algorithm.
+        match decode_sig_type(header.sig_ty) {
+            Some((_, HashType::Sha256)) => {}
+            Some((_, h)) => return Err(Error::UnsupportedHashType(h)),
+            None => return Err(Error::OutOfRange),
+        }
+
         // TODO(#57): we don't deal with hash types that aren't SHA-256.
         match HashType::from_wire_value(header.hash_type) {
             Some(HashType::Sha256) => {}
@@ -478,6 +602,7 @@ impl<'f, M: Manifest, F: Flash, Provenance> Container<'f, M, F, Provenance> {
         let toc = Toc {
             entries,
             hashes,
+            verified: Cell::new([0; 8]),
             _ph: PhantomData,
         };
         toc.check_invariants()?;
@@ -523,6 +648,14 @@ impl<'f, M: Manifest, F: Flash, Provenance> Container<'f, M, F, Provenance> {
         self.flash
     }
 
+    /// Returns the algorithm this `Container` is signed with, as recorded in
+    /// its header.
+    pub fn sig_algo(&self) -> sig::Algo {
+        decode_sig_type(self.header.sig_ty)
+            .expect("verified in parse_inner()")
+            .0
+    }
+
     /// Returns the region of flash containing the signed bytes of this
     /// `Container`.
     pub fn signed_region(&self) -> Region {
@@ -534,6 +667,121 @@ impl<'f, M: Manifest, F: Flash, Provenance> Container<'f, M, F, Provenance> {
         let signed = self.signed_region();
         Region::new(signed.len, self.header.sig_len as u32)
     }
+
+    /// Verifies the hash of a single element, streaming it out of flash.
+    ///
+    /// This function never buffers the whole element; it is hashed in small
+    /// chunks read directly from flash. On success, the entry is marked as
+    /// verified.
+    ///
+    /// Entries that have no hash, or that were already verified, are skipped.
+    /// Note that a verified entry only attests to what flash contained at the
+    /// time; element readers use [`Container::verify_read()`] instead, which
+    /// checks the bytes they actually parse.
+    pub fn verify_element(
+        &self,
+        entry: TocEntry<'_, 'f, M>,
+        sha: &impl sha256::Builder,
+    ) -> Result<(), Error> {
+        if entry.is_verified() {
+            return Ok(());
+        }
+        self.verify_read(entry, &[], sha)
+    }
+
+    /// Verifies the hash of a single element, given `prefix`, the leading
+    /// bytes of the element that the caller has already read out of flash.
+    ///
+    /// `prefix` is hashed as-is, followed by the rest of the element, which is
+    /// streamed out of flash in small chunks. Thus, on success, the bytes in
+    /// `prefix` are exactly those covered by the hash in the TOC, even if
+    /// flash has changed since they were read. The entry is then marked as
+    /// verified.
+    ///
+    /// This check is performed even if the entry was already verified.
+    /// Entries that have no hash are skipped.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `prefix` is longer than the element.
+    pub fn verify_read(
+        &self,
+        entry: TocEntry<'_, 'f, M>,
+        prefix: &[u8],
+        sha: &impl sha256::Builder,
+    ) -> Result<(), Error> {
+        let expected = match entry.hash() {
+            Some(h) => h,
+            None => return Ok(()),
+        };
+
+        let region = entry.region();
+        let prefix_len = prefix.len() as u32;
+        assert!(prefix_len <= region.len, "prefix longer than element");
+        let rest =
+            Region::new(region.offset + prefix_len, region.len - prefix_len);
+
+        let mut hasher = sha.new_hasher()?;
+        hasher.write(prefix)?;
+        flash::hash_regions(
+            &self.flash,
+            Some(rest),
+            &mut hasher,
+            &mut [0; 64],
+            true,
+        )?;
+
+        let mut hash = [0; 32];
+        hasher.finish(&mut hash)?;
+        if &hash != expected {
+            return Err(Error::BadElementHash {
+                toc_index: entry.index(),
+            });
+        }
+        entry.mark_verified();
+        Ok(())
+    }
+
+    /// Verifies the hashes of every element in this `Container`, streaming
+    /// each out of flash with a fixed-size buffer.
+    ///
+    /// This is suitable for devices with too little memory to buffer large
+    /// elements; see [`Container::verify_element()`].
+    pub fn verify_elements(
+        &self,
+        sha: &impl sha256::Builder,
+    ) -> Result<(), Error> {
+        for entry in self.toc.entries() {
+            self.verify_element(entry, sha)?;
+        }
+        Ok(())
+    }
+
+    /// Copies the serialized contents of this `Container`'s backing storage
+    /// to `dest`, flushing it afterwards.
+    ///
+    /// This is a helper for implementing [`Parse::copy_to()`].
+    ///
+    /// [`Parse::copy_to()`]: crate::manifest::Parse::copy_to
+    pub(crate) fn copy_to(&self, dest: &mut impl Flash) -> Result<(), Error> {
+        let src = self.flash();
+        let len = src.size()? as usize;
+        let mut bytes_left = len;
+
+        let mut buf = [0; 32];
+        while bytes_left > 0 {
+            let bytes_to_copy = bytes_left.min(buf.len());
+            let buf = &mut buf[..bytes_to_copy];
+
+            let offset = (len - bytes_left) as u32;
+            src.read(offset, buf)?;
+            dest.program(offset, buf)?;
+
+            bytes_left -= bytes_to_copy;
+        }
+        dest.flush()?;
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -542,6 +790,8 @@ pub(crate) mod test {
 
     use crate::crypto::ring;
     use crate::crypto::testdata;
+    use crate::hardware::flash::Fault;
+    use crate::hardware::flash::Faulty;
     use crate::hardware::flash::Ram;
     use crate::manifest::owned;
     use crate::manifest::pfm;
@@ -621,6 +871,181 @@ pub(crate) mod test {
         assert_eq!(first.children().count(), 0);
     }
 
+    #[test]
+    fn streaming_verify() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, mut signer) = testdata::rsa();
+
+        #[rustfmt::skip]
+        let pfm: owned::Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": [{ "platform_id": "blah" }]
+        }"#).unwrap();
+        let bytes = Ram(pfm.sign(0x0, &sha, &mut signer).unwrap());
+        type Flash = Ram<Vec<u8>>;
+
+        let container: Container<'_, Pfm, Flash> = Container::parse_and_verify(
+            &bytes,
+            &sha,
+            &mut rsa,
+            &OutOfMemory,
+            &OutOfMemory,
+        )
+        .unwrap();
+        let first = container.toc().entry(0).unwrap();
+        assert!(!first.is_verified());
+        container.verify_elements(&sha).unwrap();
+        assert!(first.is_verified());
+
+        let pfm = pfm::ParsedPfm::new(container);
+        let id = pfm.platform_id(&sha, &OutOfMemory).unwrap().unwrap();
+        assert_eq!(id.id_string(), b"blah");
+    }
+
+    #[test]
+    fn accessors_mark_verified() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, mut signer) = testdata::rsa();
+
+        #[rustfmt::skip]
+        let pfm: owned::Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": [{ "platform_id": "blah" }]
+        }"#).unwrap();
+        let bytes = Ram(pfm.sign(0x0, &sha, &mut signer).unwrap());
+        type Flash = Ram<Vec<u8>>;
+
+        let container: Container<'_, Pfm, Flash> = Container::parse_and_verify(
+            &bytes,
+            &sha,
+            &mut rsa,
+            &OutOfMemory,
+            &OutOfMemory,
+        )
+        .unwrap();
+        assert!(!container.toc().entry(0).unwrap().is_verified());
+
+        // Reading the element streams its hash rather than hashing a copy,
+        // and records the result for later reads.
+        let pfm = pfm::ParsedPfm::new(container);
+        let id = pfm.platform_id(&sha, &OutOfMemory).unwrap().unwrap();
+        assert_eq!(id.id_string(), b"blah");
+        assert!(id.entry().is_verified());
+    }
+
+    #[test]
+    fn injected_faults() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, mut signer) = testdata::rsa();
+
+        #[rustfmt::skip]
+        let pfm: owned::Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": [{ "platform_id": "blah" }]
+        }"#).unwrap();
+        let bytes = Ram(pfm.sign(0x0, &sha, &mut signer).unwrap());
+        type Flash = Faulty<Ram<Vec<u8>>>;
+
+        let element = Container::<'_, Pfm, _, provenance::Adhoc>::parse(
+            &bytes,
+            &OutOfMemory,
+        )
+        .unwrap()
+        .toc()
+        .entry(0)
+        .unwrap()
+        .region();
+
+        let mut flash = Faulty::new(bytes.clone());
+        flash.inject(Fault::BitFlip {
+            offset: element.offset + 5,
+            mask: 0x20,
+        });
+        assert!(matches!(
+            Container::<'_, Pfm, Flash>::parse_and_verify(
+                &flash,
+                &sha,
+                &mut rsa,
+                &OutOfMemory,
+                &OutOfMemory,
+            ),
+            Err(Error::SignatureFailure)
+        ));
+        let container = Container::<'_, Pfm, Flash, provenance::Adhoc>::parse(
+            &flash,
+            &OutOfMemory,
+        )
+        .unwrap();
+        assert!(matches!(
+            container.verify_elements(&sha),
+            Err(Error::BadElementHash { toc_index: 0 })
+        ));
+
+        let mut flash = Faulty::new(bytes.clone());
+        flash.truncate(element.end() - 1);
+        assert!(Container::<'_, Pfm, Flash>::parse_and_verify(
+            &flash,
+            &sha,
+            &mut rsa,
+            &OutOfMemory,
+            &OutOfMemory,
+        )
+        .is_err());
+
+        let mut flash = Faulty::new(bytes);
+        flash.inject(Fault::Read {
+            region: Region::new(mem::size_of::<RawHeader>() as u32, 1),
+            error: flash::Error::Locked,
+        });
+        assert!(matches!(
+            Container::<'_, Pfm, Flash>::parse_and_verify(
+                &flash,
+                &sha,
+                &mut rsa,
+                &OutOfMemory,
+                &OutOfMemory,
+            ),
+            Err(Error::Flash(flash::Error::Locked))
+        ));
+    }
+
+    #[test]
+    fn streaming_verify_corrupt() {
+        let sha = ring::sha256::Builder::new();
+        let (_, mut signer) = testdata::rsa();
+
+        #[rustfmt::skip]
+        let pfm: owned::Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": [{ "platform_id": "blah" }]
+        }"#).unwrap();
+        let mut bytes = Ram(pfm.sign(0x0, &sha, &mut signer).unwrap());
+        type Flash = Ram<Vec<u8>>;
+
+        let region = Container::<'_, Pfm, Flash, provenance::Adhoc>::parse(
+            &bytes,
+            &OutOfMemory,
+        )
+        .unwrap()
+        .toc()
+        .entry(0)
+        .unwrap()
+        .region();
+        let last = (region.offset + region.len - 1) as usize;
+        bytes.0[last] ^= 0xff;
+
+        let container = Container::<'_, Pfm, Flash, provenance::Adhoc>::parse(
+            &bytes,
+            &OutOfMemory,
+        )
+        .unwrap();
+        assert!(matches!(
+            container.verify_elements(&sha),
+            Err(Error::BadElementHash { toc_index: 0 })
+        ));
+        assert!(!container.toc().entry(0).unwrap().is_verified());
+    }
+
     #[test]
     fn with_child() {
         let sha = ring::sha256::Builder::new();
@@ -673,4 +1098,81 @@ pub(crate) mod test {
         assert_eq!(second.parent().unwrap().index(), 0);
         assert!(second.hash().is_none());
     }
+
+    #[test]
+    fn sig_type() {
+        let algos = [
+            sig::Algo::Rsa(rsa::ModulusLength::Bits2048),
+            sig::Algo::Rsa(rsa::ModulusLength::Bits3072),
+            sig::Algo::Rsa(rsa::ModulusLength::Bits4096),
+            sig::Algo::Ecdsa(ecdsa::Curve::P256),
+            sig::Algo::Ecdsa(ecdsa::Curve::P384),
+        ];
+        for &algo in &algos {
+            let byte = encode_sig_type(algo, HashType::Sha384);
+            assert_eq!(decode_sig_type(byte), Some((algo, HashType::Sha384)));
+        }
+
+        // Cerberus's encoding of RSA-2048 with SHA-256 is all zeroes.
+        assert_eq!(
+            decode_sig_type(0x00),
+            Some((
+                sig::Algo::Rsa(rsa::ModulusLength::Bits2048),
+                HashType::Sha256
+            ))
+        );
+        // ECC-521 is not supported.
+        assert_eq!(decode_sig_type(0x50), None);
+    }
+
+    #[test]
+    fn ecdsa_signed() {
+        let sha = ring::sha256::Builder::new();
+        let (rsa, _) = testdata::rsa();
+        let (ecdsa, mut signer) = testdata::ecdsa();
+
+        #[rustfmt::skip]
+        let pfm: owned::Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": [{ "platform_id": "blah" }]
+        }"#).unwrap();
+        let bytes = Ram(pfm.sign(0x0, &sha, &mut signer).unwrap());
+        type Flash = Ram<Vec<u8>>;
+
+        // An RSA engine alone cannot check an ECDSA signature.
+        let mut sig = (rsa, ecdsa);
+        let result = Container::<'_, Pfm, Flash>::parse_and_verify(
+            &bytes,
+            &sha,
+            &mut sig.0,
+            &OutOfMemory,
+            &OutOfMemory,
+        );
+        assert!(matches!(result, Err(Error::UnsupportedSignature)));
+
+        // But a pair of engines will select the right one.
+        let container: Container<'_, Pfm, Flash> = Container::parse_and_verify(
+            &bytes,
+            &sha,
+            &mut sig,
+            &OutOfMemory,
+            &OutOfMemory,
+        )
+        .unwrap();
+        assert_eq!(container.sig_algo(), sig::Algo::Ecdsa(ecdsa::Curve::P384));
+        assert_eq!(container.signature_region().len, 96);
+
+        // Corrupting the signature is detected.
+        let mut corrupt = bytes.0.clone();
+        *corrupt.last_mut().unwrap() ^= 0x01;
+        let corrupt = Ram(corrupt);
+        let result = Container::<'_, Pfm, Flash>::parse_and_verify(
+            &corrupt,
+            &sha,
+            &mut sig,
+            &OutOfMemory,
+            &OutOfMemory,
+        );
+        assert!(matches!(result, Err(Error::SignatureFailure)));
+    }
 }
@@ -0,0 +1,413 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Dual host flash management.
+//!
+//! A PA-RoT may protect a host with two flash parts, only one of which is
+//! connected to the host at a time by a [`hardware::FlashMux`]. The host boots
+//! from the *active* part, while updates are staged in the *inactive* one.
+//!
+//! Once an update has been written, [`DualFlash::activate()`] validates the
+//! inactive part against the active PFM and, if it passes, makes its contents
+//! active, either by switching the mux over to it or by copying it into the
+//! active part; see [`Strategy`].
+//!
+//! On startup, [`DualFlash::recover()`] checks the active part, and falls back
+//! to the inactive one if necessary. This also recovers from losing power
+//! part-way through a [`Strategy::Copy`] activation, since the inactive part
+//! still holds the validated image.
+
+use crate::crypto::sha256;
+use crate::hardware;
+use crate::hardware::flash::Flash;
+use crate::hardware::flash::Region;
+use crate::hardware::FlashPart;
+use crate::manifest::pfm::ParsedPfm;
+use crate::manifest::provenance::Provenance;
+use crate::manifest::Error;
+use crate::manifest::ValidationTime;
+use crate::mem::Arena;
+
+/// How [`DualFlash::activate()`] makes a validated update active.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Strategy {
+    /// Switches the mux over to the inactive part, so that the two parts swap
+    /// roles.
+    Swap,
+    /// Copies the inactive part over the active one, leaving the mux alone.
+    ///
+    /// This keeps the host booting from the same physical part, at the cost
+    /// of rewriting it.
+    Copy,
+}
+
+/// A pair of host flash parts, and the mux that selects between them.
+///
+/// Both parts hold the same host flash device; a PFM that describes several
+/// devices needs a `DualFlash` for each of them.
+///
+/// See the [module documentation](index.html) for more information.
+pub struct DualFlash<Flash, Mux> {
+    device: usize,
+    parts: [Flash; 2],
+    mux: Mux,
+}
+
+impl<F: Flash, M: hardware::FlashMux> DualFlash<F, M> {
+    /// Creates a new `DualFlash` out of the given parts, with `mux` selecting
+    /// between them.
+    ///
+    /// The parts hold the `device`th host flash device described by the PFMs
+    /// they are validated against; see [`ParsedPfm::device_fws()`].
+    pub fn new(device: usize, parts: [F; 2], mux: M) -> Self {
+        Self { device, parts, mux }
+    }
+
+    /// Returns the part the host is currently booting from.
+    pub fn active_part(&self) -> FlashPart {
+        self.mux.active()
+    }
+
+    /// Returns the active part.
+    pub fn active(&self) -> &F {
+        self.part(self.active_part())
+    }
+
+    /// Returns the inactive part.
+    pub fn inactive(&self) -> &F {
+        self.part(self.active_part().other())
+    }
+
+    /// Returns the inactive part, for staging an update into.
+    pub fn inactive_mut(&mut self) -> &mut F {
+        let part = self.active_part().other();
+        &mut self.parts[index(part)]
+    }
+
+    /// Returns a reference to the mux.
+    pub fn mux(&self) -> &M {
+        &self.mux
+    }
+
+    /// Consumes this `DualFlash`, returning the parts and the mux.
+    pub fn into_inner(self) -> ([F; 2], M) {
+        (self.parts, self.mux)
+    }
+
+    /// Validates the inactive part against `pfm` and, if it passes, makes its
+    /// contents active according to `strategy`.
+    ///
+    /// If validation fails, nothing is modified. With [`Strategy::Copy`], the
+    /// active part is validated again once the copy completes.
+    ///
+    /// This function should only be called while the host is held in reset.
+    /// `arena` must have room for validating against `pfm` twice.
+    pub fn activate<'pfm, PF: 'pfm + Flash, P: Provenance>(
+        &mut self,
+        pfm: &ParsedPfm<'pfm, PF, P>,
+        strategy: Strategy,
+        sha: &impl sha256::Builder,
+        arena: &'pfm impl Arena,
+    ) -> Result<(), Error> {
+        let active = self.active_part();
+        self.validate(
+            active.other(),
+            pfm,
+            ValidationTime::Activation,
+            sha,
+            arena,
+        )?;
+
+        match strategy {
+            Strategy::Swap => self.mux.set_active(active.other())?,
+            Strategy::Copy => {
+                self.copy(active.other(), active)?;
+                self.validate(
+                    active,
+                    pfm,
+                    ValidationTime::Activation,
+                    sha,
+                    arena,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the active part is valid according to `pfm`, switching
+    /// the mux over to the inactive part if it is not but the inactive part
+    /// is.
+    ///
+    /// Returns the part that is active afterwards. If neither part is valid,
+    /// the mux is left alone, and the error from validating the active part
+    /// is returned.
+    ///
+    /// This function is intended to be called on startup, while the host is
+    /// held in reset.
+    pub fn recover<'pfm, PF: 'pfm + Flash, P: Provenance>(
+        &mut self,
+        pfm: &ParsedPfm<'pfm, PF, P>,
+        sha: &impl sha256::Builder,
+        arena: &'pfm impl Arena,
+    ) -> Result<FlashPart, Error> {
+        let active = self.active_part();
+        let error = match self.validate(
+            active,
+            pfm,
+            ValidationTime::Startup,
+            sha,
+            arena,
+        ) {
+            Ok(()) => return Ok(active),
+            Err(e) => e,
+        };
+
+        if self
+            .validate(active.other(), pfm, ValidationTime::Startup, sha, arena)
+            .is_err()
+        {
+            return Err(error);
+        }
+        self.mux.set_active(active.other())?;
+        Ok(active.other())
+    }
+
+    fn part(&self, part: FlashPart) -> &F {
+        &self.parts[index(part)]
+    }
+
+    /// Validates the contents of `part` against `pfm`.
+    fn validate<'pfm, PF: 'pfm + Flash, P: Provenance>(
+        &self,
+        part: FlashPart,
+        pfm: &ParsedPfm<'pfm, PF, P>,
+        when: ValidationTime,
+        sha: &impl sha256::Builder,
+        arena: &'pfm impl Arena,
+    ) -> Result<(), Error> {
+        pfm.validate_device(self.device, when, self.part(part), sha, arena)
+    }
+
+    /// Erases `dest` and copies all of `src` into it.
+    fn copy(&mut self, src: FlashPart, dest: FlashPart) -> Result<(), Error> {
+        let len = self.part(src).size()?;
+        if self.part(dest).size()? != len {
+            return Err(Error::OutOfRange);
+        }
+
+        let [a, b] = &mut self.parts;
+        let (src, dest) = match src {
+            FlashPart::A => (&*a, b),
+            FlashPart::B => (&*b, a),
+        };
+
+        // Chunks are aligned to their own size, so keeping them no larger
+        // than a page means they never cross a page boundary.
+        let mut buf = [0; 64];
+        let chunk = dest.geometry()?.page_size.min(buf.len() as u32);
+        let buf = &mut buf[..chunk as usize];
+
+        dest.erase(Region::new(0, len))?;
+        let mut offset = 0;
+        while offset < len {
+            let buf = &mut buf[..(len - offset).min(chunk) as usize];
+            src.read(offset, buf)?;
+            dest.program(offset, buf)?;
+            offset += buf.len() as u32;
+        }
+        dest.flush()?;
+        Ok(())
+    }
+}
+
+fn index(part: FlashPart) -> usize {
+    match part {
+        FlashPart::A => 0,
+        FlashPart::B => 1,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::crypto::ring;
+    use crate::crypto::sha256::Builder as _;
+    use crate::crypto::testdata;
+    use crate::hardware::fake;
+    use crate::hardware::flash::Faulty;
+    use crate::hardware::flash::Ram;
+    use crate::hardware::flash::RamMut;
+    use crate::manifest::owned;
+    use crate::manifest::Container;
+    use crate::manifest::HashType;
+    use crate::manifest::Metadata;
+    use crate::mem::BumpArena;
+
+    type Part = Faulty<RamMut<Vec<u8>>>;
+
+    const IMAGE: Region = Region::new(0x0, 0x100);
+
+    /// Returns the contents of a host flash part holding `version`.
+    fn host_image(version: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0xff; 0x200];
+        bytes[..version.len()].copy_from_slice(version);
+        for (i, b) in bytes[0x10..0x100].iter_mut().enumerate() {
+            *b = i as u8 ^ version[1];
+        }
+        bytes
+    }
+
+    /// Returns a signed PFM allowing each of `versions`.
+    fn signed_pfm(versions: &[&[u8]]) -> Vec<u8> {
+        let sha = ring::sha256::Builder::new();
+        let (_, mut signer) = testdata::rsa();
+
+        let version = |version_str: &[u8]| {
+            let mut hash = [0; 32];
+            let image = host_image(version_str);
+            sha.hash_contiguous(
+                &image[IMAGE.offset as usize..][..0x100],
+                &mut hash,
+            )
+            .unwrap();
+            owned::Node {
+                element: owned::pfm::Element::FwVersion {
+                    version_addr: 0x0,
+                    version_str: version_str.to_vec(),
+                    rw_regions: vec![],
+                    image_regions: vec![owned::pfm::Image {
+                        flags: 0b1,
+                        hash_type: HashType::Sha256,
+                        hash: hash.to_vec(),
+                        regions: vec![IMAGE],
+                    }],
+                    format_version: 0,
+                },
+                children: vec![],
+                hashed: true,
+            }
+        };
+        let pfm = owned::Pfm {
+            metadata: Metadata { version_id: 1 },
+            elements: vec![owned::Node {
+                element: owned::pfm::Element::AllowableFw {
+                    version_count: versions.len() as u8,
+                    firmware_id: b"bmc".to_vec(),
+                    flags: 0,
+                },
+                children: versions.iter().map(|v| version(v)).collect(),
+                hashed: true,
+            }],
+        };
+        pfm.sign(0x0, &sha, &mut signer).unwrap()
+    }
+
+    fn dual(
+        a: Vec<u8>,
+        b: Vec<u8>,
+        active: FlashPart,
+    ) -> DualFlash<Part, fake::FlashMux> {
+        DualFlash::new(
+            0,
+            [Faulty::new(RamMut(a)), Faulty::new(RamMut(b))],
+            fake::FlashMux::new(active),
+        )
+    }
+
+    #[test]
+    fn activate() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, _) = testdata::rsa();
+        let mut arena = vec![0; 0x4000];
+        let arena = BumpArena::new(&mut arena);
+
+        let pfm = Ram(signed_pfm(&[b"v1.0", b"v2.0"]));
+        let pfm = ParsedPfm::new(
+            Container::parse_and_verify(&pfm, &sha, &mut rsa, &arena, &arena)
+                .unwrap(),
+        );
+
+        // A corrupt update is refused outright.
+        let mut corrupt = host_image(b"v2.0");
+        corrupt[0x80] ^= 0xff;
+        let mut host = dual(host_image(b"v1.0"), corrupt, FlashPart::A);
+        assert!(matches!(
+            host.activate(&pfm, Strategy::Swap, &sha, &arena),
+            Err(Error::BadGuardedHash { .. })
+        ));
+        assert!(matches!(
+            host.activate(&pfm, Strategy::Copy, &sha, &arena),
+            Err(Error::BadGuardedHash { .. })
+        ));
+        assert_eq!(host.active_part(), FlashPart::A);
+        assert_eq!(host.active().inner().0, host_image(b"v1.0"));
+
+        // Unknown versions are refused, too.
+        host.inactive_mut()
+            .program(0, &host_image(b"v3.0"))
+            .unwrap();
+        assert!(matches!(
+            host.activate(&pfm, Strategy::Swap, &sha, &arena),
+            Err(Error::NoMatchingVersion { .. })
+        ));
+
+        host.inactive_mut()
+            .program(0, &host_image(b"v2.0"))
+            .unwrap();
+        host.activate(&pfm, Strategy::Swap, &sha, &arena).unwrap();
+        assert_eq!(host.active_part(), FlashPart::B);
+        assert_eq!(host.mux().switches(), 1);
+        assert_eq!(host.inactive().inner().0, host_image(b"v1.0"));
+
+        host.inactive_mut()
+            .program(0, &host_image(b"v1.0"))
+            .unwrap();
+        host.activate(&pfm, Strategy::Copy, &sha, &arena).unwrap();
+        assert_eq!(host.active_part(), FlashPart::B);
+        assert_eq!(host.mux().switches(), 1);
+        assert_eq!(host.active().inner().0, host_image(b"v1.0"));
+    }
+
+    #[test]
+    fn recover() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, _) = testdata::rsa();
+        let mut arena = vec![0; 0x4000];
+        let arena = BumpArena::new(&mut arena);
+
+        let pfm = Ram(signed_pfm(&[b"v1.0", b"v2.0"]));
+        let pfm = ParsedPfm::new(
+            Container::parse_and_verify(&pfm, &sha, &mut rsa, &arena, &arena)
+                .unwrap(),
+        );
+
+        // Lose power part-way through copying the update into the active
+        // part: after it has been erased, but before the copy completes.
+        let mut a = Faulty::new(RamMut(host_image(b"v1.0")));
+        a.power_loss_after(0x280);
+        let mut host = DualFlash::new(
+            0,
+            [a, Faulty::new(RamMut(host_image(b"v2.0")))],
+            fake::FlashMux::new(FlashPart::A),
+        );
+        assert_eq!(host.recover(&pfm, &sha, &arena).unwrap(), FlashPart::A);
+        assert!(host.activate(&pfm, Strategy::Copy, &sha, &arena).is_err());
+        let ([mut a, b], mux) = host.into_inner();
+        a.restore_power();
+        assert_ne!(a.inner().0, host_image(b"v1.0"));
+        assert_ne!(a.inner().0, host_image(b"v2.0"));
+
+        // On the next boot, the host falls back to the validated update.
+        let mut host = DualFlash::new(0, [a, b], mux);
+        assert_eq!(host.recover(&pfm, &sha, &arena).unwrap(), FlashPart::B);
+        assert_eq!(host.active_part(), FlashPart::B);
+
+        // If neither part is any good, nothing changes.
+        let mut host = dual(vec![0xff; 0x200], vec![0xff; 0x200], FlashPart::B);
+        assert!(host.recover(&pfm, &sha, &arena).is_err());
+        assert_eq!(host.active_part(), FlashPart::B);
+        assert_eq!(host.mux().switches(), 0);
+    }
+}
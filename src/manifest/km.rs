@@ -0,0 +1,620 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The Key Manifest (KM).
+//!
+//! A KM is a manifest, signed by a RoT's root key, that lists which other
+//! keys may sign manifests or firmware on the RoT's behalf. This allows
+//! signing keys to be rotated, and leaked keys to be revoked, by issuing a
+//! new KM rather than by reprovisioning the RoT.
+//!
+//! Keys are identified by the SHA-256 digest of their public key encoding;
+//! which encoding is used is up to the integration, so long as the same one
+//! is used when minting the KM and when checking a key against it. Each
+//! allowed key also carries a numeric ID, which `RevokedKey` elements refer
+//! to. A key whose ID is revoked is never authorized, even if it is also
+//! listed as allowed.
+//!
+//! Since an older KM may still allow keys that a newer one revokes, a RoT
+//! should keep its KM in a [`Manager`], whose anti-rollback policy prevents
+//! reverting to a KM older than the newest one activated.
+//!
+//! Like the other manifests, the KM is encoded as a
+//! ["table of contents"](../struct.Toc.html) describing a number of elements.
+//!
+//! The [`ParsedKm`] type is the entry-point for this module.
+//!
+//! [`Manager`]: crate::manifest::manager::Manager
+
+use core::mem;
+
+use bitflags::bitflags;
+
+use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+
+use crate::crypto::sha256;
+use crate::hardware::flash::Flash;
+use crate::manifest::provenance;
+use crate::manifest::provenance::Provenance;
+use crate::manifest::Container;
+use crate::manifest::Error;
+use crate::manifest::Manifest;
+use crate::manifest::ManifestType;
+use crate::manifest::Parse;
+use crate::manifest::ParsedManifest;
+use crate::manifest::TocEntry;
+use crate::manifest::ValidationTime;
+use crate::mem::Arena;
+
+wire_enum! {
+    /// A KM element type.
+    pub enum ElementType: u8 {
+      /// A key that is authorized to sign on the root key's behalf.
+      AllowedKey = 0x01,
+
+      /// The ID of a key that is no longer authorized.
+      RevokedKey = 0x02,
+    }
+}
+
+bitflags! {
+    /// What a key listed in a KM is authorized to sign.
+    pub struct KeyUsage: u8 {
+        /// The key may sign manifests, such as PFMs.
+        const MANIFEST = 0b01;
+        /// The key may sign firmware images.
+        const FIRMWARE = 0b10;
+    }
+}
+
+/// A Key Manifest.
+///
+/// This type provides functions for parsing a KM's table of contents and
+/// using it to extract other portions of the KM.
+///
+/// This type only maintains the TOC in memory for book-keeping.
+pub struct ParsedKm<'km, Flash, Provenance = provenance::Signed> {
+    container: Container<'km, Km, Flash, Provenance>,
+}
+
+/// A [`Manifest`] implementation mapping onto [`ParsedKm`], for use in generic
+/// contexts.
+///
+/// See [`Manifest`] and [`Parse`].
+pub enum Km {}
+
+impl Manifest for Km {
+    type ElementType = ElementType;
+    const TYPE: ManifestType = ManifestType::Km;
+
+    fn min_version(_: ElementType) -> u8 {
+        0
+    }
+}
+
+impl<'f, F: 'f + Flash, P> Parse<'f, F, P> for Km {
+    type Parsed = ParsedKm<'f, F, P>;
+
+    fn parse(
+        container: Container<'f, Self, F, P>,
+    ) -> Result<Self::Parsed, Error> {
+        Ok(ParsedKm::new(container))
+    }
+
+    fn copy_to<F2: Flash>(
+        manifest: &Self::Parsed,
+        dest: &mut F2,
+    ) -> Result<(), Error> {
+        manifest.container.copy_to(dest)
+    }
+
+    type Guarded = ();
+    fn validate(
+        _manifest: &Self::Parsed,
+        _when: ValidationTime,
+        _args: &Self::Guarded,
+        _sha: &impl sha256::Builder,
+        _arena: &'f impl Arena,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<F, P> ParsedManifest for ParsedKm<'_, F, P> {
+    type Manifest = Km;
+}
+
+impl<'km, F, P> ParsedKm<'km, F, P> {
+    /// Creates a new KM handle using the given `Container`.
+    pub fn new(container: Container<'km, Km, F, P>) -> Self {
+        ParsedKm { container }
+    }
+}
+
+/// Reads a fixed-size element described by `entry` out of `container`,
+/// verifying its hash if the provenance calls for it.
+///
+/// Unlike most elements, KM elements are small and fixed-size, so they are
+/// read onto the stack rather than into an arena; this allows the whole KM to
+/// be scanned without exhausting one.
+fn read_element<T, F: Flash, P: Provenance>(
+    container: &Container<'_, Km, F, P>,
+    entry: TocEntry<'_, '_, Km>,
+    sha: &impl sha256::Builder,
+) -> Result<T, Error>
+where
+    T: Default + AsBytes + FromBytes,
+{
+    let region = entry.region();
+    if (region.len as usize) < mem::size_of::<T>() {
+        return Err(Error::TooShort {
+            toc_index: entry.index(),
+        });
+    }
+
+    let mut value = T::default();
+    container
+        .flash()
+        .read(region.offset, value.as_bytes_mut())?;
+
+    if P::AUTHENTICATED {
+        // The element may be longer than `T`, if it was encoded with a newer
+        // format version; the remainder is hashed straight out of flash.
+        container.verify_read(entry, value.as_bytes(), sha)?;
+    }
+
+    Ok(value)
+}
+
+impl<'km, F: Flash, P> ParsedKm<'km, F, P>
+where
+    P: Provenance,
+{
+    /// Returns an iterator over the `AllowedKey` elements of this KM.
+    ///
+    /// The returned values only contain the `Toc` information for the entry,
+    /// allowing the user to lazily select which entries to read from flash.
+    pub fn allowed_keys(
+        &self,
+    ) -> impl Iterator<Item = AllowedKeyEntry<'_, 'km, F, P>> + '_ {
+        self.container
+            .toc()
+            .entries()
+            .filter(|e| e.element_type() == Some(ElementType::AllowedKey))
+            .map(move |entry| AllowedKeyEntry { km: self, entry })
+    }
+
+    /// Returns an iterator over the `RevokedKey` elements of this KM.
+    ///
+    /// The returned values only contain the `Toc` information for the entry,
+    /// allowing the user to lazily select which entries to read from flash.
+    pub fn revoked_keys(
+        &self,
+    ) -> impl Iterator<Item = RevokedKeyEntry<'_, 'km, F, P>> + '_ {
+        self.container
+            .toc()
+            .entries()
+            .filter(|e| e.element_type() == Some(ElementType::RevokedKey))
+            .map(move |entry| RevokedKeyEntry { km: self, entry })
+    }
+
+    /// Checks whether the key with ID `key_id` has been revoked by this KM.
+    pub fn is_revoked(
+        &self,
+        key_id: u32,
+        sha: &impl sha256::Builder,
+    ) -> Result<bool, Error> {
+        for revoked in self.revoked_keys() {
+            if revoked.read(sha)?.key_id() == key_id {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+impl<'km, F: Flash> ParsedKm<'km, F, provenance::Signed> {
+    /// Checks that the key whose digest is `key_digest` is authorized by this
+    /// KM to perform `usage`, returning its ID.
+    ///
+    /// The key must be listed in an `AllowedKey` element that permits all of
+    /// `usage`, and whose ID has not been revoked. If the key is listed more
+    /// than once, every listing is considered; [`Error::RevokedKey`] is only
+    /// returned if all of the matching listings have been revoked.
+    ///
+    /// This function is only available on KMs with a verified signature;
+    /// an unauthenticated KM cannot authorize anything.
+    pub fn authorize(
+        &self,
+        key_digest: &sha256::Digest,
+        usage: KeyUsage,
+        sha: &impl sha256::Builder,
+    ) -> Result<u32, Error> {
+        let mut revoked = None;
+        for allowed in self.allowed_keys() {
+            let allowed = allowed.read(sha)?;
+            if allowed.digest() != key_digest
+                || !allowed.usage().contains(usage)
+            {
+                continue;
+            }
+
+            let key_id = allowed.key_id();
+            if !self.is_revoked(key_id, sha)? {
+                return Ok(key_id);
+            }
+            revoked.get_or_insert(key_id);
+        }
+
+        match revoked {
+            Some(key_id) => Err(Error::RevokedKey { key_id }),
+            None => Err(Error::UnauthorizedKey),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+struct AllowedKeyRaw {
+    key_id: u32,
+    usage: u8,
+    _unused: [u8; 3],
+    digest: sha256::Digest,
+}
+
+/// An "allowed key" element entry in a KM's `Toc`.
+///
+/// This type allows for lazily reading the [`AllowedKey`] described by this
+/// entry, as obtained from [`ParsedKm::allowed_keys()`].
+pub struct AllowedKeyEntry<'a, 'km, Flash, Provenance = provenance::Signed> {
+    km: &'a ParsedKm<'km, Flash, Provenance>,
+    entry: TocEntry<'a, 'km, Km>,
+}
+
+impl<'a, 'km, F: Flash, P> AllowedKeyEntry<'a, 'km, F, P>
+where
+    P: Provenance,
+{
+    /// Returns the `Toc` entry defining this element.
+    pub fn entry(&self) -> TocEntry<'a, 'km, Km> {
+        self.entry
+    }
+
+    /// Reads the contents of this element, verifying its hash.
+    pub fn read(
+        self,
+        sha: &impl sha256::Builder,
+    ) -> Result<AllowedKey<'a, 'km>, Error> {
+        let raw = read_element(&self.km.container, self.entry, sha)?;
+        Ok(AllowedKey {
+            entry: self.entry,
+            raw,
+        })
+    }
+}
+
+/// A key that a KM authorizes to sign on the root key's behalf.
+pub struct AllowedKey<'a, 'km> {
+    entry: TocEntry<'a, 'km, Km>,
+    raw: AllowedKeyRaw,
+}
+
+impl<'a, 'km> AllowedKey<'a, 'km> {
+    /// Returns the `Toc` entry defining this element.
+    pub fn entry(&self) -> TocEntry<'a, 'km, Km> {
+        self.entry
+    }
+
+    /// Returns the ID of this key, which revocations refer to.
+    pub fn key_id(&self) -> u32 {
+        self.raw.key_id
+    }
+
+    /// Returns what this key is authorized to sign.
+    ///
+    /// Unknown usage bits are ignored.
+    pub fn usage(&self) -> KeyUsage {
+        KeyUsage::from_bits_truncate(self.raw.usage)
+    }
+
+    /// Returns the raw encoded usage flags for this element.
+    pub fn raw_usage(&self) -> u8 {
+        self.raw.usage
+    }
+
+    /// Returns the SHA-256 digest of this key's public key encoding.
+    pub fn digest(&self) -> &sha256::Digest {
+        &self.raw.digest
+    }
+}
+
+#[derive(Copy, Clone, Default, AsBytes, FromBytes)]
+#[repr(C)]
+struct RevokedKeyRaw {
+    key_id: u32,
+}
+
+/// A "revoked key" element entry in a KM's `Toc`.
+///
+/// This type allows for lazily reading the [`RevokedKey`] described by this
+/// entry, as obtained from [`ParsedKm::revoked_keys()`].
+pub struct RevokedKeyEntry<'a, 'km, Flash, Provenance = provenance::Signed> {
+    km: &'a ParsedKm<'km, Flash, Provenance>,
+    entry: TocEntry<'a, 'km, Km>,
+}
+
+impl<'a, 'km, F: Flash, P> RevokedKeyEntry<'a, 'km, F, P>
+where
+    P: Provenance,
+{
+    /// Returns the `Toc` entry defining this element.
+    pub fn entry(&self) -> TocEntry<'a, 'km, Km> {
+        self.entry
+    }
+
+    /// Reads the contents of this element, verifying its hash.
+    pub fn read(
+        self,
+        sha: &impl sha256::Builder,
+    ) -> Result<RevokedKey<'a, 'km>, Error> {
+        let raw: RevokedKeyRaw =
+            read_element(&self.km.container, self.entry, sha)?;
+        Ok(RevokedKey {
+            entry: self.entry,
+            key_id: raw.key_id,
+        })
+    }
+}
+
+/// A revocation of a previously-allowed key.
+pub struct RevokedKey<'a, 'km> {
+    entry: TocEntry<'a, 'km, Km>,
+    key_id: u32,
+}
+
+impl<'a, 'km> RevokedKey<'a, 'km> {
+    /// Returns the `Toc` entry defining this element.
+    pub fn entry(&self) -> TocEntry<'a, 'km, Km> {
+        self.entry
+    }
+
+    /// Returns the ID of the revoked key.
+    pub fn key_id(&self) -> u32 {
+        self.key_id
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::crypto::ring;
+    use crate::crypto::sha256::Builder as _;
+    use crate::crypto::sig;
+    use crate::crypto::sig::Sign as _;
+    use crate::crypto::sig::Verify as _;
+    use crate::crypto::testdata;
+    use crate::hardware::fake;
+    use crate::hardware::flash::Ram;
+    use crate::hardware::flash::RamMut;
+    use crate::manifest::manager::Manager;
+    use crate::manifest::manager::Slot;
+    use crate::manifest::owned;
+    use crate::manifest::pfm::Pfm;
+    use crate::manifest::Metadata;
+    use crate::mem::BumpArena;
+    use crate::mem::OutOfMemory;
+
+    fn digest(key: &[u8]) -> sha256::Digest {
+        let sha = ring::sha256::Builder::new();
+        let mut digest = [0; 32];
+        sha.hash_contiguous(key, &mut digest).unwrap();
+        digest
+    }
+
+    fn allowed_key(
+        key_id: u32,
+        usage: KeyUsage,
+        digest: sha256::Digest,
+    ) -> owned::Node<owned::km::Element> {
+        owned::Node {
+            element: owned::km::Element::AllowedKey {
+                key_id,
+                usage: usage.bits(),
+                digest,
+            },
+            hashed: true,
+            children: vec![],
+        }
+    }
+
+    fn revoked_key(key_id: u32) -> owned::Node<owned::km::Element> {
+        owned::Node {
+            element: owned::km::Element::RevokedKey {
+                revoked_key_id: key_id,
+            },
+            hashed: true,
+            children: vec![],
+        }
+    }
+
+    fn signed_km(
+        version_id: u32,
+        elements: Vec<owned::Node<owned::km::Element>>,
+    ) -> Vec<u8> {
+        let sha = ring::sha256::Builder::new();
+        let (_, mut root) = testdata::rsa();
+        let km = owned::Km {
+            metadata: Metadata { version_id },
+            elements,
+        };
+        km.sign(0x00, &sha, &mut root).unwrap()
+    }
+
+    #[test]
+    fn authorize() {
+        let sha = ring::sha256::Builder::new();
+        let (mut root, _) = testdata::rsa();
+
+        let bytes = Ram(signed_km(
+            1,
+            vec![
+                allowed_key(1, KeyUsage::MANIFEST, digest(b"old pfm key")),
+                allowed_key(2, KeyUsage::all(), digest(b"pfm key")),
+                allowed_key(3, KeyUsage::FIRMWARE, digest(b"fw key")),
+                // A key listed twice, only one of which is revoked.
+                allowed_key(4, KeyUsage::MANIFEST, digest(b"reissued key")),
+                allowed_key(5, KeyUsage::MANIFEST, digest(b"reissued key")),
+                revoked_key(1),
+                revoked_key(4),
+            ],
+        ));
+        let km = ParsedKm::new(
+            Container::parse_and_verify(
+                &bytes,
+                &sha,
+                &mut root,
+                &OutOfMemory,
+                &OutOfMemory,
+            )
+            .unwrap(),
+        );
+        assert_eq!(km.allowed_keys().count(), 5);
+        assert_eq!(km.revoked_keys().count(), 2);
+        assert!(km.is_revoked(1, &sha).unwrap());
+        assert!(!km.is_revoked(2, &sha).unwrap());
+
+        let id = km
+            .authorize(&digest(b"pfm key"), KeyUsage::MANIFEST, &sha)
+            .unwrap();
+        assert_eq!(id, 2);
+        let id = km
+            .authorize(&digest(b"fw key"), KeyUsage::FIRMWARE, &sha)
+            .unwrap();
+        assert_eq!(id, 3);
+        let id = km
+            .authorize(&digest(b"reissued key"), KeyUsage::MANIFEST, &sha)
+            .unwrap();
+        assert_eq!(id, 5);
+
+        assert!(matches!(
+            km.authorize(&digest(b"fw key"), KeyUsage::MANIFEST, &sha),
+            Err(Error::UnauthorizedKey)
+        ));
+        assert!(matches!(
+            km.authorize(&digest(b"some key"), KeyUsage::MANIFEST, &sha),
+            Err(Error::UnauthorizedKey)
+        ));
+        assert!(matches!(
+            km.authorize(&digest(b"old pfm key"), KeyUsage::MANIFEST, &sha),
+            Err(Error::RevokedKey { key_id: 1 })
+        ));
+    }
+
+    #[test]
+    fn verify_with_km() {
+        let sha = ring::sha256::Builder::new();
+        let (mut root, mut root_signer) = testdata::rsa();
+        let (mut ecdsa, mut signer) = testdata::ecdsa();
+        let ecdsa_digest = ecdsa.key_digest(signer.algo()).unwrap();
+
+        let parse_km = |bytes| {
+            ParsedKm::new(
+                Container::parse_and_verify(
+                    bytes,
+                    &sha,
+                    &mut testdata::rsa().0,
+                    &OutOfMemory,
+                    &OutOfMemory,
+                )
+                .unwrap(),
+            )
+        };
+        let good_km = Ram(signed_km(
+            1,
+            vec![allowed_key(2, KeyUsage::MANIFEST, ecdsa_digest)],
+        ));
+        let revoked_km = Ram(signed_km(
+            2,
+            vec![
+                allowed_key(2, KeyUsage::MANIFEST, ecdsa_digest),
+                revoked_key(2),
+            ],
+        ));
+        let good = parse_km(&good_km);
+        let revoked = parse_km(&revoked_km);
+
+        let pfm = owned::Pfm {
+            metadata: Metadata { version_id: 5 },
+            elements: vec![],
+        };
+        let ecdsa_pfm = Ram(pfm.sign(0x00, &sha, &mut signer).unwrap());
+        let rsa_pfm = Ram(pfm.sign(0x00, &sha, &mut root_signer).unwrap());
+
+        type Flash = Ram<Vec<u8>>;
+        let verify = |pfm, km, mut verifier: &mut dyn sig::Verify| {
+            Container::<'_, Pfm, Flash>::parse_and_verify_with_km(
+                pfm,
+                &sha,
+                &mut verifier,
+                km,
+                &OutOfMemory,
+                &OutOfMemory,
+            )
+        };
+
+        let container = verify(&ecdsa_pfm, &good, &mut ecdsa).unwrap();
+        assert_eq!(container.metadata().version_id, 5);
+
+        assert!(matches!(
+            verify(&ecdsa_pfm, &revoked, &mut ecdsa),
+            Err(Error::RevokedKey { key_id: 2 })
+        ));
+
+        // The signer is identified by the key that checks the signature, so
+        // a valid signature by a key the KM doesn't list is refused.
+        assert!(matches!(
+            verify(&rsa_pfm, &good, &mut (&mut ecdsa, &mut root)),
+            Err(Error::UnauthorizedKey)
+        ));
+    }
+
+    #[test]
+    fn rollback() {
+        let sha = ring::sha256::Builder::new();
+        let (mut root, _) = testdata::rsa();
+        let mut arena = vec![0; 1024];
+        let mut arena = BumpArena::new(&mut arena);
+
+        let slot = |version_id, elements| {
+            let mut bytes = signed_km(version_id, elements);
+            bytes.resize(1024, 0xff);
+            RamMut(bytes)
+        };
+        let key = || allowed_key(1, KeyUsage::MANIFEST, digest(b"pfm key"));
+
+        // The newer KM revokes the key, so going back to the older one must
+        // not be possible.
+        let mut manager = Manager::<Km, _, _>::new(
+            [slot(1, vec![key()]), slot(2, vec![key(), revoked_key(1)])],
+            fake::AntiRollback::new(0),
+            &sha,
+            &mut root,
+            &mut arena,
+        );
+        assert_eq!(manager.min_version(), 2);
+        assert_eq!(manager.active_slot(), Some(Slot::B));
+
+        *manager.pending_flash() = slot(3, vec![key(), revoked_key(1)]);
+        manager.activate(&sha, &mut root, &mut arena).unwrap();
+        assert_eq!(manager.min_version(), 3);
+
+        *manager.pending_flash() = slot(1, vec![key()]);
+        assert!(matches!(
+            manager.activate(&sha, &mut root, &mut arena),
+            Err(Error::Rollback { version_id: 1 })
+        ));
+    }
+}
@@ -0,0 +1,423 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Active/pending manifest management.
+//!
+//! A RoT stores each kind of manifest it understands in two flash "slots":
+//! the *active* slot holds the manifest currently in force, while the
+//! *pending* slot is where updates are written before being activated.
+//!
+//! The [`Manager`] type keeps track of which slot is which, and enforces an
+//! anti-rollback policy, backed by [`hardware::AntiRollback`], when deciding
+//! whether a manifest may become active.
+//!
+//! The anti-rollback minimum doubles as the record of which slot is active:
+//! activating a manifest raises the minimum to its `version_id`, and on
+//! startup the valid manifest whose `version_id` equals the minimum becomes
+//! active. Merely writing a newer manifest into the pending slot therefore
+//! does not make it active across a reboot. Because raising the minimum is
+//! the only persistent step of an activation, if power is lost
+//! mid-activation, the next [`Manager::new()`] call will select either the
+//! old manifest or the new one, never neither.
+
+use core::marker::PhantomData;
+
+use crate::crypto::sha256;
+use crate::crypto::sig;
+use crate::hardware;
+use crate::hardware::flash::Flash;
+use crate::manifest::Container;
+use crate::manifest::Error;
+use crate::manifest::Manifest;
+use crate::mem::Arena;
+
+/// A flash slot managed by a [`Manager`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Slot {
+    /// The first slot.
+    A,
+    /// The second slot.
+    B,
+}
+
+impl Slot {
+    /// Returns the slot that is not `self`.
+    pub fn other(self) -> Self {
+        match self {
+            Self::A => Self::B,
+            Self::B => Self::A,
+        }
+    }
+
+    fn index(self) -> usize {
+        match self {
+            Self::A => 0,
+            Self::B => 1,
+        }
+    }
+}
+
+/// A manager for a pair of flash slots containing manifests of type `M`.
+///
+/// See the [module documentation](index.html) for more information.
+pub struct Manager<M, Flash, AntiRollback> {
+    slots: [Flash; 2],
+    active: Option<(Slot, u32)>,
+    rollback: AntiRollback,
+    _ph: PhantomData<fn() -> M>,
+}
+
+impl<M, F, R> Manager<M, F, R>
+where
+    M: Manifest,
+    F: Flash,
+    R: hardware::AntiRollback,
+{
+    /// Creates a new `Manager` out of the given slots, selecting one of them
+    /// as the active slot.
+    ///
+    /// Both slots are parsed and verified, and those whose `version_id` is
+    /// below `rollback`'s minimum version are discarded. A slot whose
+    /// `version_id` equals the minimum version was the last one activated,
+    /// and becomes active again. Otherwise, such as on first boot, the one
+    /// with the greatest `version_id` becomes active and the minimum version
+    /// is raised to match. [`Slot::A`] is preferred on a tie. If neither slot
+    /// contains an acceptable manifest, there will be no active manifest.
+    ///
+    /// `arena` is used for scratch space, and is reset after every use.
+    pub fn new(
+        slots: [F; 2],
+        rollback: R,
+        sha: &impl sha256::Builder,
+        sig: &mut impl sig::Verify,
+        arena: &mut impl Arena,
+    ) -> Self {
+        let mut manager = Self {
+            slots,
+            active: None,
+            rollback,
+            _ph: PhantomData,
+        };
+
+        let min_version = manager.rollback.min_version();
+        let mut newest = None;
+        for &slot in &[Slot::A, Slot::B] {
+            let version = match manager.verify(slot, sha, sig, arena) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            if version == min_version && manager.active.is_none() {
+                manager.active = Some((slot, version));
+            }
+            match newest {
+                Some((_, newest)) if newest >= version => {}
+                _ => newest = Some((slot, version)),
+            }
+        }
+
+        if manager.active.is_none() {
+            if let Some((slot, version)) = newest {
+                // If this fails, the same selection is simply made again on
+                // the next boot.
+                let _ = manager.rollback.raise_min_version(version);
+                manager.active = Some((slot, version));
+            }
+        }
+
+        manager
+    }
+
+    /// Parses and verifies the manifest in `slot`, returning its version if
+    /// it is acceptable to the anti-rollback policy.
+    fn verify(
+        &self,
+        slot: Slot,
+        sha: &impl sha256::Builder,
+        sig: &mut impl sig::Verify,
+        arena: &mut impl Arena,
+    ) -> Result<u32, Error> {
+        let version = Container::<M, F>::parse_and_verify(
+            &self.slots[slot.index()],
+            sha,
+            sig,
+            &*arena,
+            &*arena,
+        )
+        .map(|c| c.metadata().version_id);
+        arena.reset();
+
+        let version = version?;
+        if version < self.rollback.min_version() {
+            return Err(Error::Rollback {
+                version_id: version,
+            });
+        }
+        Ok(version)
+    }
+
+    /// Returns the currently active slot, if there is one.
+    pub fn active_slot(&self) -> Option<Slot> {
+        self.active.map(|(slot, _)| slot)
+    }
+
+    /// Returns the slot that updates should be written to.
+    ///
+    /// This is the slot that is not active; if there is no active slot, it is
+    /// [`Slot::A`].
+    pub fn pending_slot(&self) -> Slot {
+        self.active_slot().map(Slot::other).unwrap_or(Slot::A)
+    }
+
+    /// Returns the smallest `version_id` that this `Manager` will accept.
+    pub fn min_version(&self) -> u32 {
+        self.rollback.min_version()
+    }
+
+    /// Parses and verifies the active manifest, if there is one.
+    ///
+    /// Note that this function performs a full signature check every time it
+    /// is called.
+    pub fn active<'a>(
+        &'a self,
+        sha: &impl sha256::Builder,
+        sig: &mut impl sig::Verify,
+        arena: &'a impl Arena,
+    ) -> Result<Option<Container<'a, M, F>>, Error> {
+        let slot = match self.active_slot() {
+            Some(slot) => slot,
+            None => return Ok(None),
+        };
+        Container::parse_and_verify(
+            &self.slots[slot.index()],
+            sha,
+            sig,
+            arena,
+            arena,
+        )
+        .map(Some)
+    }
+
+    /// Parses and verifies the contents of the pending slot.
+    ///
+    /// This is useful for validating a manifest before calling
+    /// [`Manager::activate()`].
+    pub fn pending<'a>(
+        &'a self,
+        sha: &impl sha256::Builder,
+        sig: &mut impl sig::Verify,
+        arena: &'a impl Arena,
+    ) -> Result<Container<'a, M, F>, Error> {
+        Container::parse_and_verify(
+            &self.slots[self.pending_slot().index()],
+            sha,
+            sig,
+            arena,
+            arena,
+        )
+    }
+
+    /// Returns the flash backing the pending slot, for writing an update into.
+    pub fn pending_flash(&mut self) -> &mut F {
+        &mut self.slots[self.pending_slot().index()]
+    }
+
+    /// Activates the manifest in the pending slot.
+    ///
+    /// The pending manifest must verify, must not be below the minimum
+    /// version, and must be strictly newer than the active manifest. On
+    /// success, the minimum version is raised to that of the newly active
+    /// manifest, and the previously active slot becomes the pending slot.
+    ///
+    /// `arena` is used for scratch space, and is reset after every use.
+    pub fn activate(
+        &mut self,
+        sha: &impl sha256::Builder,
+        sig: &mut impl sig::Verify,
+        arena: &mut impl Arena,
+    ) -> Result<(), Error> {
+        let pending = self.pending_slot();
+        let version = self.verify(pending, sha, sig, arena)?;
+        if let Some((_, active)) = self.active {
+            if version <= active {
+                return Err(Error::Rollback {
+                    version_id: version,
+                });
+            }
+        }
+
+        self.rollback.raise_min_version(version)?;
+        self.active = Some((pending, version));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::crypto::ring;
+    use crate::crypto::testdata;
+    use crate::hardware::fake;
+    use crate::hardware::flash::RamMut;
+    use crate::manifest::owned;
+    use crate::manifest::pfm::Pfm;
+    use crate::manifest::Metadata;
+    use crate::mem::BumpArena;
+
+    fn signed_pfm(version_id: u32) -> Vec<u8> {
+        let sha = ring::sha256::Builder::new();
+        let (_, mut signer) = testdata::rsa();
+        let pfm = owned::Pfm {
+            metadata: Metadata { version_id },
+            elements: vec![],
+        };
+        let mut bytes = pfm.sign(0x0, &sha, &mut signer).unwrap();
+        bytes.resize(1024, 0xff);
+        bytes
+    }
+
+    fn new_manager(
+        a: Vec<u8>,
+        b: Vec<u8>,
+        min_version: u32,
+    ) -> Manager<Pfm, RamMut<Vec<u8>>, fake::AntiRollback> {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, _) = testdata::rsa();
+        let mut arena = [0; 512];
+        let mut arena = BumpArena::new(&mut arena);
+        Manager::new(
+            [RamMut(a), RamMut(b)],
+            fake::AntiRollback::new(min_version),
+            &sha,
+            &mut rsa,
+            &mut arena,
+        )
+    }
+
+    #[test]
+    fn select_newest() {
+        let manager = new_manager(signed_pfm(1), signed_pfm(2), 0);
+        assert_eq!(manager.active_slot(), Some(Slot::B));
+        assert_eq!(manager.pending_slot(), Slot::A);
+
+        let manager = new_manager(signed_pfm(5), signed_pfm(2), 0);
+        assert_eq!(manager.active_slot(), Some(Slot::A));
+
+        let manager = new_manager(signed_pfm(3), signed_pfm(3), 0);
+        assert_eq!(manager.active_slot(), Some(Slot::A));
+    }
+
+    #[test]
+    fn select_skips_invalid() {
+        let mut corrupt = signed_pfm(9);
+        corrupt[20] ^= 0xff;
+        let manager = new_manager(signed_pfm(1), corrupt, 0);
+        assert_eq!(manager.active_slot(), Some(Slot::A));
+
+        let manager = new_manager(vec![0xff; 1024], vec![0xff; 1024], 0);
+        assert_eq!(manager.active_slot(), None);
+        assert_eq!(manager.pending_slot(), Slot::A);
+    }
+
+    #[test]
+    fn select_skips_rollback() {
+        let manager = new_manager(signed_pfm(1), signed_pfm(2), 3);
+        assert_eq!(manager.active_slot(), None);
+
+        let manager = new_manager(signed_pfm(4), signed_pfm(2), 3);
+        assert_eq!(manager.active_slot(), Some(Slot::A));
+        assert_eq!(manager.min_version(), 4);
+    }
+
+    #[test]
+    fn select_last_activated() {
+        // The older manifest is the one the minimum version points at.
+        let manager = new_manager(signed_pfm(2), signed_pfm(5), 2);
+        assert_eq!(manager.active_slot(), Some(Slot::A));
+        assert_eq!(manager.min_version(), 2);
+
+        let manager = new_manager(signed_pfm(5), signed_pfm(5), 5);
+        assert_eq!(manager.active_slot(), Some(Slot::A));
+    }
+
+    #[test]
+    fn pending_survives_reboot() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, _) = testdata::rsa();
+        let mut arena = [0; 512];
+        let mut arena = BumpArena::new(&mut arena);
+
+        let mut manager = new_manager(signed_pfm(1), vec![0xff; 1024], 0);
+        assert_eq!(manager.active_slot(), Some(Slot::A));
+        assert_eq!(manager.min_version(), 1);
+
+        // Write a newer manifest without activating it, then "reboot".
+        manager.pending_flash().0.copy_from_slice(&signed_pfm(7));
+        let Manager {
+            slots: [a, b],
+            rollback,
+            ..
+        } = manager;
+        let mut manager: Manager<Pfm, _, _> =
+            Manager::new([a, b], rollback, &sha, &mut rsa, &mut arena);
+        assert_eq!(manager.active_slot(), Some(Slot::A));
+        assert_eq!(manager.min_version(), 1);
+
+        // Once activated, the new manifest is selected after a reboot.
+        manager.activate(&sha, &mut rsa, &mut arena).unwrap();
+        let Manager {
+            slots: [a, b],
+            rollback,
+            ..
+        } = manager;
+        let manager: Manager<Pfm, _, _> =
+            Manager::new([a, b], rollback, &sha, &mut rsa, &mut arena);
+        assert_eq!(manager.active_slot(), Some(Slot::B));
+        assert_eq!(manager.min_version(), 7);
+    }
+
+    #[test]
+    fn activate() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, _) = testdata::rsa();
+        let mut arena = [0; 512];
+        let mut arena = BumpArena::new(&mut arena);
+
+        let mut manager = new_manager(signed_pfm(1), vec![0xff; 1024], 0);
+        assert_eq!(manager.active_slot(), Some(Slot::A));
+
+        // Garbage in the pending slot cannot be activated.
+        assert!(manager.activate(&sha, &mut rsa, &mut arena).is_err());
+        assert_eq!(manager.active_slot(), Some(Slot::A));
+
+        // Neither can a manifest that isn't strictly newer.
+        manager.pending_flash().0.copy_from_slice(&signed_pfm(1));
+        assert!(matches!(
+            manager.activate(&sha, &mut rsa, &mut arena),
+            Err(Error::Rollback { version_id: 1 })
+        ));
+
+        manager.pending_flash().0.copy_from_slice(&signed_pfm(7));
+        let pending = manager.pending(&sha, &mut rsa, &arena).unwrap();
+        assert_eq!(pending.metadata().version_id, 7);
+        arena.reset();
+
+        manager.activate(&sha, &mut rsa, &mut arena).unwrap();
+        assert_eq!(manager.active_slot(), Some(Slot::B));
+        assert_eq!(manager.pending_slot(), Slot::A);
+        assert_eq!(manager.min_version(), 7);
+
+        let active = manager.active(&sha, &mut rsa, &arena).unwrap().unwrap();
+        assert_eq!(active.metadata().version_id, 7);
+        arena.reset();
+
+        // The old manifest is now below the minimum version, so it cannot be
+        // reactivated.
+        manager.pending_flash().0.copy_from_slice(&signed_pfm(1));
+        assert!(matches!(
+            manager.activate(&sha, &mut rsa, &mut arena),
+            Err(Error::Rollback { version_id: 1 })
+        ));
+    }
+}
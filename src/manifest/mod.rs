@@ -73,10 +73,12 @@
 //! for use by tooling. The [`owned::Container`] type is the relevant entry
 //! point.
 
-use crate::crypto::rsa;
 use crate::crypto::sha256;
+use crate::crypto::sig;
+use crate::hardware::filter;
 use crate::hardware::flash;
 use crate::io;
+use crate::mem::Arena;
 use crate::mem::OutOfMemory;
 use crate::protocol::wire::WireEnum;
 
@@ -90,8 +92,12 @@ pub use container::Metadata;
 pub use container::Toc;
 pub use container::TocEntry;
 
+pub mod host;
+pub mod km;
+pub mod manager;
 #[cfg(feature = "std")]
 pub mod owned;
+pub mod pcd;
 pub mod pfm;
 
 #[cfg(test)]
@@ -107,6 +113,18 @@ wire_enum! {
         ///
         /// ["Platform Firmware Manifest"]: pfm/index.html
         Pfm = 0x706d,
+
+        /// A ["Platform Configuration Data"] manifest, which describes the
+        /// topology of the platform a RoT is responsible for.
+        ///
+        /// ["Platform Configuration Data"]: pcd/index.html
+        Pcd = 0x1029,
+
+        /// A ["Key Manifest"], which describes which keys may sign on behalf
+        /// of a RoT's root key.
+        ///
+        /// ["Key Manifest"]: km/index.html
+        Km = 0x6b6d,
     }
 }
 
@@ -153,6 +171,15 @@ pub enum Error {
         toc_index: usize,
     },
 
+    /// Indicates that a TOC entry's format version is older than the oldest
+    /// one Manticore understands for its element type.
+    ///
+    /// See [`Manifest::min_version()`].
+    OldFormatVersion {
+        /// The index of the bad entry.
+        toc_index: usize,
+    },
+
     /// Indicates that parsing of a particular element failed because it was
     /// below the minimum length.
     TooShort {
@@ -186,6 +213,73 @@ pub enum Error {
 
     /// Indicates that a signature operation failed for some reason.
     SignatureFailure,
+
+    /// Indicates that a manifest was signed with an algorithm that the
+    /// signature engine being used does not support.
+    UnsupportedSignature,
+
+    /// Indicates that none of the versions allowed by a particular element
+    /// matched the data the manifest guards.
+    NoMatchingVersion {
+        /// The index of the entry that failed to match.
+        toc_index: usize,
+    },
+
+    /// Indicates that more than one of the versions allowed by a particular
+    /// element matched the data the manifest guards, and none of them was a
+    /// longer match than the others.
+    AmbiguousVersion {
+        /// The index of the entry whose versions matched.
+        toc_index: usize,
+    },
+
+    /// Indicates that data guarded by a particular element did not hash to
+    /// the value the manifest expects.
+    BadGuardedHash {
+        /// The index of the entry whose guarded data is bad.
+        toc_index: usize,
+    },
+
+    /// Indicates that the firmware counts recorded by a PFM's flash devices
+    /// did not add up to its `AllowableFw` elements.
+    BadFirmwareCount {
+        /// The index of the offending flash device.
+        toc_index: usize,
+    },
+
+    /// Indicates that validation was given a different number of guarded
+    /// devices than the manifest describes.
+    WrongDeviceCount,
+
+    /// Indicates that enforcing a policy required restoring data from a
+    /// backup, but none was available.
+    NoBackup,
+
+    /// Indicates that a read-write region had a failure policy that Manticore
+    /// does not recognize.
+    UnknownFailurePolicy,
+
+    /// Indicates that a manifest was refused because loading it would roll
+    /// back to an older version.
+    Rollback {
+        /// The version of the refused manifest.
+        version_id: u32,
+    },
+
+    /// Indicates that a manifest's policy could not be applied to a SPI
+    /// filter.
+    Filter(filter::Error),
+
+    /// Indicates that a key was not authorized by a key manifest to perform
+    /// the requested operation.
+    UnauthorizedKey,
+
+    /// Indicates that a key was authorized by a key manifest, but was
+    /// subsequently revoked.
+    RevokedKey {
+        /// The ID of the revoked key.
+        key_id: u32,
+    },
 }
 
 impl From<io::Error> for Error {
@@ -200,15 +294,33 @@ impl From<flash::Error> for Error {
     }
 }
 
+impl From<filter::Error> for Error {
+    fn from(e: filter::Error) -> Self {
+        Self::Filter(e)
+    }
+}
+
+impl From<flash::HashError> for Error {
+    fn from(e: flash::HashError) -> Self {
+        match e {
+            flash::HashError::Flash(e) => Self::Flash(e),
+            flash::HashError::HashingError(e) => Self::HashingError(e),
+        }
+    }
+}
+
 impl From<OutOfMemory> for Error {
     fn from(_: OutOfMemory) -> Self {
         Self::OutOfMemory
     }
 }
 
-impl<E> From<rsa::Error<E>> for Error {
-    fn from(_: rsa::Error<E>) -> Self {
-        Self::SignatureFailure
+impl From<sig::Error> for Error {
+    fn from(e: sig::Error) -> Self {
+        match e {
+            sig::Error::Unsupported => Self::UnsupportedSignature,
+            _ => Self::SignatureFailure,
+        }
     }
 }
 
@@ -248,6 +360,7 @@ pub trait Manifest: Sized {
 /// Some manifests may choose to skip parts of the validation process on
 /// startup; this enum is used to indicate when validation is occurring to
 /// [`Parse::validate()`].
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 pub enum ValidationTime {
     /// Indicates "startup", i.e., a manifest already present in device flash
     /// is being parsed. Some integrations may choose to skip validation at
@@ -285,11 +398,14 @@ pub trait Parse<'f, Flash, Provenance>: Manifest {
     ) -> Result<(), Error>;
 
     /// The type of data this manifest guards.
-    type Guarded;
+    type Guarded: ?Sized;
     /// Validates that `manifest` is "valid"; that is, whatever state of
     /// the system this manifest protects is consistent with the manifest's
     /// expectation.
     ///
+    /// `sha` is used for any hashing the validation process requires, and
+    /// `arena` for any book-keeping of elements read out of `manifest`.
+    ///
     /// Some manifests may not have anything interesting to do here; in that
     /// case `Self::Guarded` should be `()` and this function should do
     /// nothing.
@@ -297,6 +413,8 @@ pub trait Parse<'f, Flash, Provenance>: Manifest {
         manifest: &Self::Parsed,
         when: ValidationTime,
         args: &Self::Guarded,
+        sha: &impl sha256::Builder,
+        arena: &'f impl Arena,
     ) -> Result<(), Error>;
 }
 
@@ -0,0 +1,674 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Structural manifest diffing.
+//!
+//! Comparing two encoded manifests byte-by-byte is not very illuminating,
+//! since a small change, like adding a region, shifts every element after it.
+//! The functions in this module instead compare owned manifests element by
+//! element, matching up elements by what they describe (such as a firmware
+//! ID or version string) rather than by their position.
+
+use std::fmt;
+
+use crate::hardware::flash::Region;
+use crate::manifest::owned;
+use crate::manifest::owned::pfm;
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// A firmware version, identified by its firmware ID and version string.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Version {
+    /// The ID of the firmware this version belongs to.
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::serde::se_bytestring")
+    )]
+    pub firmware_id: Vec<u8>,
+    /// The version string.
+    #[cfg_attr(
+        feature = "serde",
+        serde(serialize_with = "crate::serde::se_bytestring")
+    )]
+    pub version_str: Vec<u8>,
+}
+
+/// A single difference between two manifests.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "change", rename_all = "snake_case"))]
+pub enum Change {
+    /// The manifests' version IDs differ.
+    VersionId {
+        /// The old version ID.
+        old: u32,
+        /// The new version ID.
+        new: u32,
+    },
+
+    /// A firmware ID was added.
+    FirmwareAdded {
+        /// The added firmware ID.
+        #[cfg_attr(
+            feature = "serde",
+            serde(serialize_with = "crate::serde::se_bytestring")
+        )]
+        firmware_id: Vec<u8>,
+    },
+
+    /// A firmware ID was removed.
+    FirmwareRemoved {
+        /// The removed firmware ID.
+        #[cfg_attr(
+            feature = "serde",
+            serde(serialize_with = "crate::serde::se_bytestring")
+        )]
+        firmware_id: Vec<u8>,
+    },
+
+    /// A version was added to a firmware ID present in both manifests.
+    VersionAdded {
+        /// The added version.
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        version: Version,
+    },
+
+    /// A version was removed from a firmware ID present in both manifests.
+    VersionRemoved {
+        /// The removed version.
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        version: Version,
+    },
+
+    /// A version's version address changed.
+    VersionAddrChanged {
+        /// The changed version.
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        version: Version,
+        /// The old address.
+        old: u32,
+        /// The new address.
+        new: u32,
+    },
+
+    /// A read-write region was added to a version.
+    RwAdded {
+        /// The changed version.
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        version: Version,
+        /// The added region.
+        rw: pfm::Rw,
+    },
+
+    /// A read-write region was removed from a version.
+    RwRemoved {
+        /// The changed version.
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        version: Version,
+        /// The removed region.
+        rw: pfm::Rw,
+    },
+
+    /// A read-write region's flags changed.
+    RwChanged {
+        /// The changed version.
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        version: Version,
+        /// The old region.
+        old: pfm::Rw,
+        /// The new region.
+        new: pfm::Rw,
+    },
+
+    /// An image was added to a version.
+    ImageAdded {
+        /// The changed version.
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        version: Version,
+        /// The added image.
+        image: pfm::Image,
+    },
+
+    /// An image was removed from a version.
+    ImageRemoved {
+        /// The changed version.
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        version: Version,
+        /// The removed image.
+        image: pfm::Image,
+    },
+
+    /// An image's hash, regions, or flags changed.
+    ///
+    /// Images are considered "the same" if they cover the same regions or
+    /// have the same hash.
+    ImageChanged {
+        /// The changed version.
+        #[cfg_attr(feature = "serde", serde(flatten))]
+        version: Version,
+        /// The old image.
+        old: pfm::Image,
+        /// The new image.
+        new: pfm::Image,
+    },
+}
+
+struct Str<'a>(&'a [u8]);
+impl fmt::Display for Str<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", String::from_utf8_lossy(self.0))
+    }
+}
+
+struct Hex<'a>(&'a [u8]);
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in self.0 {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+struct Regions<'a>(&'a [Region]);
+impl fmt::Display for Regions<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, r) in self.0.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "[{:#x}, {:#x})", r.offset, r.end())?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "firmware {} version {}",
+            Str(&self.firmware_id),
+            Str(&self.version_str)
+        )
+    }
+}
+
+fn fmt_image(image: &pfm::Image, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(
+        f,
+        "image {} (flags {:#b}, {:?} {})",
+        Regions(&image.regions),
+        image.flags,
+        image.hash_type,
+        Hex(&image.hash)
+    )
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::VersionId { old, new } => {
+                write!(f, "~ version id: {} -> {}", old, new)
+            }
+            Self::FirmwareAdded { firmware_id } => {
+                write!(f, "+ firmware {}", Str(firmware_id))
+            }
+            Self::FirmwareRemoved { firmware_id } => {
+                write!(f, "- firmware {}", Str(firmware_id))
+            }
+            Self::VersionAdded { version } => write!(f, "+ {}", version),
+            Self::VersionRemoved { version } => write!(f, "- {}", version),
+            Self::VersionAddrChanged { version, old, new } => write!(
+                f,
+                "~ {}: version address {:#x} -> {:#x}",
+                version, old, new
+            ),
+            Self::RwAdded { version, rw } => write!(
+                f,
+                "+ {}: rw region {} (flags {:#b})",
+                version,
+                Regions(&[rw.region]),
+                rw.flags
+            ),
+            Self::RwRemoved { version, rw } => write!(
+                f,
+                "- {}: rw region {} (flags {:#b})",
+                version,
+                Regions(&[rw.region]),
+                rw.flags
+            ),
+            Self::RwChanged { version, old, new } => write!(
+                f,
+                "~ {}: rw region {}: flags {:#b} -> {:#b}",
+                version,
+                Regions(&[old.region]),
+                old.flags,
+                new.flags
+            ),
+            Self::ImageAdded { version, image } => {
+                write!(f, "+ {}: ", version)?;
+                fmt_image(image, f)
+            }
+            Self::ImageRemoved { version, image } => {
+                write!(f, "- {}: ", version)?;
+                fmt_image(image, f)
+            }
+            Self::ImageChanged { version, old, new } => {
+                write!(f, "~ {}: ", version)?;
+                fmt_image(old, f)?;
+                write!(f, " -> ")?;
+                fmt_image(new, f)
+            }
+        }
+    }
+}
+
+/// Removes pairs of elements for which `eq` holds from `old` and `new`,
+/// returning them.
+///
+/// Each element is paired at most once, with the first unpaired element it
+/// matches.
+fn pair_off<'a, T>(
+    old: &mut Vec<&'a T>,
+    new: &mut Vec<&'a T>,
+    eq: impl Fn(&T, &T) -> bool,
+) -> Vec<(&'a T, &'a T)> {
+    let mut pairs = Vec::new();
+    let mut i = 0;
+    while i < old.len() {
+        match new.iter().position(|n| eq(old[i], n)) {
+            Some(j) => pairs.push((old.remove(i), new.remove(j))),
+            None => i += 1,
+        }
+    }
+    pairs
+}
+
+/// A `FwVersion` element, borrowed out of a PFM.
+struct FwVersion<'a> {
+    version_str: &'a [u8],
+    version_addr: u32,
+    rw_regions: &'a [pfm::Rw],
+    image_regions: &'a [pfm::Image],
+}
+
+/// An `AllowableFw` element, borrowed out of a PFM.
+struct Firmware<'a> {
+    firmware_id: &'a [u8],
+    versions: Vec<FwVersion<'a>>,
+}
+
+/// Collects every `AllowableFw` element in `nodes`, at any depth.
+fn collect_firmware<'a>(
+    nodes: &'a [owned::Node<pfm::Element>],
+    out: &mut Vec<Firmware<'a>>,
+) {
+    for node in nodes {
+        if let pfm::Element::AllowableFw { firmware_id, .. } = &node.element {
+            let versions = node
+                .children
+                .iter()
+                .filter_map(|child| match &child.element {
+                    pfm::Element::FwVersion {
+                        version_addr,
+                        version_str,
+                        rw_regions,
+                        image_regions,
+                        ..
+                    } => Some(FwVersion {
+                        version_str,
+                        version_addr: *version_addr,
+                        rw_regions,
+                        image_regions,
+                    }),
+                    _ => None,
+                })
+                .collect();
+            out.push(Firmware {
+                firmware_id,
+                versions,
+            });
+        } else {
+            collect_firmware(&node.children, out);
+        }
+    }
+}
+
+/// Compares two versions of the same firmware.
+fn diff_version(
+    old: &FwVersion,
+    new: &FwVersion,
+    version: Version,
+    changes: &mut Vec<Change>,
+) {
+    if old.version_addr != new.version_addr {
+        changes.push(Change::VersionAddrChanged {
+            version: version.clone(),
+            old: old.version_addr,
+            new: new.version_addr,
+        });
+    }
+
+    let mut old_rws = old.rw_regions.iter().collect::<Vec<_>>();
+    let mut new_rws = new.rw_regions.iter().collect::<Vec<_>>();
+    pair_off(&mut old_rws, &mut new_rws, |a, b| a == b);
+    for (old, new) in
+        pair_off(&mut old_rws, &mut new_rws, |a, b| a.region == b.region)
+    {
+        changes.push(Change::RwChanged {
+            version: version.clone(),
+            old: old.clone(),
+            new: new.clone(),
+        });
+    }
+    for rw in old_rws {
+        changes.push(Change::RwRemoved {
+            version: version.clone(),
+            rw: rw.clone(),
+        });
+    }
+    for rw in new_rws {
+        changes.push(Change::RwAdded {
+            version: version.clone(),
+            rw: rw.clone(),
+        });
+    }
+
+    let mut old_images = old.image_regions.iter().collect::<Vec<_>>();
+    let mut new_images = new.image_regions.iter().collect::<Vec<_>>();
+    pair_off(&mut old_images, &mut new_images, |a, b| a == b);
+    let mut changed = pair_off(&mut old_images, &mut new_images, |a, b| {
+        a.regions == b.regions
+    });
+    changed.extend(pair_off(&mut old_images, &mut new_images, |a, b| {
+        a.hash_type == b.hash_type && a.hash == b.hash
+    }));
+    for (old, new) in changed {
+        changes.push(Change::ImageChanged {
+            version: version.clone(),
+            old: old.clone(),
+            new: new.clone(),
+        });
+    }
+    for image in old_images {
+        changes.push(Change::ImageRemoved {
+            version: version.clone(),
+            image: image.clone(),
+        });
+    }
+    for image in new_images {
+        changes.push(Change::ImageAdded {
+            version: version.clone(),
+            image: image.clone(),
+        });
+    }
+}
+
+/// Computes the differences between two PFMs.
+///
+/// Firmware is matched up by firmware ID, and versions by version string.
+/// Within a version, regions are matched up by their location, and images by
+/// either their location or their hash.
+pub fn pfm(old: &owned::Pfm, new: &owned::Pfm) -> Vec<Change> {
+    let mut changes = Vec::new();
+    if old.metadata.version_id != new.metadata.version_id {
+        changes.push(Change::VersionId {
+            old: old.metadata.version_id,
+            new: new.metadata.version_id,
+        });
+    }
+
+    let mut old_fws = Vec::new();
+    collect_firmware(&old.elements, &mut old_fws);
+    let mut new_fws = Vec::new();
+    collect_firmware(&new.elements, &mut new_fws);
+
+    let mut old_fws = old_fws.iter().collect::<Vec<_>>();
+    let mut new_fws = new_fws.iter().collect::<Vec<_>>();
+    for (old_fw, new_fw) in pair_off(&mut old_fws, &mut new_fws, |a, b| {
+        a.firmware_id == b.firmware_id
+    }) {
+        let mut old_vs = old_fw.versions.iter().collect::<Vec<_>>();
+        let mut new_vs = new_fw.versions.iter().collect::<Vec<_>>();
+        for (old_v, new_v) in pair_off(&mut old_vs, &mut new_vs, |a, b| {
+            a.version_str == b.version_str
+        }) {
+            let version = Version {
+                firmware_id: old_fw.firmware_id.to_vec(),
+                version_str: old_v.version_str.to_vec(),
+            };
+            diff_version(old_v, new_v, version, &mut changes);
+        }
+
+        for v in old_vs {
+            changes.push(Change::VersionRemoved {
+                version: Version {
+                    firmware_id: old_fw.firmware_id.to_vec(),
+                    version_str: v.version_str.to_vec(),
+                },
+            });
+        }
+        for v in new_vs {
+            changes.push(Change::VersionAdded {
+                version: Version {
+                    firmware_id: new_fw.firmware_id.to_vec(),
+                    version_str: v.version_str.to_vec(),
+                },
+            });
+        }
+    }
+
+    for fw in old_fws {
+        changes.push(Change::FirmwareRemoved {
+            firmware_id: fw.firmware_id.to_vec(),
+        });
+    }
+    for fw in new_fws {
+        changes.push(Change::FirmwareAdded {
+            firmware_id: fw.firmware_id.to_vec(),
+        });
+    }
+    changes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::manifest::HashType;
+    use crate::manifest::Metadata;
+
+    use pretty_assertions::assert_eq;
+
+    fn image(hash: u8, regions: Vec<Region>) -> pfm::Image {
+        pfm::Image {
+            flags: 0,
+            hash_type: HashType::Sha256,
+            hash: vec![hash; 32],
+            regions,
+        }
+    }
+
+    fn rw(flags: u8, region: Region) -> pfm::Rw {
+        pfm::Rw { flags, region }
+    }
+
+    fn version(
+        version_str: &[u8],
+        version_addr: u32,
+        rw_regions: Vec<pfm::Rw>,
+        image_regions: Vec<pfm::Image>,
+    ) -> owned::Node<pfm::Element> {
+        owned::Node {
+            element: pfm::Element::FwVersion {
+                version_addr,
+                version_str: version_str.to_vec(),
+                rw_regions,
+                image_regions,
+                format_version: 0,
+            },
+            hashed: true,
+            children: vec![],
+        }
+    }
+
+    fn pfm(
+        version_id: u32,
+        fws: Vec<(&[u8], Vec<owned::Node<pfm::Element>>)>,
+    ) -> owned::Pfm {
+        owned::Pfm {
+            metadata: Metadata { version_id },
+            elements: fws
+                .into_iter()
+                .map(|(id, children)| owned::Node {
+                    element: pfm::Element::AllowableFw {
+                        version_count: children.len() as u8,
+                        firmware_id: id.to_vec(),
+                        flags: 0,
+                    },
+                    hashed: true,
+                    children,
+                })
+                .collect(),
+        }
+    }
+
+    fn v(firmware_id: &[u8], version_str: &[u8]) -> Version {
+        Version {
+            firmware_id: firmware_id.to_vec(),
+            version_str: version_str.to_vec(),
+        }
+    }
+
+    #[test]
+    fn identical() {
+        let a = pfm(
+            1,
+            vec![(
+                b"fw",
+                vec![version(
+                    b"v1",
+                    0,
+                    vec![rw(0, Region::new(0, 0x1000))],
+                    vec![image(1, vec![Region::new(0x1000, 0x1000)])],
+                )],
+            )],
+        );
+        assert_eq!(super::pfm(&a, &a.clone()), vec![]);
+    }
+
+    #[test]
+    fn changes() {
+        let old = pfm(
+            1,
+            vec![
+                (
+                    b"fw",
+                    vec![
+                        version(
+                            b"v1",
+                            0x100,
+                            vec![
+                                rw(0, Region::new(0x0000, 0x1000)),
+                                rw(0, Region::new(0x1000, 0x1000)),
+                            ],
+                            vec![
+                                image(1, vec![Region::new(0x2000, 0x1000)]),
+                                image(2, vec![Region::new(0x3000, 0x1000)]),
+                                image(3, vec![Region::new(0x4000, 0x1000)]),
+                            ],
+                        ),
+                        version(b"v2", 0, vec![], vec![]),
+                    ],
+                ),
+                (b"old", vec![]),
+            ],
+        );
+        let new = pfm(
+            2,
+            vec![
+                (
+                    b"fw",
+                    vec![
+                        version(b"v3", 0, vec![], vec![]),
+                        version(
+                            b"v1",
+                            0x200,
+                            vec![
+                                rw(1, Region::new(0x0000, 0x1000)),
+                                rw(0, Region::new(0x5000, 0x1000)),
+                            ],
+                            vec![
+                                image(4, vec![Region::new(0x2000, 0x1000)]),
+                                image(2, vec![Region::new(0x6000, 0x1000)]),
+                            ],
+                        ),
+                    ],
+                ),
+                (b"new", vec![]),
+            ],
+        );
+
+        let changes = super::pfm(&old, &new);
+        assert_eq!(
+            changes,
+            vec![
+                Change::VersionId { old: 1, new: 2 },
+                Change::VersionAddrChanged {
+                    version: v(b"fw", b"v1"),
+                    old: 0x100,
+                    new: 0x200,
+                },
+                Change::RwChanged {
+                    version: v(b"fw", b"v1"),
+                    old: rw(0, Region::new(0x0000, 0x1000)),
+                    new: rw(1, Region::new(0x0000, 0x1000)),
+                },
+                Change::RwRemoved {
+                    version: v(b"fw", b"v1"),
+                    rw: rw(0, Region::new(0x1000, 0x1000)),
+                },
+                Change::RwAdded {
+                    version: v(b"fw", b"v1"),
+                    rw: rw(0, Region::new(0x5000, 0x1000)),
+                },
+                Change::ImageChanged {
+                    version: v(b"fw", b"v1"),
+                    old: image(1, vec![Region::new(0x2000, 0x1000)]),
+                    new: image(4, vec![Region::new(0x2000, 0x1000)]),
+                },
+                Change::ImageChanged {
+                    version: v(b"fw", b"v1"),
+                    old: image(2, vec![Region::new(0x3000, 0x1000)]),
+                    new: image(2, vec![Region::new(0x6000, 0x1000)]),
+                },
+                Change::ImageRemoved {
+                    version: v(b"fw", b"v1"),
+                    image: image(3, vec![Region::new(0x4000, 0x1000)]),
+                },
+                Change::VersionRemoved {
+                    version: v(b"fw", b"v2"),
+                },
+                Change::VersionAdded {
+                    version: v(b"fw", b"v3"),
+                },
+                Change::FirmwareRemoved {
+                    firmware_id: b"old".to_vec(),
+                },
+                Change::FirmwareAdded {
+                    firmware_id: b"new".to_vec(),
+                },
+            ]
+        );
+
+        assert_eq!(
+            changes[2].to_string(),
+            r#"~ firmware "fw" version "v1": rw region [0x0, 0x1000): flags 0b0 -> 0b1"#
+        );
+    }
+}
@@ -0,0 +1,238 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! KM element structures.
+//!
+//! See [`owned::Km`](../type.Km.html).
+
+use crate::crypto::ring::sha256::Builder as RingSha;
+use crate::crypto::sha256;
+use crate::hardware::flash::Flash;
+use crate::manifest;
+use crate::manifest::km::ElementType;
+use crate::manifest::owned;
+use crate::manifest::owned::EncodingError;
+use crate::manifest::provenance;
+use crate::manifest::Error;
+use crate::manifest::ManifestType;
+use crate::protocol::wire::WireEnum as _;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// An owned KM element.
+///
+/// Note that variants are listed from most to least specific, since
+/// deserialization picks the first variant whose fields are all present.
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+#[allow(missing_docs)]
+pub enum Element {
+    AllowedKey {
+        key_id: u32,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_bin",
+            )
+        )]
+        usage: u8,
+        digest: sha256::Digest,
+    },
+    RevokedKey {
+        revoked_key_id: u32,
+    },
+    Raw(owned::Raw),
+}
+
+impl owned::Element for Element {
+    type ElementType = ElementType;
+    const TYPE: ManifestType = ManifestType::Km;
+
+    fn element_type(&self) -> u8 {
+        match self {
+            Self::AllowedKey { .. } => ElementType::AllowedKey.to_wire_value(),
+            Self::RevokedKey { .. } => ElementType::RevokedKey.to_wire_value(),
+            Self::Raw(raw) => raw.element_type,
+        }
+    }
+
+    fn format_version(&self) -> u8 {
+        match self {
+            Self::Raw(raw) => raw.format_version,
+            _ => 0,
+        }
+    }
+
+    fn to_bytes(&self, padding_byte: u8) -> Result<Vec<u8>, EncodingError> {
+        match self {
+            Self::AllowedKey {
+                key_id,
+                usage,
+                digest,
+            } => {
+                let mut bytes = key_id.to_le_bytes().to_vec();
+                bytes.extend_from_slice(&[
+                    *usage,
+                    padding_byte,
+                    padding_byte,
+                    padding_byte,
+                ]);
+                bytes.extend_from_slice(digest);
+                Ok(bytes)
+            }
+            Self::RevokedKey { revoked_key_id } => {
+                Ok(revoked_key_id.to_le_bytes().to_vec())
+            }
+            Self::Raw(raw) => Ok(raw.data.clone()),
+        }
+    }
+
+    fn from_raw(raw: owned::Raw) -> Self {
+        Self::Raw(raw)
+    }
+}
+
+impl<'f, F: 'f + Flash> owned::FromUnowned<'f, F> for Element {
+    type Manifest = manifest::km::Km;
+
+    fn from_container(
+        container: manifest::Container<
+            'f,
+            Self::Manifest,
+            F,
+            provenance::Adhoc,
+        >,
+    ) -> Result<Vec<(usize, Self)>, Error> {
+        let km = manifest::km::ParsedKm::new(container);
+        let sha = RingSha::new();
+        let mut elements = Vec::new();
+
+        for allowed in km.allowed_keys() {
+            let allowed = match owned::or_raw(allowed.read(&sha))? {
+                Some(allowed) => allowed,
+                None => continue,
+            };
+            elements.push((
+                allowed.entry().index(),
+                Element::AllowedKey {
+                    key_id: allowed.key_id(),
+                    usage: allowed.raw_usage(),
+                    digest: *allowed.digest(),
+                },
+            ));
+        }
+
+        for revoked in km.revoked_keys() {
+            let revoked = match owned::or_raw(revoked.read(&sha))? {
+                Some(revoked) => revoked,
+                None => continue,
+            };
+            elements.push((
+                revoked.entry().index(),
+                Element::RevokedKey {
+                    revoked_key_id: revoked.key_id(),
+                },
+            ));
+        }
+
+        Ok(elements)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::crypto::ring::sha256;
+    use crate::crypto::testdata;
+    use crate::manifest::owned;
+    use crate::manifest::owned::Km;
+    use crate::manifest::Metadata;
+
+    use pretty_assertions::assert_eq;
+    use serde_json::from_str;
+
+    #[test]
+    fn parse_elements() {
+        #[rustfmt::skip]
+        let km: Km = from_str(r#"{
+            "version_id": 7,
+            "elements": [
+                { "key_id": 1, "usage": "0b01", "digest": [
+                    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+                    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1
+                ] },
+                { "revoked_key_id": 0 }
+            ]
+        }"#).unwrap();
+
+        assert_eq!(
+            km,
+            owned::Container {
+                metadata: Metadata { version_id: 7 },
+                elements: vec![
+                    owned::Node {
+                        element: Element::AllowedKey {
+                            key_id: 1,
+                            usage: 0b01,
+                            digest: [1; 32],
+                        },
+                        children: vec![],
+                        hashed: true,
+                    },
+                    owned::Node {
+                        element: Element::RevokedKey { revoked_key_id: 0 },
+                        children: vec![],
+                        hashed: true,
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let km = owned::Container {
+            metadata: Metadata { version_id: 7 },
+            elements: vec![
+                owned::Node {
+                    element: Element::AllowedKey {
+                        key_id: 1,
+                        usage: 0b11,
+                        digest: [1; 32],
+                    },
+                    children: vec![],
+                    hashed: true,
+                },
+                owned::Node {
+                    element: Element::AllowedKey {
+                        key_id: 2,
+                        usage: 0b01,
+                        digest: [2; 32],
+                    },
+                    children: vec![],
+                    hashed: false,
+                },
+                owned::Node {
+                    element: Element::RevokedKey { revoked_key_id: 0 },
+                    children: vec![],
+                    hashed: true,
+                },
+            ],
+        };
+        let sha = sha256::Builder::new();
+        let (mut rsa, mut signer) = testdata::rsa();
+
+        let bytes = km.sign(0x00, &sha, &mut signer).unwrap();
+        let km2 =
+            owned::Container::parse(&bytes, &sha, Some(&mut rsa)).unwrap();
+        assert!(!km2.bad_signature);
+        assert!(!km2.bad_toc_hash);
+        assert!(km2.bad_hashes.is_empty());
+        assert_eq!(km, km2.container);
+    }
+}
@@ -0,0 +1,492 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Host flash layouts.
+//!
+//! A [`Layout`] describes where firmware binaries live in host flash, and
+//! what a PFM should say about them. [`Layout::build()`] assembles both the
+//! flash image and an unsigned [`owned::Pfm`] from it, computing image hashes
+//! from the assembled image itself, so that the two cannot disagree.
+
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::crypto::sha256;
+use crate::hardware::flash::Region;
+use crate::manifest::owned;
+use crate::manifest::owned::pfm;
+use crate::manifest::HashType;
+use crate::manifest::Metadata;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+/// A host flash layout.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Layout {
+    /// The version ID for the generated PFM.
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::serde::de_radix")
+    )]
+    pub version_id: u32,
+    /// The size of the flash image, in bytes.
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::serde::de_radix")
+    )]
+    pub flash_size: u32,
+    /// The value of an erased byte; unused parts of the flash image are
+    /// filled with it.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            default = "default_blank_byte",
+            deserialize_with = "crate::serde::de_radix"
+        )
+    )]
+    pub blank_byte: u8,
+    /// The platform ID for the generated PFM, if any.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, deserialize_with = "de_platform_id")
+    )]
+    pub platform_id: Option<Vec<u8>>,
+    /// The firmware stored in flash.
+    pub firmware: Vec<Firmware>,
+}
+
+#[cfg(feature = "serde")]
+fn de_platform_id<'de, D>(d: D) -> Result<Option<Vec<u8>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    crate::serde::de_bytestring(d).map(Some)
+}
+
+#[cfg(feature = "serde")]
+fn de_len<'de, D>(d: D) -> Result<Option<u32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    crate::serde::de_radix(d).map(Some)
+}
+
+#[cfg(feature = "serde")]
+fn default_blank_byte() -> u8 {
+    0xff
+}
+
+/// Firmware with a particular ID.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Firmware {
+    /// The firmware ID.
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::serde::de_bytestring")
+    )]
+    pub firmware_id: Vec<u8>,
+    /// The `AllowableFw` flags.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, deserialize_with = "crate::serde::de_radix")
+    )]
+    pub flags: u8,
+    /// The versions of this firmware stored in flash.
+    pub versions: Vec<Version>,
+}
+
+/// A version of some [`Firmware`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Version {
+    /// The version string.
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::serde::de_bytestring")
+    )]
+    pub version_str: Vec<u8>,
+    /// The flash address at which `version_str` is found.
+    ///
+    /// If no binary covers this address, `version_str` is written there.
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::serde::de_radix")
+    )]
+    pub version_addr: u32,
+    /// Regions this firmware may write to.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub rw_regions: Vec<pfm::Rw>,
+    /// The images making up this version.
+    pub images: Vec<Image>,
+}
+
+/// A signed image, made up of one or more binaries.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Image {
+    /// The image flags.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, deserialize_with = "crate::serde::de_radix")
+    )]
+    pub flags: u8,
+    /// The binaries making up this image; their hash is computed over them
+    /// in order.
+    pub binaries: Vec<Binary>,
+}
+
+/// A binary file placed in flash.
+#[derive(Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Binary {
+    /// The path to the binary.
+    pub path: PathBuf,
+    /// The offset in flash to place it at.
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::serde::de_radix")
+    )]
+    pub offset: u32,
+    /// The length of the region the binary occupies, if larger than the
+    /// binary itself; the rest of the region is left blank, and is covered
+    /// by the image hash.
+    #[cfg_attr(feature = "serde", serde(default, deserialize_with = "de_len"))]
+    pub len: Option<u32>,
+}
+
+/// An error returned by [`Layout::build()`].
+#[derive(Debug)]
+pub enum LayoutError {
+    /// Indicates that a binary could not be read.
+    Io(PathBuf, io::Error),
+    /// Indicates that a binary is larger than the region it was given.
+    TooLarge(PathBuf),
+    /// Indicates that a region extends past the end of flash.
+    OutOfBounds(Region),
+    /// Indicates that two binaries overlap.
+    Overlap(Region, Region),
+    /// Indicates that a version string does not match the contents of flash
+    /// at its address.
+    VersionMismatch(Vec<u8>),
+    /// Indicates an error while computing a hash.
+    HashError(sha256::Error),
+}
+
+impl<E> From<sha256::Error<E>> for LayoutError {
+    fn from(e: sha256::Error<E>) -> Self {
+        Self::HashError(e.erased())
+    }
+}
+
+/// The products of [`Layout::build()`].
+#[derive(Clone, Debug)]
+pub struct Build {
+    /// The flash image.
+    pub flash: Vec<u8>,
+    /// The PFM describing `flash`, ready for signing.
+    pub pfm: owned::Pfm,
+}
+
+impl Layout {
+    /// Assembles a flash image and a PFM describing it.
+    ///
+    /// `read` is used to read each binary's contents from its path.
+    pub fn build(
+        &self,
+        sha: &impl sha256::Builder,
+        mut read: impl FnMut(&Path) -> io::Result<Vec<u8>>,
+    ) -> Result<Build, LayoutError> {
+        let mut flash = vec![self.blank_byte; self.flash_size as usize];
+        let mut placed = Vec::<Region>::new();
+        let mut place = |region: Region| -> Result<(), LayoutError> {
+            if region.offset.checked_add(region.len).is_none()
+                || region.end() > self.flash_size
+            {
+                return Err(LayoutError::OutOfBounds(region));
+            }
+            if let Some(&other) = placed
+                .iter()
+                .find(|r| region.offset < r.end() && r.offset < region.end())
+            {
+                return Err(LayoutError::Overlap(other, region));
+            }
+            placed.push(region);
+            Ok(())
+        };
+
+        let mut elements = vec![owned::Node {
+            element: pfm::Element::FlashDevice {
+                blank_byte: self.blank_byte,
+                firmware_count: None,
+            },
+            hashed: true,
+            children: vec![],
+        }];
+
+        // Place every binary before dealing with version strings, since a
+        // version string may live inside any of them.
+        let mut images = Vec::new();
+        for binary in self.firmware.iter().flat_map(|fw| {
+            fw.versions
+                .iter()
+                .flat_map(|v| v.images.iter().flat_map(|i| &i.binaries))
+        }) {
+            let data = read(&binary.path)
+                .map_err(|e| LayoutError::Io(binary.path.clone(), e))?;
+            let len = binary.len.unwrap_or(data.len() as u32);
+            if data.len() > len as usize {
+                return Err(LayoutError::TooLarge(binary.path.clone()));
+            }
+
+            let region = Region::new(binary.offset, len);
+            place(region)?;
+            let start = binary.offset as usize;
+            flash[start..start + data.len()].copy_from_slice(&data);
+            images.push(region);
+        }
+        let mut images = images.into_iter();
+
+        for fw in &self.firmware {
+            let mut versions = Vec::new();
+            for version in &fw.versions {
+                let version_str = &version.version_str[..];
+                let region =
+                    Region::new(version.version_addr, version_str.len() as u32);
+                let range = region.offset as usize..region.end() as usize;
+                match place(region) {
+                    Ok(()) => flash[range].copy_from_slice(version_str),
+                    Err(LayoutError::Overlap(..))
+                        if &flash[range] == version_str => {}
+                    Err(LayoutError::Overlap(..)) => {
+                        return Err(LayoutError::VersionMismatch(
+                            version.version_str.clone(),
+                        ))
+                    }
+                    Err(e) => return Err(e),
+                }
+
+                let mut image_regions = Vec::new();
+                for image in &version.images {
+                    let regions = images
+                        .by_ref()
+                        .take(image.binaries.len())
+                        .collect::<Vec<_>>();
+
+                    let mut hasher = sha.new_hasher()?;
+                    for r in &regions {
+                        sha256::Hasher::write(
+                            &mut hasher,
+                            &flash[r.offset as usize..r.end() as usize],
+                        )?;
+                    }
+                    let mut hash = [0; 32];
+                    sha256::Hasher::finish(hasher, &mut hash)?;
+
+                    image_regions.push(pfm::Image {
+                        flags: image.flags,
+                        hash_type: HashType::Sha256,
+                        hash: hash.to_vec(),
+                        regions,
+                    });
+                }
+
+                versions.push(owned::Node {
+                    element: pfm::Element::FwVersion {
+                        version_addr: version.version_addr,
+                        version_str: version_str.to_vec(),
+                        rw_regions: version.rw_regions.clone(),
+                        image_regions,
+                        format_version: 0,
+                    },
+                    hashed: true,
+                    children: vec![],
+                });
+            }
+
+            elements.push(owned::Node {
+                element: pfm::Element::AllowableFw {
+                    version_count: versions.len() as u8,
+                    firmware_id: fw.firmware_id.clone(),
+                    flags: fw.flags,
+                },
+                hashed: true,
+                children: versions,
+            });
+        }
+
+        if let Some(platform_id) = &self.platform_id {
+            elements.push(owned::Node {
+                element: pfm::Element::PlatformId {
+                    platform_id: platform_id.clone(),
+                },
+                hashed: true,
+                children: vec![],
+            });
+        }
+
+        Ok(Build {
+            flash,
+            pfm: owned::Pfm {
+                metadata: Metadata {
+                    version_id: self.version_id,
+                },
+                elements,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use crate::crypto::ring::sha256;
+    use crate::crypto::testdata;
+    use crate::hardware::flash::Ram;
+    use crate::manifest::container::Container;
+    use crate::manifest::pfm::ParsedPfm;
+    use crate::manifest::pfm::Pfm;
+    use crate::manifest::Parse as _;
+    use crate::manifest::ValidationTime;
+    use crate::mem::BumpArena;
+    use crate::mem::OutOfMemory;
+
+    use serde_json::from_str;
+
+    fn files() -> HashMap<PathBuf, Vec<u8>> {
+        let mut files = HashMap::new();
+        files.insert(PathBuf::from("boot.bin"), vec![0xaa; 0x300]);
+        files.insert(PathBuf::from("main.bin"), b"main-v1.0 code".to_vec());
+        files
+    }
+
+    fn read(path: &Path) -> io::Result<Vec<u8>> {
+        files()
+            .remove(path)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    #[test]
+    fn build_and_validate() {
+        let layout: Layout = from_str(
+            r#"{
+            "version_id": 5,
+            "flash_size": "0x2000",
+            "firmware": [{
+                "firmware_id": "bmc",
+                "versions": [{
+                    "version_str": "main-v1.0",
+                    "version_addr": "0x1000",
+                    "rw_regions": [{
+                        "flags": "0b0",
+                        "region": { "offset": "0x1800", "len": "0x800" }
+                    }],
+                    "images": [{
+                        "flags": "0b1",
+                        "binaries": [
+                            { "path": "boot.bin", "offset": "0x0", "len": "0x400" },
+                            { "path": "main.bin", "offset": "0x1000" }
+                        ]
+                    }]
+                }]
+            }]
+        }"#,
+        )
+        .unwrap();
+
+        let sha = sha256::Builder::new();
+        let build = layout.build(&sha, read).unwrap();
+        assert_eq!(build.flash.len(), 0x2000);
+        assert_eq!(&build.flash[0x300..0x400], &[0xff; 0x100][..]);
+        assert_eq!(&build.flash[0x1000..0x1009], b"main-v1.0");
+
+        let (mut rsa, mut signer) = testdata::rsa();
+        let pfm = Ram(build.pfm.sign(0x00, &sha, &mut signer).unwrap());
+        let pfm = ParsedPfm::new(
+            Container::parse_and_verify(
+                &pfm,
+                &sha,
+                &mut rsa,
+                &OutOfMemory,
+                &OutOfMemory,
+            )
+            .unwrap(),
+        );
+
+        let mut arena = [0; 256];
+        let arena = BumpArena::new(&mut arena);
+        Pfm::validate(
+            &pfm,
+            ValidationTime::Activation,
+            &[&Ram(&build.flash[..])],
+            &sha,
+            &arena,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn bad_layouts() {
+        let sha = sha256::Builder::new();
+        let layout = |version_str: &[u8], binaries: Vec<Binary>| Layout {
+            version_id: 0,
+            flash_size: 0x1000,
+            blank_byte: 0xff,
+            platform_id: None,
+            firmware: vec![Firmware {
+                firmware_id: b"fw".to_vec(),
+                flags: 0,
+                versions: vec![Version {
+                    version_str: version_str.to_vec(),
+                    version_addr: 0x800,
+                    rw_regions: vec![],
+                    images: vec![Image { flags: 0, binaries }],
+                }],
+            }],
+        };
+        let binary = |path: &str, offset, len| Binary {
+            path: path.into(),
+            offset,
+            len,
+        };
+
+        assert!(matches!(
+            layout(b"v1", vec![binary("boot.bin", 0xe00, None)])
+                .build(&sha, read),
+            Err(LayoutError::OutOfBounds(_))
+        ));
+        assert!(matches!(
+            layout(b"v1", vec![binary("boot.bin", 0, Some(0x100))])
+                .build(&sha, read),
+            Err(LayoutError::TooLarge(_))
+        ));
+        assert!(matches!(
+            layout(
+                b"v1",
+                vec![
+                    binary("boot.bin", 0, None),
+                    binary("main.bin", 0x200, None)
+                ]
+            )
+            .build(&sha, read),
+            Err(LayoutError::Overlap(_, _))
+        ));
+        assert!(matches!(
+            layout(b"v1", vec![binary("main.bin", 0x800, None)])
+                .build(&sha, read),
+            Err(LayoutError::VersionMismatch(_))
+        ));
+        assert!(matches!(
+            layout(b"v1", vec![binary("missing.bin", 0, None)])
+                .build(&sha, read),
+            Err(LayoutError::Io(_, _))
+        ));
+    }
+}
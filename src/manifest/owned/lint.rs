@@ -0,0 +1,519 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! Manifest linting.
+//!
+//! A manifest can be well-formed and correctly signed, and yet describe
+//! something that will not work on a real platform, such as overlapping flash
+//! regions. The functions in this module look for such semantic problems, so
+//! that they can be caught before a manifest is deployed.
+//!
+//! Problems are reported as [`Finding`]s, which refer to elements by their
+//! index in the table of contents. For owned manifests, this is the index the
+//! element would have when encoded, i.e., its position in a pre-order
+//! traversal of the element tree.
+
+use std::fmt;
+use std::mem;
+
+use zerocopy::AsBytes;
+use zerocopy::FromBytes;
+
+use crate::hardware::flash::Region;
+use crate::manifest::container::RawHeader;
+use crate::manifest::container::RawTocEntry;
+use crate::manifest::owned;
+use crate::manifest::owned::pfm;
+use crate::manifest::Error;
+use crate::manifest::Manifest;
+use crate::protocol::wire::WireEnum as _;
+
+/// A problem found while linting a manifest.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Problem {
+    /// The element's format version is older than the oldest one Manticore
+    /// understands.
+    OldFormatVersion {
+        /// The element's format version.
+        format_version: u8,
+        /// The minimum format version for the element's type.
+        min_version: u8,
+    },
+
+    /// The element is not covered by a hash in the table of contents, so
+    /// it is not protected by the manifest's signature once read out of
+    /// flash.
+    Unhashed,
+
+    /// An `AllowableFw` element does not list any firmware versions, so no
+    /// firmware can ever match it.
+    EmptyAllowableFw,
+
+    /// A `FwVersion` element has the same version string as an earlier
+    /// sibling.
+    DuplicateVersion {
+        /// The TOC index of the earlier sibling.
+        first: usize,
+    },
+
+    /// A region's start or length is not a multiple of the required
+    /// alignment.
+    UnalignedRegion(Region),
+
+    /// A region extends past the end of flash.
+    RegionOutOfBounds(Region),
+
+    /// Two regions guarded by the same element overlap.
+    OverlappingRegions(Region, Region),
+}
+
+/// A [`Problem`] with a particular element.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Finding {
+    /// The TOC index of the problematic element.
+    pub toc_index: usize,
+    /// The problem itself.
+    pub problem: Problem,
+}
+
+struct DisplayRegion(Region);
+impl fmt::Display for DisplayRegion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{:#x}, {:#x})", self.0.offset, self.0.end())
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "toc entry {}: ", self.toc_index)?;
+        match &self.problem {
+            Problem::OldFormatVersion {
+                format_version,
+                min_version,
+            } => write!(
+                f,
+                "format version {} is older than the minimum of {}",
+                format_version, min_version
+            ),
+            Problem::Unhashed => write!(f, "element is not hashed"),
+            Problem::EmptyAllowableFw => {
+                write!(f, "allowable firmware lists no versions")
+            }
+            Problem::DuplicateVersion { first } => write!(
+                f,
+                "version string duplicates the one in toc entry {}",
+                first
+            ),
+            Problem::UnalignedRegion(r) => {
+                write!(f, "region {} is unaligned", DisplayRegion(*r))
+            }
+            Problem::RegionOutOfBounds(r) => {
+                write!(f, "region {} is out of bounds", DisplayRegion(*r))
+            }
+            Problem::OverlappingRegions(a, b) => write!(
+                f,
+                "regions {} and {} overlap",
+                DisplayRegion(*a),
+                DisplayRegion(*b)
+            ),
+        }
+    }
+}
+
+/// Options for linting flash layouts.
+#[derive(Copy, Clone, Debug)]
+pub struct Options {
+    /// The alignment, in bytes, that every region's start and length must
+    /// have; usually, the flash's erase sector size.
+    pub alignment: u32,
+    /// The size of the flash the manifest describes, if known.
+    pub flash_size: Option<u32>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            alignment: 0x1000,
+            flash_size: None,
+        }
+    }
+}
+
+/// Checks the table of contents of `bytes`, an encoded manifest of type `M`,
+/// for elements whose format version is below [`Manifest::min_version()`].
+///
+/// This check operates on the raw encoding, since such a manifest will be
+/// rejected by [`owned::Container::parse()`].
+pub fn format_versions<M: Manifest>(
+    bytes: &[u8],
+) -> Result<Vec<Finding>, Error> {
+    fn read<T>(bytes: &[u8], offset: usize) -> Option<T>
+    where
+        T: AsBytes + FromBytes + Default,
+    {
+        let mut value = T::default();
+        let len = mem::size_of::<T>();
+        value
+            .as_bytes_mut()
+            .copy_from_slice(bytes.get(offset..offset + len)?);
+        Some(value)
+    }
+
+    let header = read::<RawHeader>(bytes, 0).ok_or(Error::OutOfRange)?;
+    let entries = (0..header.entry_count as usize)
+        .map(|i| {
+            let offset =
+                mem::size_of::<RawHeader>() + i * mem::size_of::<RawTocEntry>();
+            read::<RawTocEntry>(bytes, offset).ok_or(Error::OutOfRange)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut findings = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let ty = match M::ElementType::from_wire_value(entry.element_type) {
+            Some(ty) => ty,
+            None => continue,
+        };
+        let min_version = M::min_version(ty);
+        if entry.format_version < min_version {
+            findings.push(Finding {
+                toc_index: i,
+                problem: Problem::OldFormatVersion {
+                    format_version: entry.format_version,
+                    min_version,
+                },
+            });
+        }
+    }
+    Ok(findings)
+}
+
+/// Calls `f` on each node in `nodes`, in pre-order, along with its TOC index.
+fn walk<'a, E>(
+    nodes: &'a [owned::Node<E>],
+    index: &mut usize,
+    f: &mut impl FnMut(usize, &'a owned::Node<E>),
+) {
+    for node in nodes {
+        f(*index, node);
+        *index += 1;
+        walk(&node.children, index, f);
+    }
+}
+
+/// Returns the number of TOC entries `node` and its descendants occupy.
+fn tree_len<E>(node: &owned::Node<E>) -> usize {
+    1 + node.children.iter().map(tree_len).sum::<usize>()
+}
+
+/// Checks `container` for elements that are not covered by a hash.
+pub fn unhashed<E>(container: &owned::Container<E>) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    walk(&container.elements, &mut 0, &mut |i, node| {
+        if !node.hashed {
+            findings.push(Finding {
+                toc_index: i,
+                problem: Problem::Unhashed,
+            });
+        }
+    });
+    findings
+}
+
+/// Checks the firmware descriptions in `pfm` for problems:
+/// - `AllowableFw` elements with no versions.
+/// - `FwVersion` elements with duplicate version strings.
+/// - Regions that are unaligned or out of bounds, according to `options`.
+/// - Regions within a single `FwVersion` that overlap.
+pub fn pfm(pfm: &owned::Pfm, options: &Options) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    walk(&pfm.elements, &mut 0, &mut |i, node| match &node.element {
+        pfm::Element::AllowableFw { .. } => {
+            if node.children.is_empty() {
+                findings.push(Finding {
+                    toc_index: i,
+                    problem: Problem::EmptyAllowableFw,
+                });
+            }
+
+            let mut seen = Vec::<(&[u8], usize)>::new();
+            let mut index = i + 1;
+            for child in &node.children {
+                let child_index = index;
+                index += tree_len(child);
+                let version_str = match &child.element {
+                    pfm::Element::FwVersion { version_str, .. } => version_str,
+                    _ => continue,
+                };
+                match seen.iter().find(|(s, _)| s == version_str) {
+                    Some(&(_, first)) => findings.push(Finding {
+                        toc_index: child_index,
+                        problem: Problem::DuplicateVersion { first },
+                    }),
+                    None => seen.push((version_str, child_index)),
+                }
+            }
+        }
+        pfm::Element::FwVersion {
+            rw_regions,
+            image_regions,
+            ..
+        } => {
+            let regions = rw_regions
+                .iter()
+                .map(|rw| rw.region)
+                .chain(image_regions.iter().flat_map(|i| i.regions.clone()))
+                .collect::<Vec<_>>();
+            lint_regions(i, &regions, options, &mut findings);
+        }
+        _ => {}
+    });
+    findings
+}
+
+/// Checks a list of regions guarded by the element at `toc_index`.
+fn lint_regions(
+    toc_index: usize,
+    regions: &[Region],
+    options: &Options,
+    findings: &mut Vec<Finding>,
+) {
+    let mut push = |problem| findings.push(Finding { toc_index, problem });
+    for (j, &r) in regions.iter().enumerate() {
+        if options.alignment > 1
+            && (r.offset % options.alignment != 0
+                || r.len % options.alignment != 0)
+        {
+            push(Problem::UnalignedRegion(r));
+        }
+
+        let out_of_bounds = match options.flash_size {
+            Some(size) => {
+                r.offset.checked_add(r.len).map_or(true, |e| e > size)
+            }
+            None => r.offset.checked_add(r.len).is_none(),
+        };
+        if out_of_bounds {
+            push(Problem::RegionOutOfBounds(r));
+        }
+
+        for &r2 in &regions[..j] {
+            if r.offset < r2.end() && r2.offset < r.end() {
+                push(Problem::OverlappingRegions(r2, r));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::crypto::ring::sha256;
+    use crate::crypto::testdata;
+    use crate::manifest::pfm::Pfm;
+    use crate::manifest::HashType;
+    use crate::manifest::Metadata;
+
+    fn version(
+        version_str: &[u8],
+        rw: Vec<Region>,
+        image: Vec<Region>,
+    ) -> owned::Node<pfm::Element> {
+        owned::Node {
+            element: pfm::Element::FwVersion {
+                version_addr: 0,
+                version_str: version_str.to_vec(),
+                rw_regions: rw
+                    .into_iter()
+                    .map(|region| pfm::Rw { flags: 0, region })
+                    .collect(),
+                image_regions: vec![pfm::Image {
+                    flags: 0,
+                    hash_type: HashType::Sha256,
+                    hash: vec![0; 32],
+                    regions: image,
+                }],
+                format_version: 0,
+            },
+            hashed: true,
+            children: vec![],
+        }
+    }
+
+    fn allowable_fw(
+        children: Vec<owned::Node<pfm::Element>>,
+    ) -> owned::Node<pfm::Element> {
+        owned::Node {
+            element: pfm::Element::AllowableFw {
+                version_count: children.len() as u8,
+                firmware_id: b"fw".to_vec(),
+                flags: 0,
+            },
+            hashed: true,
+            children,
+        }
+    }
+
+    #[test]
+    fn clean() {
+        let pfm = owned::Pfm {
+            metadata: Metadata { version_id: 1 },
+            elements: vec![allowable_fw(vec![
+                version(
+                    b"v1",
+                    vec![Region::new(0x0000, 0x1000)],
+                    vec![Region::new(0x1000, 0x2000)],
+                ),
+                // Different versions may use the same regions.
+                version(
+                    b"v2",
+                    vec![Region::new(0x0000, 0x1000)],
+                    vec![Region::new(0x1000, 0x2000)],
+                ),
+            ])],
+        };
+        let options = Options {
+            flash_size: Some(0x4000),
+            ..Options::default()
+        };
+        assert_eq!(super::pfm(&pfm, &options), vec![]);
+        assert_eq!(unhashed(&pfm), vec![]);
+
+        let sha = sha256::Builder::new();
+        let (_, mut signer) = testdata::rsa();
+        let bytes = pfm.sign(0x00, &sha, &mut signer).unwrap();
+        assert_eq!(format_versions::<Pfm>(&bytes).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn problems() {
+        let mut unhashed_fw = allowable_fw(vec![]);
+        unhashed_fw.hashed = false;
+
+        let pfm = owned::Pfm {
+            metadata: Metadata { version_id: 1 },
+            elements: vec![
+                allowable_fw(vec![
+                    version(
+                        b"v1",
+                        vec![Region::new(0x0000, 0x1000)],
+                        vec![
+                            Region::new(0x0800, 0x1000),
+                            Region::new(0x3000, 0x2000),
+                        ],
+                    ),
+                    version(b"v1", vec![], vec![]),
+                ]),
+                unhashed_fw,
+            ],
+        };
+        let options = Options {
+            flash_size: Some(0x4000),
+            ..Options::default()
+        };
+
+        assert_eq!(
+            super::pfm(&pfm, &options),
+            vec![
+                Finding {
+                    toc_index: 2,
+                    problem: Problem::DuplicateVersion { first: 1 },
+                },
+                Finding {
+                    toc_index: 1,
+                    problem: Problem::UnalignedRegion(Region::new(
+                        0x0800, 0x1000
+                    )),
+                },
+                Finding {
+                    toc_index: 1,
+                    problem: Problem::OverlappingRegions(
+                        Region::new(0x0000, 0x1000),
+                        Region::new(0x0800, 0x1000),
+                    ),
+                },
+                Finding {
+                    toc_index: 1,
+                    problem: Problem::RegionOutOfBounds(Region::new(
+                        0x3000, 0x2000
+                    )),
+                },
+                Finding {
+                    toc_index: 3,
+                    problem: Problem::EmptyAllowableFw,
+                },
+            ]
+        );
+        assert_eq!(
+            unhashed(&pfm),
+            vec![Finding {
+                toc_index: 3,
+                problem: Problem::Unhashed,
+            }]
+        );
+    }
+
+    #[test]
+    fn old_format_version() {
+        use crate::hardware::flash::Ram;
+        use crate::manifest::pfm::ElementType;
+        use crate::manifest::provenance;
+        use crate::manifest::Container;
+        use crate::manifest::ManifestType;
+        use crate::mem::OutOfMemory;
+
+        /// A PFM that no longer understands version 0 `FwVersion`s.
+        enum NewPfm {}
+        impl Manifest for NewPfm {
+            type ElementType = ElementType;
+            const TYPE: ManifestType = ManifestType::Pfm;
+
+            fn min_version(ty: ElementType) -> u8 {
+                match ty {
+                    ElementType::FwVersion => 1,
+                    _ => 0,
+                }
+            }
+        }
+
+        let mut new_version = version(b"v2", vec![], vec![]);
+        if let pfm::Element::FwVersion { format_version, .. } =
+            &mut new_version.element
+        {
+            *format_version = 1;
+        }
+        let pfm = owned::Pfm {
+            metadata: Metadata { version_id: 1 },
+            elements: vec![allowable_fw(vec![
+                version(b"v1", vec![], vec![]),
+                new_version,
+            ])],
+        };
+
+        let sha = sha256::Builder::new();
+        let (_, mut signer) = testdata::rsa();
+        let bytes = pfm.sign(0x00, &sha, &mut signer).unwrap();
+        assert_eq!(format_versions::<Pfm>(&bytes).unwrap(), vec![]);
+        assert_eq!(
+            format_versions::<NewPfm>(&bytes).unwrap(),
+            vec![Finding {
+                toc_index: 1,
+                problem: Problem::OldFormatVersion {
+                    format_version: 0,
+                    min_version: 1,
+                },
+            }]
+        );
+
+        // This is the same check that parsing performs.
+        assert!(matches!(
+            Container::<NewPfm, _, provenance::Adhoc>::parse(
+                &Ram(&bytes[..]),
+                &OutOfMemory
+            ),
+            Err(Error::OldFormatVersion { toc_index: 1 })
+        ));
+    }
+}
@@ -15,13 +15,14 @@ use std::mem;
 
 use zerocopy::AsBytes;
 
-use crate::crypto::rsa;
 use crate::crypto::sha256;
+use crate::crypto::sig;
 use crate::hardware::flash::Flash;
 use crate::hardware::flash::Ram;
 use crate::io::write::StdWrite;
 use crate::io::Write as _;
 use crate::manifest;
+use crate::manifest::container;
 use crate::manifest::container::RawTocEntry;
 use crate::manifest::provenance;
 use crate::manifest::Error;
@@ -35,6 +36,11 @@ use crate::protocol::wire::WireEnum;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
+pub mod diff;
+pub mod km;
+pub mod layout;
+pub mod lint;
+pub mod pcd;
 pub mod pfm;
 
 /// An "owned" manifest element.
@@ -55,12 +61,48 @@ pub trait Element: Sized {
     /// this trait.
     const TYPE: ManifestType;
 
-    /// Returns the element type of a specific element.
-    fn element_type(&self) -> Self::ElementType;
+    /// Returns the wire value of the element type of a specific element.
+    ///
+    /// This is a raw byte, rather than an `ElementType`, so that [`Raw`]
+    /// elements of unknown type can be represented.
+    fn element_type(&self) -> u8;
+
+    /// Returns the format version this element should be encoded with.
+    fn format_version(&self) -> u8 {
+        0
+    }
 
     /// Attempts to encode this `Element` into bytes, using the given
     /// padding byte as "filler".
     fn to_bytes(&self, padding_byte: u8) -> Result<Vec<u8>, EncodingError>;
+
+    /// Wraps a [`Raw`] element.
+    fn from_raw(raw: Raw) -> Self;
+}
+
+/// An element that Manticore does not understand, either because its type is
+/// unknown or because it has a newer format version than Manticore knows how
+/// to decode.
+///
+/// Raw elements are preserved byte-for-byte when a manifest is parsed and
+/// re-encoded, so that tooling can handle manifests produced by newer
+/// generators.
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Raw {
+    /// The wire value of the element's type.
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            deserialize_with = "crate::serde::de_radix",
+            serialize_with = "crate::serde::se_hex",
+        )
+    )]
+    pub element_type: u8,
+    /// The element's format version.
+    pub format_version: u8,
+    /// The element's encoded contents.
+    pub data: Vec<u8>,
 }
 
 /// An "owned" manifest element that can be built from its unowned counterpart.
@@ -71,8 +113,12 @@ pub trait FromUnowned<'f, F: Flash>: Element {
     /// The "unowned" type.
     type Manifest: Manifest;
 
-    /// Walks a parsed container of this manifest type, building a tree of
-    /// elements along the way.
+    /// Walks a parsed container of this manifest type, decoding each element
+    /// it understands, along with that element's TOC index.
+    ///
+    /// Elements that are not returned are preserved as [`Raw`] elements;
+    /// implementations should use [`or_raw()`] so that one element that fails
+    /// to decode does not prevent decoding the rest.
     fn from_container(
         container: manifest::Container<
             'f,
@@ -80,7 +126,21 @@ pub trait FromUnowned<'f, F: Flash>: Element {
             F,
             provenance::Adhoc,
         >,
-    ) -> Result<Vec<Node<Self>>, Error>;
+    ) -> Result<Vec<(usize, Self)>, Error>;
+}
+
+/// Filters the result of decoding a single element, so that an element that
+/// fails to decode is kept [`Raw`] instead.
+///
+/// Usually, such an element was written in a newer format than this crate
+/// understands. Running out of scratch space is still an error, since it says
+/// nothing about the element itself.
+pub(crate) fn or_raw<T>(decoded: Result<T, Error>) -> Result<Option<T>, Error> {
+    match decoded {
+        Ok(element) => Ok(Some(element)),
+        Err(Error::OutOfMemory) => Err(Error::OutOfMemory),
+        Err(_) => Ok(None),
+    }
 }
 
 /// A heap-allocated PFM.
@@ -88,13 +148,24 @@ pub trait FromUnowned<'f, F: Flash>: Element {
 /// See [`manifest::pfm`] for lazy parsing out of flash.
 pub type Pfm = Container<self::pfm::Element>;
 
+/// A heap-allocated PCD.
+///
+/// See [`manifest::pcd`] for lazy parsing out of flash.
+pub type Pcd = Container<self::pcd::Element>;
+
+/// A heap-allocated KM.
+///
+/// See [`manifest::km`] for lazy parsing out of flash.
+pub type Km = Container<self::km::Element>;
+
 /// A heap-allocated Cerberus manifest, represented as a tree structure.
 ///
 /// Prefer to access this type through one of the provided type aliases,
 /// instead:
 /// - Platform Firmware Manifest: [`Pfm`](type.Pfm.html)
 /// - Component Firmware Manifest: NYI
-/// - Platform Configuration Descriptor: NYI
+/// - Platform Configuration Data: [`Pcd`](type.Pcd.html)
+/// - Key Manifest: [`Km`](type.Km.html)
 #[derive(Clone, PartialEq, Eq, Debug)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Container<E> {
@@ -173,11 +244,21 @@ pub enum EncodingError {
     /// Indicates a range was empty when it shouldn't have been.
     EmptyRegion,
 
+    /// Indicates that a hash's length did not match its hash type.
+    BadHashLength,
+
+    /// Indicates that an element's contents cannot be encoded with its
+    /// requested format version.
+    BadFormatVersion,
+
+    /// Indicates that a signature's length did not match its algorithm.
+    BadSignatureLength,
+
     /// Indicates an error while computing a hash.
     HashError(sha256::Error),
 
-    /// Indicates an error while computing an RSA signature.
-    RsaError(rsa::Error),
+    /// Indicates an error while computing a signature.
+    SignatureError(sig::Error),
 }
 
 impl<E> From<sha256::Error<E>> for EncodingError {
@@ -186,9 +267,9 @@ impl<E> From<sha256::Error<E>> for EncodingError {
     }
 }
 
-impl<E> From<rsa::Error<E>> for EncodingError {
-    fn from(e: rsa::Error<E>) -> Self {
-        Self::RsaError(e.erased())
+impl From<sig::Error> for EncodingError {
+    fn from(e: sig::Error) -> Self {
+        Self::SignatureError(e)
     }
 }
 
@@ -201,7 +282,7 @@ impl<E: Element> Container<E> {
     pub fn parse(
         bytes: &[u8],
         sha: &impl sha256::Builder,
-        rsa: Option<&mut impl rsa::Engine>,
+        sig: Option<&mut impl sig::Verify>,
     ) -> Result<Parse<E>, Error>
     where
         E: for<'f> FromUnowned<'f, Ram<&'f [u8]>>,
@@ -229,32 +310,75 @@ impl<E: Element> Container<E> {
 
         parse.container.metadata = container.metadata();
         parse.bad_toc_hash = container.verify_toc_hash(sha).is_err();
-        if let Some(rsa) = rsa {
+        if let Some(sig) = sig {
             parse.bad_signature =
-                container.verify_signature(sha, rsa, &OutOfMemory).is_err();
+                container.verify_signature(sha, sig, &OutOfMemory).is_err();
         }
 
+        let mut entries = Vec::new();
         for (i, entry) in container.toc().entries().enumerate() {
-            let expected = match entry.hash() {
-                Some(h) => h,
-                None => continue,
-            };
-
             let region = entry.region();
             let start = region.offset as usize;
             let end = region.end() as usize;
-            let bytes = bytes
+            let data = bytes
                 .get(start..end)
                 .ok_or(Error::TooShort { toc_index: i })?;
 
-            let mut hash = [0; 32];
-            sha.hash_contiguous(bytes, &mut hash)?;
-            if &hash != expected {
-                parse.bad_hashes.push(i);
+            if let Some(expected) = entry.hash() {
+                let mut hash = [0; 32];
+                sha.hash_contiguous(data, &mut hash)?;
+                if &hash != expected {
+                    parse.bad_hashes.push(i);
+                }
+            }
+
+            let raw = Raw {
+                element_type: entry.raw().element_type,
+                format_version: entry.format_version(),
+                data: data.to_vec(),
+            };
+            let parent = entry.parent().map(|p| p.index());
+            entries.push((raw, entry.hash().is_some(), parent));
+        }
+
+        // Anything that decodes to a different format version than it was
+        // encoded with would not survive a round-trip, so keep it raw.
+        let mut decoded = entries.iter().map(|_| None).collect::<Vec<_>>();
+        for (i, element) in E::from_container(container)? {
+            if element.format_version() == entries[i].0.format_version {
+                decoded[i] = Some(element);
+            }
+        }
+
+        let mut nodes = entries
+            .into_iter()
+            .zip(decoded)
+            .map(|((raw, hashed, parent), element)| {
+                let node = Node {
+                    element: element.unwrap_or_else(|| E::from_raw(raw)),
+                    hashed,
+                    children: Vec::new(),
+                };
+                (Some(node), parent)
+            })
+            .collect::<Vec<_>>();
+
+        // Parents always precede their children, so building the tree
+        // back-to-front means every node is complete before it is moved
+        // into its parent.
+        for i in (0..nodes.len()).rev() {
+            let node = nodes[i].0.take().expect("each node is taken once");
+            match nodes[i].1 {
+                Some(p) => nodes[p]
+                    .0
+                    .as_mut()
+                    .expect("parents precede children")
+                    .children
+                    .insert(0, node),
+                None => parse.container.elements.insert(0, node),
             }
         }
 
-        parse.container.elements = E::from_container(container)?;
         Ok(parse)
     }
 
@@ -266,8 +390,31 @@ impl<E: Element> Container<E> {
         &self,
         padding_byte: u8,
         sha: &impl sha256::Builder,
-        rsa: &mut impl rsa::Signer,
+        signer: &mut impl sig::Sign,
     ) -> Result<Vec<u8>, EncodingError> {
+        let unsigned = self.to_be_signed(padding_byte, sha, signer.algo())?;
+        let message = unsigned.message(sha)?;
+
+        let mut bytes = unsigned.bytes;
+        let start = bytes.len();
+        bytes.resize(start + unsigned.algo.sig_len(), 0);
+        signer.sign(&message, &mut bytes[start..])?;
+        Ok(bytes)
+    }
+
+    /// Encodes this `Container` for signing with `algo`, without actually
+    /// signing it.
+    ///
+    /// This is useful when the signing key is not directly accessible, such
+    /// as when it is held in a hardware security module. See [`Unsigned`].
+    ///
+    /// `padding_byte` is as in [`Container::sign()`].
+    pub fn to_be_signed(
+        &self,
+        padding_byte: u8,
+        sha: &impl sha256::Builder,
+        algo: sig::Algo,
+    ) -> Result<Unsigned, EncodingError> {
         let mut bytes = Vec::new();
         let mut w = StdWrite(&mut bytes);
 
@@ -275,8 +422,8 @@ impl<E: Element> Container<E> {
         let _ = w.write_le(0u16); // To be filled in later.
         let _ = w.write_le(E::TYPE.to_wire_value());
         let _ = w.write_le(self.metadata.version_id);
-        let _ = w.write_le(rsa.pub_len().byte_len() as u16);
-        let _ = w.write_le(0u8); // Should be sig_type.
+        let _ = w.write_le(algo.sig_len() as u16);
+        let _ = w.write_le(container::encode_sig_type(algo, HashType::Sha256));
         let _ = w.write_le(padding_byte);
 
         let mut index = 0;
@@ -321,10 +468,10 @@ impl<E: Element> Container<E> {
                     .try_into()
                     .map_err(|_| EncodingError::OutOfSpace)?;
 
-                let element_type = node.element.element_type().to_wire_value();
+                let element_type = node.element.element_type();
                 let entry = RawTocEntry {
                     element_type,
-                    format_version: 0, // TODO(#59)
+                    format_version: node.element.format_version(),
                     offset: *offset,
                     len,
                     parent_type,
@@ -396,17 +543,158 @@ impl<E: Element> Container<E> {
             bytes.extend_from_slice(data);
         }
 
-        let total_len: u16 = (bytes.len() + rsa.pub_len().byte_len())
+        let total_len: u16 = (bytes.len() + algo.sig_len())
             .try_into()
             .map_err(|_| EncodingError::OutOfSpace)?;
         bytes[0..2].copy_from_slice(&total_len.to_le_bytes());
 
-        let mut signed = [0; 32];
-        let mut signature = vec![0; rsa.pub_len().byte_len()];
-        sha.hash_contiguous(&bytes, &mut signed)?;
-        rsa.sign(&signed, &mut signature)?;
-        bytes.extend_from_slice(&signature);
+        Ok(Unsigned { bytes, algo })
+    }
+}
+
+/// An encoded manifest that is still missing its signature.
+///
+/// The signature is created by signing [`Unsigned::message()`] with the
+/// algorithm given by [`Unsigned::algo()`], and is then added with
+/// [`Unsigned::attach_signature()`]. The encoded bytes can be saved with
+/// [`Unsigned::as_bytes()`] and recovered later with
+/// [`Unsigned::from_bytes()`], so that these steps need not happen in the
+/// same process.
+#[derive(Clone, Debug)]
+pub struct Unsigned {
+    bytes: Vec<u8>,
+    algo: sig::Algo,
+}
+
+impl Unsigned {
+    /// Recovers an `Unsigned` from the output of [`Unsigned::as_bytes()`].
+    ///
+    /// This only checks that the header is consistent with a missing
+    /// signature; the rest of the manifest is checked once it is parsed.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, Error> {
+        let mut header = container::RawHeader::default();
+        header.as_bytes_mut().copy_from_slice(
+            bytes
+                .get(..mem::size_of::<container::RawHeader>())
+                .ok_or(Error::OutOfRange)?,
+        );
 
+        let (algo, _) = container::decode_sig_type(header.sig_ty)
+            .ok_or(Error::OutOfRange)?;
+        if header.sig_len as usize != algo.sig_len() {
+            return Err(Error::BadSignatureLen);
+        }
+        if header.total_len as usize != bytes.len() + algo.sig_len() {
+            return Err(Error::OutOfRange);
+        }
+        Ok(Self { bytes, algo })
+    }
+
+    /// Returns the encoded manifest, without a signature.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns the algorithm the signature must be created with.
+    pub fn algo(&self) -> sig::Algo {
+        self.algo
+    }
+
+    /// Computes the message that must be signed.
+    ///
+    /// This is the SHA-256 digest of [`Unsigned::as_bytes()`], which is
+    /// itself hashed by the signature algorithm.
+    pub fn message(
+        &self,
+        sha: &impl sha256::Builder,
+    ) -> Result<sha256::Digest, EncodingError> {
+        let mut digest = [0; 32];
+        sha.hash_contiguous(&self.bytes, &mut digest)?;
+        Ok(digest)
+    }
+
+    /// Attaches `signature` to this manifest, returning the complete encoded
+    /// manifest.
+    ///
+    /// The signature is checked with `verify` before being attached.
+    pub fn attach_signature(
+        self,
+        signature: &[u8],
+        sha: &impl sha256::Builder,
+        verify: &mut impl sig::Verify,
+    ) -> Result<Vec<u8>, EncodingError> {
+        if signature.len() != self.algo.sig_len() {
+            return Err(EncodingError::BadSignatureLength);
+        }
+        let message = self.message(sha)?;
+        verify.verify_signature(self.algo, signature, &message)?;
+
+        let mut bytes = self.bytes;
+        bytes.extend_from_slice(signature);
         Ok(bytes)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::crypto::ring::sha256;
+    use crate::crypto::sig::Sign as _;
+    use crate::crypto::testdata;
+
+    #[test]
+    fn detached_signing() {
+        let km = Km {
+            metadata: Metadata { version_id: 3 },
+            elements: vec![Node {
+                element: km::Element::RevokedKey { revoked_key_id: 7 },
+                hashed: true,
+                children: vec![],
+            }],
+        };
+        let sha = sha256::Builder::new();
+        let (mut rsa, mut signer) = testdata::rsa();
+        let (mut ecdsa, _) = testdata::ecdsa();
+
+        let unsigned = km.to_be_signed(0x00, &sha, signer.algo()).unwrap();
+        let unsigned =
+            Unsigned::from_bytes(unsigned.as_bytes().to_vec()).unwrap();
+        assert_eq!(unsigned.algo(), signer.algo());
+
+        let message = unsigned.message(&sha).unwrap();
+        let mut signature = vec![0; unsigned.algo().sig_len()];
+        signer.sign(&message, &mut signature).unwrap();
+
+        assert!(matches!(
+            unsigned
+                .clone()
+                .attach_signature(&signature[1..], &sha, &mut rsa),
+            Err(EncodingError::BadSignatureLength)
+        ));
+        assert!(matches!(
+            unsigned
+                .clone()
+                .attach_signature(&signature, &sha, &mut ecdsa),
+            Err(EncodingError::SignatureError(sig::Error::Unsupported))
+        ));
+        let mut bad = signature.clone();
+        bad[0] ^= 1;
+        assert!(matches!(
+            unsigned.clone().attach_signature(&bad, &sha, &mut rsa),
+            Err(EncodingError::SignatureError(_))
+        ));
+
+        let bytes = unsigned
+            .attach_signature(&signature, &sha, &mut rsa)
+            .unwrap();
+        assert_eq!(bytes, km.sign(0x00, &sha, &mut signer).unwrap());
+        let parse = Km::parse(&bytes, &sha, Some(&mut rsa)).unwrap();
+        assert!(!parse.bad_signature);
+        assert_eq!(parse.container, km);
+
+        let mut truncated = bytes.clone();
+        truncated.truncate(bytes.len() - signature.len() - 1);
+        assert!(Unsigned::from_bytes(truncated).is_err());
+    }
+}
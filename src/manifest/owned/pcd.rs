@@ -0,0 +1,857 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! PCD element structures.
+//!
+//! See [`owned::Pcd`](../type.Pcd.html).
+
+use core::convert::TryInto;
+
+use crate::crypto::ring::sha256::Builder as RingSha;
+use crate::hardware::flash::Flash;
+use crate::manifest;
+use crate::manifest::owned;
+use crate::manifest::owned::EncodingError;
+use crate::manifest::pcd::Connection;
+use crate::manifest::pcd::ElementType;
+use crate::manifest::pcd::I2cInfo;
+use crate::manifest::provenance;
+use crate::manifest::Error;
+use crate::manifest::ManifestType;
+use crate::mem::misalign_of;
+use crate::mem::Arena as _;
+use crate::mem::BumpArena;
+
+use crate::protocol::wire::WireEnum as _;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// An owned PCD element.
+///
+/// Note that variants are listed from most to least specific, since
+/// deserialization picks the first variant whose fields are all present.
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(untagged))]
+#[allow(missing_docs)]
+pub enum Element {
+    BridgeComponent {
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_bin",
+            )
+        )]
+        policy: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_hex",
+            )
+        )]
+        power_ctrl_reg: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_bin",
+            )
+        )]
+        power_ctrl_mask: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_bytestring",
+                serialize_with = "crate::serde::se_bytestring",
+            )
+        )]
+        component_type: Vec<u8>,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_hex",
+            )
+        )]
+        device_id: u16,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_hex",
+            )
+        )]
+        vendor_id: u16,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_hex",
+            )
+        )]
+        subsys_device_id: u16,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_hex",
+            )
+        )]
+        subsys_vendor_id: u16,
+        #[cfg_attr(
+            feature = "serde",
+            serde(deserialize_with = "crate::serde::de_radix")
+        )]
+        component_count: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_hex",
+            )
+        )]
+        eid: u8,
+    },
+    DirectComponent {
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_bin",
+            )
+        )]
+        policy: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_hex",
+            )
+        )]
+        power_ctrl_reg: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_bin",
+            )
+        )]
+        power_ctrl_mask: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_bytestring",
+                serialize_with = "crate::serde::se_bytestring",
+            )
+        )]
+        component_type: Vec<u8>,
+        i2c: I2c,
+    },
+    Port {
+        #[cfg_attr(
+            feature = "serde",
+            serde(deserialize_with = "crate::serde::de_radix")
+        )]
+        port_id: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_bin",
+            )
+        )]
+        port_flags: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_bin",
+            )
+        )]
+        policy: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(deserialize_with = "crate::serde::de_radix")
+        )]
+        pulse_interval: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(deserialize_with = "crate::serde::de_radix")
+        )]
+        spi_frequency_hz: u32,
+    },
+    Rot {
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_bin",
+            )
+        )]
+        rot_flags: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(deserialize_with = "crate::serde::de_radix")
+        )]
+        port_count: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(deserialize_with = "crate::serde::de_radix")
+        )]
+        component_count: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_hex",
+            )
+        )]
+        rot_address: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_hex",
+            )
+        )]
+        rot_eid: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_hex",
+            )
+        )]
+        bridge_address: u8,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_radix",
+                serialize_with = "crate::serde::se_hex",
+            )
+        )]
+        bridge_eid: u8,
+    },
+    PowerController {
+        power_controller: I2c,
+    },
+    PlatformId {
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                deserialize_with = "crate::serde::de_bytestring",
+                serialize_with = "crate::serde::se_bytestring",
+            )
+        )]
+        platform_id: Vec<u8>,
+    },
+    Raw(owned::Raw),
+}
+
+/// An I2C connection description.
+#[allow(missing_docs)]
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct I2c {
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            deserialize_with = "crate::serde::de_radix",
+            serialize_with = "crate::serde::se_bin",
+        )
+    )]
+    pub flags: u8,
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::serde::de_radix")
+    )]
+    pub bus: u8,
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            deserialize_with = "crate::serde::de_radix",
+            serialize_with = "crate::serde::se_hex",
+        )
+    )]
+    pub address: u8,
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            deserialize_with = "crate::serde::de_radix",
+            serialize_with = "crate::serde::se_hex",
+        )
+    )]
+    pub eid: u8,
+    pub muxes: Vec<I2cMux>,
+}
+
+/// An I2C mux along an [`I2c`] connection.
+#[allow(missing_docs)]
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct I2cMux {
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            deserialize_with = "crate::serde::de_radix",
+            serialize_with = "crate::serde::se_hex",
+        )
+    )]
+    pub address: u8,
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "crate::serde::de_radix")
+    )]
+    pub channel: u8,
+}
+
+impl I2c {
+    fn from_info(info: &I2cInfo) -> Self {
+        I2c {
+            flags: info.raw_flags(),
+            bus: info.bus(),
+            address: info.address(),
+            eid: info.eid(),
+            muxes: info
+                .muxes()
+                .map(|m| I2cMux {
+                    address: m.address,
+                    channel: m.channel,
+                })
+                .collect(),
+        }
+    }
+
+    fn encode(
+        &self,
+        padding_byte: u8,
+        bytes: &mut Vec<u8>,
+    ) -> Result<(), EncodingError> {
+        let mux_count: u8 = self
+            .muxes
+            .len()
+            .try_into()
+            .map_err(|_| EncodingError::TooManyElements)?;
+        bytes.extend_from_slice(&[
+            mux_count,
+            self.flags,
+            self.bus,
+            self.address,
+            self.eid,
+            padding_byte,
+            padding_byte,
+            padding_byte,
+        ]);
+        for mux in &self.muxes {
+            bytes.extend_from_slice(&[
+                mux.address,
+                mux.channel,
+                padding_byte,
+                padding_byte,
+            ]);
+        }
+        Ok(())
+    }
+}
+
+/// Encodes the common header shared by both kinds of component.
+fn encode_component_header(
+    policy: u8,
+    power_ctrl_reg: u8,
+    power_ctrl_mask: u8,
+    component_type: &[u8],
+    padding_byte: u8,
+) -> Result<Vec<u8>, EncodingError> {
+    let type_len: u8 = component_type
+        .len()
+        .try_into()
+        .map_err(|_| EncodingError::StringTooLong(component_type.to_vec()))?;
+    let mut bytes = vec![policy, power_ctrl_reg, power_ctrl_mask, type_len];
+
+    bytes.extend_from_slice(component_type);
+    for _ in 0..misalign_of(bytes.len(), 4) {
+        bytes.push(padding_byte);
+    }
+    Ok(bytes)
+}
+
+impl owned::Element for Element {
+    type ElementType = ElementType;
+    const TYPE: ManifestType = ManifestType::Pcd;
+
+    fn element_type(&self) -> u8 {
+        let ty = match self {
+            Self::BridgeComponent { .. } => ElementType::BridgeComponent,
+            Self::DirectComponent { .. } => ElementType::DirectComponent,
+            Self::Port { .. } => ElementType::SpiFlashPort,
+            Self::Rot { .. } => ElementType::Rot,
+            Self::PowerController { .. } => ElementType::PowerController,
+            Self::PlatformId { .. } => ElementType::PlatformId,
+            Self::Raw(raw) => return raw.element_type,
+        };
+        ty.to_wire_value()
+    }
+
+    fn format_version(&self) -> u8 {
+        match self {
+            Self::Raw(raw) => raw.format_version,
+            _ => 0,
+        }
+    }
+
+    fn to_bytes(&self, padding_byte: u8) -> Result<Vec<u8>, EncodingError> {
+        match self {
+            Self::BridgeComponent {
+                policy,
+                power_ctrl_reg,
+                power_ctrl_mask,
+                component_type,
+                device_id,
+                vendor_id,
+                subsys_device_id,
+                subsys_vendor_id,
+                component_count,
+                eid,
+            } => {
+                let mut bytes = encode_component_header(
+                    *policy,
+                    *power_ctrl_reg,
+                    *power_ctrl_mask,
+                    component_type,
+                    padding_byte,
+                )?;
+                bytes.extend_from_slice(&device_id.to_le_bytes());
+                bytes.extend_from_slice(&vendor_id.to_le_bytes());
+                bytes.extend_from_slice(&subsys_device_id.to_le_bytes());
+                bytes.extend_from_slice(&subsys_vendor_id.to_le_bytes());
+                bytes.extend_from_slice(&[
+                    *component_count,
+                    *eid,
+                    padding_byte,
+                    padding_byte,
+                ]);
+                Ok(bytes)
+            }
+            Self::DirectComponent {
+                policy,
+                power_ctrl_reg,
+                power_ctrl_mask,
+                component_type,
+                i2c,
+            } => {
+                let mut bytes = encode_component_header(
+                    *policy,
+                    *power_ctrl_reg,
+                    *power_ctrl_mask,
+                    component_type,
+                    padding_byte,
+                )?;
+                i2c.encode(padding_byte, &mut bytes)?;
+                Ok(bytes)
+            }
+            Self::Port {
+                port_id,
+                port_flags,
+                policy,
+                pulse_interval,
+                spi_frequency_hz,
+            } => {
+                let mut bytes =
+                    vec![*port_id, *port_flags, *policy, *pulse_interval];
+                bytes.extend_from_slice(&spi_frequency_hz.to_le_bytes());
+                Ok(bytes)
+            }
+            Self::Rot {
+                rot_flags,
+                port_count,
+                component_count,
+                rot_address,
+                rot_eid,
+                bridge_address,
+                bridge_eid,
+            } => Ok(vec![
+                *rot_flags,
+                *port_count,
+                *component_count,
+                *rot_address,
+                *rot_eid,
+                *bridge_address,
+                *bridge_eid,
+                padding_byte,
+            ]),
+            Self::PowerController { power_controller } => {
+                let mut bytes = Vec::new();
+                power_controller.encode(padding_byte, &mut bytes)?;
+                Ok(bytes)
+            }
+            Self::PlatformId { platform_id: id } => {
+                let id_len: u8 = id
+                    .len()
+                    .try_into()
+                    .map_err(|_| EncodingError::StringTooLong(id.clone()))?;
+                let mut bytes = vec![padding_byte; 4];
+                bytes[0] = id_len;
+
+                bytes.extend_from_slice(&id);
+                for _ in 0..misalign_of(bytes.len(), 4) {
+                    bytes.push(padding_byte);
+                }
+
+                Ok(bytes)
+            }
+            Self::Raw(raw) => Ok(raw.data.clone()),
+        }
+    }
+
+    fn from_raw(raw: owned::Raw) -> Self {
+        Self::Raw(raw)
+    }
+}
+
+impl<'f, F: 'f + Flash> owned::FromUnowned<'f, F> for Element {
+    type Manifest = manifest::pcd::Pcd;
+
+    fn from_container(
+        container: manifest::Container<
+            'f,
+            Self::Manifest,
+            F,
+            provenance::Adhoc,
+        >,
+    ) -> Result<Vec<(usize, Self)>, Error> {
+        let mut arena = vec![0; 2048];
+        let mut arena = BumpArena::new(&mut arena);
+        let pcd = manifest::pcd::ParsedPcd::new(container);
+        let sha = RingSha::new();
+        let mut elements = Vec::new();
+
+        if let Some(id) =
+            owned::or_raw(pcd.platform_id(&sha, &arena))?.flatten()
+        {
+            elements.push((
+                id.entry().index(),
+                Element::PlatformId {
+                    platform_id: id.id_string().to_vec(),
+                },
+            ));
+        }
+        arena.reset();
+
+        if let Some(rot) = owned::or_raw(pcd.rot(&sha, &arena))?.flatten() {
+            elements.push((
+                rot.entry().index(),
+                Element::Rot {
+                    rot_flags: rot.raw_flags(),
+                    port_count: rot.port_count() as u8,
+                    component_count: rot.component_count() as u8,
+                    rot_address: rot.address(),
+                    rot_eid: rot.eid(),
+                    bridge_address: rot.bridge_address(),
+                    bridge_eid: rot.bridge_eid(),
+                },
+            ));
+
+            for port in rot.ports() {
+                let port = match owned::or_raw(port.read(&sha, &arena))? {
+                    Some(port) => port,
+                    None => continue,
+                };
+                elements.push((
+                    port.entry().index(),
+                    Element::Port {
+                        port_id: port.port_id(),
+                        port_flags: port.raw_flags(),
+                        policy: port
+                            .policy()
+                            .map(|p| p.to_wire_value())
+                            .unwrap_or(0),
+                        pulse_interval: port.pulse_interval(),
+                        spi_frequency_hz: port.spi_frequency_hz(),
+                    },
+                ));
+            }
+        }
+        arena.reset();
+
+        for controller in pcd.power_controllers() {
+            if let Some(controller) =
+                owned::or_raw(controller.read(&sha, &arena))?
+            {
+                elements.push((
+                    controller.entry().index(),
+                    Element::PowerController {
+                        power_controller: I2c::from_info(controller.i2c()),
+                    },
+                ));
+            }
+            arena.reset();
+        }
+
+        for component in pcd.components() {
+            let component = match owned::or_raw(component.read(&sha, &arena))? {
+                Some(component) => component,
+                None => {
+                    arena.reset();
+                    continue;
+                }
+            };
+            let policy =
+                component.policy().map(|p| p.to_wire_value()).unwrap_or(0);
+            let element = match component.connection() {
+                Connection::Direct(i2c) => Element::DirectComponent {
+                    policy,
+                    power_ctrl_reg: component.power_ctrl_reg(),
+                    power_ctrl_mask: component.power_ctrl_mask(),
+                    component_type: component.component_type().to_vec(),
+                    i2c: I2c::from_info(i2c),
+                },
+                Connection::Bridge(bridge) => Element::BridgeComponent {
+                    policy,
+                    power_ctrl_reg: component.power_ctrl_reg(),
+                    power_ctrl_mask: component.power_ctrl_mask(),
+                    component_type: component.component_type().to_vec(),
+                    device_id: bridge.device_id(),
+                    vendor_id: bridge.vendor_id(),
+                    subsys_device_id: bridge.subsys_device_id(),
+                    subsys_vendor_id: bridge.subsys_vendor_id(),
+                    component_count: bridge.component_count() as u8,
+                    eid: bridge.eid(),
+                },
+            };
+            elements.push((component.entry().index(), element));
+            arena.reset();
+        }
+
+        Ok(elements)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::crypto::ring::sha256;
+    use crate::crypto::testdata;
+    use crate::manifest::owned;
+    use crate::manifest::owned::Pcd;
+    use crate::manifest::Metadata;
+
+    use pretty_assertions::assert_eq;
+    use serde_json::from_str;
+
+    #[test]
+    fn parse_elements() {
+        #[rustfmt::skip]
+        let pcd: Pcd = from_str(r#"{
+            "version_id": 42,
+            "elements": [
+                { "platform_id": "my cool platform" },
+                {
+                    "rot_flags": "0b0",
+                    "port_count": 1,
+                    "component_count": 1,
+                    "rot_address": "0x41",
+                    "rot_eid": "0x0b",
+                    "bridge_address": "0x10",
+                    "bridge_eid": "0x08",
+                    "children": [{
+                        "port_id": 0,
+                        "port_flags": "0b1",
+                        "policy": "0b0",
+                        "pulse_interval": 0,
+                        "spi_frequency_hz": 50000000
+                    }]
+                },
+                {
+                    "policy": "0b1",
+                    "power_ctrl_reg": "0x10",
+                    "power_ctrl_mask": "0b1",
+                    "component_type": "nic",
+                    "hashed": false,
+                    "i2c": {
+                        "flags": 0,
+                        "bus": 1,
+                        "address": "0x33",
+                        "eid": "0x0d",
+                        "muxes": [{ "address": "0x70", "channel": 2 }]
+                    }
+                }
+            ]
+        }"#).unwrap();
+
+        assert_eq!(
+            pcd,
+            owned::Container {
+                metadata: Metadata { version_id: 42 },
+                elements: vec![
+                    owned::Node {
+                        element: Element::PlatformId {
+                            platform_id: b"my cool platform".to_vec(),
+                        },
+                        children: vec![],
+                        hashed: true,
+                    },
+                    owned::Node {
+                        element: Element::Rot {
+                            rot_flags: 0,
+                            port_count: 1,
+                            component_count: 1,
+                            rot_address: 0x41,
+                            rot_eid: 0x0b,
+                            bridge_address: 0x10,
+                            bridge_eid: 0x08,
+                        },
+                        children: vec![owned::Node {
+                            element: Element::Port {
+                                port_id: 0,
+                                port_flags: 1,
+                                policy: 0,
+                                pulse_interval: 0,
+                                spi_frequency_hz: 50_000_000,
+                            },
+                            children: vec![],
+                            hashed: true,
+                        }],
+                        hashed: true,
+                    },
+                    owned::Node {
+                        element: Element::DirectComponent {
+                            policy: 1,
+                            power_ctrl_reg: 0x10,
+                            power_ctrl_mask: 1,
+                            component_type: b"nic".to_vec(),
+                            i2c: I2c {
+                                flags: 0,
+                                bus: 1,
+                                address: 0x33,
+                                eid: 0x0d,
+                                muxes: vec![I2cMux {
+                                    address: 0x70,
+                                    channel: 2,
+                                }],
+                            },
+                        },
+                        children: vec![],
+                        hashed: false,
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let i2c = I2c {
+            flags: 0,
+            bus: 3,
+            address: 0x22,
+            eid: 0x0c,
+            muxes: vec![
+                I2cMux {
+                    address: 0x70,
+                    channel: 1,
+                },
+                I2cMux {
+                    address: 0x71,
+                    channel: 5,
+                },
+            ],
+        };
+        let pcd = owned::Container {
+            metadata: Metadata { version_id: 42 },
+            elements: vec![
+                owned::Node {
+                    element: Element::PlatformId {
+                        platform_id: b"abcdfg".to_vec(),
+                    },
+                    children: vec![],
+                    hashed: false,
+                },
+                owned::Node {
+                    element: Element::Rot {
+                        rot_flags: 1,
+                        port_count: 2,
+                        component_count: 2,
+                        rot_address: 0x41,
+                        rot_eid: 0x0b,
+                        bridge_address: 0x10,
+                        bridge_eid: 0x08,
+                    },
+                    children: vec![
+                        owned::Node {
+                            element: Element::Port {
+                                port_id: 0,
+                                port_flags: 0b1010,
+                                policy: 1,
+                                pulse_interval: 10,
+                                spi_frequency_hz: 33_000_000,
+                            },
+                            children: vec![],
+                            hashed: true,
+                        },
+                        owned::Node {
+                            element: Element::Port {
+                                port_id: 1,
+                                port_flags: 0,
+                                policy: 0,
+                                pulse_interval: 0,
+                                spi_frequency_hz: 50_000_000,
+                            },
+                            children: vec![],
+                            hashed: false,
+                        },
+                    ],
+                    hashed: true,
+                },
+                owned::Node {
+                    element: Element::PowerController {
+                        power_controller: i2c.clone(),
+                    },
+                    children: vec![],
+                    hashed: true,
+                },
+                owned::Node {
+                    element: Element::DirectComponent {
+                        policy: 1,
+                        power_ctrl_reg: 0x10,
+                        power_ctrl_mask: 0b100,
+                        component_type: b"nic".to_vec(),
+                        i2c,
+                    },
+                    children: vec![],
+                    hashed: true,
+                },
+                owned::Node {
+                    element: Element::BridgeComponent {
+                        policy: 0,
+                        power_ctrl_reg: 0x11,
+                        power_ctrl_mask: 0b1,
+                        component_type: b"gpu".to_vec(),
+                        device_id: 0x1234,
+                        vendor_id: 0x5678,
+                        subsys_device_id: 0x9abc,
+                        subsys_vendor_id: 0xdef0,
+                        component_count: 4,
+                        eid: 0x20,
+                    },
+                    children: vec![],
+                    hashed: true,
+                },
+            ],
+        };
+        let sha = sha256::Builder::new();
+        let (mut rsa, mut signer) = testdata::rsa();
+
+        let bytes = pcd.sign(0x00, &sha, &mut signer).unwrap();
+        let pcd2 =
+            owned::Container::parse(&bytes, &sha, Some(&mut rsa)).unwrap();
+        assert!(!pcd2.bad_signature);
+        assert!(!pcd2.bad_toc_hash);
+        assert!(pcd2.bad_hashes.is_empty());
+        assert_eq!(pcd, pcd2.container);
+    }
+}
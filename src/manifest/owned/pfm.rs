@@ -11,7 +11,6 @@
 use core::convert::TryInto;
 
 use crate::crypto::ring::sha256::Builder as RingSha;
-use crate::crypto::sha256;
 use crate::hardware::flash::Flash;
 use crate::hardware::flash::Region;
 use crate::manifest;
@@ -31,6 +30,9 @@ use crate::protocol::wire::WireEnum as _;
 #[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
+/// The newest `FwVersion` format version that can be decoded and encoded.
+const FW_VERSION_MAX_FORMAT: u8 = 1;
+
 /// An owned PFM element.
 #[derive(Clone, PartialEq, Eq, Debug, Hash)]
 #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
@@ -46,6 +48,12 @@ pub enum Element {
             )
         )]
         blank_byte: u8,
+        /// If present, this element is encoded with format version 1.
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "Option::is_none")
+        )]
+        firmware_count: Option<u8>,
     },
     AllowableFw {
         #[cfg_attr(
@@ -89,6 +97,13 @@ pub enum Element {
         version_str: Vec<u8>,
         rw_regions: Vec<Rw>,
         image_regions: Vec<Image>,
+        /// The format version to encode this element with. Images hashed
+        /// with anything but SHA-256 require version 1.
+        #[cfg_attr(
+            feature = "serde",
+            serde(default, skip_serializing_if = "crate::serde::skip_if_zero")
+        )]
+        format_version: u8,
     },
     PlatformId {
         #[cfg_attr(
@@ -100,6 +115,7 @@ pub enum Element {
         )]
         platform_id: Vec<u8>,
     },
+    Raw(owned::Raw),
 }
 
 /// A read-write region.
@@ -132,7 +148,7 @@ pub struct Image {
     )]
     pub flags: u8,
     pub hash_type: HashType,
-    pub hash: sha256::Digest,
+    pub hash: Vec<u8>,
     pub regions: Vec<Region>,
 }
 
@@ -140,20 +156,40 @@ impl owned::Element for Element {
     type ElementType = ElementType;
     const TYPE: ManifestType = ManifestType::Pfm;
 
-    fn element_type(&self) -> ElementType {
-        match self {
+    fn element_type(&self) -> u8 {
+        let ty = match self {
             Self::FlashDevice { .. } => ElementType::FlashDevice,
             Self::AllowableFw { .. } => ElementType::AllowableFw,
             Self::FwVersion { .. } => ElementType::FwVersion,
             Self::PlatformId { .. } => ElementType::PlatformId,
+            Self::Raw(raw) => return raw.element_type,
+        };
+        ty.to_wire_value()
+    }
+
+    fn format_version(&self) -> u8 {
+        match self {
+            Self::FlashDevice {
+                firmware_count: Some(_),
+                ..
+            } => 1,
+            Self::FwVersion { format_version, .. } => *format_version,
+            Self::Raw(raw) => raw.format_version,
+            _ => 0,
         }
     }
 
     fn to_bytes(&self, padding_byte: u8) -> Result<Vec<u8>, EncodingError> {
         match self {
-            Self::FlashDevice { blank_byte } => {
+            Self::FlashDevice {
+                blank_byte,
+                firmware_count,
+            } => {
                 let mut bytes = vec![padding_byte; 4];
                 bytes[0] = *blank_byte;
+                if let Some(count) = firmware_count {
+                    bytes[1] = *count;
+                }
                 Ok(bytes)
             }
             Self::AllowableFw {
@@ -180,7 +216,17 @@ impl owned::Element for Element {
                 version_str,
                 rw_regions,
                 image_regions,
+                format_version,
             } => {
+                let sha256_only = image_regions
+                    .iter()
+                    .all(|i| i.hash_type == HashType::Sha256);
+                if *format_version > FW_VERSION_MAX_FORMAT
+                    || (*format_version == 0 && !sha256_only)
+                {
+                    return Err(EncodingError::BadFormatVersion);
+                }
+
                 let rw_len: u8 = rw_regions
                     .len()
                     .try_into()
@@ -227,6 +273,9 @@ impl owned::Element for Element {
                         image.flags,
                         padding_byte,
                     ]);
+                    if image.hash.len() != image.hash_type.digest_len() {
+                        return Err(EncodingError::BadHashLength);
+                    }
                     bytes.extend_from_slice(&image.hash);
                     for region in &image.regions {
                         let (start, end) = region
@@ -254,8 +303,13 @@ impl owned::Element for Element {
 
                 Ok(bytes)
             }
+            Self::Raw(raw) => Ok(raw.data.clone()),
         }
     }
+
+    fn from_raw(raw: owned::Raw) -> Self {
+        Self::Raw(raw)
+    }
 }
 
 impl<'f, F: 'f + Flash> owned::FromUnowned<'f, F> for Element {
@@ -268,88 +322,120 @@ impl<'f, F: 'f + Flash> owned::FromUnowned<'f, F> for Element {
             F,
             provenance::Adhoc,
         >,
-    ) -> Result<Vec<owned::Node<Self>>, Error> {
+    ) -> Result<Vec<(usize, Self)>, Error> {
         let mut arena = vec![0; 2048];
         let mut arena = BumpArena::new(&mut arena);
         let pfm = manifest::pfm::ParsedPfm::new(container);
         let sha = RingSha::new();
-        let mut nodes = Vec::new();
-
-        if let Some(id) = pfm.platform_id(&sha, &arena)? {
-            nodes.push(owned::Node {
-                element: Element::PlatformId {
+        let mut elements = Vec::new();
+
+        if let Some(id) =
+            owned::or_raw(pfm.platform_id(&sha, &arena))?.flatten()
+        {
+            elements.push((
+                id.entry().index(),
+                Element::PlatformId {
                     platform_id: id.id_string().to_vec(),
                 },
-                hashed: id.entry().hash().is_some(),
-                children: Vec::new(),
-            })
+            ));
         }
         arena.reset();
 
-        if let Some(info) = pfm.flash_device_info(&sha, &arena)? {
-            nodes.push(owned::Node {
-                element: Element::FlashDevice {
-                    blank_byte: info.blank_byte(),
-                },
-                hashed: info.entry().hash().is_some(),
-                children: Vec::new(),
-            })
+        for device in pfm.flash_devices() {
+            if let Some(info) = owned::or_raw(device.read(&sha, &arena))? {
+                elements.push((
+                    info.entry().index(),
+                    Element::FlashDevice {
+                        blank_byte: info.blank_byte(),
+                        firmware_count: info.firmware_count().map(|c| c as u8),
+                    },
+                ));
+            }
+            arena.reset();
         }
-        arena.reset();
 
+        // This includes the `AllowableFw`s nested under version 1
+        // `FlashDevice`s, too.
         for allowable_fw in pfm.allowable_fws() {
-            let allowable_fw = allowable_fw.read(&sha, &arena)?;
-
-            let mut node = owned::Node {
-                element: Element::AllowableFw {
-                    version_count: allowable_fw.firmware_count() as u8,
-                    firmware_id: allowable_fw.firmware_id().to_vec(),
-                    flags: allowable_fw.raw_flags(),
-                },
-                hashed: allowable_fw.entry().hash().is_some(),
-                children: Vec::new(),
-            };
-
-            for fw in allowable_fw.firmware_versions() {
-                let fw = fw.read(&sha, &arena)?;
+            decode_allowable_fw(allowable_fw, &arena, &mut elements)?;
+            arena.reset();
+        }
 
-                let mut rw_regions = Vec::new();
-                for rw in fw.rw_regions() {
-                    rw_regions.push(Rw {
-                        flags: rw.raw_flags(),
-                        region: rw.region(),
-                    });
-                }
+        Ok(elements)
+    }
+}
 
-                let mut image_regions = Vec::new();
-                for image in fw.image_regions() {
-                    image_regions.push(Image {
-                        flags: image.raw_flags(),
-                        hash_type: HashType::Sha256,
-                        hash: *image.image_hash(),
-                        regions: image.regions().collect(),
-                    });
-                }
+/// Decodes an `AllowableFw` and all of its `FwVersion`s.
+fn decode_allowable_fw<'pfm, F: Flash>(
+    allowable_fw: manifest::pfm::AllowableFwEntry<
+        '_,
+        'pfm,
+        F,
+        provenance::Adhoc,
+    >,
+    arena: &'pfm BumpArena,
+    elements: &mut Vec<(usize, Element)>,
+) -> Result<(), Error> {
+    let sha = RingSha::new();
+    // If the `AllowableFw` itself cannot be decoded, its `FwVersion`s cannot
+    // be found either, so they are all kept raw.
+    let allowable_fw = match owned::or_raw(allowable_fw.read(&sha, arena))? {
+        Some(allowable_fw) => allowable_fw,
+        None => return Ok(()),
+    };
+
+    elements.push((
+        allowable_fw.entry().index(),
+        Element::AllowableFw {
+            version_count: allowable_fw.firmware_count() as u8,
+            firmware_id: allowable_fw.firmware_id().to_vec(),
+            flags: allowable_fw.raw_flags(),
+        },
+    ));
+
+    for fw in allowable_fw.firmware_versions() {
+        // Newer versions may carry fields this type cannot represent, and may
+        // not even be laid out like the versions this crate knows about.
+        if fw.entry().format_version() > FW_VERSION_MAX_FORMAT {
+            continue;
+        }
+        let fw = match owned::or_raw(fw.read(&sha, arena))? {
+            Some(fw) => fw,
+            None => continue,
+        };
 
-                let (version_region, version_str) = fw.version();
-                node.children.push(owned::Node {
-                    element: Element::FwVersion {
-                        version_addr: version_region.offset,
-                        version_str: version_str.to_vec(),
-                        rw_regions,
-                        image_regions,
-                    },
-                    hashed: fw.entry().hash().is_some(),
-                    children: Vec::new(),
-                });
-            }
+        let mut rw_regions = Vec::new();
+        for rw in fw.rw_regions() {
+            rw_regions.push(Rw {
+                flags: rw.raw_flags(),
+                region: rw.region(),
+            });
+        }
 
-            nodes.push(node);
-            arena.reset();
+        let mut image_regions = Vec::new();
+        for image in fw.image_regions() {
+            image_regions.push(Image {
+                flags: image.raw_flags(),
+                hash_type: image.hash_type(),
+                hash: image.image_hash().to_vec(),
+                regions: image.regions().collect(),
+            });
         }
 
-        Ok(nodes)
+        let (version_region, version_str) = fw.version();
+        elements.push((
+            fw.entry().index(),
+            Element::FwVersion {
+                version_addr: version_region.offset,
+                version_str: version_str.to_vec(),
+                rw_regions,
+                image_regions,
+                format_version: fw.entry().format_version(),
+            },
+        ));
     }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -358,9 +444,12 @@ mod test {
 
     use crate::crypto::ring::sha256;
     use crate::crypto::testdata;
+    use crate::hardware::flash::Ram;
     use crate::manifest::owned;
+    use crate::manifest::owned::Element as _;
     use crate::manifest::owned::Pfm;
     use crate::manifest::Metadata;
+    use crate::mem::OutOfMemory;
 
     use pretty_assertions::assert_eq;
     use serde_json::from_str;
@@ -470,7 +559,10 @@ mod test {
                 metadata: Metadata { version_id: 42 },
                 elements: vec![
                     owned::Node {
-                        element: Element::FlashDevice { blank_byte: 0xff },
+                        element: Element::FlashDevice {
+                            blank_byte: 0xff,
+                            firmware_count: None,
+                        },
                         children: vec![],
                         hashed: true,
                     },
@@ -492,7 +584,7 @@ mod test {
                                     Image {
                                         flags: 0o7,
                                         hash_type: HashType::Sha256,
-                                        hash: [42; 32],
+                                        hash: vec![42; 32],
                                         regions: vec![
                                             Region::new(0x10000, 0x1000),
                                             Region::new(0x18000, 0x800),
@@ -501,13 +593,14 @@ mod test {
                                     Image {
                                         flags: 0,
                                         hash_type: HashType::Sha256,
-                                        hash: [77; 32],
+                                        hash: vec![77; 32],
                                         regions: vec![
                                             Region::new(0x20000, 0x800),
                                             Region::new(0x28000, 0x1000),
                                         ],
                                     },
                                 ],
+                                format_version: 0,
                             },
                             children: vec![],
                             hashed: true,
@@ -532,7 +625,10 @@ mod test {
                     hashed: false,
                 },
                 owned::Node {
-                    element: Element::FlashDevice { blank_byte: 0xff },
+                    element: Element::FlashDevice {
+                        blank_byte: 0xff,
+                        firmware_count: None,
+                    },
                     children: vec![],
                     hashed: true,
                 },
@@ -554,7 +650,7 @@ mod test {
                                 Image {
                                     flags: 0o7,
                                     hash_type: HashType::Sha256,
-                                    hash: [42; 32],
+                                    hash: vec![42; 32],
                                     regions: vec![
                                         Region::new(0x10000, 0x1000),
                                         Region::new(0x18000, 0x800),
@@ -563,13 +659,14 @@ mod test {
                                 Image {
                                     flags: 0,
                                     hash_type: HashType::Sha256,
-                                    hash: [77; 32],
+                                    hash: vec![77; 32],
                                     regions: vec![
                                         Region::new(0x20000, 0x800),
                                         Region::new(0x28000, 0x1000),
                                     ],
                                 },
                             ],
+                            format_version: 0,
                         },
                         children: vec![],
                         hashed: true,
@@ -589,4 +686,293 @@ mod test {
         assert!(pfm2.bad_hashes.is_empty());
         assert_eq!(pfm, pfm2.container);
     }
+
+    #[test]
+    fn round_trip_v1() {
+        let version = |addr, hash_type, hash| owned::Node {
+            element: Element::FwVersion {
+                version_addr: addr,
+                version_str: b"v1".to_vec(),
+                rw_regions: vec![],
+                image_regions: vec![Image {
+                    flags: 0b1,
+                    hash_type,
+                    hash,
+                    regions: vec![Region::new(0x10000, 0x1000)],
+                }],
+                format_version: 1,
+            },
+            children: vec![],
+            hashed: true,
+        };
+        let pfm = owned::Container {
+            metadata: Metadata { version_id: 42 },
+            elements: vec![
+                owned::Node {
+                    element: Element::FlashDevice {
+                        blank_byte: 0xff,
+                        firmware_count: Some(1),
+                    },
+                    children: vec![owned::Node {
+                        element: Element::AllowableFw {
+                            version_count: 1,
+                            firmware_id: b"bmc".to_vec(),
+                            flags: 0,
+                        },
+                        children: vec![version(
+                            0x100,
+                            HashType::Sha384,
+                            vec![42; 48],
+                        )],
+                        hashed: true,
+                    }],
+                    hashed: true,
+                },
+                owned::Node {
+                    element: Element::FlashDevice {
+                        blank_byte: 0x00,
+                        firmware_count: Some(1),
+                    },
+                    children: vec![owned::Node {
+                        element: Element::AllowableFw {
+                            version_count: 1,
+                            firmware_id: b"bios".to_vec(),
+                            flags: 0,
+                        },
+                        children: vec![version(
+                            0x200,
+                            HashType::Sha256,
+                            vec![77; 32],
+                        )],
+                        hashed: true,
+                    }],
+                    hashed: true,
+                },
+            ],
+        };
+        let sha = sha256::Builder::new();
+        let (mut rsa, mut signer) = testdata::rsa();
+
+        let bytes = pfm.sign(0x00, &sha, &mut signer).unwrap();
+        let pfm2 =
+            owned::Container::parse(&bytes, &sha, Some(&mut rsa)).unwrap();
+        assert!(!pfm2.bad_signature);
+        assert!(!pfm2.bad_toc_hash);
+        assert!(pfm2.bad_hashes.is_empty());
+        assert_eq!(pfm, pfm2.container);
+    }
+
+    #[test]
+    fn round_trip_raw() {
+        let fw_version = Element::FwVersion {
+            version_addr: 0x100,
+            version_str: b"v1".to_vec(),
+            rw_regions: vec![],
+            image_regions: vec![],
+            format_version: 0,
+        };
+        // A `FwVersion` from the future, with extra trailing fields.
+        let mut future_data = fw_version.to_bytes(0x00).unwrap();
+        future_data.extend_from_slice(&[1, 2, 3, 4]);
+
+        let pfm = owned::Container {
+            metadata: Metadata { version_id: 42 },
+            elements: vec![
+                owned::Node {
+                    element: Element::Raw(owned::Raw {
+                        element_type: 0x7e,
+                        format_version: 3,
+                        data: vec![0xde, 0xad, 0xbe, 0xef],
+                    }),
+                    children: vec![owned::Node {
+                        element: Element::AllowableFw {
+                            version_count: 2,
+                            firmware_id: b"bmc".to_vec(),
+                            flags: 0,
+                        },
+                        children: vec![
+                            owned::Node {
+                                element: fw_version,
+                                children: vec![],
+                                hashed: true,
+                            },
+                            owned::Node {
+                                element: Element::Raw(owned::Raw {
+                                    element_type: ElementType::FwVersion
+                                        .to_wire_value(),
+                                    format_version: 7,
+                                    data: future_data,
+                                }),
+                                children: vec![],
+                                hashed: false,
+                            },
+                        ],
+                        hashed: true,
+                    }],
+                    hashed: true,
+                },
+                owned::Node {
+                    element: Element::PlatformId {
+                        platform_id: b"platform".to_vec(),
+                    },
+                    children: vec![],
+                    hashed: true,
+                },
+            ],
+        };
+        let sha = sha256::Builder::new();
+        let (mut rsa, mut signer) = testdata::rsa();
+
+        let bytes = pfm.sign(0x00, &sha, &mut signer).unwrap();
+        let pfm2 =
+            owned::Container::parse(&bytes, &sha, Some(&mut rsa)).unwrap();
+        assert!(!pfm2.bad_signature);
+        assert!(!pfm2.bad_toc_hash);
+        assert!(pfm2.bad_hashes.is_empty());
+        assert_eq!(pfm, pfm2.container);
+        assert_eq!(
+            bytes,
+            pfm2.container.sign(0x00, &sha, &mut signer).unwrap()
+        );
+
+        let json = serde_json::to_string(&pfm).unwrap();
+        assert_eq!(from_str::<Pfm>(&json).unwrap(), pfm);
+    }
+
+    #[test]
+    fn round_trip_unreadable_future() {
+        // A `FwVersion` from the future, whose image regions are no longer
+        // stored inline; read as a current `FwVersion`, it is truncated.
+        let mut future_data = vec![3, 0, 2, 0];
+        future_data.extend_from_slice(&0x100u32.to_le_bytes());
+        future_data.extend_from_slice(b"v2\0\0");
+
+        let pfm = owned::Container {
+            metadata: Metadata { version_id: 42 },
+            elements: vec![owned::Node {
+                element: Element::AllowableFw {
+                    version_count: 2,
+                    firmware_id: b"bmc".to_vec(),
+                    flags: 0,
+                },
+                children: vec![
+                    owned::Node {
+                        element: Element::Raw(owned::Raw {
+                            element_type: ElementType::FwVersion
+                                .to_wire_value(),
+                            format_version: 2,
+                            data: future_data,
+                        }),
+                        children: vec![],
+                        hashed: true,
+                    },
+                    owned::Node {
+                        element: Element::FwVersion {
+                            version_addr: 0x100,
+                            version_str: b"v1".to_vec(),
+                            rw_regions: vec![],
+                            image_regions: vec![],
+                            format_version: 1,
+                        },
+                        children: vec![],
+                        hashed: true,
+                    },
+                ],
+                hashed: true,
+            }],
+        };
+        let sha = sha256::Builder::new();
+        let (mut rsa, mut signer) = testdata::rsa();
+        let bytes = pfm.sign(0x00, &sha, &mut signer).unwrap();
+
+        // The unowned parser cannot make sense of the newer element.
+        let ram = Ram(&bytes[..]);
+        let container = manifest::Container::<
+            '_,
+            manifest::pfm::Pfm,
+            _,
+            provenance::Adhoc,
+        >::parse(&ram, &OutOfMemory)
+        .unwrap();
+        let parsed = manifest::pfm::ParsedPfm::new(container);
+        let mut arena = vec![0; 1024];
+        let arena = BumpArena::new(&mut arena);
+        let allowable_fw = parsed
+            .allowable_fws()
+            .next()
+            .unwrap()
+            .read(&sha, &arena)
+            .unwrap();
+        let mut versions = allowable_fw.firmware_versions();
+        assert!(versions.next().unwrap().read(&sha, &arena).is_err());
+        assert!(versions.next().unwrap().read(&sha, &arena).is_ok());
+
+        // The owned parser keeps it raw, and still decodes its sibling.
+        let pfm2 =
+            owned::Container::parse(&bytes, &sha, Some(&mut rsa)).unwrap();
+        assert!(!pfm2.bad_signature);
+        assert_eq!(pfm, pfm2.container);
+        assert_eq!(
+            bytes,
+            pfm2.container.sign(0x00, &sha, &mut signer).unwrap()
+        );
+    }
+
+    #[test]
+    fn bad_hash_length() {
+        let pfm = owned::Container {
+            metadata: Metadata { version_id: 42 },
+            elements: vec![owned::Node {
+                element: Element::FwVersion {
+                    version_addr: 0,
+                    version_str: b"v1".to_vec(),
+                    rw_regions: vec![],
+                    image_regions: vec![Image {
+                        flags: 0,
+                        hash_type: HashType::Sha384,
+                        hash: vec![42; 32],
+                        regions: vec![Region::new(0, 0x100)],
+                    }],
+                    format_version: 1,
+                },
+                children: vec![],
+                hashed: true,
+            }],
+        };
+        let sha = sha256::Builder::new();
+        let (_, mut signer) = testdata::rsa();
+        assert!(matches!(
+            pfm.sign(0x00, &sha, &mut signer),
+            Err(EncodingError::BadHashLength)
+        ));
+    }
+
+    #[test]
+    fn bad_format_version() {
+        let version =
+            |format_version, hash_type: HashType| Element::FwVersion {
+                version_addr: 0,
+                version_str: b"v1".to_vec(),
+                rw_regions: vec![],
+                image_regions: vec![Image {
+                    flags: 0,
+                    hash_type,
+                    hash: vec![42; hash_type.digest_len()],
+                    regions: vec![Region::new(0, 0x100)],
+                }],
+                format_version,
+            };
+
+        assert!(version(0, HashType::Sha256).to_bytes(0).is_ok());
+        assert!(version(1, HashType::Sha256).to_bytes(0).is_ok());
+        assert!(version(1, HashType::Sha512).to_bytes(0).is_ok());
+        assert!(matches!(
+            version(0, HashType::Sha512).to_bytes(0),
+            Err(EncodingError::BadFormatVersion)
+        ));
+        assert!(matches!(
+            version(2, HashType::Sha256).to_bytes(0),
+            Err(EncodingError::BadFormatVersion)
+        ));
+    }
 }
@@ -0,0 +1,1020 @@
+// Copyright lowRISC contributors.
+// Licensed under the Apache License, Version 2.0, see LICENSE for details.
+// SPDX-License-Identifier: Apache-2.0
+
+//! The Platform Configuration Data (PCD) manifest.
+//!
+//! A PCD is a computer-readable document describing the topology of the
+//! platform a RoT sits on: which buses it is attached to, which components
+//! sit on those buses and how to address them, and which policies the RoT
+//! should apply to each of them.
+//!
+//! Like the [PFM](../pfm/index.html), the PCD is encoded as a
+//! ["table of contents"](../struct.Toc.html) describing a number of elements,
+//! which can be read out of flash in arbitrary order and are protected by
+//! hashes in the TOC.
+//!
+//! The [`ParsedPcd`] type is the entry-point for this module.
+
+use core::mem;
+
+use zerocopy::FromBytes;
+use zerocopy::LayoutVerified;
+
+use crate::crypto::sha256;
+use crate::hardware::flash::Flash;
+use crate::manifest::provenance;
+use crate::manifest::provenance::Provenance;
+use crate::manifest::Container;
+use crate::manifest::Error;
+use crate::manifest::Manifest;
+use crate::manifest::ManifestType;
+use crate::manifest::Parse;
+use crate::manifest::ParsedManifest;
+use crate::manifest::TocEntry;
+use crate::manifest::ValidationTime;
+use crate::mem::misalign_of;
+use crate::mem::Arena;
+
+use crate::protocol::wire::WireEnum as _;
+
+wire_enum! {
+    /// A PCD element type.
+    pub enum ElementType: u8 {
+      /// A bytestring identifier for the platform that this PCD describes.
+      PlatformId = 0x01,
+
+      /// A description of the RoT itself, including how it can be addressed
+      /// on the platform's buses.
+      Rot = 0x40,
+
+      /// A SPI flash port on the RoT, as a subelement of a `Rot`.
+      SpiFlashPort = 0x41,
+
+      /// An I2C power management controller.
+      PowerController = 0x42,
+
+      /// A component that the RoT talks to over a direct I2C connection.
+      DirectComponent = 0x43,
+
+      /// A component that the RoT talks to through an MCTP bridge.
+      BridgeComponent = 0x44,
+    }
+}
+
+wire_enum! {
+    /// The kind of RoT a PCD describes.
+    pub enum RotType: u8 {
+        /// A Platform Active RoT.
+        PaRot = 0b0,
+        /// An Active Component RoT.
+        AcRot = 0b1,
+    }
+}
+
+wire_enum! {
+    /// A policy for how the RoT treats a port or component.
+    pub enum Policy: u8 {
+        /// The RoT only observes the port or component.
+        Passive = 0b0,
+        /// The RoT actively manages the port or component, such as by holding
+        /// it in reset until it has been verified.
+        Active = 0b1,
+    }
+}
+
+/// A Platform Configuration Data manifest.
+///
+/// This type provides functions for parsing a PCD's table of contents and
+/// using it to extract other portions of the PCD.
+///
+/// This type only maintains the TOC in memory for book-keeping.
+pub struct ParsedPcd<'pcd, Flash, Provenance = provenance::Signed> {
+    container: Container<'pcd, Pcd, Flash, Provenance>,
+}
+
+/// A [`Manifest`] implementation mapping onto [`ParsedPcd`], for use in generic
+/// contexts.
+///
+/// See [`Manifest`] and [`Parse`].
+pub enum Pcd {}
+
+impl Manifest for Pcd {
+    type ElementType = ElementType;
+    const TYPE: ManifestType = ManifestType::Pcd;
+
+    fn min_version(_: ElementType) -> u8 {
+        0
+    }
+}
+
+impl<'f, F: 'f + Flash, P> Parse<'f, F, P> for Pcd {
+    type Parsed = ParsedPcd<'f, F, P>;
+
+    fn parse(
+        container: Container<'f, Self, F, P>,
+    ) -> Result<Self::Parsed, Error> {
+        Ok(ParsedPcd::new(container))
+    }
+
+    fn copy_to<F2: Flash>(
+        manifest: &Self::Parsed,
+        dest: &mut F2,
+    ) -> Result<(), Error> {
+        manifest.container.copy_to(dest)
+    }
+
+    type Guarded = ();
+    fn validate(
+        _manifest: &Self::Parsed,
+        _when: ValidationTime,
+        _args: &Self::Guarded,
+        _sha: &impl sha256::Builder,
+        _arena: &'f impl Arena,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<F, P> ParsedManifest for ParsedPcd<'_, F, P> {
+    type Manifest = Pcd;
+}
+
+impl<'pcd, F, P> ParsedPcd<'pcd, F, P> {
+    /// Creates a new PCD handle using the given `Container`.
+    pub fn new(container: Container<'pcd, Pcd, F, P>) -> Self {
+        ParsedPcd { container }
+    }
+}
+
+/// Reads the element described by `entry` out of `container`, verifying its
+/// hash if the provenance calls for it.
+fn read_element<'pcd, F: Flash, P: Provenance>(
+    container: &Container<'pcd, Pcd, F, P>,
+    entry: TocEntry<'_, 'pcd, Pcd>,
+    sha: &impl sha256::Builder,
+    arena: &'pcd impl Arena,
+) -> Result<&'pcd [u8], Error> {
+    let data = container.flash().read_direct(
+        entry.region(),
+        arena,
+        mem::align_of::<u32>(),
+    )?;
+    if P::AUTHENTICATED {
+        container.verify_read(entry, data, sha)?;
+    }
+    Ok(data)
+}
+
+impl<'pcd, F: Flash, P> ParsedPcd<'pcd, F, P>
+where
+    P: Provenance,
+{
+    /// Extracts the Platform ID from this PCD, allocating it onto the provided
+    /// arena. Returns `None` if the Platform ID is missing.
+    ///
+    /// This function will also verify the hash of the Platform ID, if one is
+    /// present.
+    pub fn platform_id<'a>(
+        &'a self,
+        sha: &impl sha256::Builder,
+        arena: &'pcd impl Arena,
+    ) -> Result<Option<PlatformId<'a, 'pcd>>, Error> {
+        let entry =
+            match self.container.toc().singleton(ElementType::PlatformId) {
+                Some(x) => x,
+                None => return Ok(None),
+            };
+        let data = read_element(&self.container, entry, sha, arena)?;
+
+        #[derive(FromBytes)]
+        #[repr(C)]
+        struct PlatformIdHeader {
+            len: u8,
+            _unused: [u8; 3],
+        }
+        let (header, rest) =
+            LayoutVerified::<_, PlatformIdHeader>::new_from_prefix(data)
+                .ok_or(Error::TooShort {
+                    toc_index: entry.index(),
+                })?;
+
+        let len = header.len as usize;
+        if rest.len() < len {
+            return Err(Error::TooShort {
+                toc_index: entry.index(),
+            });
+        }
+
+        Ok(Some(PlatformId {
+            entry,
+            id: &rest[..len],
+        }))
+    }
+
+    /// Extracts the `Rot` element from this PCD.
+    ///
+    /// This function will also verify the hash of the `Rot` if one is present.
+    pub fn rot<'a>(
+        &'a self,
+        sha: &impl sha256::Builder,
+        arena: &'pcd impl Arena,
+    ) -> Result<Option<Rot<'a, 'pcd, F, P>>, Error> {
+        let entry = match self.container.toc().singleton(ElementType::Rot) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+        let data = read_element(&self.container, entry, sha, arena)?;
+        let header = LayoutVerified::<_, RotHeader>::new_from_prefix(data)
+            .ok_or(Error::TooShort {
+                toc_index: entry.index(),
+            })?
+            .0
+            .into_ref();
+
+        Ok(Some(Rot {
+            pcd: self,
+            entry,
+            header,
+        }))
+    }
+
+    /// Returns an iterator over the `PowerController` elements of this PCD.
+    ///
+    /// The returned values only contain the `Toc` information for the entry,
+    /// allowing the user to lazily select which entries to read from flash.
+    pub fn power_controllers(
+        &self,
+    ) -> impl Iterator<Item = PowerControllerEntry<'_, 'pcd, F, P>> + '_ {
+        self.container
+            .toc()
+            .entries()
+            .filter(|e| e.element_type() == Some(ElementType::PowerController))
+            .map(move |entry| PowerControllerEntry { pcd: self, entry })
+    }
+
+    /// Returns an iterator over the component elements of this PCD, of both
+    /// the `DirectComponent` and `BridgeComponent` variety.
+    ///
+    /// The returned values only contain the `Toc` information for the entry,
+    /// allowing the user to lazily select which entries to read from flash.
+    pub fn components(
+        &self,
+    ) -> impl Iterator<Item = ComponentEntry<'_, 'pcd, F, P>> + '_ {
+        self.container
+            .toc()
+            .entries()
+            .filter(|e| {
+                matches!(
+                    e.element_type(),
+                    Some(ElementType::DirectComponent)
+                        | Some(ElementType::BridgeComponent)
+                )
+            })
+            .map(move |entry| ComponentEntry { pcd: self, entry })
+    }
+}
+
+/// An identifier for the platform a PCD is for.
+pub struct PlatformId<'a, 'pcd> {
+    entry: TocEntry<'a, 'pcd, Pcd>,
+    id: &'pcd [u8],
+}
+
+impl<'a, 'pcd> PlatformId<'a, 'pcd> {
+    /// Returns the `Toc` entry defining this element.
+    pub fn entry(&self) -> TocEntry<'a, 'pcd, Pcd> {
+        self.entry
+    }
+
+    /// Returns the byte-string identifier that represents the platform this
+    /// PCD is for.
+    pub fn id_string(&self) -> &'pcd [u8] {
+        self.id
+    }
+}
+
+#[derive(FromBytes)]
+#[repr(C)]
+struct RotHeader {
+    flags: u8,
+    port_count: u8,
+    component_count: u8,
+    address: u8,
+    eid: u8,
+    bridge_address: u8,
+    bridge_eid: u8,
+    _unused: u8,
+}
+
+/// A description of the RoT a PCD is for.
+///
+/// To obtain a value of this type, see [`ParsedPcd::rot()`].
+pub struct Rot<'a, 'pcd, Flash, Provenance = provenance::Signed> {
+    pcd: &'a ParsedPcd<'pcd, Flash, Provenance>,
+    entry: TocEntry<'a, 'pcd, Pcd>,
+    header: &'pcd RotHeader,
+}
+
+impl<'a, 'pcd, F: Flash, P> Rot<'a, 'pcd, F, P> {
+    /// Returns the `Toc` entry defining this element.
+    pub fn entry(&self) -> TocEntry<'a, 'pcd, Pcd> {
+        self.entry
+    }
+
+    /// Returns the kind of RoT this is.
+    pub fn rot_type(&self) -> RotType {
+        RotType::from_wire_value(self.header.flags & 0b1)
+            .expect("all single-bit values are valid")
+    }
+
+    /// Returns the raw encoded flags for this element.
+    pub fn raw_flags(&self) -> u8 {
+        self.header.flags
+    }
+
+    /// Returns the number of SPI flash ports this RoT has.
+    ///
+    /// Note that this may be inconsistent with the number of children actually
+    /// encoded in the PCD.
+    pub fn port_count(&self) -> usize {
+        self.header.port_count as usize
+    }
+
+    /// Returns the number of components this RoT is responsible for.
+    ///
+    /// Note that this may be inconsistent with the number of components
+    /// actually encoded in the PCD.
+    pub fn component_count(&self) -> usize {
+        self.header.component_count as usize
+    }
+
+    /// Returns the 7-bit I2C address of the RoT.
+    pub fn address(&self) -> u8 {
+        self.header.address
+    }
+
+    /// Returns the MCTP endpoint ID of the RoT.
+    pub fn eid(&self) -> u8 {
+        self.header.eid
+    }
+
+    /// Returns the 7-bit I2C address of the MCTP bridge the RoT is behind.
+    pub fn bridge_address(&self) -> u8 {
+        self.header.bridge_address
+    }
+
+    /// Returns the MCTP endpoint ID of the MCTP bridge the RoT is behind.
+    pub fn bridge_eid(&self) -> u8 {
+        self.header.bridge_eid
+    }
+}
+
+impl<'a, 'pcd, F: Flash, P> Rot<'a, 'pcd, F, P>
+where
+    P: Provenance,
+{
+    /// Returns an iterator over the `SpiFlashPort` subelements of this `Rot`.
+    ///
+    /// The returned values only contain the `Toc` information for the entry,
+    /// allowing the user to lazily select which entries to read from flash.
+    pub fn ports(&self) -> impl Iterator<Item = PortEntry<'a, 'pcd, F, P>> {
+        let pcd = self.pcd;
+        self.entry
+            .children()
+            .filter(|e| e.element_type() == Some(ElementType::SpiFlashPort))
+            .map(move |entry| PortEntry { pcd, entry })
+    }
+}
+
+/// A "SPI flash port" element entry in a PCD's `Toc`.
+///
+/// This type allows for lazily reading the [`Port`] described by this
+/// entry, as obtained from [`Rot::ports()`].
+pub struct PortEntry<'a, 'pcd, Flash, Provenance = provenance::Signed> {
+    pcd: &'a ParsedPcd<'pcd, Flash, Provenance>,
+    entry: TocEntry<'a, 'pcd, Pcd>,
+}
+
+impl<'a, 'pcd, F: Flash, P> PortEntry<'a, 'pcd, F, P>
+where
+    P: Provenance,
+{
+    /// Returns the `Toc` entry defining this element.
+    pub fn entry(&self) -> TocEntry<'a, 'pcd, Pcd> {
+        self.entry
+    }
+
+    /// Reads the contents of this element into memory, verifying its hash
+    /// and potentially allocating it on `arena`.
+    pub fn read(
+        self,
+        sha: &impl sha256::Builder,
+        arena: &'pcd impl Arena,
+    ) -> Result<Port<'a, 'pcd>, Error> {
+        let data = read_element(&self.pcd.container, self.entry, sha, arena)?;
+        let header = LayoutVerified::<_, PortHeader>::new_from_prefix(data)
+            .ok_or(Error::TooShort {
+                toc_index: self.entry.index(),
+            })?
+            .0
+            .into_ref();
+
+        Ok(Port {
+            entry: self.entry,
+            header,
+        })
+    }
+}
+
+#[derive(FromBytes)]
+#[repr(C)]
+struct PortHeader {
+    port_id: u8,
+    flags: u8,
+    policy: u8,
+    pulse_interval: u8,
+    spi_frequency_hz: u32,
+}
+
+/// A SPI flash port on the RoT.
+///
+/// To obtain a value of this type, see [`Rot::ports()`] and
+/// [`PortEntry::read()`].
+pub struct Port<'a, 'pcd> {
+    entry: TocEntry<'a, 'pcd, Pcd>,
+    header: &'pcd PortHeader,
+}
+
+impl<'a, 'pcd> Port<'a, 'pcd> {
+    /// Returns the `Toc` entry defining this element.
+    pub fn entry(&self) -> TocEntry<'a, 'pcd, Pcd> {
+        self.entry
+    }
+
+    /// Returns the RoT-specific identifier for this port.
+    pub fn port_id(&self) -> u8 {
+        self.header.port_id
+    }
+
+    /// Returns the raw encoded flags for this element.
+    ///
+    /// Cerberus uses these to describe the flash mode, reset control, and
+    /// runtime verification settings for the port.
+    pub fn raw_flags(&self) -> u8 {
+        self.header.flags
+    }
+
+    /// Returns the policy the RoT should apply to this port.
+    pub fn policy(&self) -> Option<Policy> {
+        Policy::from_wire_value(self.header.policy & 0b1)
+    }
+
+    /// Returns the reset pulse interval, in milliseconds, for an active port.
+    pub fn pulse_interval(&self) -> u8 {
+        self.header.pulse_interval
+    }
+
+    /// Returns the SPI clock frequency of the port, in Hz.
+    pub fn spi_frequency_hz(&self) -> u32 {
+        self.header.spi_frequency_hz
+    }
+}
+
+#[derive(FromBytes)]
+#[repr(C)]
+struct I2cHeader {
+    mux_count: u8,
+    flags: u8,
+    bus: u8,
+    address: u8,
+    eid: u8,
+    _unused: [u8; 3],
+}
+
+#[derive(FromBytes)]
+#[repr(C)]
+struct RawI2cMux {
+    address: u8,
+    channel: u8,
+    _unused: [u8; 2],
+}
+
+/// Parses an [`I2cInfo`] out of `bytes`, returning it and whatever bytes
+/// follow it.
+fn parse_i2c(
+    bytes: &[u8],
+    toc_index: usize,
+) -> Result<(I2cInfo<'_>, &[u8]), Error> {
+    let (header, rest) = LayoutVerified::<_, I2cHeader>::new_from_prefix(bytes)
+        .ok_or(Error::TooShort { toc_index })?;
+
+    let muxes_len = header.mux_count as usize * mem::size_of::<RawI2cMux>();
+    if rest.len() < muxes_len {
+        return Err(Error::TooShort { toc_index });
+    }
+    let (muxes, rest) = rest.split_at(muxes_len);
+    let muxes = LayoutVerified::<_, [RawI2cMux]>::new_slice(muxes)
+        .ok_or(Error::TooShort { toc_index })?
+        .into_slice();
+
+    Ok((
+        I2cInfo {
+            header: header.into_ref(),
+            muxes,
+        },
+        rest,
+    ))
+}
+
+/// A description of how to reach a device over I2C.
+///
+/// This is shared by [`PowerController`]s and directly-connected
+/// [`Component`]s.
+pub struct I2cInfo<'pcd> {
+    header: &'pcd I2cHeader,
+    muxes: &'pcd [RawI2cMux],
+}
+
+impl I2cInfo<'_> {
+    /// Returns the raw encoded flags for this element.
+    pub fn raw_flags(&self) -> u8 {
+        self.header.flags
+    }
+
+    /// Returns the I2C bus the device is attached to.
+    pub fn bus(&self) -> u8 {
+        self.header.bus
+    }
+
+    /// Returns the 7-bit I2C address of the device.
+    pub fn address(&self) -> u8 {
+        self.header.address
+    }
+
+    /// Returns the MCTP endpoint ID of the device.
+    pub fn eid(&self) -> u8 {
+        self.header.eid
+    }
+
+    /// Returns the number of I2C muxes between the RoT and the device.
+    pub fn mux_count(&self) -> usize {
+        self.muxes.len()
+    }
+
+    /// Returns the `idx`th I2C mux between the RoT and the device, if there
+    /// is one.
+    pub fn mux(&self, idx: usize) -> Option<I2cMux> {
+        let mux = self.muxes.get(idx)?;
+        Some(I2cMux {
+            address: mux.address,
+            channel: mux.channel,
+        })
+    }
+
+    /// Returns an iterator over the I2C muxes between the RoT and the device,
+    /// in the order they need to be configured.
+    pub fn muxes(&self) -> impl Iterator<Item = I2cMux> + '_ {
+        (0..self.mux_count()).map(move |i| self.mux(i).unwrap())
+    }
+}
+
+/// An I2C mux that must be configured to reach a device.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct I2cMux {
+    /// The 7-bit I2C address of the mux.
+    pub address: u8,
+    /// The channel the mux should be set to.
+    pub channel: u8,
+}
+
+/// A "power controller" element entry in a PCD's `Toc`.
+///
+/// This type allows for lazily reading the [`PowerController`] described by
+/// this entry, as obtained from [`ParsedPcd::power_controllers()`].
+pub struct PowerControllerEntry<
+    'a,
+    'pcd,
+    Flash,
+    Provenance = provenance::Signed,
+> {
+    pcd: &'a ParsedPcd<'pcd, Flash, Provenance>,
+    entry: TocEntry<'a, 'pcd, Pcd>,
+}
+
+impl<'a, 'pcd, F: Flash, P> PowerControllerEntry<'a, 'pcd, F, P>
+where
+    P: Provenance,
+{
+    /// Returns the `Toc` entry defining this element.
+    pub fn entry(&self) -> TocEntry<'a, 'pcd, Pcd> {
+        self.entry
+    }
+
+    /// Reads the contents of this element into memory, verifying its hash
+    /// and potentially allocating it on `arena`.
+    pub fn read(
+        self,
+        sha: &impl sha256::Builder,
+        arena: &'pcd impl Arena,
+    ) -> Result<PowerController<'a, 'pcd>, Error> {
+        let data = read_element(&self.pcd.container, self.entry, sha, arena)?;
+        let (i2c, _) = parse_i2c(data, self.entry.index())?;
+        Ok(PowerController {
+            entry: self.entry,
+            i2c,
+        })
+    }
+}
+
+/// An I2C power management controller.
+///
+/// To obtain a value of this type, see [`ParsedPcd::power_controllers()`] and
+/// [`PowerControllerEntry::read()`].
+pub struct PowerController<'a, 'pcd> {
+    entry: TocEntry<'a, 'pcd, Pcd>,
+    i2c: I2cInfo<'pcd>,
+}
+
+impl<'a, 'pcd> PowerController<'a, 'pcd> {
+    /// Returns the `Toc` entry defining this element.
+    pub fn entry(&self) -> TocEntry<'a, 'pcd, Pcd> {
+        self.entry
+    }
+
+    /// Returns a description of how to reach this controller over I2C.
+    pub fn i2c(&self) -> &I2cInfo<'pcd> {
+        &self.i2c
+    }
+}
+
+/// A "component" element entry in a PCD's `Toc`.
+///
+/// This type allows for lazily reading the [`Component`] described by this
+/// entry, as obtained from [`ParsedPcd::components()`].
+pub struct ComponentEntry<'a, 'pcd, Flash, Provenance = provenance::Signed> {
+    pcd: &'a ParsedPcd<'pcd, Flash, Provenance>,
+    entry: TocEntry<'a, 'pcd, Pcd>,
+}
+
+#[derive(FromBytes)]
+#[repr(C)]
+struct ComponentHeader {
+    policy: u8,
+    power_ctrl_reg: u8,
+    power_ctrl_mask: u8,
+    type_len: u8,
+}
+
+#[derive(FromBytes)]
+#[repr(C)]
+struct RawBridgeInfo {
+    device_id: u16,
+    vendor_id: u16,
+    subsys_device_id: u16,
+    subsys_vendor_id: u16,
+    component_count: u8,
+    eid: u8,
+    _unused: [u8; 2],
+}
+
+impl<'a, 'pcd, F: Flash, P> ComponentEntry<'a, 'pcd, F, P>
+where
+    P: Provenance,
+{
+    /// Returns the `Toc` entry defining this element.
+    pub fn entry(&self) -> TocEntry<'a, 'pcd, Pcd> {
+        self.entry
+    }
+
+    /// Reads the contents of this element into memory, verifying its hash
+    /// and potentially allocating it on `arena`.
+    pub fn read(
+        self,
+        sha: &impl sha256::Builder,
+        arena: &'pcd impl Arena,
+    ) -> Result<Component<'a, 'pcd>, Error> {
+        let toc_index = self.entry.index();
+        let data = read_element(&self.pcd.container, self.entry, sha, arena)?;
+
+        let (header, rest) =
+            LayoutVerified::<_, ComponentHeader>::new_from_prefix(data)
+                .ok_or(Error::TooShort { toc_index })?;
+        if rest.len() < header.type_len as usize {
+            return Err(Error::TooShort { toc_index });
+        }
+        let (component_type, mut buf) = rest.split_at(header.type_len as usize);
+
+        // Align back to 4-byte boundary.
+        buf = buf
+            .get(misalign_of(buf.as_ptr() as usize, 4)..)
+            .ok_or(Error::TooShort { toc_index })?;
+
+        let connection = match self.entry.element_type() {
+            Some(ElementType::DirectComponent) => {
+                Connection::Direct(parse_i2c(buf, toc_index)?.0)
+            }
+            _ => {
+                let bridge =
+                    LayoutVerified::<_, RawBridgeInfo>::new_from_prefix(buf)
+                        .ok_or(Error::TooShort { toc_index })?
+                        .0
+                        .into_ref();
+                Connection::Bridge(BridgeInfo { raw: bridge })
+            }
+        };
+
+        Ok(Component {
+            entry: self.entry,
+            header: header.into_ref(),
+            component_type,
+            connection,
+        })
+    }
+}
+
+/// A component on the platform that the RoT is responsible for.
+///
+/// To obtain a value of this type, see [`ParsedPcd::components()`] and
+/// [`ComponentEntry::read()`].
+pub struct Component<'a, 'pcd> {
+    entry: TocEntry<'a, 'pcd, Pcd>,
+    header: &'pcd ComponentHeader,
+    component_type: &'pcd [u8],
+    connection: Connection<'pcd>,
+}
+
+impl<'a, 'pcd> Component<'a, 'pcd> {
+    /// Returns the `Toc` entry defining this element.
+    pub fn entry(&self) -> TocEntry<'a, 'pcd, Pcd> {
+        self.entry
+    }
+
+    /// Returns the policy the RoT should apply to this component.
+    pub fn policy(&self) -> Option<Policy> {
+        Policy::from_wire_value(self.header.policy & 0b1)
+    }
+
+    /// Returns the register on the power controller that controls this
+    /// component.
+    pub fn power_ctrl_reg(&self) -> u8 {
+        self.header.power_ctrl_reg
+    }
+
+    /// Returns the bitmask within [`Component::power_ctrl_reg()`] that
+    /// controls this component.
+    pub fn power_ctrl_mask(&self) -> u8 {
+        self.header.power_ctrl_mask
+    }
+
+    /// Returns the bytestring identifying the type of this component.
+    pub fn component_type(&self) -> &'pcd [u8] {
+        self.component_type
+    }
+
+    /// Returns how the RoT is connected to this component.
+    pub fn connection(&self) -> &Connection<'pcd> {
+        &self.connection
+    }
+}
+
+/// A description of how the RoT is connected to a [`Component`].
+pub enum Connection<'pcd> {
+    /// The component is directly connected over I2C.
+    Direct(I2cInfo<'pcd>),
+    /// The component is reached through an MCTP bridge.
+    Bridge(BridgeInfo<'pcd>),
+}
+
+/// A description of a [`Component`] behind an MCTP bridge.
+pub struct BridgeInfo<'pcd> {
+    raw: &'pcd RawBridgeInfo,
+}
+
+impl BridgeInfo<'_> {
+    /// Returns the PCI device ID of the component.
+    pub fn device_id(&self) -> u16 {
+        self.raw.device_id
+    }
+
+    /// Returns the PCI vendor ID of the component.
+    pub fn vendor_id(&self) -> u16 {
+        self.raw.vendor_id
+    }
+
+    /// Returns the PCI subsystem device ID of the component.
+    pub fn subsys_device_id(&self) -> u16 {
+        self.raw.subsys_device_id
+    }
+
+    /// Returns the PCI subsystem vendor ID of the component.
+    pub fn subsys_vendor_id(&self) -> u16 {
+        self.raw.subsys_vendor_id
+    }
+
+    /// Returns the number of identical components reachable behind the
+    /// bridge.
+    pub fn component_count(&self) -> usize {
+        self.raw.component_count as usize
+    }
+
+    /// Returns the MCTP endpoint ID of the component.
+    pub fn eid(&self) -> u8 {
+        self.raw.eid
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::crypto::ring;
+    use crate::crypto::testdata::rsa as test_rsa;
+    use crate::hardware::flash::Ram;
+    use crate::manifest::owned;
+    use crate::mem::BumpArena;
+    use crate::mem::OutOfMemory;
+
+    use serde_json::from_str;
+
+    #[test]
+    fn empty() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, mut signer) = test_rsa();
+
+        #[rustfmt::skip]
+        let pcd: owned::Pcd = from_str(r#"{
+            "version_id": 42,
+            "elements": []
+        }"#).unwrap();
+        let bytes = Ram(pcd.sign(0x0, &sha, &mut signer).unwrap());
+
+        let container = Container::parse_and_verify(
+            &bytes,
+            &sha,
+            &mut rsa,
+            &OutOfMemory,
+            &OutOfMemory,
+        )
+        .unwrap();
+        let pcd = ParsedPcd::new(container);
+
+        assert!(pcd.platform_id(&sha, &OutOfMemory).unwrap().is_none());
+        assert!(pcd.rot(&sha, &OutOfMemory).unwrap().is_none());
+        assert_eq!(pcd.power_controllers().count(), 0);
+        assert_eq!(pcd.components().count(), 0);
+    }
+
+    #[test]
+    fn topology() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, mut signer) = test_rsa();
+
+        #[rustfmt::skip]
+        let pcd: owned::Pcd = from_str(r#"{
+            "version_id": 42,
+            "elements": [
+                { "platform_id": "my board" },
+                {
+                    "rot_flags": "0b1",
+                    "port_count": 1,
+                    "component_count": 2,
+                    "rot_address": "0x41",
+                    "rot_eid": "0x0b",
+                    "bridge_address": "0x10",
+                    "bridge_eid": "0x08",
+                    "children": [{
+                        "port_id": 0,
+                        "port_flags": "0b1010",
+                        "policy": "0b1",
+                        "pulse_interval": 10,
+                        "spi_frequency_hz": 33000000
+                    }]
+                },
+                {
+                    "power_controller": {
+                        "flags": 0,
+                        "bus": 2,
+                        "address": "0x22",
+                        "eid": "0x0c",
+                        "muxes": [{ "address": "0x70", "channel": 3 }]
+                    }
+                },
+                {
+                    "policy": "0b1",
+                    "power_ctrl_reg": "0x10",
+                    "power_ctrl_mask": "0b100",
+                    "component_type": "nic",
+                    "i2c": {
+                        "flags": 0,
+                        "bus": 1,
+                        "address": "0x33",
+                        "eid": "0x0d",
+                        "muxes": []
+                    }
+                },
+                {
+                    "policy": "0b0",
+                    "power_ctrl_reg": "0x11",
+                    "power_ctrl_mask": "0b1",
+                    "component_type": "accelerator",
+                    "device_id": "0x1234",
+                    "vendor_id": "0x5678",
+                    "subsys_device_id": "0x9abc",
+                    "subsys_vendor_id": "0xdef0",
+                    "component_count": 4,
+                    "eid": "0x20"
+                }
+            ]
+        }"#).unwrap();
+        let bytes = Ram(pcd.sign(0x0, &sha, &mut signer).unwrap());
+
+        let container = Container::parse_and_verify(
+            &bytes,
+            &sha,
+            &mut rsa,
+            &OutOfMemory,
+            &OutOfMemory,
+        )
+        .unwrap();
+        let pcd = ParsedPcd::new(container);
+
+        let id = pcd.platform_id(&sha, &OutOfMemory).unwrap().unwrap();
+        assert_eq!(id.id_string(), b"my board");
+
+        let rot = pcd.rot(&sha, &OutOfMemory).unwrap().unwrap();
+        assert_eq!(rot.rot_type(), RotType::AcRot);
+        assert_eq!(rot.port_count(), 1);
+        assert_eq!(rot.component_count(), 2);
+        assert_eq!(rot.address(), 0x41);
+        assert_eq!(rot.eid(), 0x0b);
+        assert_eq!(rot.bridge_address(), 0x10);
+        assert_eq!(rot.bridge_eid(), 0x08);
+
+        let ports = rot.ports().collect::<Vec<_>>();
+        assert_eq!(ports.len(), 1);
+        let port = ports.into_iter().next().unwrap();
+        let port = port.read(&sha, &OutOfMemory).unwrap();
+        assert_eq!(port.port_id(), 0);
+        assert_eq!(port.raw_flags(), 0b1010);
+        assert_eq!(port.policy(), Some(Policy::Active));
+        assert_eq!(port.pulse_interval(), 10);
+        assert_eq!(port.spi_frequency_hz(), 33_000_000);
+
+        let mut arena = [0; 256];
+        let arena = BumpArena::new(&mut arena);
+
+        let controllers = pcd.power_controllers().collect::<Vec<_>>();
+        assert_eq!(controllers.len(), 1);
+        let controller = controllers.into_iter().next().unwrap();
+        let controller = controller.read(&sha, &arena).unwrap();
+        assert_eq!(controller.i2c().bus(), 2);
+        assert_eq!(controller.i2c().address(), 0x22);
+        assert_eq!(controller.i2c().eid(), 0x0c);
+        assert_eq!(
+            controller.i2c().muxes().collect::<Vec<_>>(),
+            vec![I2cMux {
+                address: 0x70,
+                channel: 3
+            }]
+        );
+
+        let mut components = pcd
+            .components()
+            .map(|c| c.read(&sha, &arena).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(components.len(), 2);
+
+        let bridged = components.pop().unwrap();
+        assert_eq!(bridged.policy(), Some(Policy::Passive));
+        assert_eq!(bridged.power_ctrl_reg(), 0x11);
+        assert_eq!(bridged.power_ctrl_mask(), 0b1);
+        assert_eq!(bridged.component_type(), b"accelerator");
+        match bridged.connection() {
+            Connection::Bridge(bridge) => {
+                assert_eq!(bridge.device_id(), 0x1234);
+                assert_eq!(bridge.vendor_id(), 0x5678);
+                assert_eq!(bridge.subsys_device_id(), 0x9abc);
+                assert_eq!(bridge.subsys_vendor_id(), 0xdef0);
+                assert_eq!(bridge.component_count(), 4);
+                assert_eq!(bridge.eid(), 0x20);
+            }
+            _ => panic!("expected a bridged component"),
+        }
+
+        let direct = components.pop().unwrap();
+        assert_eq!(direct.policy(), Some(Policy::Active));
+        assert_eq!(direct.power_ctrl_reg(), 0x10);
+        assert_eq!(direct.power_ctrl_mask(), 0b100);
+        assert_eq!(direct.component_type(), b"nic");
+        match direct.connection() {
+            Connection::Direct(i2c) => {
+                assert_eq!(i2c.bus(), 1);
+                assert_eq!(i2c.address(), 0x33);
+                assert_eq!(i2c.eid(), 0x0d);
+                assert_eq!(i2c.mux_count(), 0);
+            }
+            _ => panic!("expected a direct component"),
+        }
+    }
+}
@@ -18,6 +18,21 @@
 //! signature check.
 //!
 //! The [`ParsedPfm`] type is the entry-point for this module.
+//!
+//! # Format Versions
+//!
+//! Manticore understands two format versions for PFM elements, as recorded
+//! in [`TocEntry::format_version()`]:
+//! - Version 0 is Manticore's original layout, which describes a single flash
+//!   device whose firmware images are hashed with SHA-256.
+//! - Version 1 matches the layout produced by current Cerberus tooling. A
+//!   `FlashDevice` additionally records how many firmware components it
+//!   contains, and a PFM may describe several flash devices, each of which
+//!   owns the next that many `AllowableFw` elements (see
+//!   [`ParsedPfm::device_fws()`]). A `FwVersion`'s images may also be hashed
+//!   with SHA-384 or SHA-512.
+//!
+//! Elements of either version may appear in the same PFM.
 
 use core::mem;
 
@@ -26,6 +41,10 @@ use zerocopy::FromBytes;
 use zerocopy::LayoutVerified;
 
 use crate::crypto::sha256;
+use crate::crypto::sha256::Hasher as _;
+use crate::hardware::filter;
+use crate::hardware::filter::SpiFilter;
+use crate::hardware::flash;
 use crate::hardware::flash::Flash;
 use crate::hardware::flash::Region;
 use crate::manifest::provenance;
@@ -89,7 +108,7 @@ impl Manifest for Pfm {
     }
 }
 
-impl<'f, F: 'f + Flash, P> Parse<'f, F, P> for Pfm {
+impl<'f, F: 'f + Flash, P: Provenance> Parse<'f, F, P> for Pfm {
     type Parsed = ParsedPfm<'f, F, P>;
 
     fn parse(
@@ -102,35 +121,49 @@ impl<'f, F: 'f + Flash, P> Parse<'f, F, P> for Pfm {
         manifest: &Self::Parsed,
         dest: &mut F2,
     ) -> Result<(), Error> {
-        let src = manifest.container.flash();
-        let len = src.size()? as usize;
-        let mut bytes_left = len;
-
-        let mut buf = [0; 32];
-        while bytes_left > 0 {
-            let bytes_to_copy = bytes_left.min(buf.len());
-            let buf = &mut buf[..bytes_to_copy];
-
-            let offset = (len - bytes_left) as u32;
-            src.read(offset, buf)?;
-            dest.program(offset, buf)?;
-
-            bytes_left -= bytes_to_copy;
-        }
-        dest.flush()?;
-        Ok(())
+        manifest.container.copy_to(dest)
     }
 
-    type Guarded = ();
+    /// The host flash devices that the PFM describes, in the order of its
+    /// `FlashDevice` elements.
+    ///
+    /// See [`ParsedPfm::device_fws()`].
+    type Guarded = [&'f dyn Flash];
     fn validate(
-        _manifest: &Self::Parsed,
-        _when: ValidationTime,
-        _args: &Self::Guarded,
+        manifest: &Self::Parsed,
+        when: ValidationTime,
+        hosts: &Self::Guarded,
+        sha: &impl sha256::Builder,
+        arena: &'f impl Arena,
     ) -> Result<(), Error> {
+        if hosts.len() != manifest.device_count() {
+            return Err(Error::WrongDeviceCount);
+        }
+        for (device, host) in hosts.iter().enumerate() {
+            manifest.validate_device(device, when, *host, sha, arena)?;
+        }
         Ok(())
     }
 }
 
+/// Checks whether the contents of `region` in `flash` are exactly `expected`.
+fn region_eq(
+    flash: &dyn Flash,
+    region: Region,
+    expected: &[u8],
+) -> Result<bool, Error> {
+    let mut buf = [0; 32];
+    for (i, chunk) in expected.chunks(buf.len()).enumerate() {
+        let offset = region.offset + (i * buf.len()) as u32;
+        let buf = &mut buf[..chunk.len()];
+        flash.read(offset, buf)?;
+        if buf != chunk {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
 impl<F, P> ParsedManifest for ParsedPfm<'_, F, P> {
     type Manifest = Pfm;
 }
@@ -161,14 +194,15 @@ where
                 Some(x) => x,
                 None => return Ok(None),
             };
-        if entry.region().len < 4 {
+        let region = entry.region();
+        if region.len < 4 {
             return Err(Error::OutOfRange);
         }
 
-        let data =
-            self.container
-                .flash()
-                .read_direct(entry.region(), arena, 1)?;
+        let data = self.container.flash().read_direct(region, arena, 1)?;
+        if P::AUTHENTICATED {
+            self.container.verify_read(entry, data, sha)?;
+        }
 
         #[derive(FromBytes)]
         #[repr(C)]
@@ -190,27 +224,14 @@ where
         }
         let id = &rest[..len];
 
-        if P::AUTHENTICATED {
-            if let Some(expected) = entry.hash() {
-                let mut hash = [0; 32];
-                sha.hash_contiguous(&data, &mut hash)?;
-                if &hash != expected {
-                    return Err(Error::BadElementHash {
-                        toc_index: entry.index(),
-                    });
-                }
-            }
-        }
-
-        Ok(Some(PlatformId {
-            _data: data,
-            entry,
-            id,
-        }))
+        Ok(Some(PlatformId { entry, id }))
     }
 
     /// Extracts the `FlashDeviceInfo` element from this PFM.
     ///
+    /// If this PFM describes more than one flash device, this returns the
+    /// first one; see [`ParsedPfm::flash_devices()`].
+    ///
     /// This function will also verify the hash of the `FlashDeviceInfo` if one
     /// is present.
     pub fn flash_device_info<'a>(
@@ -218,52 +239,28 @@ where
         sha: &impl sha256::Builder,
         arena: &'pfm impl Arena,
     ) -> Result<Option<FlashDeviceInfo<'a, 'pfm>>, Error> {
-        let entry =
-            match self.container.toc().singleton(ElementType::FlashDevice) {
-                Some(x) => x,
-                None => return Ok(None),
-            };
-        if entry.region().len < 4 {
-            return Err(Error::OutOfRange);
-        }
-
-        let data =
-            self.container
-                .flash()
-                .read_direct(entry.region(), arena, 1)?;
-
-        #[derive(FromBytes)]
-        #[repr(C)]
-        pub struct FlashDeviceHeader {
-            blank_byte: u8,
-            _unused: [u8; 3],
-        }
-        let (header, _) =
-            LayoutVerified::<_, FlashDeviceHeader>::new_from_prefix(data)
-                .ok_or(Error::TooShort {
-                    toc_index: entry.index(),
-                })?;
-
-        if P::AUTHENTICATED {
-            if let Some(expected) = entry.hash() {
-                let mut hash = [0; 32];
-                sha.hash_contiguous(&data, &mut hash)?;
-                if &hash != expected {
-                    return Err(Error::BadElementHash {
-                        toc_index: entry.index(),
-                    });
-                }
-            }
+        match self.flash_devices().next() {
+            Some(device) => device.read(sha, arena).map(Some),
+            None => Ok(None),
         }
+    }
 
-        Ok(Some(FlashDeviceInfo {
-            _data: data,
-            entry,
-            blank_byte: header.blank_byte,
-        }))
+    /// Returns an iterator over the `FlashDevice` elements of this PFM.
+    ///
+    /// The returned values only contain the `Toc` information for the entry,
+    /// allowing the user to lazily select which entries to read from flash.
+    pub fn flash_devices(
+        &self,
+    ) -> impl Iterator<Item = FlashDeviceEntry<'_, 'pfm, F, P>> + '_ {
+        self.container
+            .toc()
+            .entries()
+            .filter(|e| e.element_type() == Some(ElementType::FlashDevice))
+            .map(move |entry| FlashDeviceEntry { pfm: self, entry })
     }
 
-    /// Returns an iterator over the `AllowableFw` elements of this PFM.
+    /// Returns an iterator over the `AllowableFw` elements of this PFM,
+    /// across all flash devices.
     ///
     /// The returned values only contain the `Toc` information for the entry,
     /// allowing the user to lazily select which entries to read from flash.
@@ -276,11 +273,215 @@ where
             .filter(|e| e.element_type() == Some(ElementType::AllowableFw))
             .map(move |entry| AllowableFwEntry { pfm: self, entry })
     }
+
+    /// Returns the number of host flash devices this PFM describes.
+    ///
+    /// A PFM without any `FlashDevice` elements describes a single device.
+    pub fn device_count(&self) -> usize {
+        self.flash_devices().count().max(1)
+    }
+
+    /// Returns an iterator over the `AllowableFw` elements describing the
+    /// firmware on the `device`th flash device of this PFM.
+    ///
+    /// `AllowableFw`s are assigned to devices in `Toc` order: each version 1
+    /// `FlashDevice` describes the next
+    /// [`FlashDeviceInfo::firmware_count()`] of them. A PFM with no
+    /// `FlashDevice`, or a single version 0 one, describes one device, which
+    /// every `AllowableFw` belongs to.
+    ///
+    /// This function reads every `FlashDevice`, verifying their hashes, and
+    /// checks that their firmware counts account for every `AllowableFw`.
+    pub fn device_fws<'a>(
+        &'a self,
+        device: usize,
+        sha: &impl sha256::Builder,
+        arena: &'pfm impl Arena,
+    ) -> Result<
+        impl Iterator<Item = AllowableFwEntry<'a, 'pfm, F, P>> + 'a,
+        Error,
+    > {
+        let total = self.allowable_fws().count();
+        let device_count = self.flash_devices().count();
+
+        let mut start = 0;
+        let mut found = if device_count == 0 && device == 0 {
+            Some((0, total, None))
+        } else {
+            None
+        };
+        for (i, entry) in self.flash_devices().enumerate() {
+            let info = entry.read(sha, arena)?;
+            let count = match info.firmware_count() {
+                Some(count) => count,
+                None if device_count == 1 => total,
+                None => {
+                    return Err(Error::BadFirmwareCount {
+                        toc_index: entry.entry().index(),
+                    })
+                }
+            };
+            if i == device {
+                found = Some((start, count, Some(entry.entry())));
+            }
+
+            start += count;
+            if start > total || (i + 1 == device_count && start != total) {
+                return Err(Error::BadFirmwareCount {
+                    toc_index: entry.entry().index(),
+                });
+            }
+        }
+        let (start, count, parent) = found.ok_or(Error::OutOfRange)?;
+
+        // An `AllowableFw` nested under a `FlashDevice` must be nested under
+        // the one it is assigned to.
+        for fw in self.allowable_fws().skip(start).take(count) {
+            let fw_parent = fw
+                .entry()
+                .parent()
+                .filter(|p| p.element_type() == Some(ElementType::FlashDevice));
+            if let (Some(p1), Some(p2)) = (parent, fw_parent) {
+                if p1.index() != p2.index() {
+                    return Err(Error::BadParent {
+                        toc_index: fw.entry().index(),
+                    });
+                }
+            }
+        }
+
+        Ok(self.allowable_fws().skip(start).take(count))
+    }
+
+    /// Validates the `device`th host flash device this PFM describes against
+    /// `host`.
+    ///
+    /// This performs the checks of [`Parse::validate()`] for only the
+    /// `AllowableFw`s returned by [`ParsedPfm::device_fws()`]: for each of
+    /// them, a version must be present in `host`, and the images of that
+    /// version must match their hashes.
+    ///
+    /// Images hashed with SHA-384 or SHA-512 can only be checked if `sha`
+    /// supports [`sha256::Builder::new_wide_hasher()`]; otherwise, this
+    /// function returns [`Error::UnsupportedHashType`].
+    pub fn validate_device(
+        &self,
+        device: usize,
+        when: ValidationTime,
+        host: &dyn Flash,
+        sha: &impl sha256::Builder,
+        arena: &'pfm impl Arena,
+    ) -> Result<(), Error> {
+        for allowable_fw in self.device_fws(device, sha, arena)? {
+            let allowable_fw = allowable_fw.read(sha, arena)?;
+
+            let attempts = allowable_fw.version_attempts(arena)?;
+            let fw =
+                match allowable_fw.find_version(attempts, host, sha, arena)? {
+                    Some(fw) => fw,
+                    None => {
+                        return Err(Error::NoMatchingVersion {
+                            toc_index: allowable_fw.entry().index(),
+                        })
+                    }
+                };
+
+            for (_, result) in fw.validate_images(when, host, sha) {
+                result?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finds the `AllowableFw` with the given firmware ID, and selects
+    /// whichever of its versions is currently present in `host`.
+    ///
+    /// See [`AllowableFw::select_version()`].
+    pub fn select_version<'a>(
+        &'a self,
+        firmware_id: &[u8],
+        host: &dyn Flash,
+        sha: &impl sha256::Builder,
+        arena: &'pfm impl Arena,
+    ) -> Result<Selected<'a, 'pfm, F, P>, SelectError<'pfm>> {
+        for fw in self.allowable_fws() {
+            let fw = fw.read(sha, arena)?;
+            if fw.firmware_id() != firmware_id {
+                continue;
+            }
+            let version = fw.select_version(host, sha, arena)?;
+            return Ok((fw, version));
+        }
+        Err(SelectError::UnknownFirmware)
+    }
+
+    /// Programs `filter` to enforce this PFM's policy on `host`, the
+    /// `device`th host flash device it describes.
+    ///
+    /// For each of [`ParsedPfm::device_fws()`], the version currently present
+    /// in `host` is selected, and its [`FwVersion::filter_rules()`] are added to `filter`;
+    /// any existing rules are replaced.
+    ///
+    /// Rules are only added for a version once every one of its images
+    /// matches its hash in `host`, as [`ParsedPfm::validate_device()`] checks
+    /// them at [`ValidationTime::Activation`]; otherwise, an unauthenticated
+    /// image could be locked in as read-only. If no version of some firmware
+    /// matches, if an image does not, or if the rules cannot be applied,
+    /// `filter` is left with no rules, blocking the host entirely.
+    pub fn apply_filter(
+        &self,
+        device: usize,
+        host: &dyn Flash,
+        filter: &mut dyn SpiFilter,
+        sha: &impl sha256::Builder,
+        arena: &'pfm impl Arena,
+    ) -> Result<(), Error> {
+        let result = self.program_filter(device, host, filter, sha, arena);
+        if result.is_err() {
+            let _ = filter.clear();
+        }
+        result
+    }
+
+    /// Implements [`ParsedPfm::apply_filter()`], without clearing `filter` on
+    /// failure.
+    fn program_filter(
+        &self,
+        device: usize,
+        host: &dyn Flash,
+        filter: &mut dyn SpiFilter,
+        sha: &impl sha256::Builder,
+        arena: &'pfm impl Arena,
+    ) -> Result<(), Error> {
+        filter.clear()?;
+        for allowable_fw in self.device_fws(device, sha, arena)? {
+            let allowable_fw = allowable_fw.read(sha, arena)?;
+            let attempts = allowable_fw.version_attempts(arena)?;
+            let fw =
+                match allowable_fw.find_version(attempts, host, sha, arena)? {
+                    Some(fw) => fw,
+                    None => {
+                        return Err(Error::NoMatchingVersion {
+                            toc_index: allowable_fw.entry().index(),
+                        })
+                    }
+                };
+            for (_, result) in
+                fw.validate_images(ValidationTime::Activation, host, sha)
+            {
+                result?;
+            }
+            for rule in fw.filter_rules()? {
+                filter.add_rule(rule)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 /// An identifier for the platform a PFM is for.
 pub struct PlatformId<'a, 'pfm> {
-    _data: &'pfm [u8],
     entry: TocEntry<'a, 'pfm, Pfm>,
     id: &'pfm [u8],
 }
@@ -298,14 +499,80 @@ impl<'a, 'pfm> PlatformId<'a, 'pfm> {
     }
 }
 
+/// A "flash device" element entry in a PFM's `Toc`.
+///
+/// This type allows for lazily reading the [`FlashDeviceInfo`] described by
+/// this entry, as obtained from [`ParsedPfm::flash_devices()`].
+pub struct FlashDeviceEntry<'a, 'pfm, Flash, Provenance = provenance::Signed> {
+    pfm: &'a ParsedPfm<'pfm, Flash, Provenance>,
+    entry: TocEntry<'a, 'pfm, Pfm>,
+}
+
+impl<'a, 'pfm, F: Flash, P> FlashDeviceEntry<'a, 'pfm, F, P>
+where
+    P: Provenance,
+{
+    /// Returns the `Toc` entry defining this element.
+    pub fn entry(&self) -> TocEntry<'a, 'pfm, Pfm> {
+        self.entry
+    }
+
+    /// Reads the contents of this element into memory, verifying its hash
+    /// and potentially allocating it on `arena`.
+    pub fn read(
+        &self,
+        sha: &impl sha256::Builder,
+        arena: &'pfm impl Arena,
+    ) -> Result<FlashDeviceInfo<'a, 'pfm>, Error> {
+        let entry = self.entry;
+        let region = entry.region();
+        if region.len < 4 {
+            return Err(Error::OutOfRange);
+        }
+
+        #[derive(FromBytes)]
+        #[repr(C)]
+        pub struct FlashDeviceHeader {
+            blank_byte: u8,
+            fw_count: u8,
+            _unused: [u8; 2],
+        }
+        // Only the header is needed; the element may be longer if it was
+        // encoded with a newer format version, in which case the rest of it
+        // is only hashed.
+        let header = self.pfm.container.flash().read_direct(
+            Region::new(region.offset, 4),
+            arena,
+            1,
+        )?;
+        if P::AUTHENTICATED {
+            self.pfm.container.verify_read(entry, header, sha)?;
+        }
+        let header = LayoutVerified::<_, FlashDeviceHeader>::new(header)
+            .ok_or(Error::TooShort {
+                toc_index: entry.index(),
+            })?;
+
+        Ok(FlashDeviceInfo {
+            entry,
+            blank_byte: header.blank_byte,
+            fw_count: if entry.format_version() >= 1 {
+                Some(header.fw_count)
+            } else {
+                None
+            },
+        })
+    }
+}
+
 /// A descriptor for a flash device protected by a PFM.
 ///
 /// Note that this is distinct from the flash device that the PFM itself is
 /// stored in.
 pub struct FlashDeviceInfo<'a, 'pfm> {
-    _data: &'pfm [u8],
     entry: TocEntry<'a, 'pfm, Pfm>,
     blank_byte: u8,
+    fw_count: Option<u8>,
 }
 
 impl<'a, 'pfm> FlashDeviceInfo<'a, 'pfm> {
@@ -322,6 +589,14 @@ impl<'a, 'pfm> FlashDeviceInfo<'a, 'pfm> {
     pub fn blank_byte(&self) -> u8 {
         self.blank_byte
     }
+
+    /// Returns the number of firmware components stored on this device.
+    ///
+    /// This is only recorded by version 1 `FlashDevice`s; for earlier
+    /// versions, this returns `None`.
+    pub fn firmware_count(&self) -> Option<usize> {
+        self.fw_count.map(|c| c as usize)
+    }
 }
 
 /// An "allowable firmware" element entry in a PFM's `Toc`.
@@ -354,6 +629,9 @@ where
             arena,
             1,
         )?;
+        if P::AUTHENTICATED {
+            self.pfm.container.verify_read(self.entry, data, sha)?;
+        }
 
         #[derive(FromBytes)]
         #[repr(C)]
@@ -377,21 +655,8 @@ where
         }
         let fw_id = &rest[..id_len];
 
-        if P::AUTHENTICATED {
-            if let Some(expected) = self.entry.hash() {
-                let mut hash = [0; 32];
-                sha.hash_contiguous(&data, &mut hash)?;
-                if &hash != expected {
-                    return Err(Error::BadElementHash {
-                        toc_index: self.entry.index(),
-                    });
-                }
-            }
-        }
-
         Ok(AllowableFw {
             entry: self,
-            _data: data,
             fw_count: header.fw_count,
             fw_id,
             flags: header.flags,
@@ -406,7 +671,6 @@ where
 /// [`AllowableFwEntry::read()`].
 pub struct AllowableFw<'a, 'pfm, Flash, Provenance = provenance::Signed> {
     entry: AllowableFwEntry<'a, 'pfm, Flash, Provenance>,
-    _data: &'pfm [u8],
     fw_count: u8,
     fw_id: &'pfm [u8],
     flags: u8,
@@ -443,14 +707,183 @@ impl<'a, 'pfm, F: Flash, P> AllowableFw<'a, 'pfm, F, P> {
     /// allowing the user to lazily select which entries to read from flash.
     pub fn firmware_versions(
         &self,
-    ) -> impl Iterator<Item = FwVersionEntry<'_, 'pfm, F, P>> + '_ {
+    ) -> impl Iterator<Item = FwVersionEntry<'a, 'pfm, F, P>> + '_ {
+        let pfm = self.entry.pfm;
         self.entry()
             .children()
             .filter(|e| e.element_type() == Some(ElementType::FwVersion))
-            .map(move |entry| FwVersionEntry {
-                version: self,
-                entry,
-            })
+            .map(move |entry| FwVersionEntry { pfm, entry })
+    }
+}
+
+impl<'a, 'pfm, F: Flash, P> AllowableFw<'a, 'pfm, F, P>
+where
+    P: Provenance,
+{
+    /// Selects the `FwVersion` of this element whose version string is
+    /// currently present in `host`.
+    ///
+    /// A version matches if its version string is a prefix of the bytes at its
+    /// version address. If several versions match, the one with the longest
+    /// version string is selected, so that a host holding `v2.0.1` selects
+    /// `v2.0.1` over `v2.0`; if there is no single longest match, the PFM is
+    /// ambiguous and [`Error::AmbiguousVersion`] is returned.
+    ///
+    /// Versions are grouped by their version address, so that each distinct
+    /// address is read out of `host` exactly once, regardless of how many
+    /// versions share it. Each `FwVersion` is read (and hash-checked) at most
+    /// once.
+    ///
+    /// If no version matches, a [`SelectError::NoMatch`] is returned, listing
+    /// every version that was compared; that list is allocated on `arena`.
+    pub fn select_version(
+        &self,
+        host: &dyn Flash,
+        sha: &impl sha256::Builder,
+        arena: &'pfm impl Arena,
+    ) -> Result<FwVersion<'a, 'pfm, F, P>, SelectError<'pfm>> {
+        let tried = self.version_attempts(arena)?;
+        match self.find_version(tried, host, sha, arena)? {
+            Some(version) => Ok(version),
+            None => Err(SelectError::NoMatch {
+                toc_index: self.entry().index(),
+                tried,
+            }),
+        }
+    }
+
+    /// Reads the version address of each of this element's versions, in `Toc`
+    /// order, allocating the list on `arena`.
+    fn version_attempts(
+        &self,
+        arena: &'pfm impl Arena,
+    ) -> Result<&'pfm [VersionAttempt], Error> {
+        let count = self.firmware_versions().count();
+        let attempts = arena.alloc_slice::<VersionAttempt>(count)?;
+        for (attempt, version) in
+            attempts.iter_mut().zip(self.firmware_versions())
+        {
+            // NOTE: Peeking at the version address does not authenticate it;
+            // it only determines which versions get compared against which
+            // read of `host`. The comparison itself uses the fully-read,
+            // hash-checked element.
+            *attempt = VersionAttempt {
+                toc_index: version.entry().index() as u32,
+                version_addr: version.peek_version_addr()?,
+            };
+        }
+        Ok(attempts)
+    }
+
+    /// Implements the search for [`AllowableFw::select_version()`], returning
+    /// `None` if nothing matched.
+    ///
+    /// `attempts` must be the result of [`AllowableFw::version_attempts()`].
+    fn find_version(
+        &self,
+        attempts: &[VersionAttempt],
+        host: &dyn Flash,
+        sha: &impl sha256::Builder,
+        arena: &'pfm impl Arena,
+    ) -> Result<Option<FwVersion<'a, 'pfm, F, P>>, Error> {
+        // Version strings have a one-byte length, so this is always enough to
+        // hold the longest string that might be found at any one address.
+        let mut buf = [0; u8::MAX as usize];
+        let host_len = host.size()?;
+
+        let mut best = None::<FwVersion<'a, 'pfm, F, P>>;
+        let mut ambiguous = false;
+        for (i, leader) in attempts.iter().enumerate() {
+            let addr = leader.version_addr;
+            if attempts[..i].iter().any(|a| a.version_addr == addr) {
+                continue;
+            }
+
+            let len = host_len.saturating_sub(addr).min(buf.len() as u32);
+            let found = &mut buf[..len as usize];
+            if !found.is_empty() {
+                host.read(addr, found)?;
+            }
+
+            let versions = self.firmware_versions().zip(attempts).skip(i);
+            for (entry, attempt) in versions {
+                if attempt.version_addr != addr {
+                    continue;
+                }
+
+                let version = entry.read(sha, arena)?;
+                let (region, expected) = version.version();
+                let matched = if region.offset == addr {
+                    found.get(..expected.len()) == Some(expected)
+                } else {
+                    region_eq(host, region, expected)?
+                };
+                if !matched {
+                    continue;
+                }
+
+                let len = expected.len();
+                match &best {
+                    Some(b) if b.version().1.len() > len => {}
+                    Some(b) if b.version().1.len() == len => ambiguous = true,
+                    _ => {
+                        best = Some(version);
+                        ambiguous = false;
+                    }
+                }
+            }
+        }
+
+        if ambiguous {
+            return Err(Error::AmbiguousVersion {
+                toc_index: self.entry().index(),
+            });
+        }
+        Ok(best)
+    }
+}
+
+/// A matched pair of an `AllowableFw` and one of its `FwVersion`s, as returned
+/// by [`ParsedPfm::select_version()`].
+pub type Selected<'a, 'pfm, F, P> =
+    (AllowableFw<'a, 'pfm, F, P>, FwVersion<'a, 'pfm, F, P>);
+
+/// A version that was compared against host flash by
+/// [`AllowableFw::select_version()`], without matching.
+#[derive(Copy, Clone, PartialEq, Eq, Debug, AsBytes, FromBytes)]
+#[repr(C)]
+pub struct VersionAttempt {
+    /// The index of the `FwVersion` entry in the `Toc`.
+    pub toc_index: u32,
+    /// The address its version string was expected at.
+    pub version_addr: u32,
+}
+
+/// An error returned while selecting a firmware version.
+///
+/// See [`AllowableFw::select_version()`] and [`ParsedPfm::select_version()`].
+#[derive(Clone, Debug)]
+pub enum SelectError<'pfm> {
+    /// Indicates that reading the PFM or the host flash failed.
+    Manifest(Error),
+
+    /// Indicates that the PFM has no `AllowableFw` with the requested
+    /// firmware ID.
+    UnknownFirmware,
+
+    /// Indicates that none of an `AllowableFw`'s versions were present in
+    /// host flash.
+    NoMatch {
+        /// The index of the `AllowableFw` entry in the `Toc`.
+        toc_index: usize,
+        /// Every version that was tried, in `Toc` order.
+        tried: &'pfm [VersionAttempt],
+    },
+}
+
+impl From<Error> for SelectError<'_> {
+    fn from(e: Error) -> Self {
+        Self::Manifest(e)
     }
 }
 
@@ -459,7 +892,7 @@ impl<'a, 'pfm, F: Flash, P> AllowableFw<'a, 'pfm, F, P> {
 /// This type allows for lazily reading the [`FwVersion`] described by this
 /// entry, as obtained from [`AllowableFw::firmware_versions()`].
 pub struct FwVersionEntry<'a, 'pfm, Flash, Provenance = provenance::Signed> {
-    version: &'a AllowableFw<'a, 'pfm, Flash, Provenance>,
+    pfm: &'a ParsedPfm<'pfm, Flash, Provenance>,
     entry: TocEntry<'a, 'pfm, Pfm>,
 }
 
@@ -472,6 +905,23 @@ where
         self.entry
     }
 
+    /// Reads just the version address out of this element's header, without
+    /// checking the element's hash.
+    fn peek_version_addr(&self) -> Result<u32, Error> {
+        let region = self.entry.region();
+        if region.len < 8 {
+            return Err(Error::TooShort {
+                toc_index: self.entry.index(),
+            });
+        }
+        let mut addr = [0; 4];
+        self.pfm
+            .container
+            .flash()
+            .read(region.offset + 4, &mut addr)?;
+        Ok(u32::from_le_bytes(addr))
+    }
+
     /// Reads the contents of this element into memory, verifying its hash
     /// and potentially allocating it on `arena`.
     pub fn read(
@@ -479,22 +929,13 @@ where
         sha: &impl sha256::Builder,
         arena: &'pfm impl Arena,
     ) -> Result<FwVersion<'a, 'pfm, F, P>, Error> {
-        #[rustfmt::skip]
-        let data = self.version.entry.pfm.container.flash().read_direct(
+        let data = self.pfm.container.flash().read_direct(
             self.entry.region(),
             arena,
             mem::align_of::<u32>(),
         )?;
         if P::AUTHENTICATED {
-            if let Some(expected) = self.entry.hash() {
-                let mut hash = [0; 32];
-                sha.hash_contiguous(data, &mut hash)?;
-                if &hash != expected {
-                    return Err(Error::BadElementHash {
-                        toc_index: self.entry.index(),
-                    });
-                }
-            }
+            self.pfm.container.verify_read(self.entry, data, sha)?;
         }
 
         #[derive(FromBytes)]
@@ -564,22 +1005,25 @@ where
                             toc_index: self.entry.index(),
                         })?;
 
-                // TODO(#57): we don't deal with hash types that aren't SHA-256.
-                match HashType::from_wire_value(img_header.hash_type) {
-                    Some(HashType::Sha256) => {}
-                    Some(h) => return Err(Error::UnsupportedHashType(h)),
-                    None => return Err(Error::OutOfRange),
-                }
+                // Version 0 elements only support SHA-256.
+                let hash_type =
+                    match HashType::from_wire_value(img_header.hash_type) {
+                        Some(HashType::Sha256) => HashType::Sha256,
+                        Some(h) if self.entry.format_version() >= 1 => h,
+                        Some(h) => return Err(Error::UnsupportedHashType(h)),
+                        None => return Err(Error::OutOfRange),
+                    };
+                let hash_len = hash_type.digest_len();
 
                 let ranges_len = img_header.region_count as usize
                     * mem::size_of::<FwRegionRange>();
-                if rest.len() < ranges_len {
+                if rest.len() < hash_len + ranges_len {
                     return Err(Error::TooShort {
                         toc_index: self.entry.index(),
                     });
                 }
                 let ranges = LayoutVerified::<_, [FwRegionRange]>::new_slice(
-                    &rest[..ranges_len],
+                    &rest[hash_len..hash_len + ranges_len],
                 )
                 .ok_or(Error::TooShort {
                     toc_index: self.entry.index(),
@@ -595,7 +1039,7 @@ where
                 if i != header.image_count - 1 {
                     image_region_offsets[(i + 1) as usize] =
                         image_region_offsets[i as usize]
-                            + ranges_len as u32
+                            + (hash_len + ranges_len) as u32
                             + mem::size_of::<FwRegionHeader>() as u32;
                 }
             }
@@ -685,12 +1129,16 @@ impl<'a, 'pfm, F, P> FwVersion<'a, 'pfm, F, P> {
         let (header, bytes) =
             LayoutVerified::<_, FwRegionHeader>::new_from_prefix(bytes)
                 .unwrap();
+        let hash_type = HashType::from_wire_value(header.hash_type).unwrap();
+        let (hash, bytes) = bytes.split_at(hash_type.digest_len());
         let ranges =
             LayoutVerified::<_, [FwRegionRange]>::new_slice(bytes).unwrap();
         debug_assert!(ranges.len() == header.region_count as usize);
 
         Some(FwRegion {
             header: header.into_ref(),
+            hash_type,
+            hash,
             ranges: ranges.into_slice(),
         })
     }
@@ -699,6 +1147,166 @@ impl<'a, 'pfm, F, P> FwVersion<'a, 'pfm, F, P> {
     pub fn image_regions(&self) -> impl Iterator<Item = FwRegion<'_>> + '_ {
         (0..self.image_count()).map(move |n| self.image_region(n).unwrap())
     }
+
+    /// Checks the images of this `FwVersion` that must be validated at `when`
+    /// against their hashes, returning each checked image along with the
+    /// result of checking it.
+    ///
+    /// Images are checked lazily, in order, as the iterator is advanced.
+    /// Images that are not checked at `when` (see
+    /// [`FwRegion::must_validate()`]) are skipped.
+    pub fn validate_images<'v>(
+        &'v self,
+        when: ValidationTime,
+        host: &'v dyn Flash,
+        sha: &'v impl sha256::Builder,
+    ) -> impl Iterator<Item = (FwRegion<'v>, Result<(), Error>)> + 'v {
+        self.image_regions()
+            .filter(move |image| image.must_validate(when))
+            .map(move |image| {
+                let result = self.validate_image(&image, host, sha);
+                (image, result)
+            })
+    }
+
+    /// Checks a single image of this `FwVersion` against its hash in `host`.
+    fn validate_image(
+        &self,
+        image: &FwRegion<'_>,
+        host: &dyn Flash,
+        sha: &impl sha256::Builder,
+    ) -> Result<(), Error> {
+        let hash_type = image.hash_type();
+        let mut hasher = match hash_type {
+            HashType::Sha256 => sha.new_hasher(),
+            _ => sha.new_wide_hasher(hash_type.digest_len()),
+        }
+        .map_err(|e| match e {
+            sha256::Error::Unsupported => Error::UnsupportedHashType(hash_type),
+            e => e.into(),
+        })?;
+        flash::hash_regions(
+            host,
+            image.regions(),
+            &mut hasher,
+            &mut [0; 64],
+            true,
+        )?;
+
+        let mut hash = [0; 64];
+        let hash = &mut hash[..hash_type.digest_len()];
+        hasher.finish_wide(hash)?;
+        if hash != image.image_hash() {
+            return Err(Error::BadGuardedHash {
+                toc_index: self.entry().index(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Returns the SPI filter rules that enforce this `FwVersion`'s policy:
+    /// its image regions are read-only, and its read-write regions are
+    /// writable.
+    ///
+    /// Returns [`filter::Error::Conflict`] if any read-write region overlaps
+    /// an image region.
+    pub fn filter_rules(
+        &self,
+    ) -> Result<impl Iterator<Item = filter::Rule> + '_, filter::Error> {
+        let images = move || {
+            (0..self.image_count()).flat_map(move |n| {
+                let image = self.image_region(n).unwrap();
+                (0..image.region_count()).map(move |k| image.region(k).unwrap())
+            })
+        };
+
+        for rw in self.rw_regions() {
+            if images().any(|r| r.overlaps(rw.region())) {
+                return Err(filter::Error::Conflict);
+            }
+        }
+
+        let read_only = images().map(|region| filter::Rule {
+            region,
+            access: filter::Access::ReadOnly,
+        });
+        let read_write = self.rw_regions().map(|rw| filter::Rule {
+            region: rw.region(),
+            access: filter::Access::ReadWrite,
+        });
+        Ok(read_only.chain(read_write))
+    }
+
+    /// Enforces the [`RwFailurePolicy`] of each of this `FwVersion`'s
+    /// read-write regions on `host`.
+    ///
+    /// This function is intended to be called after the platform reports an
+    /// authentication failure. Regions with an `Erase` policy are erased and
+    /// then filled with `blank_byte` (which should be
+    /// [`FlashDeviceInfo::blank_byte()`]), and regions with a `RestoreFromRo`
+    /// policy are erased and then overwritten with the same region of
+    /// `backup`.
+    ///
+    /// Every region that is acted on must consist of whole sectors of `host`,
+    /// so that erasing it does not disturb neighboring data.
+    ///
+    /// If any region has an unrecognized policy, requires restoration but
+    /// `backup` is `None`, or is not erasable, an error is returned before
+    /// `host` is modified.
+    pub fn enforce_rw_failure_policy(
+        &self,
+        host: &mut dyn Flash,
+        blank_byte: u8,
+        backup: Option<&dyn Flash>,
+    ) -> Result<(), Error> {
+        let geometry = host.geometry()?;
+        for rw in self.rw_regions() {
+            match rw.failure_policy() {
+                Some(RwFailurePolicy::DoNothing) => continue,
+                Some(RwFailurePolicy::RestoreFromRo) if backup.is_none() => {
+                    return Err(Error::NoBackup)
+                }
+                Some(_) => {}
+                None => return Err(Error::UnknownFailurePolicy),
+            }
+            if geometry.erase_cover(rw.region()) != Some(rw.region()) {
+                return Err(flash::Error::Unaligned.into());
+            }
+        }
+
+        let mut buf = [0; 32];
+        for rw in self.rw_regions() {
+            let policy = rw.failure_policy().expect("checked above");
+            if policy == RwFailurePolicy::DoNothing {
+                continue;
+            }
+
+            let region = rw.region();
+            host.erase(region)?;
+            if policy == RwFailurePolicy::Erase
+                && blank_byte == flash::Geometry::ERASED
+            {
+                continue;
+            }
+
+            let mut offset = 0;
+            while offset < region.len {
+                let len = (region.len - offset).min(buf.len() as u32);
+                let buf = &mut buf[..len as usize];
+                let addr = region.offset + offset;
+                match (policy, backup) {
+                    (RwFailurePolicy::RestoreFromRo, Some(backup)) => {
+                        backup.read(addr, buf)?
+                    }
+                    _ => buf.iter_mut().for_each(|b| *b = blank_byte),
+                }
+                flash::program_paged(host, addr, buf)?;
+                offset += len;
+            }
+        }
+        host.flush()?;
+        Ok(())
+    }
 }
 
 wire_enum! {
@@ -754,6 +1362,8 @@ impl RwRegion {
 /// Currently, Manticore only supports SHA-256 hashes here.
 pub struct FwRegion<'a> {
     header: &'a FwRegionHeader,
+    hash_type: HashType,
+    hash: &'a [u8],
     ranges: &'a [FwRegionRange],
 }
 
@@ -764,7 +1374,6 @@ struct FwRegionHeader {
     region_count: u8,
     flags: u8,
     _reserved: u8,
-    image_hash: sha256::Digest,
 }
 
 #[derive(FromBytes)]
@@ -781,14 +1390,35 @@ impl FwRegion<'_> {
         (self.header.flags & 1) == 1
     }
 
+    /// Returns whether this region is checked when validating at `when`.
+    ///
+    /// Every region is checked on activation, but only those that
+    /// [must be validated on boot](Self::must_validate_on_boot) are checked at
+    /// startup.
+    pub fn must_validate(&self, when: ValidationTime) -> bool {
+        match when {
+            ValidationTime::Startup => self.must_validate_on_boot(),
+            ValidationTime::Activation => true,
+        }
+    }
+
     /// Returns the raw encoded flags for this element.
     pub fn raw_flags(&self) -> u8 {
         self.header.flags
     }
 
+    /// Returns the type of hash that [`FwRegion::image_hash()`] is.
+    ///
+    /// This is always [`HashType::Sha256`] for version 0 `FwVersion`s.
+    pub fn hash_type(&self) -> HashType {
+        self.hash_type
+    }
+
     /// Returns the hash that this region is expected to conform to.
-    pub fn image_hash(&self) -> &sha256::Digest {
-        &self.header.image_hash
+    ///
+    /// The length of the hash is determined by [`FwRegion::hash_type()`].
+    pub fn image_hash(&self) -> &[u8] {
+        self.hash
     }
 
     /// Returns the number of flash regions that actually make up this image
@@ -821,7 +1451,13 @@ mod test {
     use crate::crypto::ring;
     use crate::crypto::sha256::Builder as _;
     use crate::crypto::testdata::rsa as test_rsa;
+    use crate::hardware::filter::Simulated;
+    use crate::hardware::flash::Cached;
+    use crate::hardware::flash::Fault;
+    use crate::hardware::flash::Faulty;
+    use crate::hardware::flash::Nor;
     use crate::hardware::flash::Ram;
+    use crate::hardware::flash::RamMut;
     use crate::io::Write as _;
     use crate::manifest::owned;
     use crate::manifest::testdata;
@@ -885,6 +1521,109 @@ mod test {
         assert_eq!(id.id_string(), b"my pfm");
     }
 
+    #[test]
+    fn element_changed_after_verify() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, mut signer) = test_rsa();
+
+        #[rustfmt::skip]
+        let pfm: owned::Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": [
+                { "platform_id": "my pfm" },
+                { "blank_byte": "0xff" },
+                {
+                    "version_count": 1,
+                    "firmware_id": "my cool firmware",
+                    "flags": 0,
+                    "children": [{
+                        "version_addr": "0x0",
+                        "version_str": "v1.0",
+                        "rw_regions": [],
+                        "image_regions": []
+                    }]
+                }
+            ]
+        }"#).unwrap();
+        let bytes = Ram(pfm.sign(0x0, &sha, &mut signer).unwrap());
+        let regions = Container::<'_, Pfm, _, provenance::Adhoc>::parse(
+            &bytes,
+            &OutOfMemory,
+        )
+        .unwrap()
+        .toc()
+        .entries()
+        .map(|e| e.region())
+        .collect::<Vec<_>>();
+
+        // Each element changes after being read twice: once to check the
+        // signature, and once by `verify_elements()`. Readers must notice,
+        // rather than trusting the earlier verification.
+        let mut flash = Faulty::new(bytes);
+        for &toc_index in &[0, 3] {
+            flash.inject(Fault::LateBitFlip {
+                offset: regions[toc_index].end() - 1,
+                mask: 0x01,
+                after: 2,
+            });
+        }
+
+        let mut arena = [0; 256];
+        let arena = BumpArena::new(&mut arena);
+        let container = Container::parse_and_verify(
+            &flash,
+            &sha,
+            &mut rsa,
+            &OutOfMemory,
+            &OutOfMemory,
+        )
+        .unwrap();
+        container.verify_elements(&sha).unwrap();
+        let pfm = ParsedPfm::new(container);
+        assert!(pfm.container.toc().entry(0).unwrap().is_verified());
+        assert!(matches!(
+            pfm.platform_id(&sha, &arena),
+            Err(Error::BadElementHash { toc_index: 0 })
+        ));
+
+        let fw = pfm.allowable_fws().next().unwrap().read(&sha, &arena);
+        let version = fw.unwrap().firmware_versions().next().unwrap();
+        assert!(version.entry().is_verified());
+        assert!(matches!(
+            version.read(&sha, &arena),
+            Err(Error::BadElementHash { toc_index: 3 })
+        ));
+    }
+
+    #[test]
+    fn cached() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, mut signer) = test_rsa();
+
+        #[rustfmt::skip]
+        let pfm: owned::Pfm = from_str(r#"{
+            "version_id": 42,
+            "elements": [{ "platform_id": "my pfm" }]
+        }"#).unwrap();
+        let bytes = RamMut(pfm.sign(0x0, &sha, &mut signer).unwrap());
+
+        let mut cache = [0; 512];
+        let cache = BumpArena::new(&mut cache);
+        let mut cached = Cached::new(&bytes, 64, 4, &cache).unwrap();
+        cached.read_ahead(1);
+
+        let mut arena = [0; 1024];
+        let arena = BumpArena::new(&mut arena);
+        let container = Container::parse_and_verify(
+            &cached, &sha, &mut rsa, &arena, &arena,
+        )
+        .unwrap();
+        let pfm = ParsedPfm::new(container);
+
+        let id = pfm.platform_id(&sha, &arena).unwrap().unwrap();
+        assert_eq!(id.id_string(), b"my pfm");
+    }
+
     #[test]
     fn fw_versions() {
         let sha = ring::sha256::Builder::new();
@@ -991,19 +1730,697 @@ mod test {
         let imgs = fw.image_regions().collect::<Vec<_>>();
         assert_eq!(imgs.len(), 2);
 
-        assert_eq!(imgs[0].image_hash(), &[42; 32]);
+        assert_eq!(imgs[0].image_hash(), &[42; 32][..]);
         assert_eq!(imgs[0].region_count(), 2);
         assert_eq!(imgs[0].region(0), Some(Region::new(0x10000, 0x1000)));
         assert_eq!(imgs[0].region(1), Some(Region::new(0x18000, 0x800)));
         assert!(imgs[0].region(2).is_none());
 
-        assert_eq!(imgs[1].image_hash(), &[77; 32]);
+        assert_eq!(imgs[1].image_hash(), &[77; 32][..]);
         assert_eq!(imgs[1].region_count(), 2);
         assert_eq!(imgs[1].region(0), Some(Region::new(0x20000, 0x800)));
         assert_eq!(imgs[1].region(1), Some(Region::new(0x28000, 0x1000)));
         assert!(imgs[1].region(2).is_none());
     }
 
+    #[test]
+    fn validate_host() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, mut signer) = test_rsa();
+
+        let mut host = vec![0xff; 0x1000];
+        host[0x10..0x14].copy_from_slice(b"v2.0");
+        for (i, b) in host[0x100..0x300].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let boot_regions =
+            vec![Region::new(0x100, 0x80), Region::new(0x200, 0x100)];
+        let update_regions = vec![Region::new(0x180, 0x80)];
+
+        let hash = |regions: &[Region]| {
+            let mut hasher = sha.new_hasher().unwrap();
+            flash::hash_regions(
+                &Ram(&host[..]),
+                regions.iter().copied(),
+                &mut hasher,
+                &mut [0; 16],
+                false,
+            )
+            .unwrap();
+            let mut digest = [0; 32];
+            hasher.finish(&mut digest).unwrap();
+            digest
+        };
+        let boot_hash = hash(&boot_regions);
+        let update_hash = hash(&update_regions);
+
+        let version = |version_str: &[u8], image_regions| owned::Node {
+            element: owned::pfm::Element::FwVersion {
+                version_addr: 0x10,
+                version_str: version_str.to_vec(),
+                rw_regions: vec![],
+                image_regions,
+                format_version: 0,
+            },
+            children: vec![],
+            hashed: true,
+        };
+        let pfm = owned::Pfm {
+            metadata: Metadata { version_id: 42 },
+            elements: vec![owned::Node {
+                element: owned::pfm::Element::AllowableFw {
+                    version_count: 2,
+                    firmware_id: b"bmc".to_vec(),
+                    flags: 0,
+                },
+                children: vec![
+                    version(b"v1.0", vec![]),
+                    version(
+                        b"v2.0",
+                        vec![
+                            owned::pfm::Image {
+                                flags: 0b1,
+                                hash_type: HashType::Sha256,
+                                hash: boot_hash.to_vec(),
+                                regions: boot_regions,
+                            },
+                            owned::pfm::Image {
+                                flags: 0b0,
+                                hash_type: HashType::Sha256,
+                                hash: update_hash.to_vec(),
+                                regions: update_regions,
+                            },
+                        ],
+                    ),
+                ],
+                hashed: true,
+            }],
+        };
+        let bytes = Ram(pfm.sign(0x0, &sha, &mut signer).unwrap());
+
+        let container = Container::parse_and_verify(
+            &bytes,
+            &sha,
+            &mut rsa,
+            &OutOfMemory,
+            &OutOfMemory,
+        )
+        .unwrap();
+        let pfm = ParsedPfm::new(container);
+
+        let mut arena = [0; 1024];
+        let arena = BumpArena::new(&mut arena);
+        let validate = |host: &[u8], when| {
+            Pfm::validate(&pfm, when, &[&Ram(host)], &sha, &arena)
+        };
+
+        assert!(validate(&host, ValidationTime::Startup).is_ok());
+        assert!(validate(&host, ValidationTime::Activation).is_ok());
+
+        // Corrupting a region only checked on activation is not caught at
+        // startup.
+        let mut corrupt = host.clone();
+        corrupt[0x1c0] ^= 0xff;
+        assert!(validate(&corrupt, ValidationTime::Startup).is_ok());
+        assert!(matches!(
+            validate(&corrupt, ValidationTime::Activation),
+            Err(Error::BadGuardedHash { toc_index: 2 })
+        ));
+
+        // Each image is reported on separately.
+        let fw = pfm.allowable_fws().next().unwrap().read(&sha, &arena);
+        let fw = fw.unwrap().firmware_versions().nth(1).unwrap();
+        let fw = fw.read(&sha, &arena).unwrap();
+        let host_flash = Ram(&corrupt[..]);
+        let results = fw
+            .validate_images(ValidationTime::Activation, &host_flash, &sha)
+            .map(|(image, result)| (image.must_validate_on_boot(), result))
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            results[..],
+            [
+                (true, Ok(())),
+                (false, Err(Error::BadGuardedHash { toc_index: 2 }))
+            ]
+        ));
+        let count = fw
+            .validate_images(ValidationTime::Startup, &host_flash, &sha)
+            .count();
+        assert_eq!(count, 1);
+
+        let mut corrupt = host.clone();
+        corrupt[0x2ff] ^= 0xff;
+        assert!(matches!(
+            validate(&corrupt, ValidationTime::Startup),
+            Err(Error::BadGuardedHash { toc_index: 2 })
+        ));
+
+        // An unmatched version string fails outright; a match with the other
+        // version succeeds, since it guards no images.
+        let mut other = host;
+        other[0x10..0x14].copy_from_slice(b"v3.0");
+        assert!(matches!(
+            validate(&other, ValidationTime::Activation),
+            Err(Error::NoMatchingVersion { toc_index: 0 })
+        ));
+        other[0x10..0x14].copy_from_slice(b"v1.0");
+        assert!(validate(&other, ValidationTime::Activation).is_ok());
+    }
+
+    #[test]
+    fn validate_devices() {
+        use ::ring::digest;
+
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, mut signer) = test_rsa();
+
+        let image = Region::new(0x100, 0x100);
+        let host = |version: &[u8], fill: u8| {
+            let mut host = vec![fill; 0x400];
+            host[..version.len()].copy_from_slice(version);
+            host
+        };
+        let bmc = host(b"bmc1", 0xaa);
+        let bios = host(b"bios", 0x55);
+
+        let device = |fw_count, fw_id: &[u8], hash_type, hash: &[u8]| {
+            let version_str = match fw_id {
+                b"bmc" => b"bmc1".to_vec(),
+                _ => b"bios".to_vec(),
+            };
+            owned::Node {
+                element: owned::pfm::Element::FlashDevice {
+                    blank_byte: 0xff,
+                    firmware_count: Some(fw_count),
+                },
+                children: vec![owned::Node {
+                    element: owned::pfm::Element::AllowableFw {
+                        version_count: 1,
+                        firmware_id: fw_id.to_vec(),
+                        flags: 0,
+                    },
+                    children: vec![owned::Node {
+                        element: owned::pfm::Element::FwVersion {
+                            version_addr: 0,
+                            version_str,
+                            rw_regions: vec![],
+                            image_regions: vec![owned::pfm::Image {
+                                flags: 0b1,
+                                hash_type,
+                                hash: hash.to_vec(),
+                                regions: vec![image],
+                            }],
+                            format_version: 1,
+                        },
+                        children: vec![],
+                        hashed: true,
+                    }],
+                    hashed: true,
+                }],
+                hashed: true,
+            }
+        };
+        let digest = |algo, host: &[u8]| {
+            digest::digest(algo, &host[image.offset as usize..][..0x100])
+        };
+        let bmc_hash = digest(&digest::SHA384, &bmc);
+        let bios_device = device(
+            1,
+            b"bios",
+            HashType::Sha512,
+            digest(&digest::SHA512, &bios).as_ref(),
+        );
+
+        let mut sign = |elements| {
+            let pfm = owned::Pfm {
+                metadata: Metadata { version_id: 42 },
+                elements,
+            };
+            Ram(pfm.sign(0x0, &sha, &mut signer).unwrap())
+        };
+        let good = sign(vec![
+            device(1, b"bmc", HashType::Sha384, bmc_hash.as_ref()),
+            bios_device.clone(),
+        ]);
+        // The first device claims both `AllowableFw`s, leaving none for the
+        // second.
+        let miscounted = sign(vec![
+            device(2, b"bmc", HashType::Sha384, bmc_hash.as_ref()),
+            bios_device,
+        ]);
+
+        let mut arena = [0; 1024];
+        let arena = BumpArena::new(&mut arena);
+
+        let pfm = ParsedPfm::new(
+            Container::parse_and_verify(
+                &good,
+                &sha,
+                &mut rsa,
+                &OutOfMemory,
+                &OutOfMemory,
+            )
+            .unwrap(),
+        );
+        assert_eq!(pfm.device_count(), 2);
+        let ids = |device| {
+            pfm.device_fws(device, &sha, &arena)
+                .unwrap()
+                .map(|fw| fw.read(&sha, &arena).unwrap().firmware_id())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(0), vec![b"bmc"]);
+        assert_eq!(ids(1), vec![b"bios"]);
+        assert!(matches!(
+            pfm.device_fws(2, &sha, &arena),
+            Err(Error::OutOfRange)
+        ));
+
+        let when = ValidationTime::Activation;
+        Pfm::validate(&pfm, when, &[&Ram(&bmc), &Ram(&bios)], &sha, &arena)
+            .unwrap();
+        assert!(matches!(
+            Pfm::validate(&pfm, when, &[&Ram(&bios), &Ram(&bmc)], &sha, &arena),
+            Err(Error::NoMatchingVersion { .. })
+        ));
+        assert!(matches!(
+            Pfm::validate(&pfm, when, &[&Ram(&bmc)], &sha, &arena),
+            Err(Error::WrongDeviceCount)
+        ));
+
+        let mut corrupt = bios;
+        corrupt[0x1ff] ^= 0xff;
+        assert!(matches!(
+            pfm.validate_device(1, when, &Ram(&corrupt), &sha, &arena),
+            Err(Error::BadGuardedHash { .. })
+        ));
+
+        let pfm = ParsedPfm::new(
+            Container::parse_and_verify(
+                &miscounted,
+                &sha,
+                &mut rsa,
+                &OutOfMemory,
+                &OutOfMemory,
+            )
+            .unwrap(),
+        );
+        assert!(matches!(
+            pfm.validate_device(0, when, &Ram(&bmc), &sha, &arena),
+            Err(Error::BadFirmwareCount { .. })
+        ));
+    }
+
+    #[test]
+    fn select_version() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, mut signer) = test_rsa();
+
+        let version = |version_addr, version_str: &[u8]| owned::Node {
+            element: owned::pfm::Element::FwVersion {
+                version_addr,
+                version_str: version_str.to_vec(),
+                rw_regions: vec![],
+                image_regions: vec![],
+                format_version: 0,
+            },
+            children: vec![],
+            hashed: true,
+        };
+        let pfm = owned::Pfm {
+            metadata: Metadata { version_id: 42 },
+            elements: vec![
+                owned::Node {
+                    element: owned::pfm::Element::AllowableFw {
+                        version_count: 5,
+                        firmware_id: b"bmc".to_vec(),
+                        flags: 0,
+                    },
+                    children: vec![
+                        version(0x10, b"v1.0"),
+                        version(0x40, b"legacy-1"),
+                        version(0x10, b"v2.0"),
+                        version(0x10, b"v2.0.1"),
+                        version(0x80, b"v2.0.1"),
+                    ],
+                    hashed: true,
+                },
+                owned::Node {
+                    element: owned::pfm::Element::AllowableFw {
+                        version_count: 1,
+                        firmware_id: b"bios".to_vec(),
+                        flags: 0,
+                    },
+                    children: vec![version(0xffc, b"rev-a")],
+                    hashed: true,
+                },
+            ],
+        };
+        let bytes = Ram(pfm.sign(0x0, &sha, &mut signer).unwrap());
+
+        let container = Container::parse_and_verify(
+            &bytes,
+            &sha,
+            &mut rsa,
+            &OutOfMemory,
+            &OutOfMemory,
+        )
+        .unwrap();
+        let pfm = ParsedPfm::new(container);
+
+        let mut arena = [0; 1024];
+        let arena = BumpArena::new(&mut arena);
+
+        // The longest matching version wins, regardless of `Toc` order.
+        let mut host = vec![0xff; 0x1000];
+        host[0x10..0x16].copy_from_slice(b"v2.0.1");
+        let (fw, version) = pfm
+            .select_version(b"bmc", &Ram(&host[..]), &sha, &arena)
+            .unwrap();
+        assert_eq!(fw.firmware_id(), b"bmc");
+        assert_eq!(version.version().1, b"v2.0.1");
+        assert_eq!(version.entry().index(), 4);
+
+        // A version string is still a prefix of what it matches.
+        host[0x10..0x16].copy_from_slice(b"v2.0.2");
+        let (_, version) = pfm
+            .select_version(b"bmc", &Ram(&host[..]), &sha, &arena)
+            .unwrap();
+        assert_eq!(version.version().1, b"v2.0");
+        assert_eq!(version.entry().index(), 3);
+
+        // Two equally long matches cannot be told apart.
+        host[0x10..0x16].copy_from_slice(b"v2.0.1");
+        host[0x80..0x86].copy_from_slice(b"v2.0.1");
+        assert!(matches!(
+            pfm.select_version(b"bmc", &Ram(&host[..]), &sha, &arena),
+            Err(SelectError::Manifest(Error::AmbiguousVersion {
+                toc_index: 0
+            }))
+        ));
+        host[0x80..0x86].copy_from_slice(&[0xff; 6]);
+
+        host[0x40..0x48].copy_from_slice(b"legacy-1");
+        host[0x10..0x16].copy_from_slice(b"v3.0.0");
+        let (_, version) = pfm
+            .select_version(b"bmc", &Ram(&host[..]), &sha, &arena)
+            .unwrap();
+        assert_eq!(version.version().1, b"legacy-1");
+
+        // A version string running off the end of flash never matches.
+        host[0xffc..].copy_from_slice(b"rev-");
+        match pfm.select_version(b"bios", &Ram(&host[..]), &sha, &arena) {
+            Err(SelectError::NoMatch { toc_index, tried }) => {
+                assert_eq!(toc_index, 6);
+                assert_eq!(
+                    tried,
+                    &[VersionAttempt {
+                        toc_index: 7,
+                        version_addr: 0xffc
+                    }]
+                );
+            }
+            _ => panic!("expected NoMatch"),
+        }
+
+        host[0x40] = b'L';
+        match pfm.select_version(b"bmc", &Ram(&host[..]), &sha, &arena) {
+            Err(SelectError::NoMatch { toc_index, tried }) => {
+                assert_eq!(toc_index, 0);
+                let tried = tried
+                    .iter()
+                    .map(|a| (a.toc_index, a.version_addr))
+                    .collect::<Vec<_>>();
+                assert_eq!(
+                    tried,
+                    vec![(1, 0x10), (2, 0x40), (3, 0x10), (4, 0x10), (5, 0x80)]
+                );
+            }
+            _ => panic!("expected NoMatch"),
+        }
+
+        assert!(matches!(
+            pfm.select_version(b"me", &Ram(&host[..]), &sha, &arena),
+            Err(SelectError::UnknownFirmware)
+        ));
+    }
+
+    #[test]
+    fn apply_filter() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, mut signer) = test_rsa();
+
+        let mut host = vec![0xff; 0x800];
+        host[..4].copy_from_slice(b"v1.0");
+        host[0x300..0x400].copy_from_slice(&[0x5a; 0x100]);
+
+        let image = |regions: Vec<Region>| {
+            let mut hasher = sha.new_hasher().unwrap();
+            for r in &regions {
+                hasher
+                    .write(&host[r.offset as usize..r.end() as usize])
+                    .unwrap();
+            }
+            let mut hash = sha256::Digest::default();
+            hasher.finish(&mut hash).unwrap();
+            owned::pfm::Image {
+                flags: 0,
+                hash_type: HashType::Sha256,
+                hash: hash.to_vec(),
+                regions,
+            }
+        };
+        let pfm = |rw: Region| owned::Pfm {
+            metadata: Metadata { version_id: 42 },
+            elements: vec![owned::Node {
+                element: owned::pfm::Element::AllowableFw {
+                    version_count: 1,
+                    firmware_id: b"bmc".to_vec(),
+                    flags: 0,
+                },
+                children: vec![owned::Node {
+                    element: owned::pfm::Element::FwVersion {
+                        version_addr: 0x0,
+                        version_str: b"v1.0".to_vec(),
+                        rw_regions: vec![owned::pfm::Rw {
+                            flags: 0,
+                            region: rw,
+                        }],
+                        image_regions: vec![
+                            image(vec![Region::new(0x0, 0x100)]),
+                            image(vec![
+                                Region::new(0x100, 0x100),
+                                Region::new(0x300, 0x100),
+                            ]),
+                        ],
+                        format_version: 0,
+                    },
+                    children: vec![],
+                    hashed: true,
+                }],
+                hashed: true,
+            }],
+        };
+
+        let good = Ram(pfm(Region::new(0x200, 0x100))
+            .sign(0x0, &sha, &mut signer)
+            .unwrap());
+        let bad = Ram(pfm(Region::new(0x380, 0x100))
+            .sign(0x0, &sha, &mut signer)
+            .unwrap());
+
+        let mut arena = [0; 2048];
+        let arena = BumpArena::new(&mut arena);
+        let parse = |bytes| {
+            ParsedPfm::new(
+                Container::parse_and_verify(
+                    bytes,
+                    &sha,
+                    &mut test_rsa().0,
+                    &arena,
+                    &arena,
+                )
+                .unwrap(),
+            )
+        };
+        let (good, bad) = (parse(&good), parse(&bad));
+
+        let mut filter = Simulated::new(RamMut(host), 8, 0x100);
+
+        good.apply_filter(0, &Ram(&[0; 0x800][..]), &mut filter, &sha, &arena)
+            .unwrap_err();
+        assert!(filter.rules().is_empty());
+
+        // The version matches, but an image does not, so it must not be
+        // locked in.
+        let mut tampered = filter.inner().0.clone();
+        tampered[0x3ff] ^= 1;
+        assert!(matches!(
+            good.apply_filter(
+                0,
+                &Ram(&tampered[..]),
+                &mut filter,
+                &sha,
+                &arena
+            ),
+            Err(Error::BadGuardedHash { toc_index: 1 })
+        ));
+        assert!(filter.rules().is_empty());
+
+        let snapshot = filter.inner().0.clone();
+        good.apply_filter(0, &Ram(&snapshot[..]), &mut filter, &sha, &arena)
+            .unwrap();
+        assert_eq!(filter.rules().len(), 4);
+
+        let mut buf = [0; 4];
+        filter.read(0x0, &mut buf).unwrap();
+        assert_eq!(&buf, b"v1.0");
+        filter.read(0x3fc, &mut buf).unwrap();
+        assert!(filter.read(0x400, &mut buf).is_err());
+        filter.program(0x2fc, b"rw!!").unwrap();
+        assert!(matches!(
+            filter.program(0x1fc, b"ro!!"),
+            Err(flash::Error::Locked)
+        ));
+        assert!(matches!(
+            filter.program(0x300, b"ro!!"),
+            Err(flash::Error::Locked)
+        ));
+        assert_eq!(&filter.inner().0[0x2fc..0x300], b"rw!!");
+
+        // A writable region that overlaps an image can't be enforced, so the
+        // host is locked out entirely.
+        assert!(matches!(
+            bad.apply_filter(0, &Ram(&snapshot[..]), &mut filter, &sha, &arena),
+            Err(Error::Filter(filter::Error::Conflict))
+        ));
+        assert!(filter.rules().is_empty());
+    }
+
+    #[test]
+    fn rw_failure_policy() {
+        let sha = ring::sha256::Builder::new();
+        let (mut rsa, mut signer) = test_rsa();
+
+        let rw = |flags, offset| owned::pfm::Rw {
+            flags,
+            region: Region::new(offset, 0x40),
+        };
+        let pfm = |rw_regions| owned::Pfm {
+            metadata: Metadata { version_id: 42 },
+            elements: vec![owned::Node {
+                element: owned::pfm::Element::AllowableFw {
+                    version_count: 1,
+                    firmware_id: b"bmc".to_vec(),
+                    flags: 0,
+                },
+                children: vec![owned::Node {
+                    element: owned::pfm::Element::FwVersion {
+                        version_addr: 0x0,
+                        version_str: b"v1.0".to_vec(),
+                        rw_regions,
+                        image_regions: vec![],
+                        format_version: 0,
+                    },
+                    children: vec![],
+                    hashed: true,
+                }],
+                hashed: true,
+            }],
+        };
+
+        let good =
+            Ram(pfm(vec![rw(0b00, 0x100), rw(0b10, 0x200), rw(0b01, 0x300)])
+                .sign(0x0, &sha, &mut signer)
+                .unwrap());
+        let bad = Ram(pfm(vec![rw(0b10, 0x100), rw(0b11, 0x200)])
+            .sign(0x0, &sha, &mut signer)
+            .unwrap());
+
+        let mut arena = [0; 1024];
+        let arena = BumpArena::new(&mut arena);
+        let parse = |bytes| {
+            ParsedPfm::new(
+                Container::parse_and_verify(
+                    bytes,
+                    &sha,
+                    &mut test_rsa().0,
+                    &arena,
+                    &arena,
+                )
+                .unwrap(),
+            )
+        };
+        let (good, bad) = (parse(&good), parse(&bad));
+
+        let backup = Ram(vec![0x5a; 0x400]);
+        let mut host = RamMut(vec![0x11; 0x400]);
+
+        let fw = good.allowable_fws().next().unwrap();
+        let fw = fw.read(&sha, &arena).unwrap();
+        let version = fw.firmware_versions().next().unwrap();
+        let version = version.read(&sha, &arena).unwrap();
+
+        assert!(matches!(
+            version.enforce_rw_failure_policy(&mut host, 0xff, None),
+            Err(Error::NoBackup)
+        ));
+        assert!(host.0.iter().all(|&b| b == 0x11));
+
+        version
+            .enforce_rw_failure_policy(&mut host, 0xff, Some(&backup))
+            .unwrap();
+        assert!(host.0[0x100..0x140].iter().all(|&b| b == 0x11));
+        assert!(host.0[0x200..0x240].iter().all(|&b| b == 0xff));
+        assert!(host.0[0x300..0x340].iter().all(|&b| b == 0x5a));
+        assert_eq!(host.0[0x140], 0x11);
+        assert_eq!(host.0[0x240], 0x11);
+        assert_eq!(host.0[0x340], 0x11);
+
+        // On NOR flash, regions must be erased before being reprogrammed, and
+        // programs must not cross page boundaries.
+        let geometry = flash::Geometry {
+            page_size: 0x10,
+            sector_size: 0x40,
+            block_size: 0x40,
+        };
+        let mut nor = Nor::new(vec![0x11; 0x400], geometry).unwrap();
+        version
+            .enforce_rw_failure_policy(&mut nor, 0x00, Some(&backup))
+            .unwrap();
+        let bytes = nor.as_bytes();
+        assert!(bytes[0x100..0x140].iter().all(|&b| b == 0x11));
+        assert!(bytes[0x200..0x240].iter().all(|&b| b == 0x00));
+        assert!(bytes[0x300..0x340].iter().all(|&b| b == 0x5a));
+        assert!(bytes[0x340..].iter().all(|&b| b == 0x11));
+
+        // Regions that aren't whole sectors can't be erased without
+        // disturbing their neighbors.
+        let geometry = flash::Geometry {
+            page_size: 0x10,
+            sector_size: 0x80,
+            block_size: 0x80,
+        };
+        let mut nor = Nor::new(vec![0x11; 0x400], geometry).unwrap();
+        assert!(matches!(
+            version.enforce_rw_failure_policy(&mut nor, 0xff, Some(&backup)),
+            Err(Error::Flash(flash::Error::Unaligned))
+        ));
+        assert!(nor.as_bytes().iter().all(|&b| b == 0x11));
+
+        let fw = bad.allowable_fws().next().unwrap();
+        let fw = fw.read(&sha, &arena).unwrap();
+        let version = fw.firmware_versions().next().unwrap();
+        let version = version.read(&sha, &arena).unwrap();
+
+        let mut host = RamMut(vec![0x11; 0x400]);
+        assert!(matches!(
+            version.enforce_rw_failure_policy(&mut host, 0xff, Some(&backup)),
+            Err(Error::UnknownFailurePolicy)
+        ));
+        assert!(host.0.iter().all(|&b| b == 0x11));
+    }
+
     #[test]
     fn baked_pfm1() {
         let sha = ring::sha256::Builder::new();
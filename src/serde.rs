@@ -30,6 +30,11 @@ pub fn skip_if_true(b: &bool) -> bool {
     *b
 }
 
+/// For skipping field serialization if it's set to zero.
+pub fn skip_if_zero(x: &u8) -> bool {
+    *x == 0
+}
+
 /// For deserializing a `Vec<u8>` from either a string or a sequence of bytes.
 #[cfg(feature = "std")]
 pub fn de_bytestring<'de, D>(d: D) -> Result<Vec<u8>, D::Error>
@@ -16,21 +16,46 @@ use std::io;
 use std::io::BufReader;
 use std::io::Read;
 use std::io::Write;
+use std::path::Path;
 use std::path::PathBuf;
 
 use serde::de::Deserialize;
 
 use structopt::StructOpt;
 
+use manticore::crypto::ecdsa;
+use manticore::crypto::ecdsa::Builder as _;
+use manticore::crypto::ecdsa::Keypair as _;
+use manticore::crypto::ecdsa::SignerBuilder as _;
 use manticore::crypto::ring;
+use manticore::crypto::rsa;
 use manticore::crypto::rsa::Builder as _;
 use manticore::crypto::rsa::Keypair as _;
 use manticore::crypto::rsa::SignerBuilder as _;
+use manticore::crypto::sig;
+use manticore::hardware::flash;
+use manticore::hardware::flash::partition;
+use manticore::hardware::flash::Ram;
 use manticore::io::write::StdWrite;
 use manticore::io::Read as _;
+use manticore::manifest;
+use manticore::manifest::km::Km;
 use manticore::manifest::owned;
+use manticore::manifest::owned::diff;
+use manticore::manifest::owned::layout;
+use manticore::manifest::owned::lint;
+use manticore::manifest::pcd::Pcd;
+use manticore::manifest::pfm::FwRegion;
+use manticore::manifest::pfm::ParsedPfm;
+use manticore::manifest::pfm::Pfm;
+use manticore::manifest::pfm::SelectError;
+use manticore::manifest::provenance;
+use manticore::manifest::provenance::Provenance;
+use manticore::manifest::Container;
 use manticore::manifest::ManifestType;
+use manticore::manifest::ValidationTime;
 use manticore::mem::BumpArena;
+use manticore::mem::OutOfMemory;
 use manticore::protocol::firmware_version;
 use manticore::protocol::wire::FromWire;
 use manticore::protocol::wire::ToWire;
@@ -64,6 +89,158 @@ fn open_files(
     (input, output)
 }
 
+/// Loads a PKCS#8-encoded RSA or ECDSA private key, returning a signer for
+/// it.
+fn load_signer(path: &Path) -> Box<dyn sig::Sign> {
+    let key = fs::read(path).expect("failed to open file");
+    pkcs8_signer(&key)
+}
+
+/// Parses a PKCS#8-encoded private key; see [`load_signer()`].
+fn pkcs8_signer(key: &[u8]) -> Box<dyn sig::Sign> {
+    if let Some(keypair) = ring::ecdsa::Keypair::from_pkcs8(key) {
+        let signer = ring::ecdsa::Builder::new()
+            .new_signer(keypair)
+            .expect("failed to create signing engine");
+        return Box::new(sig::Ecdsa(signer));
+    }
+
+    let keypair =
+        ring::rsa::Keypair::from_pkcs8(key).expect("failed to parse key");
+    let signer = ring::rsa::Builder::new()
+        .new_signer(keypair)
+        .expect("failed to create signing engine");
+    Box::new(sig::Rsa(signer))
+}
+
+/// Loads a PKCS#8-encoded RSA or ECDSA private key, returning a signature
+/// verifier for its public half.
+fn load_verifier(path: &Path) -> Box<dyn sig::Verify> {
+    let key = fs::read(path).expect("failed to open file");
+    pkcs8_verifier(&key)
+}
+
+/// Parses a PKCS#8-encoded private key; see [`load_verifier()`].
+fn pkcs8_verifier(key: &[u8]) -> Box<dyn sig::Verify> {
+    if let Some(keypair) = ring::ecdsa::Keypair::from_pkcs8(key) {
+        let engine = ring::ecdsa::Builder::new()
+            .new_engine(keypair.public())
+            .expect("failed to create signature verification engine");
+        return Box::new(sig::Ecdsa(engine));
+    }
+
+    let keypair =
+        ring::rsa::Keypair::from_pkcs8(key).expect("failed to parse key");
+    let engine = ring::rsa::Builder::new()
+        .new_engine(keypair.public())
+        .expect("failed to create signature verification engine");
+    Box::new(sig::Rsa(engine))
+}
+
+/// Reads a DER tag-length-value triple off the front of `der`, returning the
+/// tag, the value, and the remaining bytes.
+fn der_next(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = der.split_first()?;
+    let (&len, mut rest) = rest.split_first()?;
+    let len = if len < 0x80 {
+        len as usize
+    } else {
+        let n = (len & 0x7f) as usize;
+        if n > 4 || rest.len() < n {
+            return None;
+        }
+        let (len, r) = rest.split_at(n);
+        rest = r;
+        len.iter().fold(0, |acc, &b| acc << 8 | b as usize)
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let (value, rest) = rest.split_at(len);
+    Some((tag, value, rest))
+}
+
+/// Loads a DER-encoded `SubjectPublicKeyInfo` containing an RSA or ECDSA
+/// public key, returning a signature verifier for it.
+///
+/// This is the format produced by `openssl pkey -pubout -outform DER`.
+fn load_public_key(path: &Path) -> Box<dyn sig::Verify> {
+    let key = fs::read(path).expect("failed to open file");
+    parse_public_key(&key).expect("failed to parse public key")
+}
+
+/// Parses a DER-encoded `SubjectPublicKeyInfo`; see [`load_public_key()`].
+///
+/// Returns `None` for anything other than a single, well-formed RSA or ECDSA
+/// key that `ring` accepts.
+fn parse_public_key(der: &[u8]) -> Option<Box<dyn sig::Verify>> {
+    const RSA_ENCRYPTION: &[u8] =
+        &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+    const EC_PUBLIC_KEY: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+    const SEQUENCE: u8 = 0x30;
+    const OID: u8 = 0x06;
+    const BIT_STRING: u8 = 0x03;
+    const INTEGER: u8 = 0x02;
+
+    fn expect(der: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+        let (t, value, rest) = der_next(der)?;
+        if t != tag {
+            return None;
+        }
+        Some((value, rest))
+    }
+
+    fn strip_zeros(bytes: &[u8]) -> Box<[u8]> {
+        let start = bytes.iter().position(|&b| b != 0).unwrap_or(bytes.len());
+        bytes[start..].into()
+    }
+
+    let (spki, rest) = expect(der, SEQUENCE)?;
+    if !rest.is_empty() {
+        return None;
+    }
+    let (algorithm, rest) = expect(spki, SEQUENCE)?;
+    let (oid, _) = expect(algorithm, OID)?;
+    let (key, _) = expect(rest, BIT_STRING)?;
+    // Keys are always a whole number of bytes, so there must be no unused
+    // bits.
+    let key = match key.split_first()? {
+        (0, key) => key,
+        _ => return None,
+    };
+
+    if oid == RSA_ENCRYPTION {
+        let (key, _) = expect(key, SEQUENCE)?;
+        let (n, rest) = expect(key, INTEGER)?;
+        let (e, _) = expect(rest, INTEGER)?;
+        let key = ring::rsa::PublicKey::new(strip_zeros(n), strip_zeros(e))?;
+        let engine = ring::rsa::Builder::new().new_engine(key).ok()?;
+        Some(Box::new(sig::Rsa(engine)))
+    } else if oid == EC_PUBLIC_KEY {
+        let curve = [ecdsa::Curve::P256, ecdsa::Curve::P384]
+            .iter()
+            .copied()
+            .find(|c| key.len() == 1 + 2 * c.byte_len())?;
+        let key = ring::ecdsa::PublicKey::new(curve, key.into())?;
+        let engine = ring::ecdsa::Builder::new().new_engine(key).ok()?;
+        Some(Box::new(sig::Ecdsa(engine)))
+    } else {
+        None
+    }
+}
+
+/// Parses a signature algorithm name, such as `rsa-2048` or `ecdsa-p384`.
+fn parse_algo(s: &str) -> Result<sig::Algo, String> {
+    Ok(match s {
+        "rsa-2048" => sig::Algo::Rsa(rsa::ModulusLength::Bits2048),
+        "rsa-3072" => sig::Algo::Rsa(rsa::ModulusLength::Bits3072),
+        "rsa-4096" => sig::Algo::Rsa(rsa::ModulusLength::Bits4096),
+        "ecdsa-p256" => sig::Algo::Ecdsa(ecdsa::Curve::P256),
+        "ecdsa-p384" => sig::Algo::Ecdsa(ecdsa::Curve::P384),
+        _ => return Err(format!("unknown signature algorithm: {}", s)),
+    })
+}
+
 /// Deserializes a message in JSON format from `reader` and then serializes the
 /// message in wire format to `writer`.
 fn from_json_to_wire<'de, T, R, W>(reader: R, writer: W)
@@ -93,7 +270,7 @@ fn from_json(
     let mut stdwrite = StdWrite(&mut output);
 
     Header {
-        is_request: is_request,
+        is_request,
         command: cmd_type,
     }
     .to_wire(&mut stdwrite)
@@ -177,6 +354,178 @@ fn to_json(pretty: bool, input: impl Read, output: impl Write) {
     });
 }
 
+/// Reports any verification failures in `parse` to stderr, and then writes
+/// the parsed manifest out as JSON.
+fn show_manifest<E: serde::Serialize>(
+    parse: owned::Parse<E>,
+    pretty: bool,
+    output: impl Write,
+) {
+    if parse.bad_signature {
+        eprintln!("signature verification failed");
+    }
+    if parse.bad_toc_hash {
+        eprintln!("TOC hash verification failed");
+    }
+    for idx in parse.bad_hashes {
+        eprintln!("bad hash for toc entry {}", idx);
+    }
+
+    if pretty {
+        serde_json::to_writer_pretty(output, &parse.container)
+    } else {
+        serde_json::to_writer(output, &parse.container)
+    }
+    .expect("failed to serialize manifest");
+}
+
+/// Lints a manifest, printing each problem found to stdout.
+///
+/// `findings` are problems already found in the raw encoding of `buf`, and
+/// `semantic` performs manifest-specific checks on the parsed manifest.
+///
+/// Returns whether any problems were found.
+fn lint_manifest<E, V, L>(
+    buf: &[u8],
+    sha: &ring::sha256::Builder,
+    verifier: Option<&mut V>,
+    mut findings: Vec<lint::Finding>,
+    semantic: L,
+) -> bool
+where
+    V: sig::Verify,
+    L: FnOnce(&owned::Container<E>) -> Vec<lint::Finding>,
+    E: owned::Element + for<'f> owned::FromUnowned<'f, Ram<&'f [u8]>>,
+{
+    let mut problems = false;
+    match owned::Container::<E>::parse(buf, sha, verifier) {
+        Ok(parse) => {
+            if parse.bad_signature {
+                println!("signature verification failed");
+                problems = true;
+            }
+            if parse.bad_toc_hash {
+                println!("TOC hash verification failed");
+                problems = true;
+            }
+            for idx in &parse.bad_hashes {
+                println!("bad hash for toc entry {}", idx);
+                problems = true;
+            }
+
+            findings.extend(lint::unhashed(&parse.container));
+            findings.extend(semantic(&parse.container));
+        }
+        Err(e) if findings.is_empty() => {
+            panic!("failed to parse manifest: {:?}", e)
+        }
+        Err(e) => println!("failed to parse manifest: {:?}", e),
+    }
+
+    for finding in &findings {
+        println!("{}", finding);
+    }
+    problems || !findings.is_empty()
+}
+
+/// Checks the contents of `host` against `pfm`, printing a report to stdout.
+///
+/// For each allowable firmware, the version that
+/// [`AllowableFw::select_version()`] picks out of `host` is matched, and each
+/// of its images is checked with [`FwVersion::validate_images()`]; the
+/// verdict is derived from the same results that are reported.
+///
+/// [`AllowableFw::select_version()`]: manticore::manifest::pfm::AllowableFw::select_version
+/// [`FwVersion::validate_images()`]: manticore::manifest::pfm::FwVersion::validate_images
+///
+/// Returns whether `host` would be accepted.
+fn verify_flash<'pfm, P: Provenance>(
+    pfm: &ParsedPfm<'pfm, Ram<&'pfm [u8]>, P>,
+    device: usize,
+    when: ValidationTime,
+    host: &dyn flash::Flash,
+    sha: &ring::sha256::Builder,
+    arena: &'pfm BumpArena<'pfm>,
+) -> bool {
+    let fws = pfm
+        .device_fws(device, sha, arena)
+        .expect("failed to read PFM");
+    let mut accepted = true;
+    for fw in fws {
+        let fw = fw.read(sha, arena).expect("failed to read PFM");
+        let firmware_id = String::from_utf8_lossy(fw.firmware_id());
+
+        let version = match fw.select_version(host, sha, arena) {
+            Ok(version) => version,
+            Err(SelectError::NoMatch { tried, .. }) => {
+                println!("firmware {:?}: no matching version", firmware_id);
+                for attempt in tried {
+                    println!(
+                        "  tried toc entry {} at {:#x}",
+                        attempt.toc_index, attempt.version_addr
+                    );
+                }
+                accepted = false;
+                continue;
+            }
+            Err(SelectError::Manifest(
+                e @ manifest::Error::AmbiguousVersion { .. },
+            )) => {
+                println!("firmware {:?}: {:?}", firmware_id, e);
+                accepted = false;
+                continue;
+            }
+            Err(e) => panic!("failed to read PFM: {:?}", e),
+        };
+        println!(
+            "firmware {:?}: matched version {:?} (toc entry {})",
+            firmware_id,
+            String::from_utf8_lossy(version.version().1),
+            version.entry().index(),
+        );
+
+        let describe = |image: &FwRegion<'_>| {
+            image
+                .regions()
+                .map(|r| format!("[{:#x}, {:#x})", r.offset, r.end()))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        for image in version.image_regions() {
+            if !image.must_validate(when) {
+                println!("  image {}: not validated on boot", describe(&image));
+            }
+        }
+        for (image, result) in version.validate_images(when, host, sha) {
+            let status = match result {
+                Ok(()) => "ok".to_string(),
+                Err(manifest::Error::BadGuardedHash { .. }) => {
+                    "hash mismatch".to_string()
+                }
+                Err(manifest::Error::UnsupportedHashType(hash_type)) => {
+                    format!("unsupported hash type {:?}", hash_type)
+                }
+                Err(e) => format!("unreadable: {:?}", e),
+            };
+            accepted &= result.is_ok();
+            println!("  image {}: {}", describe(&image), status);
+        }
+    }
+
+    if !accepted {
+        println!("rejected");
+    }
+    accepted
+}
+
+/// Parses an integer in decimal, or in hex with a `0x` prefix.
+fn parse_u32(s: &str) -> Result<u32, std::num::ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
 #[deny(missing_docs)]
 #[derive(Debug, StructOpt)]
 #[structopt(
@@ -218,7 +567,7 @@ enum CliCommand {
     },
     /// Create a new signed manifest.
     SignManifest {
-        /// PKCS#8-encoded RSA signing key to sign with.
+        /// PKCS#8-encoded RSA or ECDSA signing key to sign with.
         #[structopt(short = "k", long, parse(from_os_str))]
         key: PathBuf,
 
@@ -234,9 +583,101 @@ enum CliCommand {
         #[structopt(short = "o", long, parse(from_os_str))]
         output: Option<PathBuf>,
     },
+    /// Encode a manifest for signing elsewhere, such as by an HSM.
+    ///
+    /// The signature must be made over the digest written to `--digest`,
+    /// and can then be added with `attach-signature`.
+    PrepareManifest {
+        /// The signature algorithm that will be used: one of `rsa-2048`,
+        /// `rsa-3072`, `rsa-4096`, `ecdsa-p256`, or `ecdsa-p384`.
+        #[structopt(short = "a", long, parse(try_from_str = parse_algo))]
+        algo: sig::Algo,
+
+        /// The manifest type for this operation.
+        #[structopt(short = "t", long)]
+        manifest_type: ManifestType,
+
+        /// Output file for the SHA-256 digest that must be signed.
+        #[structopt(short = "d", long, parse(from_os_str))]
+        digest: PathBuf,
+
+        /// JSON file containing the manifest to encode; defaults to stdin.
+        #[structopt(short = "i", long, parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// Binary output file for the unsigned manifest; defaults to stdout.
+        #[structopt(short = "o", long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Attach a signature to a manifest created by `prepare-manifest`.
+    AttachSignature {
+        /// DER-encoded public key to check the signature with.
+        #[structopt(short = "k", long, parse(from_os_str))]
+        key: PathBuf,
+
+        /// Binary file containing the signature; ECDSA signatures must be
+        /// the fixed-width concatenation of `r` and `s`.
+        #[structopt(short = "s", long, parse(from_os_str))]
+        signature: PathBuf,
+
+        /// Binary file containing the unsigned manifest; defaults to stdin.
+        #[structopt(short = "i", long, parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// Binary output file; defaults to stdout.
+        #[structopt(short = "o", long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Assemble a host flash image and a signed PFM describing it from a
+    /// layout file.
+    BuildFlash {
+        /// PKCS#8-encoded RSA or ECDSA signing key to sign with.
+        #[structopt(short = "k", long, parse(from_os_str))]
+        key: PathBuf,
+
+        /// JSON file describing the flash layout; relative binary paths are
+        /// resolved against its directory.
+        #[structopt(short = "l", long, parse(from_os_str))]
+        layout: PathBuf,
+
+        /// Output file for the flash image.
+        #[structopt(long, parse(from_os_str))]
+        flash: PathBuf,
+
+        /// Output file for the signed PFM.
+        #[structopt(long, parse(from_os_str))]
+        pfm: PathBuf,
+    },
+    /// Check whether a host flash image would be accepted by a PFM.
+    ///
+    /// Exits with a non-zero status if it would not be.
+    VerifyFlash {
+        /// PKCS#8-encoded RSA or ECDSA key to optionally verify the PFM's
+        /// signature with.
+        #[structopt(short = "k", long, parse(from_os_str))]
+        key: Option<PathBuf>,
+
+        /// Binary file containing the PFM.
+        #[structopt(long, parse(from_os_str))]
+        pfm: PathBuf,
+
+        /// Binary file containing the flash image.
+        #[structopt(long, parse(from_os_str))]
+        flash: PathBuf,
+
+        /// Index of the PFM flash device that the image belongs to.
+        #[structopt(long, default_value = "0")]
+        device: usize,
+
+        /// Only check the images that must be validated on boot, rather than
+        /// every image checked on activation.
+        #[structopt(long)]
+        startup: bool,
+    },
     /// Inspect an existing manifest.
     ShowManifest {
-        /// PKCS#8-encoded RSA public key to optionally verify the signature.
+        /// PKCS#8-encoded RSA or ECDSA key to optionally verify the signature
+        /// with.
         #[structopt(short = "k", long, parse(from_os_str))]
         key: Option<PathBuf>,
 
@@ -248,6 +689,89 @@ enum CliCommand {
         #[structopt(short = "i", long, parse(from_os_str))]
         input: Option<PathBuf>,
 
+        /// JSON output file; defaults to stdout.
+        #[structopt(short = "o", long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Compare two PFMs, listing added, removed and changed elements.
+    ///
+    /// Exits with a non-zero status if the manifests differ.
+    Diff {
+        /// PKCS#8-encoded RSA or ECDSA key to optionally verify the signatures
+        /// with.
+        #[structopt(short = "k", long, parse(from_os_str))]
+        key: Option<PathBuf>,
+
+        /// Whether to output JSON rather than a human-readable listing.
+        #[structopt(short = "j", long)]
+        json: bool,
+
+        /// Whether to pretty-print the resulting JSON.
+        #[structopt(short = "p", long)]
+        pretty: bool,
+
+        /// Output file; defaults to stdout.
+        #[structopt(short = "o", long, parse(from_os_str))]
+        output: Option<PathBuf>,
+
+        /// Binary file containing the old manifest.
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+
+        /// Binary file containing the new manifest.
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+    },
+    /// Check an existing manifest for semantic problems.
+    ///
+    /// Exits with a non-zero status if any problems are found.
+    Lint {
+        /// PKCS#8-encoded RSA or ECDSA key to optionally verify the signature
+        /// with.
+        #[structopt(short = "k", long, parse(from_os_str))]
+        key: Option<PathBuf>,
+
+        /// Size of the flash described by the manifest, in bytes; regions past
+        /// the end of it are flagged.
+        #[structopt(long, parse(try_from_str = parse_u32))]
+        flash_size: Option<u32>,
+
+        /// Alignment, in bytes, that regions must have.
+        #[structopt(long, default_value = "0x1000", parse(try_from_str = parse_u32))]
+        alignment: u32,
+
+        /// Binary file containing the manifest to lint; defaults to stdin.
+        #[structopt(short = "i", long, parse(from_os_str))]
+        input: Option<PathBuf>,
+    },
+    /// Build a flash partition table from a JSON list of partitions.
+    BuildPartitions {
+        /// Offset in flash that the table will be stored at.
+        #[structopt(long, default_value = "0x0", parse(try_from_str = parse_u32))]
+        offset: u32,
+
+        /// JSON input file; defaults to stdin.
+        #[structopt(short = "i", long, parse(from_os_str))]
+        input: Option<PathBuf>,
+
+        /// Binary output file; defaults to stdout.
+        #[structopt(short = "o", long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Inspect the flash partition table in a flash image.
+    ShowPartitions {
+        /// Offset in flash that the table is stored at.
+        #[structopt(long, default_value = "0x0", parse(try_from_str = parse_u32))]
+        offset: u32,
+
+        /// Whether to pretty-print the resulting JSON.
+        #[structopt(short = "p", long)]
+        pretty: bool,
+
+        /// Binary file containing the flash image.
+        #[structopt(long, parse(from_os_str))]
+        flash: PathBuf,
+
         /// JSON output file; defaults to stdout.
         #[structopt(short = "o", long, parse(from_os_str))]
         output: Option<PathBuf>,
@@ -281,12 +805,8 @@ fn main() {
         } => {
             let (mut input, mut output) = open_files(input, output);
 
-            let key = fs::read(key).expect("failed to open file");
-            let keypair = ring::rsa::Keypair::from_pkcs8(&key)
-                .expect("failed to parse key");
-            let mut signer = ring::rsa::Builder::new()
-                .new_signer(keypair)
-                .expect("failed to create signing engine");
+            let mut signer = load_signer(&key);
+            let mut signer = &mut *signer;
             let sha = ring::sha256::Builder::new();
 
             let mut buf = Vec::new();
@@ -298,12 +818,178 @@ fn main() {
                     pfm.sign(0x00, &sha, &mut signer)
                         .expect("failed to sign PFM")
                 }
+                ManifestType::Pcd => {
+                    let pcd: owned::Pcd = serde_json::from_slice(&buf)
+                        .expect("failed to parse PCD");
+                    pcd.sign(0x00, &sha, &mut signer)
+                        .expect("failed to sign PCD")
+                }
+                ManifestType::Km => {
+                    let km: owned::Km = serde_json::from_slice(&buf)
+                        .expect("failed to parse KM");
+                    km.sign(0x00, &sha, &mut signer).expect("failed to sign KM")
+                }
             };
 
             output
                 .write_all(&manifest)
                 .expect("failed to write manifest");
         }
+        CliCommand::PrepareManifest {
+            algo,
+            manifest_type,
+            digest,
+            input,
+            output,
+        } => {
+            let (mut input, mut output) = open_files(input, output);
+            let sha = ring::sha256::Builder::new();
+
+            let mut buf = Vec::new();
+            input.read_to_end(&mut buf).expect("failed to read file");
+            let unsigned = match manifest_type {
+                ManifestType::Pfm => {
+                    let pfm: owned::Pfm = serde_json::from_slice(&buf)
+                        .expect("failed to parse PFM");
+                    pfm.to_be_signed(0x00, &sha, algo)
+                        .expect("failed to encode PFM")
+                }
+                ManifestType::Pcd => {
+                    let pcd: owned::Pcd = serde_json::from_slice(&buf)
+                        .expect("failed to parse PCD");
+                    pcd.to_be_signed(0x00, &sha, algo)
+                        .expect("failed to encode PCD")
+                }
+                ManifestType::Km => {
+                    let km: owned::Km = serde_json::from_slice(&buf)
+                        .expect("failed to parse KM");
+                    km.to_be_signed(0x00, &sha, algo)
+                        .expect("failed to encode KM")
+                }
+            };
+
+            let message = unsigned.message(&sha).expect("failed to hash");
+            fs::write(digest, &message).expect("failed to write digest");
+            output
+                .write_all(unsigned.as_bytes())
+                .expect("failed to write manifest");
+        }
+        CliCommand::AttachSignature {
+            key,
+            signature,
+            input,
+            output,
+        } => {
+            let (mut input, mut output) = open_files(input, output);
+            let mut verifier = load_public_key(&key);
+            let mut verifier = &mut *verifier;
+            let sha = ring::sha256::Builder::new();
+
+            let signature =
+                fs::read(signature).expect("failed to read signature");
+            let mut buf = Vec::new();
+            input.read_to_end(&mut buf).expect("failed to read file");
+
+            let manifest = owned::Unsigned::from_bytes(buf)
+                .expect("failed to parse unsigned manifest")
+                .attach_signature(&signature, &sha, &mut verifier)
+                .expect("failed to attach signature");
+            output
+                .write_all(&manifest)
+                .expect("failed to write manifest");
+        }
+        CliCommand::BuildFlash {
+            key,
+            layout,
+            flash,
+            pfm,
+        } => {
+            let mut signer = load_signer(&key);
+            let mut signer = &mut *signer;
+            let sha = ring::sha256::Builder::new();
+
+            let buf = fs::read(&layout).expect("failed to read layout");
+            let base = layout.parent().unwrap_or_else(|| Path::new(""));
+            let layout: layout::Layout =
+                serde_json::from_slice(&buf).expect("failed to parse layout");
+
+            let build = layout
+                .build(&sha, |path| fs::read(base.join(path)))
+                .expect("failed to build flash image");
+            let manifest = build
+                .pfm
+                .sign(0x00, &sha, &mut signer)
+                .expect("failed to sign PFM");
+
+            fs::write(flash, &build.flash)
+                .expect("failed to write flash image");
+            fs::write(pfm, &manifest).expect("failed to write PFM");
+        }
+        CliCommand::VerifyFlash {
+            key,
+            pfm,
+            flash,
+            device,
+            startup,
+        } => {
+            let when = if startup {
+                ValidationTime::Startup
+            } else {
+                ValidationTime::Activation
+            };
+            let sha = ring::sha256::Builder::new();
+            let pfm = fs::read(pfm).expect("failed to read PFM");
+            let pfm = Ram(&pfm[..]);
+            let host = File::open(flash)
+                .and_then(flash::File::new)
+                .expect("failed to open flash image");
+
+            let mut toc_arena = vec![0; 4096];
+            let toc_arena = BumpArena::new(&mut toc_arena);
+            let mut arena = vec![0; 4096];
+            let arena = BumpArena::new(&mut arena);
+
+            let ok = match key {
+                Some(key) => {
+                    let mut verifier = load_verifier(&key);
+                    let mut verifier = &mut *verifier;
+                    let container = Container::<Pfm, _>::parse_and_verify(
+                        &pfm,
+                        &sha,
+                        &mut verifier,
+                        &toc_arena,
+                        &OutOfMemory,
+                    )
+                    .expect("failed to verify PFM");
+                    verify_flash(
+                        &ParsedPfm::new(container),
+                        device,
+                        when,
+                        &host,
+                        &sha,
+                        &arena,
+                    )
+                }
+                None => {
+                    let container =
+                        Container::<Pfm, _, provenance::Adhoc>::parse(
+                            &pfm, &toc_arena,
+                        )
+                        .expect("failed to parse PFM");
+                    verify_flash(
+                        &ParsedPfm::new(container),
+                        device,
+                        when,
+                        &host,
+                        &sha,
+                        &arena,
+                    )
+                }
+            };
+            if !ok {
+                std::process::exit(1);
+            }
+        }
         CliCommand::ShowManifest {
             key,
             pretty,
@@ -312,14 +998,8 @@ fn main() {
         } => {
             let (mut input, output) = open_files(input, output);
 
-            let mut engine = key.map(|key| {
-                let key = fs::read(key).expect("failed to open file");
-                let keypair = ring::rsa::Keypair::from_pkcs8(&key)
-                    .expect("failed to parse key");
-                ring::rsa::Builder::new()
-                    .new_engine(keypair.public())
-                    .expect("failed to create signature verification engine")
-            });
+            let mut verifier = key.map(|key| load_verifier(&key));
+            let mut engine = verifier.as_deref_mut();
             let sha = ring::sha256::Builder::new();
 
             let mut buf = Vec::new();
@@ -333,28 +1013,354 @@ fn main() {
                 Some(ManifestType::Pfm) => {
                     let parse = owned::Pfm::parse(&buf, &sha, engine.as_mut())
                         .expect("failed to parse PFM");
+                    show_manifest(parse, pretty, output);
+                }
+                Some(ManifestType::Pcd) => {
+                    let parse = owned::Pcd::parse(&buf, &sha, engine.as_mut())
+                        .expect("failed to parse PCD");
+                    show_manifest(parse, pretty, output);
+                }
+                Some(ManifestType::Km) => {
+                    let parse = owned::Km::parse(&buf, &sha, engine.as_mut())
+                        .expect("failed to parse KM");
+                    show_manifest(parse, pretty, output);
+                }
+                None => {
+                    panic!("Unsupported manifest type: 0x{:04x}", manifest_type)
+                }
+            }
+        }
+        CliCommand::Diff {
+            key,
+            json,
+            pretty,
+            output,
+            old,
+            new,
+        } => {
+            let (_, mut output) = open_files(None, output);
+
+            let mut verifier = key.map(|key| load_verifier(&key));
+            let mut engine = verifier.as_deref_mut();
+            let sha = ring::sha256::Builder::new();
 
-                    if parse.bad_signature {
-                        eprintln!("signature verification failed");
-                    }
-                    if parse.bad_toc_hash {
-                        eprintln!("TOC hash verification failed");
-                    }
-                    for idx in parse.bad_hashes {
-                        eprintln!("bad hash for toc entry {}", idx);
-                    }
-
-                    if pretty {
-                        serde_json::to_writer_pretty(output, &parse.container)
-                    } else {
-                        serde_json::to_writer(output, &parse.container)
-                    }
-                    .expect("failed to serialize PFM");
+            let mut parse = |path: &Path| {
+                let buf = fs::read(path).expect("failed to read file");
+                let parse = owned::Pfm::parse(&buf, &sha, engine.as_mut())
+                    .expect("failed to parse PFM");
+                if parse.bad_signature {
+                    eprintln!(
+                        "{}: signature verification failed",
+                        path.display()
+                    );
+                }
+                if parse.bad_toc_hash {
+                    eprintln!(
+                        "{}: TOC hash verification failed",
+                        path.display()
+                    );
+                }
+                for idx in &parse.bad_hashes {
+                    eprintln!(
+                        "{}: bad hash for toc entry {}",
+                        path.display(),
+                        idx
+                    );
+                }
+                parse.container
+            };
+            let changes = diff::pfm(&parse(&old), &parse(&new));
+
+            if json {
+                if pretty {
+                    serde_json::to_writer_pretty(&mut output, &changes)
+                } else {
+                    serde_json::to_writer(&mut output, &changes)
+                }
+                .expect("failed to write JSON");
+                writeln!(output).expect("failed to write to output");
+            } else {
+                for change in &changes {
+                    writeln!(output, "{}", change)
+                        .expect("failed to write to output");
+                }
+            }
+
+            if !changes.is_empty() {
+                output.flush().expect("failed to write to output");
+                std::process::exit(1);
+            }
+        }
+        CliCommand::Lint {
+            key,
+            flash_size,
+            alignment,
+            input,
+        } => {
+            let (mut input, _) = open_files(input, None);
+
+            let mut verifier = key.map(|key| load_verifier(&key));
+            let mut engine = verifier.as_deref_mut();
+            let sha = ring::sha256::Builder::new();
+            let options = lint::Options {
+                alignment,
+                flash_size,
+            };
+
+            let mut buf = Vec::new();
+            input.read_to_end(&mut buf).expect("failed to read file");
+
+            let mut r = &buf[..];
+            let _ = r.read_le::<u16>().expect("input len < 4");
+            let manifest_type = r.read_le::<u16>().expect("input len < 4");
+
+            let problems = match ManifestType::from_wire_value(manifest_type) {
+                Some(ManifestType::Pfm) => lint_manifest(
+                    &buf,
+                    &sha,
+                    engine.as_mut(),
+                    lint::format_versions::<Pfm>(&buf)
+                        .expect("failed to read TOC"),
+                    |pfm| lint::pfm(pfm, &options),
+                ),
+                Some(ManifestType::Pcd) => {
+                    lint_manifest::<owned::pcd::Element, _, _>(
+                        &buf,
+                        &sha,
+                        engine.as_mut(),
+                        lint::format_versions::<Pcd>(&buf)
+                            .expect("failed to read TOC"),
+                        |_| Vec::new(),
+                    )
+                }
+                Some(ManifestType::Km) => {
+                    lint_manifest::<owned::km::Element, _, _>(
+                        &buf,
+                        &sha,
+                        engine.as_mut(),
+                        lint::format_versions::<Km>(&buf)
+                            .expect("failed to read TOC"),
+                        |_| Vec::new(),
+                    )
                 }
                 None => {
                     panic!("Unsupported manifest type: 0x{:04x}", manifest_type)
                 }
+            };
+            if problems {
+                std::process::exit(1);
+            }
+        }
+        CliCommand::BuildPartitions {
+            offset,
+            input,
+            output,
+        } => {
+            let (mut input, mut output) = open_files(input, output);
+            let sha = ring::sha256::Builder::new();
+
+            let mut buf = Vec::new();
+            input.read_to_end(&mut buf).expect("failed to read file");
+            let partitions: Vec<partition::Spec> = serde_json::from_slice(&buf)
+                .expect("failed to parse partitions");
+            let table = partition::encode(offset, &partitions, &sha)
+                .expect("failed to encode partition table");
+            output.write_all(&table).expect("failed to write output");
+        }
+        CliCommand::ShowPartitions {
+            offset,
+            pretty,
+            flash,
+            output,
+        } => {
+            let (_, mut output) = open_files(None, output);
+            let sha = ring::sha256::Builder::new();
+
+            let flash = File::open(flash)
+                .and_then(flash::File::new)
+                .expect("failed to open flash image");
+            let mut arena = vec![0; 8192];
+            let arena = BumpArena::new(&mut arena);
+            let table = partition::Table::parse(&flash, offset, &sha, &arena)
+                .expect("failed to parse partition table");
+
+            let partitions =
+                table.iter().map(partition::Spec::from).collect::<Vec<_>>();
+            if pretty {
+                serde_json::to_writer_pretty(&mut output, &partitions)
+            } else {
+                serde_json::to_writer(&mut output, &partitions)
+            }
+            .expect("failed to write JSON");
+            writeln!(output).expect("failed to write output");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use manticore::crypto::sha256::Builder as _;
+    use manticore::manifest::HashType;
+
+    // Copies of the library's test keys; the public halves were extracted
+    // with `openssl pkey -pubout -outform DER`.
+    const RSA_2048_PRIV_PKCS8: &[u8] =
+        include_bytes!("testdata/rsa_2048_private_key.pk8");
+    const RSA_2048_PUB_DER: &[u8] =
+        include_bytes!("testdata/rsa_2048_public_key.der");
+    const ECDSA_P384_PRIV_PKCS8: &[u8] =
+        include_bytes!("testdata/ecdsa_p384_private_key.pk8");
+    const ECDSA_P384_PUB_DER: &[u8] =
+        include_bytes!("testdata/ecdsa_p384_public_key.der");
+
+    fn cases() -> [(&'static [u8], &'static [u8], sig::Algo); 2] {
+        [
+            (
+                RSA_2048_PUB_DER,
+                RSA_2048_PRIV_PKCS8,
+                parse_algo("rsa-2048").unwrap(),
+            ),
+            (
+                ECDSA_P384_PUB_DER,
+                ECDSA_P384_PRIV_PKCS8,
+                parse_algo("ecdsa-p384").unwrap(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn der_lengths() {
+        assert_eq!(
+            der_next(&[0x02, 1, 7, 9]),
+            Some((0x02, &[7][..], &[9][..]))
+        );
+        assert_eq!(
+            der_next(&[0x04, 0x81, 2, 1, 2]),
+            Some((0x04, &[1, 2][..], &[][..]))
+        );
+        assert_eq!(der_next(&[]), None);
+        assert_eq!(der_next(&[0x02]), None);
+        assert_eq!(der_next(&[0x02, 2, 7]), None);
+        assert_eq!(der_next(&[0x02, 0x82, 1]), None);
+        assert_eq!(der_next(&[0x02, 0x85, 0, 0, 0, 0, 1, 7]), None);
+        assert_eq!(der_next(&[0x02, 0x84, 0xff, 0xff, 0xff, 0xff, 7]), None);
+    }
+
+    #[test]
+    fn public_keys() {
+        for &(public, private, algo) in &cases() {
+            let verifier = parse_public_key(public).unwrap();
+            let keypair = pkcs8_verifier(private);
+            assert_eq!(
+                verifier.key_digest(algo).unwrap(),
+                keypair.key_digest(algo).unwrap(),
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_public_keys() {
+        for &(public, _, _) in &cases() {
+            for len in 0..public.len() {
+                assert!(parse_public_key(&public[..len]).is_none());
+            }
+
+            let mut trailing = public.to_vec();
+            trailing.push(0);
+            assert!(parse_public_key(&trailing).is_none());
+
+            for i in 0..public.len() {
+                let mut corrupt = public.to_vec();
+                corrupt[i] ^= 0x80;
+                // Must not panic; flipping a bit inside a key's value may
+                // still produce a key `ring` accepts.
+                let _ = parse_public_key(&corrupt);
             }
         }
+
+        // Nonzero unused-bits count in the key's BIT STRING.
+        let mut unused_bits = ECDSA_P384_PUB_DER.to_vec();
+        let key_start = ECDSA_P384_PUB_DER.len() - 97;
+        assert_eq!(unused_bits[key_start - 1], 0);
+        unused_bits[key_start - 1] = 1;
+        assert!(parse_public_key(&unused_bits).is_none());
+
+        // A private key is not a public key.
+        assert!(parse_public_key(RSA_2048_PRIV_PKCS8).is_none());
+        assert!(parse_public_key(ECDSA_P384_PRIV_PKCS8).is_none());
+    }
+
+    #[test]
+    fn verify_flash_images() {
+        let sha = ring::sha256::Builder::new();
+        let mut signer = pkcs8_signer(RSA_2048_PRIV_PKCS8);
+        let mut signer = &mut *signer;
+
+        let mut host = vec![0xff; 0x400];
+        host[0x10..0x14].copy_from_slice(b"v1.0");
+        for (i, b) in host[0x100..0x200].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let mut hash = [0; 32];
+        sha.hash_contiguous(&host[0x100..0x200], &mut hash).unwrap();
+
+        let pfm = owned::Pfm {
+            metadata: manifest::Metadata { version_id: 1 },
+            elements: vec![owned::Node {
+                element: owned::pfm::Element::AllowableFw {
+                    version_count: 1,
+                    firmware_id: b"bmc".to_vec(),
+                    flags: 0,
+                },
+                children: vec![owned::Node {
+                    element: owned::pfm::Element::FwVersion {
+                        version_addr: 0x10,
+                        version_str: b"v1.0".to_vec(),
+                        rw_regions: vec![],
+                        image_regions: vec![owned::pfm::Image {
+                            flags: 0b1,
+                            hash_type: HashType::Sha256,
+                            hash: hash.to_vec(),
+                            regions: vec![flash::Region::new(0x100, 0x100)],
+                        }],
+                        format_version: 0,
+                    },
+                    children: vec![],
+                    hashed: true,
+                }],
+                hashed: true,
+            }],
+        };
+        let pfm = pfm.sign(0x0, &sha, &mut signer).unwrap();
+        let pfm = Ram(&pfm[..]);
+
+        let mut arena = vec![0; 4096];
+        let arena = BumpArena::new(&mut arena);
+        let container =
+            Container::<Pfm, _, provenance::Adhoc>::parse(&pfm, &arena)
+                .unwrap();
+        let pfm = ParsedPfm::new(container);
+        let verify = |host: &[u8]| {
+            verify_flash(
+                &pfm,
+                0,
+                ValidationTime::Activation,
+                &Ram(host),
+                &sha,
+                &arena,
+            )
+        };
+
+        assert!(verify(&host));
+
+        let mut mismatch = host.clone();
+        mismatch[0x180] ^= 0xff;
+        assert!(!verify(&mismatch));
+
+        let mut unknown = host;
+        unknown[0x10..0x14].copy_from_slice(b"v2.0");
+        assert!(!verify(&unknown));
     }
 }